@@ -0,0 +1,118 @@
+use std::{
+    fmt,
+    io::ErrorKind,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
+    str::FromStr,
+};
+
+/// The SSDP IPv4 multicast group, `239.255.255.250`.
+pub const SSDP_ADDR_V4: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+
+/// The link-local SSDP IPv6 multicast group, `ff02::c`.
+pub const SSDP_ADDR_V6_LINK_LOCAL: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xc);
+
+/// The site-local SSDP IPv6 multicast group, `ff05::c`.
+pub const SSDP_ADDR_V6_SITE_LOCAL: Ipv6Addr = Ipv6Addr::new(0xff05, 0, 0, 0, 0, 0, 0, 0xc);
+
+/// The organization-local SSDP IPv6 multicast group, `ff08::c`.
+pub const SSDP_ADDR_V6_ORG_LOCAL: Ipv6Addr = Ipv6Addr::new(0xff08, 0, 0, 0, 0, 0, 0, 0xc);
+
+/// The global SSDP IPv6 multicast group, `ff0e::c`.
+pub const SSDP_ADDR_V6_GLOBAL: Ipv6Addr = Ipv6Addr::new(0xff0e, 0, 0, 0, 0, 0, 0, 0xc);
+
+/// The SSDP port, `1900`.
+pub const SSDP_PORT: u16 = 1900;
+
+/// The SSDP IPv4 multicast endpoint, `239.255.255.250:1900`.
+///
+/// # Examples
+/// ```
+/// assert_eq!(tokio_ssdp::ssdp_multicast_v4().to_string(), "239.255.255.250:1900");
+/// ```
+pub fn ssdp_multicast_v4() -> SocketAddr {
+    SocketAddr::from((SSDP_ADDR_V4, SSDP_PORT))
+}
+
+/// The link-local SSDP IPv6 multicast endpoint on the interface with index `scope_id`, `[ff02::c]:1900`.
+pub fn ssdp_multicast_v6(scope_id: u32) -> SocketAddr {
+    SocketAddr::V6(SocketAddrV6::new(
+        SSDP_ADDR_V6_LINK_LOCAL,
+        SSDP_PORT,
+        0,
+        scope_id,
+    ))
+}
+
+/// An IPv4 address with a prefix length, e.g. `192.168.1.10/24`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Ipv4Subnet {
+    addr: Ipv4Addr,
+    prefix_len: u8,
+}
+
+impl Ipv4Subnet {
+    /// Create a new subnet, returns `None` if `prefix_len` is larger than 32.
+    pub fn new(addr: Ipv4Addr, prefix_len: u8) -> Option<Self> {
+        (prefix_len <= 32).then_some(Self { addr, prefix_len })
+    }
+
+    /// The address of the subnet, as given on construction.
+    pub fn addr(&self) -> Ipv4Addr {
+        self.addr
+    }
+
+    /// The prefix length of the subnet.
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Returns `true` if `addr` is in the subnet.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::net::Ipv4Addr;
+    /// # use tokio_ssdp::Ipv4Subnet;
+    /// let subnet: Ipv4Subnet = "192.168.1.10/24".parse().unwrap();
+    /// assert!(subnet.contains(Ipv4Addr::new(192, 168, 1, 200)));
+    /// assert!(!subnet.contains(Ipv4Addr::new(192, 168, 2, 1)));
+    /// ```
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        let mask = u32::MAX
+            .checked_shl(32 - self.prefix_len as u32)
+            .unwrap_or(0);
+        u32::from(self.addr) & mask == u32::from(addr) & mask
+    }
+}
+
+impl From<Ipv4Addr> for Ipv4Subnet {
+    /// The `/32` subnet of a single address.
+    fn from(addr: Ipv4Addr) -> Self {
+        Self {
+            addr,
+            prefix_len: 32,
+        }
+    }
+}
+
+impl FromStr for Ipv4Subnet {
+    type Err = std::io::Error;
+
+    /// Parse `a.b.c.d/len`, or `a.b.c.d` as a `/32` subnet.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || std::io::Error::new(ErrorKind::InvalidInput, format!("Invalid subnet: {}", s));
+
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, len.parse().map_err(|_| invalid())?),
+            None => (s, 32),
+        };
+
+        Self::new(addr.parse().map_err(|_| invalid())?, prefix_len).ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for Ipv4Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+/// An exponential backoff policy.
+///
+/// The delay before attempt `n` (starting at 0) is `initial * 2^n`, capped at `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    max_attempts: Option<u32>,
+}
+
+impl Backoff {
+    /// Create a new backoff policy, with an unlimited number of attempts.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::time::Duration;
+    /// use tokio_ssdp::Backoff;
+    ///
+    /// Backoff::new(Duration::from_millis(100), Duration::from_secs(30))
+    ///     .max_attempts(10);
+    /// ```
+    pub const fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            max_attempts: None,
+        }
+    }
+
+    /// Give up after `max_attempts` attempts.
+    pub const fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    /// The delay before attempt `attempt`, or `None` if no attempts are left.
+    pub(crate) fn delay(&self, attempt: u32) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max| attempt >= max) {
+            return None;
+        }
+
+        let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+        Some(self.initial.saturating_mul(factor).min(self.max))
+    }
+}
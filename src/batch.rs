@@ -0,0 +1,180 @@
+use std::{io, net::SocketAddr};
+
+use crate::{Transport, transport};
+
+/// Send the datagrams `bufs` to `target`, with as few system calls as the platform
+/// allows: `sendmmsg` on Linux, one send per datagram elsewhere and on transports other
+/// than UDP sockets.
+///
+/// Returns the number of datagrams sent, which is less than `bufs.len()` if sending one
+/// of them failed. Fails only if none was sent.
+pub(crate) async fn send_to(
+    transport: &dyn Transport,
+    bufs: &[&[u8]],
+    target: SocketAddr,
+) -> io::Result<usize> {
+    match transport.udp_socket() {
+        Some(socket) => imp::send_to(socket, bufs, target).await,
+        None => send_each(transport, bufs, target).await,
+    }
+}
+
+async fn send_each(
+    transport: &dyn Transport,
+    bufs: &[&[u8]],
+    target: SocketAddr,
+) -> io::Result<usize> {
+    for (sent, buf) in bufs.iter().enumerate() {
+        if let Err(e) = transport::send_to(transport, buf, target).await {
+            return if sent == 0 { Err(e) } else { Ok(sent) };
+        }
+    }
+    Ok(bufs.len())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod imp {
+    use std::{
+        io,
+        mem::size_of_val,
+        net::{SocketAddr, SocketAddrV4},
+        os::unix::io::{AsRawFd, RawFd},
+        ptr,
+    };
+
+    use tokio::{io::Interest, net::UdpSocket};
+
+    /// The most datagrams Linux sends with one `sendmmsg`, `UIO_MAXIOV`.
+    const MAX_BATCH: usize = 1024;
+
+    pub(super) async fn send_to(
+        socket: &UdpSocket,
+        bufs: &[&[u8]],
+        target: SocketAddr,
+    ) -> io::Result<usize> {
+        let SocketAddr::V4(target) = target else {
+            return super::send_each(socket, bufs, target).await;
+        };
+
+        let fd = socket.as_raw_fd();
+        let mut sent = 0;
+        while sent < bufs.len() {
+            let chunk = &bufs[sent..bufs.len().min(sent + MAX_BATCH)];
+            match socket
+                .async_io(Interest::WRITABLE, || sendmmsg(fd, chunk, target))
+                .await
+            {
+                Ok(n) => sent += n,
+                Err(e) if sent == 0 => return Err(e),
+                Err(_) => break,
+            }
+        }
+        Ok(sent)
+    }
+
+    fn sendmmsg(fd: RawFd, bufs: &[&[u8]], target: SocketAddrV4) -> io::Result<usize> {
+        // SAFETY: all pointers in `msgs` refer to `name`, `iovs` and `bufs`, which outlive
+        // the call and are not moved during it, with lengths matching their sizes.
+        unsafe {
+            let mut name: libc::sockaddr_in = std::mem::zeroed();
+            name.sin_family = libc::AF_INET as libc::sa_family_t;
+            name.sin_port = target.port().to_be();
+            name.sin_addr.s_addr = u32::from(*target.ip()).to_be();
+
+            let mut iovs: Vec<libc::iovec> = bufs
+                .iter()
+                .map(|buf| libc::iovec {
+                    iov_base: buf.as_ptr().cast_mut().cast(),
+                    iov_len: buf.len(),
+                })
+                .collect();
+
+            let name_ptr = ptr::addr_of_mut!(name);
+            let mut msgs: Vec<libc::mmsghdr> = iovs
+                .iter_mut()
+                .map(|iov| {
+                    let mut msg: libc::mmsghdr = std::mem::zeroed();
+                    msg.msg_hdr.msg_name = name_ptr.cast();
+                    msg.msg_hdr.msg_namelen = size_of_val(&name) as libc::socklen_t;
+                    msg.msg_hdr.msg_iov = iov;
+                    msg.msg_hdr.msg_iovlen = 1;
+                    msg
+                })
+                .collect();
+
+            let n = libc::sendmmsg(fd, msgs.as_mut_ptr(), msgs.len() as _, 0);
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(n as usize)
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+mod imp {
+    use std::{io, net::SocketAddr};
+
+    use tokio::net::UdpSocket;
+
+    pub(super) async fn send_to(
+        socket: &UdpSocket,
+        bufs: &[&[u8]],
+        target: SocketAddr,
+    ) -> io::Result<usize> {
+        super::send_each(socket, bufs, target).await
+    }
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "android")))]
+mod tests {
+    use std::{net::Ipv4Addr, os::unix::io::AsRawFd};
+
+    use tokio::net::UdpSocket;
+
+    use super::*;
+
+    /// Raise the receive buffer to hold a whole batch, since over loopback a single
+    /// `sendmmsg` is delivered before the receiver gets to run.
+    fn raise_rcvbuf(socket: &UdpSocket, size: libc::c_int) {
+        for option in [libc::SO_RCVBUFFORCE, libc::SO_RCVBUF] {
+            // SAFETY: `size` is a c_int living across the call, with its size passed along.
+            let ret = unsafe {
+                libc::setsockopt(
+                    socket.as_raw_fd(),
+                    libc::SOL_SOCKET,
+                    option,
+                    (&raw const size).cast(),
+                    size_of::<libc::c_int>() as libc::socklen_t,
+                )
+            };
+            if ret == 0 {
+                return;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_batches_larger_than_one_call() {
+        const COUNT: usize = 1100;
+
+        let rx = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        raise_rcvbuf(&rx, 4 << 20);
+        let tx = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+
+        let payloads: Vec<[u8; 2]> = (0..COUNT as u16).map(u16::to_be_bytes).collect();
+        let bufs: Vec<&[u8]> = payloads.iter().map(|p| &p[..]).collect();
+        let sent = send_to(&tx, &bufs, rx.local_addr().unwrap()).await.unwrap();
+        assert_eq!(sent, COUNT);
+
+        let mut seen = vec![false; COUNT];
+        let mut buf = [0; 16];
+        for _ in 0..COUNT {
+            let (n, from) = rx.recv_from(&mut buf).await.unwrap();
+            assert_eq!(n, 2);
+            assert_eq!(from, tx.local_addr().unwrap());
+            seen[u16::from_be_bytes([buf[0], buf[1]]) as usize] = true;
+        }
+        assert!(seen.iter().all(|&seen| seen));
+    }
+}
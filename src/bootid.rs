@@ -0,0 +1,62 @@
+use std::{fmt, io::Result as IoResult, path::PathBuf};
+
+/// Persistent storage for the boot id (`BOOTID.UPNP.ORG`), see
+/// [`Server::boot_id_store`](crate::Server::boot_id_store).
+///
+/// The specification requires the boot id to increase each time a device rejoins the
+/// network, also across restarts. Implement this trait to keep it in e.g. NVRAM.
+pub trait BootIdStore: Send + Sync {
+    /// The boot id stored last, if any.
+    fn load(&self) -> IoResult<Option<u32>>;
+
+    /// Store `boot_id`, replacing the stored boot id.
+    fn store(&self, boot_id: u32) -> IoResult<()>;
+}
+
+impl fmt::Debug for dyn BootIdStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BootIdStore")
+    }
+}
+
+/// Stores the boot id as a decimal number in a file.
+#[derive(Debug, Clone)]
+pub struct FileBootIdStore {
+    path: PathBuf,
+}
+
+impl FileBootIdStore {
+    /// Store the boot id in the file at `path`, which is created as needed.
+    ///
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{FileBootIdStore, Server};
+    ///
+    /// Server::new([]).boot_id_store(FileBootIdStore::new("/var/lib/acme/bootid"));
+    /// ```
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl BootIdStore for FileBootIdStore {
+    fn load(&self) -> IoResult<Option<u32>> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(content) => content
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn store(&self, boot_id: u32) -> IoResult<()> {
+        // Replace the file atomically, so a crash never leaves it truncated
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, format!("{}\n", boot_id))?;
+        std::fs::rename(&tmp, &self.path)
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, RandomState},
+    net::Ipv4Addr,
+    sync::{Arc, Mutex},
+};
+
+use crate::{Device, MessageKind};
+
+/// Stands in for the `DATE` value while building a message to cache.
+pub(crate) const DATE_MARKER: &str = "\0date\0";
+
+/// Above this many messages, the cache is cleared.
+const MAX_ENTRIES: usize = 4096;
+
+/// Everything a cached message depends on, except the device definition itself, which
+/// is covered by clearing the cache when the devices change. The location is part of
+/// it, as it may be computed, see [`Device::location_fn`](crate::Device::location_fn).
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct Key {
+    kind: MessageKind,
+    usn: String,
+    st: String,
+    local_addr: Option<Ipv4Addr>,
+    host_addr: Ipv4Addr,
+    boot_id: u32,
+    config_id: u32,
+    location: u64,
+    extra_headers: u64,
+}
+
+/// A message split at its `DATE` value, if it has one.
+#[derive(Debug)]
+struct Template {
+    head: String,
+    tail: Option<String>,
+}
+
+/// The state a message is built in, see [`MessageCache::get_or_build`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CacheContext<'a> {
+    pub(crate) kind: MessageKind,
+    pub(crate) device: &'a Device,
+    pub(crate) local_addr: Option<Ipv4Addr>,
+    pub(crate) host_addr: Ipv4Addr,
+    pub(crate) boot_id: u32,
+    pub(crate) config_id: u32,
+    pub(crate) extra_headers: &'a str,
+}
+
+/// Search responses and notifications built before, so the hot path only fills in the
+/// date.
+///
+/// Clones share the same messages.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MessageCache {
+    messages: Arc<Mutex<HashMap<Key, Template>>>,
+    hasher: RandomState,
+}
+
+impl MessageCache {
+    /// The message for `ctx`, built with `build` unless cached. `build` is given the
+    /// value to use for the `DATE` header.
+    pub(crate) fn get_or_build(
+        &self,
+        ctx: CacheContext<'_>,
+        date: impl FnOnce() -> String,
+        build: impl FnOnce(&str) -> String,
+    ) -> String {
+        let key = Key {
+            kind: ctx.kind,
+            usn: ctx.device.usn.clone(),
+            st: ctx.device.search_target.clone(),
+            local_addr: ctx.local_addr,
+            host_addr: ctx.host_addr,
+            boot_id: ctx.boot_id,
+            config_id: ctx.config_id,
+            location: self.hasher.hash_one(ctx.device.current_location()),
+            extra_headers: self.hasher.hash_one(ctx.extra_headers),
+        };
+
+        let mut messages = self.lock();
+        if messages.len() >= MAX_ENTRIES && !messages.contains_key(&key) {
+            messages.clear();
+        }
+        let template = messages.entry(key).or_insert_with(|| {
+            let message = build(DATE_MARKER);
+            match message.split_once(DATE_MARKER) {
+                Some((head, tail)) => Template {
+                    head: head.to_string(),
+                    tail: Some(tail.to_string()),
+                },
+                None => Template {
+                    head: message,
+                    tail: None,
+                },
+            }
+        });
+
+        match &template.tail {
+            Some(tail) => {
+                let date = date();
+                let mut message =
+                    String::with_capacity(template.head.len() + date.len() + tail.len());
+                message.push_str(&template.head);
+                message.push_str(&date);
+                message.push_str(tail);
+                message
+            }
+            None => template.head.clone(),
+        }
+    }
+
+    /// Forget all messages, e.g. because the devices changed.
+    pub(crate) fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Key, Template>> {
+        self.messages.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,1005 @@
+use std::{
+    collections::HashMap,
+    future::poll_fn,
+    io::Result as IoResult,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll, ready},
+    time::{Duration, SystemTime},
+};
+
+use futures_core::Stream;
+use tokio::{
+    io::ReadBuf,
+    net::UdpSocket,
+    sync::mpsc,
+    time::{Instant, Sleep},
+};
+
+use crate::{
+    Ipv4Subnet, Notification, Registry, SSDP_ADDR_V4, SSDP_PORT, SearchRequest,
+    logging::trace,
+    message, rng,
+    shared::{Datagram, SharedLoop},
+};
+
+const DEFAULT_MX: u32 = 3;
+const DEFAULT_TTL: u32 = 2;
+
+/// A response to an `M-SEARCH` request, sent by a device on the network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SearchResponse {
+    /// The address of the device which sent the response.
+    pub remote_addr: SocketAddr,
+    /// The search target of the device.
+    pub st: String,
+    /// The unique service name of the device.
+    pub usn: String,
+    /// The URL of the description of the device.
+    pub location: String,
+    /// How long the response is valid, from `CACHE-CONTROL: max-age`.
+    pub max_age: Option<u64>,
+    /// All headers of the response, in order of appearance.
+    pub headers: Vec<(String, String)>,
+    /// The time from sending the search to receiving the response, see
+    /// [`SearchResponses::ranked`].
+    pub latency: Duration,
+    /// Whether the response passed [`message::SearchResponse::validate`]. Responses
+    /// which didn't are only yielded by [`Client::lenient`] clients.
+    pub conforming: bool,
+    /// When the response was received.
+    pub received: SystemTime,
+}
+
+impl SearchResponse {
+    /// Get the value of the first header named `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// How much longer the response is valid, i.e. its `max-age` less the time since it
+    /// was received, or `None` without `CACHE-CONTROL: max-age`.
+    ///
+    /// Together with serializing responses with the `serde` feature, this allows
+    /// resuming from saved responses without searching again, see [`Registry::restore`].
+    ///
+    /// # Examples
+    /// ```
+    /// # fn load() -> Vec<tokio_ssdp::SearchResponse> { vec![] }
+    /// use std::time::Duration;
+    ///
+    /// let saved = load();
+    /// let valid = saved
+    ///     .iter()
+    ///     .filter(|response| response.remaining_max_age() > Some(Duration::ZERO));
+    /// ```
+    pub fn remaining_max_age(&self) -> Option<Duration> {
+        let max_age = Duration::from_secs(self.max_age?);
+        let age = self.received.elapsed().unwrap_or_default();
+        Some(max_age.saturating_sub(age))
+    }
+
+    /// Parse a datagram received from `remote_addr` `latency` after sending the search,
+    /// returning `None` if it is not a search response, or not a valid one unless
+    /// `lenient`.
+    pub(crate) fn parse(
+        buf: &[u8],
+        remote_addr: SocketAddr,
+        latency: Duration,
+        lenient: bool,
+    ) -> Option<Self> {
+        let response = match message::SearchResponse::parse(buf) {
+            Ok(response) => response,
+            Err(e) => {
+                trace!(
+                    "Ignoring invalid search response from {}: {}",
+                    remote_addr, e
+                );
+                return None;
+            }
+        };
+        let conforming = match response.validate() {
+            Ok(()) => true,
+            Err(e) if lenient => {
+                trace!(
+                    "Accepting invalid search response from {}: {}",
+                    remote_addr, e
+                );
+                false
+            }
+            Err(e) => {
+                trace!(
+                    "Ignoring invalid search response from {}: {}",
+                    remote_addr, e
+                );
+                return None;
+            }
+        };
+
+        Some(Self {
+            remote_addr,
+            st: response.st,
+            usn: response.usn,
+            location: response.location,
+            max_age: response.max_age,
+            headers: response.headers,
+            latency,
+            conforming,
+            received: SystemTime::now(),
+        })
+    }
+}
+
+/// Which socket a [`Client`] searches with, see [`Client::socket_strategy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SocketStrategy {
+    /// A new socket bound to an ephemeral port for each search, receiving only the
+    /// responses to the search. Cheap, and suited to one-shot tools.
+    #[default]
+    Ephemeral,
+    /// One socket bound to the SSDP port and joined to the multicast group, shared by
+    /// all searches of the client and its clones while any of them is in use. Besides
+    /// the responses, it receives the `NOTIFY` messages of devices, which a
+    /// [`Registry`] from [`Client::discover`] tracks as well. Suited to daemons.
+    ///
+    /// The port is shared with other SSDP stacks on the host where supported, see
+    /// [`Server::reuse_port`](crate::Server::reuse_port). Unicast responses are then
+    /// delivered to only one of the sockets bound to the port, so responses can be
+    /// missed if another SSDP stack on the host bound it later. Concurrent searches on
+    /// the shared socket split the received responses between them.
+    Shared,
+}
+
+/// A SSDP client, discovering devices on the network with `M-SEARCH` requests.
+///
+/// A client can also share the socket and receive loop of a [`Server`](crate::Server)
+/// on the same host, as only one of them can practically bind the SSDP port, see
+/// [`Server::client`](crate::Server::client).
+#[derive(Debug, Clone)]
+pub struct Client {
+    ip: Ipv4Addr,
+    interface: Option<Ipv4Addr>,
+    mx: u32,
+    ttl: u32,
+    user_agent: Option<String>,
+    research_interval: Option<Duration>,
+    research_jitter: Duration,
+    filter: Filter,
+    strategy: SocketStrategy,
+    shared: Arc<Mutex<Weak<UdpSocket>>>,
+    lenient: bool,
+    server: Option<SharedLoop>,
+    multicast: SocketAddrV4,
+}
+
+/// Which responses are ignored, see [`Client::allow_subnet`], [`Client::deny_subnet`],
+/// [`Client::ignore_usn`], [`Client::ignore_st`] and [`Client::ignore_location_host`].
+#[derive(Debug, Clone, Default)]
+struct Filter {
+    allowed: Vec<Ipv4Subnet>,
+    denied: Vec<Ipv4Subnet>,
+    usns: Vec<String>,
+    sts: Vec<String>,
+    location_hosts: Vec<String>,
+}
+
+impl Filter {
+    /// Whether a message from `remote_addr` with the USN `usn`, search target `st` and
+    /// `LOCATION` `location` is yielded.
+    fn is_allowed(
+        &self,
+        remote_addr: SocketAddr,
+        usn: &str,
+        st: &str,
+        location: Option<&str>,
+    ) -> bool {
+        let allowed = match remote_addr.ip() {
+            IpAddr::V4(addr) => {
+                (self.allowed.is_empty() || self.allowed.iter().any(|subnet| subnet.contains(addr)))
+                    && !self.denied.iter().any(|subnet| subnet.contains(addr))
+            }
+            IpAddr::V6(_) => self.allowed.is_empty(),
+        };
+        let uuid = usn.split("::").next().unwrap_or_default();
+        let host = location.and_then(location_host);
+
+        allowed
+            && !self.usns.iter().any(|ignored| {
+                ignored.eq_ignore_ascii_case(usn) || ignored.eq_ignore_ascii_case(uuid)
+            })
+            && !self
+                .sts
+                .iter()
+                .any(|ignored| ignored.eq_ignore_ascii_case(st))
+            && !host.is_some_and(|host| {
+                self.location_hosts
+                    .iter()
+                    .any(|ignored| ignored.eq_ignore_ascii_case(host))
+            })
+    }
+}
+
+/// The host of the URL `location`, without brackets or port, e.g. `192.168.1.10` of
+/// `http://192.168.1.10:8080/desc.xml`.
+fn location_host(location: &str) -> Option<&str> {
+    let (_, rest) = location.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = match authority.strip_prefix('[') {
+        Some(ipv6) => ipv6.split_once(']')?.0,
+        None => authority.split(':').next()?,
+    };
+    (!host.is_empty()).then_some(host)
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self {
+            ip: Ipv4Addr::UNSPECIFIED,
+            interface: None,
+            mx: DEFAULT_MX,
+            ttl: DEFAULT_TTL,
+            user_agent: None,
+            research_interval: None,
+            research_jitter: Duration::ZERO,
+            filter: Filter::default(),
+            strategy: SocketStrategy::default(),
+            shared: Default::default(),
+            lenient: false,
+            server: None,
+            multicast: SocketAddrV4::new(SSDP_ADDR_V4, SSDP_PORT),
+        }
+    }
+}
+
+impl Client {
+    /// Create a new client.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::Client;
+    ///
+    /// let mut responses = Client::new().mx(2).search("upnp:rootdevice").await?;
+    /// while let Some(response) = responses.next().await {
+    ///     let response = response?;
+    ///     println!("{} at {}", response.usn, response.location);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bind the socket of searches to the address `ip`, defaults to all interfaces.
+    ///
+    /// Unless set with [`Client::interface`], searches are also sent from the interface
+    /// with this address.
+    pub fn bind_addr(mut self, ip: Ipv4Addr) -> Self {
+        self.ip = ip;
+        self
+    }
+
+    /// Send searches from the interface with address `ip` with `IP_MULTICAST_IF`,
+    /// instead of the interface of the default route.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use std::net::Ipv4Addr;
+    /// use tokio_ssdp::Client;
+    ///
+    /// let responses = Client::new()
+    ///     .interface(Ipv4Addr::new(192, 168, 1, 10))
+    ///     .search("ssdp:all")
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn interface(mut self, ip: Ipv4Addr) -> Self {
+        self.interface = Some(ip);
+        self
+    }
+
+    /// Set the `MX` value of searches, i.e. the number of seconds devices may wait
+    /// before responding, defaults to 3.
+    ///
+    /// Responses are collected for one second longer than this.
+    pub fn mx(mut self, mx: u32) -> Self {
+        self.mx = mx;
+        self
+    }
+
+    /// Set the IP TTL of searches, defaults to 2 as recommended by the UPnP device
+    /// architecture.
+    pub fn ttl(mut self, ttl: u32) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Set the `USER-AGENT` header of searches.
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Send searches to the multicast group `addr` and port `port` instead of
+    /// `239.255.255.250:1900`, see [`Server::multicast_endpoint`](crate::Server::multicast_endpoint).
+    pub fn multicast_endpoint(mut self, addr: Ipv4Addr, port: u16) -> Self {
+        self.multicast = SocketAddrV4::new(addr, port);
+        self
+    }
+
+    /// Repeat searches every `interval`, merging the responses into the same
+    /// [`SearchResponses`], which then doesn't end. Defaults to a single search.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use std::time::Duration;
+    /// use tokio_ssdp::Client;
+    ///
+    /// let mut responses = Client::new()
+    ///     .research_interval(Duration::from_secs(60))
+    ///     .research_jitter(Duration::from_secs(10))
+    ///     .search("ssdp:all")
+    ///     .await?;
+    /// while let Some(response) = responses.next().await {
+    ///     println!("{}", response?.usn);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn research_interval(mut self, interval: Duration) -> Self {
+        self.research_interval = Some(interval);
+        self
+    }
+
+    /// Shorten each interval between repeated searches by a random duration up to
+    /// `jitter`, so clients started together don't synchronize, defaults to none. See
+    /// [`Client::research_interval`].
+    pub fn research_jitter(mut self, jitter: Duration) -> Self {
+        self.research_jitter = jitter;
+        self
+    }
+
+    /// Only yield responses from devices in `subnet`. Can be called multiple times to
+    /// allow several subnets.
+    ///
+    /// Without allowed subnets, responses from all devices are yielded unless denied
+    /// with [`Client::deny_subnet`]. Subnets match the address responses are sent from,
+    /// see [`Client::ignore_location_host`] for the host of their `LOCATION`.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::net::Ipv4Addr;
+    /// use tokio_ssdp::Client;
+    ///
+    /// Client::new()
+    ///   .allow_subnet("192.168.1.0/24".parse().unwrap())
+    ///   .deny_addr(Ipv4Addr::new(192, 168, 1, 13))
+    ///   .ignore_usn("uuid:4d696e69-444c-164e-9d41-b827eb54e939")
+    ///   .ignore_st("urn:schemas-upnp-org:device:Printer:1")
+    ///   .ignore_location_host("192.168.1.20");
+    /// ```
+    pub fn allow_subnet(mut self, subnet: Ipv4Subnet) -> Self {
+        self.filter.allowed.push(subnet);
+        self
+    }
+
+    /// Only yield responses from `addr`, see [`Client::allow_subnet`].
+    pub fn allow_addr(self, addr: Ipv4Addr) -> Self {
+        self.allow_subnet(Ipv4Subnet::from(addr))
+    }
+
+    /// Ignore responses from devices in `subnet`, even if allowed with
+    /// [`Client::allow_subnet`].
+    pub fn deny_subnet(mut self, subnet: Ipv4Subnet) -> Self {
+        self.filter.denied.push(subnet);
+        self
+    }
+
+    /// Ignore responses from `addr`, see [`Client::deny_subnet`].
+    pub fn deny_addr(self, addr: Ipv4Addr) -> Self {
+        self.deny_subnet(Ipv4Subnet::from(addr))
+    }
+
+    /// Ignore responses with the USN `usn`, ignoring case. A bare `uuid:...` ignores
+    /// all responses of the device, whatever their search target.
+    pub fn ignore_usn(mut self, usn: impl Into<String>) -> Self {
+        self.filter.usns.push(usn.into());
+        self
+    }
+
+    /// Ignore responses with the search target `st`, ignoring case, e.g. to search for
+    /// `ssdp:all` without the responses of each service.
+    pub fn ignore_st(mut self, st: impl Into<String>) -> Self {
+        self.filter.sts.push(st.into());
+        self
+    }
+
+    /// Ignore responses whose `LOCATION` is on the host `host`, ignoring case, e.g.
+    /// `192.168.1.20` or `printer.local`, without port or brackets.
+    ///
+    /// Unlike [`Client::deny_addr`], this matches the host serving the description,
+    /// which differs from the address responses are sent from for proxied or
+    /// multi-homed devices.
+    pub fn ignore_location_host(mut self, host: impl Into<String>) -> Self {
+        self.filter.location_hosts.push(host.into());
+        self
+    }
+
+    /// Also yield responses which aren't valid according to the specification, e.g.
+    /// without `CACHE-CONTROL` or with a relative `LOCATION`, flagged by
+    /// [`SearchResponse::conforming`]. By default they are ignored.
+    ///
+    /// Responses without an `ST`, `USN` or `LOCATION` header are always ignored.
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Choose between a socket per search and one shared socket, defaults to
+    /// [`SocketStrategy::Ephemeral`].
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use std::time::Duration;
+    /// use tokio_ssdp::{Client, SocketStrategy};
+    ///
+    /// // Track devices by their responses and their NOTIFY messages
+    /// let mut registry = Client::new()
+    ///     .socket_strategy(SocketStrategy::Shared)
+    ///     .research_interval(Duration::from_secs(600))
+    ///     .discover("ssdp:all")
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn socket_strategy(mut self, strategy: SocketStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Search through the socket and receive loop of a server, see [`Server::client`].
+    ///
+    /// [`Server::client`]: crate::Server::client
+    pub(crate) fn server(mut self, server: SharedLoop) -> Self {
+        self.server = Some(server);
+        self
+    }
+
+    /// The socket to search with, see [`Client::socket_strategy`].
+    fn socket(&self) -> IoResult<Arc<UdpSocket>> {
+        if self.strategy == SocketStrategy::Ephemeral {
+            return self.new_socket(0).map(Arc::new);
+        }
+
+        let mut shared = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(socket) = shared.upgrade() {
+            return Ok(socket);
+        }
+        let socket = Arc::new(self.new_socket(self.multicast.port())?);
+        socket.join_multicast_v4(*self.multicast.ip(), self.interface.unwrap_or(self.ip))?;
+        *shared = Arc::downgrade(&socket);
+        Ok(socket)
+    }
+
+    /// Create a socket to search with, bound to `port`, see [`Client::bind_addr`] and
+    /// [`Client::interface`].
+    fn new_socket(&self, port: u16) -> IoResult<UdpSocket> {
+        let socket = if port == 0 {
+            std::net::UdpSocket::bind((self.ip, 0))?
+        } else {
+            crate::Server::new_rx_socket(port, true)?
+        };
+        socket.set_nonblocking(true)?;
+        socket.set_multicast_ttl_v4(self.ttl)?;
+
+        let interface = self
+            .interface
+            .or((!self.ip.is_unspecified()).then_some(self.ip));
+        if let Some(ip) = interface {
+            #[cfg(feature = "socket2")]
+            socket2::SockRef::from(&socket).set_multicast_if_v4(&ip)?;
+            #[cfg(not(feature = "socket2"))]
+            crate::iface::set_multicast_if(&socket, ip)?;
+        }
+
+        UdpSocket::from_std(socket)
+    }
+
+    /// The `HOST` header of searches to a multicast endpoint other than the standard one.
+    fn custom_host(&self) -> Option<String> {
+        (self.multicast != SocketAddrV4::new(SSDP_ADDR_V4, SSDP_PORT))
+            .then(|| self.multicast.to_string())
+    }
+
+    /// Search for devices with search target `st`, e.g. `ssdp:all`, returning a stream
+    /// of the responses received within `MX` seconds, or of all responses if searches
+    /// are repeated, see [`Client::research_interval`].
+    pub async fn search(&self, st: impl AsRef<str>) -> IoResult<SearchResponses> {
+        let request = SearchRequest {
+            st: st.as_ref().to_string(),
+            mx: Some(self.mx),
+            headers: self
+                .custom_host()
+                .into_iter()
+                .map(|host| ("HOST".to_string(), host))
+                .chain(
+                    self.user_agent
+                        .iter()
+                        .map(|user_agent| ("USER-AGENT".to_string(), user_agent.clone())),
+                )
+                .collect(),
+        }
+        .to_bytes();
+        trace!("Sending M-SEARCH for {}", st.as_ref());
+        let source = match &self.server {
+            Some(server) => {
+                // Subscribe first, to not miss early responses
+                let received = server.subscribe();
+                server.search(&request)?;
+                Source::Server(server.clone(), received)
+            }
+            None => {
+                let socket = self.socket()?;
+                socket.send_to(&request, self.multicast).await?;
+                Source::Socket(socket, self.multicast)
+            }
+        };
+        let sent = Instant::now();
+
+        let timeout = Duration::from_secs(self.mx as u64 + 1);
+        let research = self.research_interval.map(|interval| Research {
+            request,
+            interval,
+            jitter: self.research_jitter,
+            timer: Box::pin(tokio::time::sleep(jittered(interval, self.research_jitter))),
+        });
+        Ok(SearchResponses {
+            source,
+            deadline: research
+                .is_none()
+                .then(|| Box::pin(tokio::time::sleep_until(sent + timeout))),
+            research,
+            sent,
+            window: sent + timeout,
+            filter: self.filter.clone(),
+            lenient: self.lenient,
+            buf: vec![0; 2048],
+        })
+    }
+
+    /// Search for devices with search target `st` without an async runtime, returning
+    /// the responses received within `timeout`, or within `MX` seconds if sooner, one
+    /// per USN.
+    ///
+    /// Runs a temporary current-thread runtime, and so panics if called from within an
+    /// async runtime.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use tokio_ssdp::Client;
+    ///
+    /// let devices = Client::new()
+    ///     .mx(2)
+    ///     .discover_sync("upnp:rootdevice", Duration::from_secs(3))?;
+    /// for device in devices {
+    ///     println!("{} at {}", device.usn, device.location);
+    /// }
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn discover_sync(
+        &self,
+        st: impl AsRef<str>,
+        timeout: Duration,
+    ) -> IoResult<Vec<SearchResponse>> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        runtime.block_on(async {
+            let mut responses = self.search(st).await?;
+            let deadline = Instant::now() + timeout;
+
+            let mut found: Vec<SearchResponse> = vec![];
+            while let Ok(Some(response)) = tokio::time::timeout_at(deadline, responses.next()).await
+            {
+                let response = response?;
+                if !found.iter().any(|known| known.usn == response.usn) {
+                    found.push(response);
+                }
+            }
+            Ok(found)
+        })
+    }
+
+    /// Search for devices with search target `st`, returning a [`Registry`] of the
+    /// responding devices. The registry is a stream of the devices added, updated and
+    /// expired, keyed by USN and expiring after their `CACHE-CONTROL: max-age`.
+    ///
+    /// Unless searches are repeated more often than the `max-age` of the devices, see
+    /// [`Client::research_interval`], all devices eventually expire.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use std::time::Duration;
+    /// use tokio_ssdp::{Client, RegistryEvent};
+    ///
+    /// let mut registry = Client::new()
+    ///     .research_interval(Duration::from_secs(300))
+    ///     .discover("ssdp:all")
+    ///     .await?;
+    /// while let Some(event) = registry.next().await {
+    ///     if let RegistryEvent::Added(device) = event {
+    ///         println!("{} at {}", device.usn, device.location);
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn discover(&self, st: impl AsRef<str>) -> IoResult<Registry> {
+        let mut registry = Registry::new();
+        registry.add_search(self.search(st).await?);
+        Ok(registry)
+    }
+}
+
+/// `interval`, shortened by a random duration up to `jitter`.
+fn jittered(interval: Duration, jitter: Duration) -> Duration {
+    interval.saturating_sub(jitter.mul_f64(rng::unit()))
+}
+
+/// The state of repeated searches, see [`Client::research_interval`].
+#[derive(Debug)]
+struct Research {
+    request: Vec<u8>,
+    interval: Duration,
+    jitter: Duration,
+    timer: Pin<Box<Sleep>>,
+}
+
+/// Where [`SearchResponses`] send searches and receive from.
+#[derive(Debug)]
+enum Source {
+    /// A socket of the client, sending searches to the multicast endpoint.
+    Socket(Arc<UdpSocket>, SocketAddrV4),
+    /// The receive loop of a server, see [`Client::server`].
+    Server(SharedLoop, mpsc::Receiver<Datagram>),
+}
+
+impl Source {
+    fn try_search(&self, request: &[u8]) -> IoResult<()> {
+        match self {
+            Source::Socket(socket, target) => {
+                socket.try_send_to(request, (*target).into()).map(drop)
+            }
+            Source::Server(server, _) => server.search(request),
+        }
+    }
+
+    /// Poll for the next datagram into `buf`, returning its length and sender, or `None`
+    /// once the server stopped.
+    fn poll_recv(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut Vec<u8>,
+    ) -> Poll<Option<IoResult<(usize, SocketAddr)>>> {
+        match self {
+            Source::Socket(socket, _) => {
+                let mut read = ReadBuf::new(buf);
+                let res = ready!(socket.poll_recv_from(cx, &mut read));
+                let len = read.filled().len();
+                Poll::Ready(Some(res.map(|remote_addr| (len, remote_addr))))
+            }
+            Source::Server(_, received) => {
+                Poll::Ready(ready!(received.poll_recv(cx)).map(|(data, remote_addr)| {
+                    let len = data.len();
+                    *buf = data;
+                    Ok((len, remote_addr))
+                }))
+            }
+        }
+    }
+}
+
+/// The responses to a search, see [`Client::search`].
+///
+/// The stream ends once the `MX` of the search has passed, unless searches are
+/// repeated, see [`Client::research_interval`].
+#[derive(Debug)]
+pub struct SearchResponses {
+    source: Source,
+    deadline: Option<Pin<Box<Sleep>>>,
+    research: Option<Research>,
+    /// When the last search was sent.
+    sent: Instant,
+    /// When the `MX` of the first search passes.
+    window: Instant,
+    filter: Filter,
+    lenient: bool,
+    buf: Vec<u8>,
+}
+
+impl SearchResponses {
+    /// Wait for the next response, or `None` once the `MX` of the search has passed.
+    pub async fn next(&mut self) -> Option<IoResult<SearchResponse>> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    /// Collect the responses until the `MX` of the search has passed, returning the
+    /// fastest response of each device, ordered by [`SearchResponse::latency`].
+    ///
+    /// This is useful to pick the nearest of several equivalent devices. Responses are
+    /// delayed randomly by up to `MX` seconds, so a lower `MX` gives a better ranking.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::Client;
+    ///
+    /// let responses = Client::new()
+    ///     .mx(1)
+    ///     .search("urn:schemas-upnp-org:device:InternetGatewayDevice:1")
+    ///     .await?;
+    /// if let Some(nearest) = responses.ranked().await.first() {
+    ///     println!("{} in {:?}", nearest.location, nearest.latency);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn ranked(mut self) -> Vec<SearchResponse> {
+        let mut fastest: HashMap<String, SearchResponse> = HashMap::new();
+        while let Ok(Some(response)) = tokio::time::timeout_at(self.window, self.next()).await {
+            let response = match response {
+                Ok(response) => response,
+                Err(e) => {
+                    trace!("Failed to receive search response: {}", e);
+                    continue;
+                }
+            };
+            match fastest.get(&response.usn) {
+                Some(known) if known.latency <= response.latency => {}
+                _ => {
+                    fastest.insert(response.usn.clone(), response);
+                }
+            }
+        }
+
+        let mut ranked: Vec<_> = fastest.into_values().collect();
+        ranked.sort_by_key(|response| response.latency);
+        ranked
+    }
+}
+
+/// A message received by [`SearchResponses`].
+pub(crate) enum Received {
+    Response(SearchResponse),
+    /// A `NOTIFY` message, received on a shared socket, see [`SocketStrategy::Shared`],
+    /// or by a server, see [`Server::client`](crate::Server::client).
+    Notify(Notification),
+}
+
+impl SearchResponses {
+    /// Poll for the next response, or `NOTIFY` message.
+    pub(crate) fn poll_message(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<IoResult<Received>>> {
+        loop {
+            if let Some(deadline) = &mut self.deadline
+                && deadline.as_mut().poll(cx).is_ready()
+            {
+                return Poll::Ready(None);
+            }
+
+            if let Some(research) = &mut self.research
+                && research.timer.as_mut().poll(cx).is_ready()
+            {
+                trace!("Repeating M-SEARCH");
+                if let Err(e) = self.source.try_search(&research.request) {
+                    trace!("Repeating M-SEARCH failed: {}", e);
+                }
+                self.sent = Instant::now();
+                let next = self.sent + jittered(research.interval, research.jitter);
+                research.timer.as_mut().reset(next);
+                continue;
+            }
+
+            let (len, remote_addr) = match ready!(self.source.poll_recv(cx, &mut self.buf)) {
+                Some(Ok(received)) => received,
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => return Poll::Ready(None),
+            };
+            let buf = &self.buf[..len];
+
+            if buf.starts_with(b"NOTIFY") {
+                match Notification::parse(buf, remote_addr) {
+                    Some(notification)
+                        if self.filter.is_allowed(
+                            remote_addr,
+                            &notification.usn,
+                            &notification.nt,
+                            notification.location.as_deref(),
+                        ) =>
+                    {
+                        return Poll::Ready(Some(Ok(Received::Notify(notification))));
+                    }
+                    Some(_) => {}
+                    None => trace!("Ignoring invalid NOTIFY from {}", remote_addr),
+                }
+                continue;
+            }
+
+            match SearchResponse::parse(buf, remote_addr, self.sent.elapsed(), self.lenient) {
+                Some(response)
+                    if self.filter.is_allowed(
+                        remote_addr,
+                        &response.usn,
+                        &response.st,
+                        Some(&response.location),
+                    ) =>
+                {
+                    return Poll::Ready(Some(Ok(Received::Response(response))));
+                }
+                Some(response) => trace!("Ignoring filtered response {}", response.usn),
+                None => {}
+            }
+        }
+    }
+}
+
+impl Stream for SearchResponses {
+    type Item = IoResult<SearchResponse>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            match ready!(this.poll_message(cx)) {
+                Some(Ok(Received::Response(response))) => return Poll::Ready(Some(Ok(response))),
+                Some(Ok(Received::Notify(_))) => {}
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_responses() {
+        let filter = Client::new()
+            .allow_subnet("192.0.2.0/24".parse().unwrap())
+            .deny_addr(Ipv4Addr::new(192, 0, 2, 13))
+            .ignore_usn("uuid:ignored")
+            .ignore_st("urn:schemas-upnp-org:device:Printer:1")
+            .ignore_location_host("proxy.example")
+            .ignore_location_host("2001:db8::1")
+            .filter;
+
+        let allowed = |addr: &str, usn, st, location| {
+            filter.is_allowed(addr.parse().unwrap(), usn, st, location)
+        };
+        let root = "upnp:rootdevice";
+        let printer = "urn:schemas-upnp-org:device:Printer:1";
+        let location = Some("http://192.0.2.1/desc.xml");
+        assert!(allowed(
+            "192.0.2.1:1900",
+            "uuid:a::upnp:rootdevice",
+            root,
+            location
+        ));
+        assert!(!allowed("198.51.100.1:1900", "uuid:a", root, location));
+        assert!(!allowed("192.0.2.13:1900", "uuid:a", root, location));
+        assert!(!allowed(
+            "192.0.2.1:1900",
+            "uuid:IGNORED::upnp:rootdevice",
+            root,
+            location
+        ));
+        assert!(!allowed("192.0.2.1:1900", "uuid:a", printer, location));
+
+        // By the host of the location, not the sender
+        let proxied = Some("http://Proxy.Example:8080/a/desc.xml");
+        assert!(!allowed("192.0.2.1:1900", "uuid:a", root, proxied));
+        let ipv6 = Some("http://[2001:db8::1]:49152/desc.xml");
+        assert!(!allowed("192.0.2.1:1900", "uuid:a", root, ipv6));
+        let other = Some("http://user@proxy.example.org/desc.xml");
+        assert!(allowed("192.0.2.1:1900", "uuid:a", root, other));
+        assert!(allowed("192.0.2.1:1900", "uuid:a", root, None));
+    }
+
+    #[cfg(feature = "socket2")]
+    #[tokio::test]
+    async fn sets_multicast_interface() {
+        let socket = Client::new()
+            .interface(Ipv4Addr::LOCALHOST)
+            .new_socket(0)
+            .unwrap();
+        let multicast_if = socket2::SockRef::from(&socket).multicast_if_v4().unwrap();
+        assert_eq!(multicast_if, Ipv4Addr::LOCALHOST);
+
+        let socket = Client::new()
+            .bind_addr(Ipv4Addr::LOCALHOST)
+            .new_socket(0)
+            .unwrap();
+        let multicast_if = socket2::SockRef::from(&socket).multicast_if_v4().unwrap();
+        assert_eq!(multicast_if, Ipv4Addr::LOCALHOST);
+    }
+
+    #[test]
+    fn validates_responses() {
+        let addr = "192.0.2.1:1900".parse().unwrap();
+        let valid = b"HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=1800\r\n\
+            LOCATION: http://192.0.2.1/desc.xml\r\nST: upnp:rootdevice\r\n\
+            USN: uuid:a::upnp:rootdevice\r\n\r\n";
+        let broken = b"HTTP/1.1 200 OK\r\nLOCATION: /desc.xml\r\n\
+            ST: upnp:rootdevice\r\nUSN: uuid:a::upnp:rootdevice\r\n\r\n";
+
+        let response = SearchResponse::parse(valid, addr, Duration::ZERO, false).unwrap();
+        assert!(response.conforming);
+        assert!(SearchResponse::parse(broken, addr, Duration::ZERO, false).is_none());
+
+        let response = SearchResponse::parse(broken, addr, Duration::ZERO, true).unwrap();
+        assert!(!response.conforming);
+        assert_eq!(response.location, "/desc.xml");
+    }
+
+    #[tokio::test]
+    async fn searches_through_server() {
+        let server = SharedLoop::default();
+        let client = Client::new().server(server.clone());
+        let err = client.search("ssdp:all").await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotConnected);
+
+        let mut searches = server.serve();
+        let mut registry = client.discover("ssdp:all").await.unwrap();
+        let request = searches.recv().await.unwrap();
+        assert!(request.starts_with(b"M-SEARCH * HTTP/1.1\r\n"));
+
+        let remote_addr = "192.0.2.10:1900".parse().unwrap();
+        server.received(
+            b"HTTP/1.1 200 OK\r\n\
+            CACHE-CONTROL: max-age=1800\r\n\
+            LOCATION: http://192.0.2.10/desc.xml\r\n\
+            ST: upnp:rootdevice\r\n\
+            USN: uuid:responder::upnp:rootdevice\r\n\r\n",
+            remote_addr,
+        );
+        server.received(
+            b"NOTIFY * HTTP/1.1\r\n\
+            HOST: 239.255.255.250:1900\r\n\
+            CACHE-CONTROL: max-age=1800\r\n\
+            LOCATION: http://192.0.2.10/desc.xml\r\n\
+            NT: upnp:rootdevice\r\n\
+            NTS: ssdp:alive\r\n\
+            USN: uuid:announcer::upnp:rootdevice\r\n\r\n",
+            remote_addr,
+        );
+
+        for usn in [
+            "uuid:responder::upnp:rootdevice",
+            "uuid:announcer::upnp:rootdevice",
+        ] {
+            match registry.next().await {
+                Some(crate::RegistryEvent::Added(device)) => assert_eq!(device.usn, usn),
+                event => panic!("unexpected {:?}", event),
+            }
+        }
+    }
+}
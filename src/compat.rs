@@ -0,0 +1,127 @@
+//! Tolerating the malformed `M-SEARCH` requests of broken control points.
+
+use std::borrow::Cow;
+
+use crate::message;
+
+/// A set of quirks of broken control points to tolerate, see [`Server::compat`].
+///
+/// By default requests must follow the specification, and each quirk is enabled
+/// separately. [`Profile::tolerant`] enables all of them.
+///
+/// # Examples
+/// ```
+/// use tokio_ssdp::{compat::Profile, message::SearchRequest};
+///
+/// let request = b"M-SEARCH * HTTP/1.1\r\nMX: 2.5\r\nST: ssdp:all\r\n\r\n";
+/// assert!(SearchRequest::parse(request).is_err());
+///
+/// let profile = Profile::new().missing_man(true).lenient_mx(true);
+/// let parsed = SearchRequest::parse_with(request, &profile)?;
+/// assert_eq!(parsed.mx, Some(2));
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// [`Server::compat`]: crate::Server::compat
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Profile {
+    pub(crate) partial_request: bool,
+    pub(crate) unquoted_man: bool,
+    pub(crate) missing_man: bool,
+    pub(crate) lenient_mx: bool,
+    pub(crate) normalize: bool,
+}
+
+impl Profile {
+    /// Create a profile tolerating no quirks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A profile tolerating no quirks, the default.
+    pub fn strict() -> Self {
+        Self::default()
+    }
+
+    /// A profile tolerating all known quirks.
+    pub fn tolerant() -> Self {
+        Self {
+            partial_request: true,
+            unquoted_man: true,
+            missing_man: true,
+            lenient_mx: true,
+            normalize: true,
+        }
+    }
+
+    /// Accept requests ending in a single `\r\n` instead of `\r\n\r\n`, see
+    /// [`Server::partial_request_workaround`](crate::Server::partial_request_workaround).
+    pub fn partial_request(mut self, partial_request: bool) -> Self {
+        self.partial_request = partial_request;
+        self
+    }
+
+    /// Accept `MAN: ssdp:discover` without the mandatory quotes, or with surplus
+    /// whitespace, see [`Server::unquoted_man_workaround`](crate::Server::unquoted_man_workaround).
+    pub fn unquoted_man(mut self, unquoted_man: bool) -> Self {
+        self.unquoted_man = unquoted_man;
+        self
+    }
+
+    /// Accept requests without a `MAN` header.
+    pub fn missing_man(mut self, missing_man: bool) -> Self {
+        self.missing_man = missing_man;
+        self
+    }
+
+    /// Accept `MX` values which aren't integers, e.g. `2.5` or `3 seconds`, rounded down.
+    /// Values without a number are treated as a missing `MX`, see
+    /// [`Server::missing_mx`](crate::Server::missing_mx).
+    pub fn lenient_mx(mut self, lenient_mx: bool) -> Self {
+        self.lenient_mx = lenient_mx;
+        self
+    }
+
+    /// Accept `LF` line endings, lower case methods and NUL bytes, and add a missing empty
+    /// line at the end, see [`Server::lenient_parsing`](crate::Server::lenient_parsing).
+    /// Implies [`Profile::partial_request`] and [`Profile::unquoted_man`].
+    pub fn lf_line_endings(mut self, lf_line_endings: bool) -> Self {
+        self.normalize = lf_line_endings;
+        self
+    }
+
+    /// Rewrite `buf` into a request [`httparse`] can parse, if needed.
+    pub(crate) fn prepare<'a>(&self, buf: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.normalize {
+            return Cow::Owned(message::normalize_request(buf));
+        }
+
+        if self.partial_request && buf.ends_with(b"\r\n") && !buf.ends_with(b"\r\n\r\n") {
+            let mut buf = buf.to_vec();
+            buf.extend_from_slice(b"\r\n");
+            return Cow::Owned(buf);
+        }
+
+        Cow::Borrowed(buf)
+    }
+
+    /// Whether an unquoted `MAN` header is accepted.
+    pub(crate) fn accepts_unquoted_man(&self) -> bool {
+        self.unquoted_man || self.normalize
+    }
+
+    /// Parse the `MX` header `mx`, returning `None` if it is to be treated as missing.
+    pub(crate) fn parse_mx(&self, mx: &str) -> Result<Option<u32>, std::num::ParseIntError> {
+        if !self.lenient_mx {
+            return mx.parse().map(Some);
+        }
+
+        let mx = mx.trim();
+        let digits = &mx[..mx.find(|c: char| !c.is_ascii_digit()).unwrap_or(mx.len())];
+        if digits.is_empty() {
+            return Ok(None);
+        }
+        // Too large values are capped later, see `Server::max_mx`
+        Ok(Some(digits.parse().unwrap_or(u32::MAX)))
+    }
+}
@@ -0,0 +1,72 @@
+use std::fmt;
+
+use crate::{Device, SearchContext, SearchRequest};
+
+/// Changes to a single search response, see
+/// [`Server::customize_responses`](crate::Server::customize_responses).
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ResponseOverrides {
+    /// Headers to add to the response. They replace the headers with the same names,
+    /// and support the same placeholders.
+    pub headers: Vec<(String, String)>,
+    /// The location to send instead of the location of the device, with the same
+    /// placeholders.
+    pub location: Option<String>,
+    /// The value of `Cache-Control: max-age=` to send instead of the configured one.
+    pub max_age: Option<u64>,
+}
+
+impl ResponseOverrides {
+    /// Add a header to the response.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Send `location` instead of the location of the device.
+    pub fn location(mut self, location: impl Into<String>) -> Self {
+        self.location = Some(location.into());
+        self
+    }
+
+    /// Send `max_age` instead of the configured max age.
+    pub fn max_age(mut self, max_age: u64) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+}
+
+/// Customizes the response of each device to a search, see
+/// [`Server::customize_responses`](crate::Server::customize_responses).
+///
+/// Implemented for closures taking the request, the answering device and the context of
+/// the request.
+pub trait ResponseCustomizer: Send + Sync {
+    /// The changes to the response of `device` to `request`.
+    fn customize(
+        &self,
+        request: &SearchRequest,
+        device: &Device,
+        ctx: &SearchContext,
+    ) -> ResponseOverrides;
+}
+
+impl<F> ResponseCustomizer for F
+where
+    F: Fn(&SearchRequest, &Device, &SearchContext) -> ResponseOverrides + Send + Sync,
+{
+    fn customize(
+        &self,
+        request: &SearchRequest,
+        device: &Device,
+        ctx: &SearchContext,
+    ) -> ResponseOverrides {
+        self(request, device, ctx)
+    }
+}
+
+impl fmt::Debug for dyn ResponseCustomizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ResponseCustomizer")
+    }
+}
@@ -0,0 +1,261 @@
+use crate::{Device, UsnMode};
+
+/// A device of a [`RootDevice`], with its services and embedded devices.
+#[derive(Debug, Clone)]
+pub struct DeviceDescription {
+    uuid: String,
+    device_type: String,
+    services: Vec<String>,
+    embedded: Vec<DeviceDescription>,
+}
+
+impl DeviceDescription {
+    /// Describe the device `uuid` of type `device_type`, e.g.
+    /// `urn:schemas-upnp-org:device:MediaRenderer:1`.
+    pub fn new(uuid: impl Into<String>, device_type: impl Into<String>) -> Self {
+        Self {
+            uuid: uuid.into(),
+            device_type: device_type.into(),
+            services: vec![],
+            embedded: vec![],
+        }
+    }
+
+    /// Add a service of type `service_type`, e.g. `urn:schemas-upnp-org:service:AVTransport:1`.
+    ///
+    /// A service type is advertised once per device, however many services of that
+    /// type the device has.
+    pub fn service(mut self, service_type: impl Into<String>) -> Self {
+        let service_type = service_type.into();
+        if !self.services.contains(&service_type) {
+            self.services.push(service_type);
+        }
+        self
+    }
+
+    /// Add an embedded device.
+    pub fn embedded(mut self, device: DeviceDescription) -> Self {
+        self.embedded.push(device);
+        self
+    }
+
+    /// Read the `<device>` element `node` of a description document.
+    #[cfg(feature = "xml")]
+    fn from_xml(node: roxmltree::Node<'_, '_>) -> std::io::Result<Self> {
+        let udn = xml_text(node, "UDN")?;
+        let uuid = udn.strip_prefix("uuid:").unwrap_or(udn);
+        let mut device = Self::new(uuid, xml_text(node, "deviceType")?);
+
+        for service in xml_children(node, "serviceList", "service") {
+            device = device.service(xml_text(service, "serviceType")?);
+        }
+        for embedded in xml_children(node, "deviceList", "device") {
+            device = device.embedded(Self::from_xml(embedded)?);
+        }
+        Ok(device)
+    }
+
+    fn push_devices(&self, location: &str, devices: &mut Vec<Device>) {
+        devices.push(Device::with_mode(&self.uuid, UsnMode::Uuid, location));
+        devices.push(Device::with_mode(
+            &self.uuid,
+            UsnMode::Type(self.device_type.clone()),
+            location,
+        ));
+        for embedded in &self.embedded {
+            embedded.push_devices(location, devices);
+        }
+    }
+
+    fn push_services(&self, location: &str, devices: &mut Vec<Device>) {
+        for service_type in &self.services {
+            devices.push(Device::with_mode(
+                &self.uuid,
+                UsnMode::Type(service_type.clone()),
+                location,
+            ));
+        }
+        for embedded in &self.embedded {
+            embedded.push_services(location, devices);
+        }
+    }
+}
+
+/// A root device, generating the complete set of advertisements required by the UPnP
+/// device architecture.
+///
+/// The root device is advertised as `upnp:rootdevice`, `uuid:X` and its device type,
+/// each embedded device as `uuid:X` and its device type, and each device once for
+/// every type of service it has.
+#[derive(Debug, Clone)]
+pub struct RootDevice {
+    location: String,
+    description: DeviceDescription,
+}
+
+impl RootDevice {
+    /// Create the root device `uuid` of type `device_type`, described at `location`.
+    ///
+    /// Placeholders in `location` are expanded as for [`Device::new`].
+    ///
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{DeviceDescription, RootDevice, Server};
+    ///
+    /// let root = RootDevice::new(
+    ///     "ad8782a0-9e28-422b-a6ae-670fe7c4c043",
+    ///     "urn:schemas-upnp-org:device:MediaServer:1",
+    ///     "http://192.168.1.100:8080/desc.xml",
+    /// )
+    /// .service("urn:schemas-upnp-org:service:ContentDirectory:1")
+    /// .service("urn:schemas-upnp-org:service:ConnectionManager:1")
+    /// .embedded(
+    ///     DeviceDescription::new(
+    ///         "0c3ab6d2-4f3e-4b1a-9a0e-2b8f6f2a1f10",
+    ///         "urn:schemas-upnp-org:device:MediaRenderer:1",
+    ///     )
+    ///     .service("urn:schemas-upnp-org:service:AVTransport:1"),
+    /// );
+    ///
+    /// // 3 for the root device, 2 for the embedded device, and 3 service types
+    /// assert_eq!(root.devices().len(), 8);
+    ///
+    /// let server = Server::new(root);
+    /// ```
+    pub fn new(
+        uuid: impl Into<String>,
+        device_type: impl Into<String>,
+        location: impl Into<String>,
+    ) -> Self {
+        Self {
+            location: location.into(),
+            description: DeviceDescription::new(uuid, device_type),
+        }
+    }
+
+    /// Read the root device, its services and embedded devices from the UPnP device
+    /// description document `xml`, described at `location`.
+    ///
+    /// Placeholders in `location` are expanded as for [`Device::new`].
+    ///
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{RootDevice, Server};
+    ///
+    /// let xml = r#"<?xml version="1.0"?>
+    /// <root xmlns="urn:schemas-upnp-org:device-1-0">
+    ///   <specVersion><major>1</major><minor>0</minor></specVersion>
+    ///   <device>
+    ///     <deviceType>urn:schemas-upnp-org:device:MediaServer:1</deviceType>
+    ///     <UDN>uuid:ad8782a0-9e28-422b-a6ae-670fe7c4c043</UDN>
+    ///     <serviceList>
+    ///       <service><serviceType>urn:schemas-upnp-org:service:ContentDirectory:1</serviceType></service>
+    ///       <service><serviceType>urn:schemas-upnp-org:service:ConnectionManager:1</serviceType></service>
+    ///     </serviceList>
+    ///     <deviceList>
+    ///       <device>
+    ///         <deviceType>urn:schemas-upnp-org:device:MediaRenderer:1</deviceType>
+    ///         <UDN>uuid:0c3ab6d2-4f3e-4b1a-9a0e-2b8f6f2a1f10</UDN>
+    ///         <serviceList>
+    ///           <service><serviceType>urn:schemas-upnp-org:service:AVTransport:1</serviceType></service>
+    ///         </serviceList>
+    ///       </device>
+    ///     </deviceList>
+    ///   </device>
+    /// </root>"#;
+    ///
+    /// let root = RootDevice::from_description(xml, "http://192.168.1.100:8080/desc.xml")?;
+    /// assert_eq!(root.devices().len(), 8);
+    ///
+    /// let server = Server::new(root);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    #[cfg(feature = "xml")]
+    pub fn from_description(xml: &str, location: impl Into<String>) -> std::io::Result<Self> {
+        let doc = roxmltree::Document::parse(xml).map_err(xml_error)?;
+        let root = doc.root_element();
+        if root.tag_name().name() != "root" {
+            return Err(xml_error("<root> element not found"));
+        }
+        let device = xml_child(root, "device").ok_or_else(|| xml_error("<device> not found"))?;
+
+        Ok(Self {
+            location: location.into(),
+            description: DeviceDescription::from_xml(device)?,
+        })
+    }
+
+    /// Add a service of type `service_type` to the root device, see
+    /// [`DeviceDescription::service`].
+    pub fn service(mut self, service_type: impl Into<String>) -> Self {
+        self.description = self.description.service(service_type);
+        self
+    }
+
+    /// Add an embedded device.
+    pub fn embedded(mut self, device: DeviceDescription) -> Self {
+        self.description = self.description.embedded(device);
+        self
+    }
+
+    /// Generate the advertisements, in the order of the specification.
+    pub fn devices(&self) -> Vec<Device> {
+        let location = &self.location;
+        let mut devices = vec![Device::with_mode(
+            &self.description.uuid,
+            UsnMode::RootDevice,
+            location,
+        )];
+        self.description.push_devices(location, &mut devices);
+        self.description.push_services(location, &mut devices);
+        devices
+    }
+}
+
+impl IntoIterator for RootDevice {
+    type Item = Device;
+    type IntoIter = std::vec::IntoIter<Device>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.devices().into_iter()
+    }
+}
+
+/// The first child element of `node` named `name`, in any namespace.
+#[cfg(feature = "xml")]
+fn xml_child<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &str,
+) -> Option<roxmltree::Node<'a, 'input>> {
+    node.children()
+        .find(|child| child.is_element() && child.tag_name().name() == name)
+}
+
+/// The elements named `item` in the list element `list` of `node`, e.g. the services in
+/// `<serviceList>`.
+#[cfg(feature = "xml")]
+fn xml_children<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    list: &str,
+    item: &'static str,
+) -> impl Iterator<Item = roxmltree::Node<'a, 'input>> {
+    xml_child(node, list)
+        .into_iter()
+        .flat_map(|list| list.children())
+        .filter(move |child| child.is_element() && child.tag_name().name() == item)
+}
+
+/// The trimmed text of the child element `name` of `node`, which must not be empty.
+#[cfg(feature = "xml")]
+fn xml_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> std::io::Result<&'a str> {
+    xml_child(node, name)
+        .and_then(|child| child.text())
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .ok_or_else(|| xml_error(format!("<{}> not found", name)))
+}
+
+#[cfg(feature = "xml")]
+fn xml_error(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+}
@@ -1,16 +1,75 @@
-use log::debug;
+use std::{any::Any, borrow::Cow, fmt, sync::Arc};
+
+use crate::logging::debug;
+
+use crate::{Header, MessageKind, header::merge_headers};
+
+/// Computes the location of a device each time a message is built, see
+/// [`Device::location_fn`].
+///
+/// Implemented for closures returning the location.
+pub trait LocationProvider: Send + Sync {
+    /// The current location, which may contain the same placeholders as a fixed one.
+    fn location(&self) -> String;
+}
+
+impl<F> LocationProvider for F
+where
+    F: Fn() -> String + Send + Sync,
+{
+    fn location(&self) -> String {
+        self()
+    }
+}
+
+impl fmt::Debug for dyn LocationProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LocationProvider")
+    }
+}
+
+/// The header advertising the HTTPS URL of the device description.
+const SECURE_LOCATION: &str = "SECURELOCATION.UPNP.ORG";
+
+/// How the USN and search target of a device are derived from its UUID, see [`Device::with_mode`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum UsnMode {
+    /// USN `uuid:X`, search target `uuid:X`.
+    Uuid,
+    /// USN `uuid:X::upnp:rootdevice`, search target `upnp:rootdevice`.
+    RootDevice,
+    /// USN `uuid:X::<type>`, search target `<type>`, for a device or service type such as
+    /// `urn:schemas-upnp-org:device:MediaRenderer:1`.
+    Type(String),
+}
 
 /// Information about a SSDP device or service.
 #[derive(Debug, Clone)]
 pub struct Device {
+    pub(crate) uuid: Option<String>,
     pub(crate) usn: String,
     pub(crate) search_target: String,
     pub(crate) location: String,
+    location_provider: Option<Arc<dyn LocationProvider>>,
+    pub(crate) header_order: Option<Vec<Header>>,
+    pub(crate) max_age: Option<u64>,
+    lower_versions: bool,
+    headers: Vec<(Option<MessageKind>, String, String)>,
+    metadata: Option<Arc<dyn Any + Send + Sync>>,
 }
 
 impl Device {
     /// Create a new SSDP device or service.
     ///
+    /// `{addr}` in `location` is replaced by the address of the host, see
+    /// [`Server::watch_address`](crate::Server::watch_address), and `{local_addr}` by the
+    /// address a search was received on, see
+    /// [`Server::serve_interfaces`](crate::Server::serve_interfaces). With the `http`
+    /// feature, `{description}` is replaced by the URL of the description served by
+    /// [`Server::description`](crate::Server::description).
+    ///
     /// # Examples
     /// ```
     /// # use tokio_ssdp::Device;
@@ -38,9 +97,59 @@ impl Device {
         debug!("USN: {}", usn);
 
         Self {
+            uuid: Some(uuid.as_ref().to_string()),
             usn,
             search_target: st,
             location: location.into(),
+            location_provider: None,
+            header_order: None,
+            max_age: None,
+            lower_versions: false,
+            headers: vec![],
+            metadata: None,
+        }
+    }
+
+    /// Create a new SSDP device or service, deriving the USN and search target from `uuid`
+    /// in one of the forms required by the specification.
+    ///
+    /// # Examples
+    /// ```
+    /// # use tokio_ssdp::{Device, UsnMode};
+    /// let uuid = "ad8782a0-9e28-422b-a6ae-670fe7c4c043";
+    /// let location = "http://192.168.1.100:8080/desc.xml";
+    /// // uuid:{}::upnp:rootdevice
+    /// Device::with_mode(uuid, UsnMode::RootDevice, location);
+    /// // uuid:{}
+    /// Device::with_mode(uuid, UsnMode::Uuid, location);
+    /// // uuid:{}::urn:schemas-upnp-org:device:MediaRenderer:1
+    /// Device::with_mode(uuid, UsnMode::Type("urn:schemas-upnp-org:device:MediaRenderer:1".into()), location);
+    /// ```
+    pub fn with_mode(uuid: impl AsRef<str>, mode: UsnMode, location: impl Into<String>) -> Self {
+        let uuid = uuid.as_ref();
+
+        let (usn, st) = match mode {
+            UsnMode::Uuid => (format!("uuid:{}", uuid), format!("uuid:{}", uuid)),
+            UsnMode::RootDevice => (
+                format!("uuid:{}::upnp:rootdevice", uuid),
+                "upnp:rootdevice".to_string(),
+            ),
+            UsnMode::Type(ty) => (format!("uuid:{}::{}", uuid, ty), ty),
+        };
+
+        debug!("USN: {}", usn);
+
+        Self {
+            uuid: Some(uuid.to_string()),
+            usn,
+            search_target: st,
+            location: location.into(),
+            location_provider: None,
+            header_order: None,
+            max_age: None,
+            lower_versions: false,
+            headers: vec![],
+            metadata: None,
         }
     }
 
@@ -50,10 +159,433 @@ impl Device {
         search_target: impl Into<String>,
         location: impl Into<String>,
     ) -> Self {
+        let usn: String = unique_service_name.into();
+
         Self {
-            usn: unique_service_name.into(),
+            uuid: usn
+                .strip_prefix("uuid:")
+                .and_then(|rest| rest.split("::").next())
+                .map(String::from),
+            usn,
             search_target: search_target.into(),
             location: location.into(),
+            location_provider: None,
+            header_order: None,
+            max_age: None,
+            lower_versions: false,
+            headers: vec![],
+            metadata: None,
+        }
+    }
+
+    /// Override the USN of this device, e.g. to match a value whitelisted by a controller.
+    ///
+    /// # Examples
+    /// ```
+    /// # use tokio_ssdp::Device;
+    /// Device::new("ad8782a0-9e28-422b-a6ae-670fe7c4c043", "upnp:rootdevice", "http://192.168.1.100:8080/desc.xml")
+    ///     .usn("uuid:AD8782A0-9E28-422B-A6AE-670FE7C4C043::upnp:rootdevice");
+    /// ```
+    pub fn usn(mut self, usn: impl Into<String>) -> Self {
+        self.usn = usn.into();
+        self
+    }
+
+    /// Override the USN of this device with the result of `format`, which receives the
+    /// UUID (empty if unknown) and the search target of the device.
+    ///
+    /// # Examples
+    /// ```
+    /// # use tokio_ssdp::Device;
+    /// Device::new("ad8782a0-9e28-422b-a6ae-670fe7c4c043", "upnp:rootdevice", "http://192.168.1.100:8080/desc.xml")
+    ///     .usn_format(|uuid, st| format!("uuid:{}::{}", uuid.to_uppercase(), st));
+    /// ```
+    pub fn usn_format(mut self, format: impl FnOnce(&str, &str) -> String) -> Self {
+        self.usn = format(
+            self.uuid.as_deref().unwrap_or_default(),
+            &self.search_target,
+        );
+        self
+    }
+
+    /// Compute the location with `provider` each time a search response or notification
+    /// is built, instead of using a fixed location, e.g. when the description is served
+    /// on a port which can change while the server is running.
+    ///
+    /// The provider is called often, so it should only read the current value, e.g. from
+    /// a `watch` channel updated by the application.
+    ///
+    /// # Examples
+    /// ```
+    /// # use tokio_ssdp::{Device, MessageOptions, build_alive};
+    /// use std::sync::{
+    ///     Arc,
+    ///     atomic::{AtomicU16, Ordering},
+    /// };
+    ///
+    /// let port = Arc::new(AtomicU16::new(8080));
+    /// let device = Device::new("ad8782a0-9e28-422b-a6ae-670fe7c4c043", "upnp:rootdevice", "")
+    ///     .location_fn({
+    ///         let port = Arc::clone(&port);
+    ///         move || format!("http://{{addr}}:{}/desc.xml", port.load(Ordering::Relaxed))
+    ///     });
+    ///
+    /// port.store(8081, Ordering::Relaxed);
+    /// let alive = String::from_utf8(build_alive(&device, &MessageOptions::new())).unwrap();
+    /// assert!(alive.contains(":8081/desc.xml\r\n"));
+    /// ```
+    pub fn location_fn(mut self, provider: impl LocationProvider + 'static) -> Self {
+        self.location_provider = Some(Arc::new(provider));
+        self
+    }
+
+    /// The location template, from the provider set with [`Device::location_fn`] if any.
+    pub(crate) fn current_location(&self) -> Cow<'_, str> {
+        match &self.location_provider {
+            Some(provider) => Cow::Owned(provider.location()),
+            None => Cow::Borrowed(&self.location),
+        }
+    }
+
+    /// Replace the location, including a provider set with [`Device::location_fn`].
+    pub(crate) fn set_location(&mut self, location: String) {
+        self.location = location;
+        self.location_provider = None;
+    }
+
+    /// Attach application data to this device, which is kept in the clones of the device
+    /// handed out in events such as [`AnsweredSearch`](crate::AnsweredSearch).
+    ///
+    /// # Examples
+    /// ```
+    /// # use tokio_ssdp::Device;
+    /// struct Renderer {
+    ///     id: u32,
+    /// }
+    ///
+    /// let device = Device::new("ad8782a0-9e28-422b-a6ae-670fe7c4c043", "upnp:rootdevice", "http://192.168.1.100:8080/desc.xml")
+    ///     .metadata(Renderer { id: 7 });
+    ///
+    /// assert_eq!(device.get_metadata::<Renderer>().map(|r| r.id), Some(7));
+    /// assert!(device.get_metadata::<String>().is_none());
+    /// ```
+    pub fn metadata<M: Any + Send + Sync>(mut self, metadata: M) -> Self {
+        self.metadata = Some(Arc::new(metadata));
+        self
+    }
+
+    /// Get the application data attached with [`Device::metadata`], if it is of type `M`.
+    pub fn get_metadata<M: Any + Send + Sync>(&self) -> Option<&M> {
+        self.metadata.as_deref()?.downcast_ref()
+    }
+
+    pub(crate) fn is_root(&self) -> bool {
+        self.search_target == "upnp:rootdevice"
+    }
+
+    /// Whether to answer searches for lower versions of the device or service type of
+    /// this device, defaults to `false`.
+    ///
+    /// The specification requires e.g. a `ContentDirectory:2` service to answer searches
+    /// for `ContentDirectory:1`, with the requested version as search target. Enable
+    /// this for devices which are backwards compatible.
+    ///
+    /// # Examples
+    /// ```
+    /// # use tokio_ssdp::{Device, SearchContext, SearchRequest, Server};
+    /// let device = Device::new(
+    ///     "ad8782a0-9e28-422b-a6ae-670fe7c4c043",
+    ///     "urn:schemas-upnp-org:service:ContentDirectory:2",
+    ///     "http://192.168.1.100:8080/desc.xml",
+    /// );
+    /// let request = SearchRequest {
+    ///     st: "urn:schemas-upnp-org:service:ContentDirectory:1".into(),
+    ///     mx: Some(1),
+    ///     headers: vec![],
+    /// };
+    /// let ctx = SearchContext::new("192.168.1.20:50000".parse().unwrap());
+    ///
+    /// let strict = Server::new([device.clone()]);
+    /// assert!(strict.answer_search(&request, &ctx)?.is_empty());
+    ///
+    /// let compatible = Server::new([device.match_lower_versions(true)]);
+    /// let messages = compatible.answer_search(&request, &ctx)?;
+    /// let response = String::from_utf8_lossy(&messages[0].data);
+    /// assert!(response.contains("\r\nST: urn:schemas-upnp-org:service:ContentDirectory:1\r\n"));
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn match_lower_versions(mut self, enabled: bool) -> Self {
+        self.lower_versions = enabled;
+        self
+    }
+
+    /// The search target to answer a search for `st` with, if this device matches it.
+    pub(crate) fn matched_target<'a>(&'a self, st: &'a str) -> Option<&'a str> {
+        if st == "ssdp:all" || self.search_target.eq_ignore_ascii_case(st) {
+            return Some(&self.search_target);
+        }
+
+        let (own_type, own_version) = split_version(&self.search_target)?;
+        let (type_, version) = split_version(st)?;
+        (self.lower_versions && own_type.eq_ignore_ascii_case(type_) && version <= own_version)
+            .then_some(st)
+    }
+
+    /// Set the order of the standard headers in messages for this device,
+    /// overriding [`Server::header_order`](crate::Server::header_order).
+    ///
+    /// # Examples
+    /// ```
+    /// # use tokio_ssdp::{Device, Header};
+    /// Device::new("ad8782a0-9e28-422b-a6ae-670fe7c4c043", "upnp:rootdevice", "http://192.168.1.100:8080/desc.xml")
+    ///     .header_order([Header::St, Header::Usn, Header::Ext]);
+    /// ```
+    pub fn header_order(mut self, order: impl IntoIterator<Item = Header>) -> Self {
+        self.header_order = Some(order.into_iter().collect());
+        self
+    }
+
+    /// Set the value of `Cache-Control: max-age=` for this device, overriding
+    /// [`Server::max_age`](crate::Server::max_age) and the max age of its group.
+    pub fn max_age(mut self, max_age: u64) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Add an extra header to the messages for this device.
+    ///
+    /// The header replaces the server-level headers with the same name, such as those
+    /// added with [`Server::extra_header`](crate::Server::extra_header) or
+    /// `CONFIGID.UPNP.ORG`, and supports the same placeholders.
+    ///
+    /// # Examples
+    /// ```
+    /// # use tokio_ssdp::{Device, MessageOptions, build_alive};
+    /// let device = Device::new("ad8782a0-9e28-422b-a6ae-670fe7c4c043", "upnp:rootdevice", "http://192.168.1.100:8080/desc.xml")
+    ///     .max_age(1800)
+    ///     .extra_header("X-VENDOR", "acme");
+    ///
+    /// let opts = MessageOptions::new().extra_header("X-VENDOR", "generic");
+    /// let alive = String::from_utf8(build_alive(&device, &opts)).unwrap();
+    /// assert!(alive.contains("max-age=1800\r\n"));
+    /// assert!(alive.contains("X-VENDOR: acme\r\n"));
+    /// assert!(!alive.contains("generic"));
+    /// ```
+    pub fn extra_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((None, name.into(), value.into()));
+        self
+    }
+
+    /// Add an extra header to one kind of message for this device only, see
+    /// [`Device::extra_header`].
+    pub fn extra_header_for(
+        mut self,
+        kind: MessageKind,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        self.headers.push((Some(kind), name.into(), value.into()));
+        self
+    }
+
+    /// Advertise the HTTPS URL of the device description as `SECURELOCATION.UPNP.ORG`,
+    /// for control points implementing UPnP Device Protection.
+    ///
+    /// The header is added to `ssdp:alive`, `ssdp:update` and search responses in
+    /// addition to `LOCATION`, and supports the same placeholders. Devices only serving
+    /// their description over TLS can use the HTTPS URL as location as well.
+    ///
+    /// # Examples
+    /// ```
+    /// # use tokio_ssdp::{Device, MessageOptions, build_alive, build_byebye};
+    /// let device = Device::new("ad8782a0-9e28-422b-a6ae-670fe7c4c043", "upnp:rootdevice", "http://192.168.1.100:8080/desc.xml")
+    ///     .secure_location("https://192.168.1.100:8443/desc.xml");
+    ///
+    /// let opts = MessageOptions::new();
+    /// let alive = String::from_utf8(build_alive(&device, &opts)).unwrap();
+    /// assert!(alive.contains("\r\nSECURELOCATION.UPNP.ORG: https://192.168.1.100:8443/desc.xml\r\n"));
+    /// let byebye = String::from_utf8(build_byebye(&device, &opts)).unwrap();
+    /// assert!(!byebye.contains("SECURELOCATION"));
+    /// ```
+    pub fn secure_location(self, url: impl Into<String>) -> Self {
+        let url = url.into();
+        self.extra_header_for(MessageKind::Alive, SECURE_LOCATION, url.clone())
+            .extra_header_for(MessageKind::SearchResponse, SECURE_LOCATION, url)
+    }
+
+    /// Merge the headers of this device for `kind` into the server-level `extra_headers`.
+    pub(crate) fn merge_headers<'a>(
+        &self,
+        kind: MessageKind,
+        extra_headers: &'a str,
+    ) -> Cow<'a, str> {
+        let own: Vec<_> = self
+            .headers
+            .iter()
+            .filter(|(k, _, _)| k.is_none_or(|k| k == kind))
+            .map(|(_, name, value)| (name.as_str(), value.as_str()))
+            .collect();
+
+        merge_headers(extra_headers, &own)
+    }
+}
+
+/// Split a `urn:domain:device:type:v` or `urn:domain:service:type:v` search target into
+/// the type and the version.
+fn split_version(st: &str) -> Option<(&str, u32)> {
+    if !st.starts_with("urn:") {
+        return None;
+    }
+    let (type_, version) = st.rsplit_once(':')?;
+    Some((type_, version.parse().ok()?))
+}
+
+impl fmt::Display for Device {
+    /// Format as `USN (ST) at LOCATION`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use tokio_ssdp::Device;
+    /// let device = Device::new("ad8782a0-9e28-422b-a6ae-670fe7c4c043", "upnp:rootdevice", "http://192.168.1.100:8080/desc.xml");
+    /// assert_eq!(
+    ///     device.to_string(),
+    ///     "uuid:ad8782a0-9e28-422b-a6ae-670fe7c4c043::upnp:rootdevice (upnp:rootdevice) at http://192.168.1.100:8080/desc.xml"
+    /// );
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}) at {}",
+            self.usn,
+            self.search_target,
+            self.current_location()
+        )
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serde_impl {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error};
+
+    use super::Device;
+    use crate::{Header, MessageKind};
+
+    /// The fields of a [`Device`] which can be stored, e.g. in a config file.
+    #[derive(Serialize, Deserialize)]
+    struct DeviceConfig {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        uuid: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        usn: Option<String>,
+        #[serde(default)]
+        search_target: String,
+        location: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_age: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        header_order: Option<Vec<Header>>,
+        #[serde(default, skip_serializing_if = "is_disabled")]
+        match_lower_versions: bool,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        headers: Vec<HeaderConfig>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct HeaderConfig {
+        name: String,
+        value: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        kind: Option<MessageKind>,
+    }
+
+    fn is_disabled(enabled: &bool) -> bool {
+        !*enabled
+    }
+
+    /// Serializes the static parts of a device; a [`Device::location_fn`] and
+    /// [`Device::metadata`] are left out.
+    impl Serialize for Device {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            DeviceConfig {
+                uuid: self.uuid.clone(),
+                usn: Some(self.usn.clone()),
+                search_target: self.search_target.clone(),
+                location: self.location.clone(),
+                max_age: self.max_age,
+                header_order: self.header_order.clone(),
+                match_lower_versions: self.lower_versions,
+                headers: self
+                    .headers
+                    .iter()
+                    .map(|(kind, name, value)| HeaderConfig {
+                        name: name.clone(),
+                        value: value.clone(),
+                        kind: *kind,
+                    })
+                    .collect(),
+            }
+            .serialize(serializer)
+        }
+    }
+
+    /// Deserializes a device from its `uuid`, as [`Device::new`], or from its `usn`, as
+    /// [`Device::raw`]. With both, the `usn` overrides the derived one, as
+    /// [`Device::usn`].
+    ///
+    /// # Examples
+    /// ```
+    /// use serde::Deserialize;
+    /// use tokio_ssdp::{Device, Server};
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Config {
+    ///     devices: Vec<Device>,
+    /// }
+    ///
+    /// let config: Config = toml::from_str(r#"
+    ///     [[devices]]
+    ///     uuid = "ad8782a0-9e28-422b-a6ae-670fe7c4c043"
+    ///     search_target = "upnp:rootdevice"
+    ///     location = "http://{addr}:8080/desc.xml"
+    ///     max_age = 1800
+    ///
+    ///     [[devices.headers]]
+    ///     name = "X-VENDOR"
+    ///     value = "acme"
+    ///     kind = "alive"
+    /// "#).unwrap();
+    ///
+    /// assert_eq!(
+    ///     config.devices[0].to_string(),
+    ///     "uuid:ad8782a0-9e28-422b-a6ae-670fe7c4c043::upnp:rootdevice (upnp:rootdevice) at http://{addr}:8080/desc.xml"
+    /// );
+    /// Server::new(config.devices);
+    /// ```
+    impl<'de> Deserialize<'de> for Device {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let config = DeviceConfig::deserialize(deserializer)?;
+
+            let mut device = match (config.uuid, config.usn) {
+                (Some(uuid), usn) => {
+                    let device = Device::new(uuid, config.search_target, config.location);
+                    match usn {
+                        Some(usn) => device.usn(usn),
+                        None => device,
+                    }
+                }
+                (None, Some(usn)) => Device::raw(usn, config.search_target, config.location),
+                (None, None) => return Err(D::Error::missing_field("uuid")),
+            };
+
+            device.max_age = config.max_age;
+            device.header_order = config.header_order;
+            device.lower_versions = config.match_lower_versions;
+            device.headers = config
+                .headers
+                .into_iter()
+                .map(|header| (header.kind, header.name, header.value))
+                .collect();
+            Ok(device)
         }
     }
 }
@@ -0,0 +1,104 @@
+use crate::{Device, MessageKind, UsnMode};
+
+/// The search target of DIAL receivers.
+pub const DIAL_SEARCH_TARGET: &str = "urn:dial-multiscreen-org:service:dial:1";
+
+/// A DIAL receiver, answering searches for [`DIAL_SEARCH_TARGET`] with the headers
+/// required by the DIAL specification.
+///
+/// Converts into the device to serve, so it can be passed to [`Server::new`](crate::Server::new).
+#[derive(Debug, Clone)]
+pub struct Dial {
+    uuid: String,
+    location: String,
+    application_url: Option<String>,
+    wakeup: Option<(String, u32)>,
+}
+
+impl Dial {
+    /// Create the DIAL receiver `uuid`, whose device description is at `location`.
+    ///
+    /// Placeholders in `location` are expanded as for [`Device::new`].
+    ///
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{Dial, Server};
+    ///
+    /// let dial = Dial::new(
+    ///     "ad8782a0-9e28-422b-a6ae-670fe7c4c043",
+    ///     "http://{local_addr}:8008/ssdp/device-desc.xml",
+    /// )
+    /// .application_url("http://{local_addr}:8008/apps/")
+    /// .wakeup("10:dd:b1:c9:00:e4", 10);
+    ///
+    /// let server = Server::new(dial);
+    /// ```
+    pub fn new(uuid: impl Into<String>, location: impl Into<String>) -> Self {
+        Self {
+            uuid: uuid.into(),
+            location: location.into(),
+            application_url: None,
+            wakeup: None,
+        }
+    }
+
+    /// Send `url` as the `Application-URL` header of search responses.
+    ///
+    /// Placeholders are expanded for each response as for
+    /// [`Server::extra_header`](crate::Server::extra_header), e.g. `{local_addr}` for the
+    /// address the search was received on.
+    pub fn application_url(mut self, url: impl Into<String>) -> Self {
+        self.application_url = Some(url.into());
+        self
+    }
+
+    /// Advertise that the receiver can be woken up with Wake-on-LAN on `mac`, within
+    /// `timeout` seconds, with the `WAKEUP` header of DIAL 2.1.
+    pub fn wakeup(mut self, mac: impl Into<String>, timeout: u32) -> Self {
+        self.wakeup = Some((mac.into(), timeout));
+        self
+    }
+
+    /// The device to serve.
+    ///
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{DIAL_SEARCH_TARGET, Dial, MessageOptions, build_search_response};
+    ///
+    /// let device = Dial::new("ad8782a0-9e28-422b-a6ae-670fe7c4c043", "http://10.0.0.2/dd.xml")
+    ///     .application_url("http://10.0.0.2/apps/")
+    ///     .device();
+    /// let response = build_search_response(&device, &MessageOptions::new());
+    /// let response = String::from_utf8(response).unwrap();
+    ///
+    /// assert!(response.contains(&format!("ST: {}\r\n", DIAL_SEARCH_TARGET)));
+    /// assert!(response.contains("Application-URL: http://10.0.0.2/apps/\r\n"));
+    /// ```
+    pub fn device(&self) -> Device {
+        let mut device = Device::with_mode(
+            &self.uuid,
+            UsnMode::Type(DIAL_SEARCH_TARGET.to_string()),
+            &self.location,
+        );
+        if let Some(url) = &self.application_url {
+            device = device.extra_header_for(MessageKind::SearchResponse, "Application-URL", url);
+        }
+        if let Some((mac, timeout)) = &self.wakeup {
+            device = device.extra_header_for(
+                MessageKind::SearchResponse,
+                "WAKEUP",
+                format!("MAC={};Timeout={}", mac, timeout),
+            );
+        }
+        device
+    }
+}
+
+impl IntoIterator for Dial {
+    type Item = Device;
+    type IntoIter = std::iter::Once<Device>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        std::iter::once(self.device())
+    }
+}
@@ -0,0 +1,92 @@
+use std::{fmt, io, net::SocketAddr, sync::Arc};
+
+/// A failure inside a running server, see [`Server::errors`](crate::Server::errors).
+///
+/// These failures do not stop the server, which logs them and carries on.
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// A datagram could not be parsed as an SSDP request.
+    Parse {
+        /// The sender of the datagram.
+        remote_addr: SocketAddr,
+        /// Why parsing failed.
+        reason: String,
+    },
+    /// An `M-SEARCH` request was invalid, e.g. without `ST` header.
+    InvalidSearch {
+        /// The sender of the request.
+        remote_addr: SocketAddr,
+        /// Why the request is invalid.
+        error: Arc<io::Error>,
+    },
+    /// Sending a message failed.
+    Send {
+        /// The message, `alive`, `byebye`, `update` or `response`, as in the
+        /// [`EventLog`](crate::EventLog).
+        event: &'static str,
+        /// The USN the message is about.
+        usn: String,
+        /// The address of the control point, for search responses.
+        remote_addr: Option<SocketAddr>,
+        /// The error.
+        error: Arc<io::Error>,
+    },
+    /// Receiving failed.
+    Receive(Arc<io::Error>),
+    /// A service running alongside the server failed, e.g. the mDNS announcer.
+    Service {
+        /// The service, `mdns`, `wsd` or `description`.
+        service: &'static str,
+        /// The error.
+        error: Arc<io::Error>,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse {
+                remote_addr,
+                reason,
+            } => write!(f, "invalid request from {}: {}", remote_addr, reason),
+            Error::InvalidSearch { remote_addr, error } => {
+                write!(f, "invalid search from {}: {}", remote_addr, error)
+            }
+            Error::Send {
+                event,
+                usn,
+                remote_addr: Some(remote_addr),
+                error,
+            } => write!(
+                f,
+                "sending {} for {} to {} failed: {}",
+                event, usn, remote_addr, error
+            ),
+            Error::Send {
+                event, usn, error, ..
+            } => write!(f, "sending {} for {} failed: {}", event, usn, error),
+            Error::Receive(error) => write!(f, "receiving failed: {}", error),
+            Error::Service { service, error } => write!(f, "{} failed: {}", service, error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Parse { .. } => None,
+            Error::InvalidSearch { error, .. }
+            | Error::Send { error, .. }
+            | Error::Receive(error)
+            | Error::Service { error, .. } => Some(&**error),
+        }
+    }
+}
+
+/// A copy of `error` to share with the subscribers, keeping the OS error code if any.
+pub(crate) fn shared(error: &io::Error) -> Arc<io::Error> {
+    Arc::new(match error.raw_os_error() {
+        Some(code) => io::Error::from_raw_os_error(code),
+        None => io::Error::new(error.kind(), error.to_string()),
+    })
+}
@@ -0,0 +1,91 @@
+use std::{fmt, net::SocketAddr};
+
+use crate::{AnsweredSearch, ControlPoint};
+
+/// Something a running server did, see [`Server::events`](crate::Server::events).
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    /// A valid `M-SEARCH` request was received. It may still be ignored, e.g. when rate
+    /// limited or vetoed by a [`SearchHook`](crate::SearchHook), otherwise it is followed
+    /// by [`ServerEvent::SearchAnswered`] if any device matches.
+    SearchReceived {
+        /// The search target of the search.
+        st: String,
+        /// The sender of the search.
+        remote_addr: SocketAddr,
+        /// How the sender identified itself.
+        control_point: ControlPoint,
+    },
+    /// Responses to a search were scheduled.
+    SearchAnswered(AnsweredSearch),
+    /// A search response was sent.
+    ResponseSent {
+        /// The USN of the device the response is about.
+        usn: String,
+        /// The address of the control point which sent the search.
+        remote_addr: SocketAddr,
+    },
+    /// An `ssdp:alive` notification was sent.
+    AliveSent {
+        /// The USN the notification is about.
+        usn: String,
+    },
+    /// An `ssdp:byebye` notification was sent.
+    ByebyeSent {
+        /// The USN the notification is about.
+        usn: String,
+    },
+    /// An `ssdp:update` notification was sent.
+    UpdateSent {
+        /// The USN the notification is about.
+        usn: String,
+    },
+    /// A datagram could not be parsed as an SSDP request, or an `M-SEARCH` request was
+    /// rejected as invalid.
+    ParseFailed {
+        /// The sender of the datagram.
+        remote_addr: SocketAddr,
+        /// Why parsing failed.
+        reason: String,
+    },
+}
+
+impl ServerEvent {
+    /// The event for a notification with the `NTS` subtype `nts` sent about `usn`.
+    pub(crate) fn notification(nts: &str, usn: &str) -> Option<Self> {
+        let usn = usn.to_string();
+        match nts {
+            "ssdp:alive" => Some(ServerEvent::AliveSent { usn }),
+            "ssdp:byebye" => Some(ServerEvent::ByebyeSent { usn }),
+            "ssdp:update" => Some(ServerEvent::UpdateSent { usn }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ServerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerEvent::SearchReceived {
+                st,
+                remote_addr,
+                control_point,
+            } => write!(
+                f,
+                "search for {} from {} ({})",
+                st, remote_addr, control_point
+            ),
+            ServerEvent::SearchAnswered(search) => write!(f, "answered {}", search),
+            ServerEvent::ResponseSent { usn, remote_addr } => {
+                write!(f, "sent response for {} to {}", usn, remote_addr)
+            }
+            ServerEvent::AliveSent { usn } => write!(f, "sent alive for {}", usn),
+            ServerEvent::ByebyeSent { usn } => write!(f, "sent byebye for {}", usn),
+            ServerEvent::UpdateSent { usn } => write!(f, "sent update for {}", usn),
+            ServerEvent::ParseFailed {
+                remote_addr,
+                reason,
+            } => write!(f, "invalid request from {}: {}", remote_addr, reason),
+        }
+    }
+}
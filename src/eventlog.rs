@@ -0,0 +1,136 @@
+use std::{
+    fmt,
+    io::Write,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::logging::error;
+use tokio::sync::mpsc;
+
+use crate::Status;
+
+enum Sink {
+    Writer(Box<dyn Write + Send>),
+    Channel(mpsc::UnboundedSender<String>),
+    Status(Status),
+    Tee(Vec<EventLog>),
+}
+
+/// A structured log of server activity, see [`Server::event_log`](crate::Server::event_log).
+///
+/// Every event is a single line JSON object with a `time` (milliseconds since the unix
+/// epoch) and an `event` field, which is one of `search`, `search_rejected`, `response`,
+/// `alive` and `byebye`. Failures to send are reported in an `error` field, e.g.
+///
+/// ```text
+/// {"time":1700000000000,"event":"search","remote_addr":"192.168.1.20:50000","st":"ssdp:all","mx":"3","devices":"2","delay_ms":"1000"}
+/// ```
+///
+/// Clones share the same sink.
+#[derive(Clone)]
+pub struct EventLog {
+    sink: Arc<Mutex<Sink>>,
+}
+
+impl EventLog {
+    /// Write events to `writer`, one per line.
+    ///
+    /// Writes happen on the server task, so `writer` should be buffered, or fast.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::{fs::File, io::LineWriter};
+    /// use tokio_ssdp::{EventLog, Server};
+    ///
+    /// let file = File::create("ssdp-audit.jsonl")?;
+    /// Server::new([]).event_log(EventLog::writer(LineWriter::new(file)));
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn writer(writer: impl Write + Send + 'static) -> Self {
+        Self::new(Sink::Writer(Box::new(writer)))
+    }
+
+    /// Send events to `tx`, one line (without the line break) per event.
+    pub fn channel(tx: mpsc::UnboundedSender<String>) -> Self {
+        Self::new(Sink::Channel(tx))
+    }
+
+    /// Record events in `status`.
+    pub(crate) fn status(status: Status) -> Self {
+        Self::new(Sink::Status(status))
+    }
+
+    /// Record events in both `self` and `other`.
+    pub(crate) fn tee(self, other: EventLog) -> Self {
+        Self::new(Sink::Tee(vec![self, other]))
+    }
+
+    fn new(sink: Sink) -> Self {
+        Self {
+            sink: Arc::new(Mutex::new(sink)),
+        }
+    }
+
+    /// Record an event with the given fields.
+    pub(crate) fn record(&self, event: &str, fields: &[(&str, &dyn fmt::Display)]) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+
+        let mut line = format!("{{\"time\":{},\"event\":\"{}\"", time, escape(event));
+        for (name, value) in fields {
+            line.push_str(&format!(
+                ",\"{}\":\"{}\"",
+                escape(name),
+                escape(&value.to_string())
+            ));
+        }
+        line.push('}');
+
+        self.write(event, line);
+    }
+
+    fn write(&self, event: &str, line: String) {
+        let mut sink = self.sink.lock().unwrap_or_else(|e| e.into_inner());
+        match &mut *sink {
+            Sink::Writer(writer) => {
+                if let Err(e) = writeln!(writer, "{}", line) {
+                    error!("Failed to write event log: {}", e);
+                }
+            }
+            Sink::Channel(tx) => {
+                let _ = tx.send(line);
+            }
+            Sink::Status(status) => status.record(event, &line),
+            Sink::Tee(logs) => {
+                for log in logs.iter() {
+                    log.write(event, line.clone());
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for EventLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventLog").finish_non_exhaustive()
+    }
+}
+
+/// Escape `value` for use in a JSON string.
+pub(crate) fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
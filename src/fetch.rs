@@ -0,0 +1,401 @@
+//! Fetching the description documents of discovered devices.
+
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind, Result as IoResult},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    net::TcpStream,
+    sync::Semaphore,
+    task::JoinSet,
+    time::{Instant, timeout},
+};
+
+use crate::logging::{debug, trace};
+
+const DEFAULT_CONCURRENCY: usize = 4;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_FAILURE_TTL: Duration = Duration::from_secs(60);
+const DEFAULT_MAX_SIZE: usize = 256 * 1024;
+
+/// The failed fetches by location, with when they expire and their error.
+type Failures = HashMap<String, (Instant, ErrorKind, String)>;
+
+/// Fetches the description documents at the `LOCATION` of discovered devices over
+/// HTTP, throttled so many responding devices don't overwhelm their slow embedded HTTP
+/// servers, or the host.
+///
+/// Failed fetches are remembered for a while, and fail again right away instead of
+/// being retried. Clones share the throttle and remembered failures.
+///
+/// # Examples
+/// ```no_run
+/// # async fn run() -> std::io::Result<()> {
+/// use std::time::Duration;
+/// use tokio_ssdp::{Client, DescriptionFetcher};
+///
+/// let fetcher = DescriptionFetcher::new()
+///     .concurrency(8)
+///     .timeout(Duration::from_secs(3));
+///
+/// let mut locations = vec![];
+/// let mut responses = Client::new().search("upnp:rootdevice").await?;
+/// while let Some(response) = responses.next().await {
+///     locations.push(response?.location);
+/// }
+///
+/// for (location, description) in fetcher.fetch_all(locations).await {
+///     match description {
+///         Ok(xml) => println!("{}: {} bytes", location, xml.len()),
+///         Err(e) => println!("{}: {}", location, e),
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct DescriptionFetcher {
+    permits: Arc<Semaphore>,
+    timeout: Duration,
+    failure_ttl: Duration,
+    max_size: usize,
+    failures: Arc<Mutex<Failures>>,
+}
+
+impl Default for DescriptionFetcher {
+    fn default() -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(DEFAULT_CONCURRENCY)),
+            timeout: DEFAULT_TIMEOUT,
+            failure_ttl: DEFAULT_FAILURE_TTL,
+            max_size: DEFAULT_MAX_SIZE,
+            failures: Default::default(),
+        }
+    }
+}
+
+impl DescriptionFetcher {
+    /// Create a new fetcher.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fetch at most `concurrency` descriptions at the same time, defaults to 4.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.permits = Arc::new(Semaphore::new(concurrency.max(1)));
+        self
+    }
+
+    /// Give up on a fetch after `timeout`, from connecting to receiving the whole
+    /// description, defaults to 5 seconds. Time spent waiting for the throttle doesn't
+    /// count.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Fail fetches from a location for `ttl` after fetching from it failed, defaults
+    /// to a minute. [`Duration::ZERO`] disables remembering failures.
+    pub fn failure_ttl(mut self, ttl: Duration) -> Self {
+        self.failure_ttl = ttl;
+        self
+    }
+
+    /// Fail fetches of descriptions larger than `max_size` bytes, defaults to 256 KiB.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Fetch the description at `location`, which must be an `http://` URL.
+    pub async fn fetch(&self, location: &str) -> IoResult<String> {
+        if let Some(e) = self.failed(location) {
+            trace!("Not fetching {}, failed recently", location);
+            return Err(e);
+        }
+
+        let _permit = self
+            .permits
+            .acquire()
+            .await
+            .map_err(|_| Error::other("fetcher closed"))?;
+
+        let result = match timeout(self.timeout, get(location, self.max_size)).await {
+            Ok(result) => result,
+            Err(_) => Err(Error::new(
+                ErrorKind::TimedOut,
+                "fetching description timed out",
+            )),
+        };
+
+        if let Err(e) = &result {
+            debug!("Failed to fetch description from {}: {}", location, e);
+            if !self.failure_ttl.is_zero() {
+                self.lock().insert(
+                    location.to_string(),
+                    (Instant::now() + self.failure_ttl, e.kind(), e.to_string()),
+                );
+            }
+        }
+        result
+    }
+
+    /// Fetch the descriptions at `locations` concurrently, as far as the throttle
+    /// allows, returning them with their location in order of completion. Duplicate
+    /// locations are fetched once.
+    pub async fn fetch_all(
+        &self,
+        locations: impl IntoIterator<Item = String>,
+    ) -> Vec<(String, IoResult<String>)> {
+        let mut locations: Vec<_> = locations.into_iter().collect();
+        locations.sort();
+        locations.dedup();
+
+        let mut tasks = JoinSet::new();
+        for location in locations {
+            let this = self.clone();
+            tasks.spawn(async move {
+                let result = this.fetch(&location).await;
+                (location, result)
+            });
+        }
+
+        let mut results = Vec::with_capacity(tasks.len());
+        while let Some(result) = tasks.join_next().await {
+            match result {
+                Ok(result) => results.push(result),
+                Err(e) => debug!("Description fetch task failed: {}", e),
+            }
+        }
+        results
+    }
+
+    /// Fetch the description at `location` and build the root device it describes,
+    /// see [`RootDevice::from_description`](crate::RootDevice::from_description).
+    #[cfg(feature = "xml")]
+    pub async fn fetch_root_device(&self, location: &str) -> IoResult<crate::RootDevice> {
+        let xml = self.fetch(location).await?;
+        crate::RootDevice::from_description(&xml, location)
+    }
+
+    /// The remembered failure of fetching from `location`, if it didn't expire yet.
+    fn failed(&self, location: &str) -> Option<Error> {
+        let mut failures = self.lock();
+        let now = Instant::now();
+        failures.retain(|_, (until, _, _)| *until > now);
+        failures
+            .get(location)
+            .map(|(_, kind, message)| Error::new(*kind, message.clone()))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Failures> {
+        self.failures.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Split an `http://` URL into the `host:port` to connect to, the `Host` header, and
+/// the path.
+fn split_url(url: &str) -> IoResult<(String, &str, &str)> {
+    let invalid = || Error::new(ErrorKind::InvalidInput, format!("unsupported URL {url}"));
+
+    let rest = url
+        .get(..7)
+        .filter(|scheme| scheme.eq_ignore_ascii_case("http://"))
+        .map(|_| &url[7..])
+        .ok_or_else(invalid)?;
+    let (host, path) = match rest.find('/') {
+        Some(idx) => rest.split_at(idx),
+        None => (rest, "/"),
+    };
+    if host.is_empty() || host.contains('@') {
+        return Err(invalid());
+    }
+
+    let has_port = match host.rfind(']') {
+        Some(end) => host[end..].contains(':'),
+        None => host.contains(':'),
+    };
+    let addr = if has_port {
+        host.to_string()
+    } else {
+        format!("{host}:80")
+    };
+    Ok((addr, host, path))
+}
+
+/// A minimal HTTP/1.1 `GET` of `url`.
+async fn get(url: &str, max_size: usize) -> IoResult<String> {
+    let (addr, host, path) = split_url(url)?;
+    let stream = TcpStream::connect(&addr).await?;
+
+    let request = format!(
+        "GET {path} HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\nAccept: text/xml, */*\r\n\r\n"
+    );
+    let mut written = 0;
+    while written < request.len() {
+        stream.writable().await?;
+        match stream.try_write(&request.as_bytes()[written..]) {
+            Ok(n) => written += n,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    // The response is read until the server closes the connection, as requested
+    let mut response = Vec::new();
+    let mut buf = [0; 4096];
+    loop {
+        stream.readable().await?;
+        match stream.try_read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                response.extend_from_slice(&buf[..n]);
+                if response.len() > max_size + 8192 {
+                    return Err(Error::new(ErrorKind::InvalidData, "description too large"));
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    let body = parse_response(&response)?;
+    if body.len() > max_size {
+        return Err(Error::new(ErrorKind::InvalidData, "description too large"));
+    }
+    String::from_utf8(body).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Parse the complete HTTP response `buf`, returning its body.
+fn parse_response(buf: &[u8]) -> IoResult<Vec<u8>> {
+    let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+
+    let mut headers = [httparse::EMPTY_HEADER; 64];
+    let mut response = httparse::Response::new(&mut headers);
+    let httparse::Status::Complete(len) = response
+        .parse(buf)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?
+    else {
+        return Err(invalid("incomplete HTTP response"));
+    };
+    if response.code != Some(200) {
+        return Err(Error::other(format!(
+            "HTTP status {}",
+            response.code.unwrap_or_default()
+        )));
+    }
+
+    let header = |name: &str| {
+        response
+            .headers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .and_then(|h| std::str::from_utf8(h.value).ok())
+            .map(str::trim)
+    };
+    let body = &buf[len..];
+
+    if header("Transfer-Encoding").is_some_and(|te| te.eq_ignore_ascii_case("chunked")) {
+        return decode_chunked(body).ok_or_else(|| invalid("invalid chunked body"));
+    }
+    match header("Content-Length") {
+        Some(length) => {
+            let length: usize = length
+                .parse()
+                .map_err(|_| invalid("invalid Content-Length"))?;
+            body.get(..length)
+                .map(<[u8]>::to_vec)
+                .ok_or_else(|| invalid("truncated body"))
+        }
+        None => Ok(body.to_vec()),
+    }
+}
+
+/// Decode a body with `Transfer-Encoding: chunked`, ignoring chunk extensions and
+/// trailers.
+fn decode_chunked(mut buf: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = buf.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&buf[..line_end]).ok()?;
+        let size = size.split(';').next()?.trim();
+        let size = usize::from_str_radix(size, 16).ok()?;
+        buf = &buf[line_end + 2..];
+        if size == 0 {
+            return Some(body);
+        }
+        body.extend_from_slice(buf.get(..size)?);
+        buf = buf.get(size..)?.strip_prefix(b"\r\n")?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Serve `responses` in order on a local port, returning the URL.
+    async fn serve(responses: Vec<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/desc.xml", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![0; 1024];
+                let _ = stream.read(&mut request).await.unwrap();
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn fetches_chunked_description() {
+        let url = serve(vec![
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n6\r\n<root>\r\n7;x=y\r\n</root>\r\n0\r\n\r\n",
+        ])
+        .await;
+
+        let xml = DescriptionFetcher::new().fetch(&url).await.unwrap();
+        assert_eq!(xml, "<root></root>");
+    }
+
+    #[tokio::test]
+    async fn remembers_failures() {
+        let url = serve(vec![
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\n<root>",
+        ])
+        .await;
+
+        let fetcher = DescriptionFetcher::new();
+        assert!(fetcher.fetch(&url).await.is_err());
+        // Not retried, though the server would now succeed
+        assert!(fetcher.fetch(&url).await.is_err());
+
+        let fetcher = fetcher.failure_ttl(Duration::ZERO);
+        fetcher.lock().clear();
+        assert_eq!(fetcher.fetch(&url).await.unwrap(), "<root>");
+    }
+
+    #[test]
+    fn splits_urls() {
+        let (addr, host, path) = split_url("http://192.0.2.1:8080/desc.xml").unwrap();
+        assert_eq!(
+            (addr.as_str(), host, path),
+            ("192.0.2.1:8080", "192.0.2.1:8080", "/desc.xml")
+        );
+
+        let (addr, _, path) = split_url("HTTP://[fe80::1]").unwrap();
+        assert_eq!((addr.as_str(), path), ("[fe80::1]:80", "/"));
+
+        assert!(split_url("https://192.0.2.1/desc.xml").is_err());
+    }
+}
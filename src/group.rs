@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use crate::Device;
+
+/// A group of devices sharing an announcement schedule.
+///
+/// Each group is announced independently, so e.g. short-lived virtual devices can
+/// be advertised more often than the core devices of a server.
+#[derive(Debug, Clone)]
+pub struct DeviceGroup {
+    pub(crate) devices: Vec<Device>,
+    pub(crate) max_age: Option<u64>,
+    pub(crate) notify_interval: Option<Duration>,
+    pub(crate) pacing: Option<Duration>,
+}
+
+impl DeviceGroup {
+    /// Create a new group of devices.
+    ///
+    /// Unless overridden, the group uses the `max_age` and the notify interval of the
+    /// server, see [`Server::notify_interval`](crate::Server::notify_interval).
+    ///
+    /// # Examples
+    /// ```
+    /// use std::time::Duration;
+    /// use tokio_ssdp::{Device, DeviceGroup, Server};
+    ///
+    /// let uuid = "ad8782a0-9e28-422b-a6ae-670fe7c4c043";
+    ///
+    /// Server::new([Device::new(uuid, "upnp:rootdevice", "http://192.168.1.100:8080/desc.xml")])
+    ///     .group(
+    ///         DeviceGroup::new([Device::new(
+    ///             "0c3ab6d2-4f3e-4b1a-9a0e-2b8f6f2a1f10",
+    ///             "urn:schemas-upnp-org:device:DimmableLight:1",
+    ///             "http://192.168.1.100:8080/light.xml",
+    ///         )])
+    ///         .max_age(30)
+    ///         .notify_interval(Duration::from_secs(10)),
+    ///     );
+    /// ```
+    pub fn new(devices: impl IntoIterator<Item = Device>) -> Self {
+        Self {
+            devices: devices.into_iter().collect(),
+            max_age: None,
+            notify_interval: None,
+            pacing: None,
+        }
+    }
+
+    /// Set the value of `Cache-Control: max-age=` for devices in this group,
+    /// overriding [`Server::max_age`](crate::Server::max_age).
+    pub fn max_age(mut self, max_age: u64) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Set the interval between `ssdp:alive` announcements of this group, overriding
+    /// [`Server::notify_interval`](crate::Server::notify_interval).
+    pub fn notify_interval(mut self, notify_interval: Duration) -> Self {
+        self.notify_interval = Some(notify_interval);
+        self
+    }
+
+    /// Set the delay between consecutive messages of one announcement, overriding
+    /// [`Server::pacing`](crate::Server::pacing).
+    pub fn pacing(mut self, pacing: Duration) -> Self {
+        self.pacing = Some(pacing);
+        self
+    }
+}
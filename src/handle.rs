@@ -0,0 +1,321 @@
+use std::{io::Result as IoResult, net::SocketAddr, ops::Deref};
+
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
+
+#[cfg(feature = "signal")]
+use crate::logging::info;
+use crate::{
+    Advertiser, Device, Health, NotificationSubtype, ResponseHistory, SentResponse, server::Control,
+};
+
+/// A handle to a server running in the background, see [`Server::spawn`](crate::Server::spawn).
+///
+/// Dropping the handle leaves the server running.
+#[derive(Debug)]
+pub struct ServerHandle {
+    pub(crate) shutdown_tx: std::sync::Arc<watch::Sender<bool>>,
+    pub(crate) control_tx: mpsc::UnboundedSender<Control>,
+    pub(crate) task: JoinHandle<IoResult<()>>,
+    pub(crate) health: Health,
+    pub(crate) advertiser: Advertiser,
+    pub(crate) response_history: ResponseHistory,
+    pub(crate) ready_tx: std::sync::Arc<watch::Sender<bool>>,
+}
+
+impl ServerHandle {
+    /// Stop the server, after sending `ssdp:byebye` for all devices.
+    ///
+    /// Pending search responses are flushed or cancelled first, see
+    /// [`Server::pending_responses`](crate::Server::pending_responses). Returns the error
+    /// the server failed with, if it stopped on its own before.
+    pub async fn shutdown(self) -> IoResult<()> {
+        self.shutdown_tx.send_replace(true);
+        self.task.await.map_err(std::io::Error::other)?
+    }
+
+    /// Wait for `SIGINT` (Ctrl-C) or, on unix, `SIGTERM`, then stop the server like
+    /// [`ServerHandle::shutdown`], so control points learn the devices are gone.
+    ///
+    /// Returns early with the result of the server if it stops on its own.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::Server;
+    ///
+    /// Server::new([]).spawn()?.shutdown_on_signal().await
+    /// # }
+    /// ```
+    #[cfg(feature = "signal")]
+    pub async fn shutdown_on_signal(mut self) -> IoResult<()> {
+        tokio::select! {
+            res = &mut self.task => return res.map_err(std::io::Error::other)?,
+            res = shutdown_signal() => res?,
+        }
+
+        info!("Received signal, shutting down");
+        self.shutdown().await
+    }
+
+    /// Stop the server when the returned guard is dropped, e.g. when the task owning it
+    /// is cancelled.
+    ///
+    /// Dropping the guard starts the shutdown like [`ServerHandle::shutdown`], the server
+    /// sends `ssdp:byebye` in the background. It can not be awaited, so the server may
+    /// not get to finish when the runtime shuts down right after.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::Server;
+    ///
+    /// let guard = Server::new([]).spawn()?.shutdown_on_drop();
+    /// guard.announce_now();
+    ///
+    /// // The server stops when `guard` goes out of scope
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn shutdown_on_drop(self) -> ShutdownGuard {
+        ShutdownGuard { handle: Some(self) }
+    }
+
+    /// Add `device` to the running server, announcing it with `ssdp:alive` right away.
+    ///
+    /// The device joins the group of devices passed to [`Server::new`](crate::Server::new),
+    /// replacing a device with the same USN.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::{Device, Server};
+    ///
+    /// let handle = Server::new([]).spawn()?;
+    ///
+    /// let uuid = "ad8782a0-9e28-422b-a6ae-670fe7c4c043";
+    /// handle.add_device(Device::new(uuid, "upnp:rootdevice", "http://192.168.1.100:8080/desc.xml"));
+    /// handle.remove_device(format!("uuid:{}::upnp:rootdevice", uuid));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn add_device(&self, device: Device) {
+        let _ = self.control_tx.send(Control::AddDevice(device));
+    }
+
+    /// Remove the device with USN `usn` from the running server, announcing its
+    /// departure with `ssdp:byebye` right away.
+    pub fn remove_device(&self, usn: impl Into<String>) {
+        let _ = self.control_tx.send(Control::RemoveDevice(usn.into()));
+    }
+
+    /// Announce all devices with `ssdp:alive` right away, outside of their schedule.
+    ///
+    /// To change the location of a device, e.g. after the HTTP server moved to another
+    /// port, add it again with [`ServerHandle::add_device`] instead.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::Server;
+    ///
+    /// let handle = Server::new([]).spawn()?;
+    ///
+    /// handle.announce_now();
+    /// handle.announce_device("uuid:ad8782a0-9e28-422b-a6ae-670fe7c4c043::upnp:rootdevice");
+    /// handle.notify_update();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn announce_now(&self) {
+        let _ = self.control_tx.send(Control::Announce(None));
+    }
+
+    /// Announce the device with USN `usn` with `ssdp:alive` right away.
+    pub fn announce_device(&self, usn: impl Into<String>) {
+        let _ = self.control_tx.send(Control::Announce(Some(usn.into())));
+    }
+
+    /// Announce a new boot id with `ssdp:update`, and re-announce all devices with it,
+    /// e.g. after the network configuration of the host changed.
+    ///
+    /// The boot id is only sent with UPnP 1.1 and later, see
+    /// [`Server::upnp_version`](crate::Server::upnp_version).
+    pub fn notify_update(&self) {
+        let _ = self.control_tx.send(Control::Update(None));
+    }
+
+    /// Change the configuration id (`CONFIGID.UPNP.ORG`), e.g. after the device
+    /// description changed, and announce it like [`ServerHandle::notify_update`].
+    pub fn set_config_id(&self, config_id: u32) {
+        let _ = self.control_tx.send(Control::Update(Some(config_id)));
+    }
+
+    /// Send notifications of subtype `nts` for the devices with the USNs `usns` right away,
+    /// paced as other announcements, see [`Server::pacing`](crate::Server::pacing).
+    ///
+    /// - [`NotificationSubtype::Alive`] announces the devices, as
+    ///   [`ServerHandle::announce_device`].
+    /// - [`NotificationSubtype::ByeBye`] announces their departure, but the devices keep
+    ///   being served and announced, unlike with [`ServerHandle::remove_device`].
+    /// - [`NotificationSubtype::Update`] announces the next boot id, then re-announces
+    ///   the devices with it. The boot id is shared by all devices of the server, so this
+    ///   updates all of them, as [`ServerHandle::notify_update`], once any of the USNs is
+    ///   known.
+    ///
+    /// Unknown devices are ignored.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::{NotificationSubtype, Server};
+    ///
+    /// let handle = Server::new([]).spawn()?;
+    ///
+    /// let uuid = "ad8782a0-9e28-422b-a6ae-670fe7c4c043";
+    /// handle.notify(NotificationSubtype::ByeBye, [
+    ///     format!("uuid:{}::urn:schemas-upnp-org:service:AVTransport:1", uuid),
+    ///     format!("uuid:{}::urn:schemas-upnp-org:service:RenderingControl:1", uuid),
+    /// ]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn notify(
+        &self,
+        nts: NotificationSubtype,
+        usns: impl IntoIterator<Item = impl Into<String>>,
+    ) {
+        let _ = self.control_tx.send(Control::Notify {
+            nts,
+            usns: usns.into_iter().map(Into::into).collect(),
+        });
+    }
+
+    /// Send `ssdp:alive` for the device with USN `usn` to `target` only, e.g. to a control
+    /// point known from its searches on a network filtering multicast.
+    ///
+    /// The notification is the same as the multicast one, including the `HOST` header.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::Server;
+    ///
+    /// let server = Server::new([]);
+    /// let mut searches = server.answered_searches();
+    /// let handle = server.spawn()?;
+    ///
+    /// let usn = "uuid:ad8782a0-9e28-422b-a6ae-670fe7c4c043::upnp:rootdevice";
+    /// let search = searches.recv().await.unwrap();
+    /// handle.notify_unicast(search.remote_addr, usn);
+    /// handle.byebye_unicast(search.remote_addr, usn);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn notify_unicast(&self, target: SocketAddr, usn: impl Into<String>) {
+        let _ = self.control_tx.send(Control::Unicast {
+            target,
+            usn: usn.into(),
+            alive: true,
+        });
+    }
+
+    /// Send `ssdp:byebye` for the device with USN `usn` to `target` only, see
+    /// [`ServerHandle::notify_unicast`]. The device keeps being served.
+    pub fn byebye_unicast(&self, target: SocketAddr, usn: impl Into<String>) {
+        let _ = self.control_tx.send(Control::Unicast {
+            target,
+            usn: usn.into(),
+            alive: false,
+        });
+    }
+
+    /// Get the liveness information of the server, see [`Server::health`](crate::Server::health).
+    pub fn health(&self) -> Health {
+        self.health.clone()
+    }
+
+    /// Get a handle to temporarily advertise more often, see
+    /// [`Server::advertiser`](crate::Server::advertiser).
+    pub fn advertiser(&self) -> Advertiser {
+        self.advertiser.clone()
+    }
+
+    /// Get a future resolving once the server is ready, see
+    /// [`Server::ready`](crate::Server::ready).
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::Server;
+    ///
+    /// let handle = Server::new([]).spawn()?;
+    ///
+    /// if handle.ready().await {
+    ///     println!("Discoverable");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn ready(&self) -> impl Future<Output = bool> + Send + 'static {
+        let mut rx = self.ready_tx.subscribe();
+        async move { rx.wait_for(|ready| *ready).await.is_ok() }
+    }
+
+    /// Get the recently sent search responses, see
+    /// [`Server::keep_responses`](crate::Server::keep_responses).
+    pub fn recent_responses(&self) -> Vec<SentResponse> {
+        self.response_history.recent_responses()
+    }
+}
+
+/// Wait for a signal asking the process to terminate.
+#[cfg(feature = "signal")]
+async fn shutdown_signal() -> IoResult<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => res,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+/// Stops a server when dropped, see [`ServerHandle::shutdown_on_drop`].
+///
+/// Derefs to the [`ServerHandle`] of the server.
+#[derive(Debug)]
+pub struct ShutdownGuard {
+    handle: Option<ServerHandle>,
+}
+
+impl ShutdownGuard {
+    /// Keep the server running after all, returning its handle.
+    pub fn disarm(mut self) -> ServerHandle {
+        self.handle.take().expect("guard is armed until dropped")
+    }
+}
+
+impl Deref for ShutdownGuard {
+    type Target = ServerHandle;
+
+    fn deref(&self) -> &ServerHandle {
+        self.handle.as_ref().expect("guard is armed until dropped")
+    }
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        if let Some(handle) = &self.handle {
+            handle.shutdown_tx.send_replace(true);
+        }
+    }
+}
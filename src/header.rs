@@ -0,0 +1,200 @@
+use std::{borrow::Cow, fmt, net::SocketAddr};
+
+use crate::{Device, MessageKind};
+
+/// The message being built, see [`HeaderProvider`].
+#[derive(Debug, Clone, Copy)]
+pub struct HeaderContext<'a> {
+    /// The kind of the message.
+    pub kind: MessageKind,
+    /// The device the message is about.
+    pub device: &'a Device,
+    /// The address the message is sent to, for search responses.
+    pub remote_addr: Option<SocketAddr>,
+}
+
+/// Computes extra headers each time a message is built, see
+/// [`Server::header_provider`](crate::Server::header_provider).
+///
+/// Implemented for closures taking a [`HeaderContext`].
+pub trait HeaderProvider: Send + Sync {
+    /// The headers to add to the message described by `ctx`.
+    fn headers(&self, ctx: &HeaderContext<'_>) -> Vec<(String, String)>;
+}
+
+impl<F> HeaderProvider for F
+where
+    F: Fn(&HeaderContext<'_>) -> Vec<(String, String)> + Send + Sync,
+{
+    fn headers(&self, ctx: &HeaderContext<'_>) -> Vec<(String, String)> {
+        self(ctx)
+    }
+}
+
+impl fmt::Debug for dyn HeaderProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("HeaderProvider")
+    }
+}
+
+/// A standard header emitted by the server in search responses and notifications.
+///
+/// Used with [`Server::header_order`](crate::Server::header_order) and
+/// [`Device::header_order`](crate::Device::header_order) to control the order in which
+/// headers appear on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Header {
+    /// `CACHE-CONTROL`
+    CacheControl,
+    /// `CONTENT-LENGTH`
+    ContentLength,
+    /// `DATE`
+    Date,
+    /// `EXT`
+    Ext,
+    /// `HOST`
+    Host,
+    /// `LOCATION`
+    Location,
+    /// `NT`
+    Nt,
+    /// `NTS`
+    Nts,
+    /// `SERVER`
+    Server,
+    /// `ST`
+    St,
+    /// `USN`
+    Usn,
+}
+
+impl Header {
+    /// The name of the header as it appears on the wire.
+    pub fn name(self) -> &'static str {
+        match self {
+            Header::CacheControl => "CACHE-CONTROL",
+            Header::ContentLength => "CONTENT-LENGTH",
+            Header::Date => "DATE",
+            Header::Ext => "EXT",
+            Header::Host => "HOST",
+            Header::Location => "LOCATION",
+            Header::Nt => "NT",
+            Header::Nts => "NTS",
+            Header::Server => "SERVER",
+            Header::St => "ST",
+            Header::Usn => "USN",
+        }
+    }
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Append `headers` to `out`, one `NAME: value\r\n` line each.
+///
+/// Headers listed in `order` come first, in that order. The remaining headers
+/// follow in the order they were given.
+pub(crate) fn write_headers(out: &mut String, headers: &[(Header, String)], order: &[Header]) {
+    let ordered = order
+        .iter()
+        .filter_map(|h| headers.iter().find(|(name, _)| name == h));
+    let rest = headers.iter().filter(|(name, _)| !order.contains(name));
+
+    for (name, value) in ordered.chain(rest) {
+        out.push_str(name.name());
+        out.push(':');
+        if !value.is_empty() {
+            out.push(' ');
+            out.push_str(value);
+        }
+        out.push_str("\r\n");
+    }
+}
+
+/// Replace `{name}` placeholders in `value` with the matching entry of `vars`.
+///
+/// Unknown placeholders are left untouched.
+pub(crate) fn expand_placeholders<'a>(value: &'a str, vars: &[(&str, &str)]) -> Cow<'a, str> {
+    if !value.contains('{') {
+        return Cow::Borrowed(value);
+    }
+
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let tail = &rest[start + 1..];
+
+        let var = vars.iter().find(|(name, _)| {
+            tail.strip_prefix(name)
+                .is_some_and(|after| after.starts_with('}'))
+        });
+
+        match var {
+            Some((name, v)) => {
+                out.push_str(v);
+                rest = &tail[name.len() + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = tail;
+            }
+        }
+    }
+
+    out.push_str(rest);
+    Cow::Owned(out)
+}
+
+/// Append `headers` to the pre-concatenated `extra_headers`, replacing the headers with
+/// the same names.
+///
+/// Line breaks in the names and values of `headers` are replaced by spaces, so they can't
+/// end the header early or add headers of their own.
+pub(crate) fn merge_headers<'a>(extra_headers: &'a str, headers: &[(&str, &str)]) -> Cow<'a, str> {
+    if headers.is_empty() {
+        return Cow::Borrowed(extra_headers);
+    }
+
+    let mut merged: String = extra_headers
+        .split_inclusive("\r\n")
+        .filter(|line| {
+            let name = line.split(':').next().unwrap_or_default().trim();
+            !headers
+                .iter()
+                .any(|(own, _)| own.eq_ignore_ascii_case(name))
+        })
+        .collect();
+    for (name, value) in headers {
+        merged.push_str(&format!(
+            "{}: {}\r\n",
+            name.replace(['\r', '\n'], " "),
+            value.replace(['\r', '\n'], " ")
+        ));
+    }
+
+    Cow::Owned(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_headers() {
+        let merged = merge_headers("SERVER: a\r\nX-A: 1\r\n", &[("x-a", "2"), ("X-B", "3")]);
+        assert_eq!(merged, "SERVER: a\r\nx-a: 2\r\nX-B: 3\r\n");
+    }
+
+    #[test]
+    fn strips_line_breaks_from_headers() {
+        let merged = merge_headers("", &[("X-A\r\nX-B", "1\r\n\r\nHTTP/1.1 200 OK")]);
+        assert_eq!(merged, "X-A  X-B: 1    HTTP/1.1 200 OK\r\n");
+    }
+}
@@ -0,0 +1,60 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+/// A snapshot of the liveness of a server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HealthReport {
+    /// When a datagram was last received.
+    pub last_receive: Option<SystemTime>,
+    /// When a `ssdp:alive` message was last sent successfully.
+    pub last_notify: Option<SystemTime>,
+    /// The number of sends which failed since the last successful one.
+    pub consecutive_send_failures: u32,
+    /// Whether the server has joined the SSDP multicast group.
+    pub multicast_joined: bool,
+}
+
+/// Liveness information about a server, see [`Server::health`](crate::Server::health).
+///
+/// Clones share the same state, so a `Health` obtained before the server is
+/// started reflects the running server.
+#[derive(Debug, Clone, Default)]
+pub struct Health {
+    report: Arc<Mutex<HealthReport>>,
+}
+
+impl Health {
+    /// Get the current health of the server.
+    pub fn report(&self) -> HealthReport {
+        self.lock().clone()
+    }
+
+    pub(crate) fn received(&self) {
+        self.lock().last_receive = Some(SystemTime::now());
+    }
+
+    pub(crate) fn notified(&self) {
+        let mut report = self.lock();
+        report.last_notify = Some(SystemTime::now());
+        report.consecutive_send_failures = 0;
+    }
+
+    pub(crate) fn sent(&self) {
+        self.lock().consecutive_send_failures = 0;
+    }
+
+    pub(crate) fn send_failed(&self) {
+        let mut report = self.lock();
+        report.consecutive_send_failures = report.consecutive_send_failures.saturating_add(1);
+    }
+
+    pub(crate) fn set_multicast_joined(&self, joined: bool) {
+        self.lock().multicast_joined = joined;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HealthReport> {
+        self.report.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
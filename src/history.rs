@@ -0,0 +1,71 @@
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+/// A search response sent by a server, see [`ResponseHistory`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentResponse {
+    /// When the response was sent.
+    pub time: SystemTime,
+    /// The address of the control point which sent the search.
+    pub remote_addr: SocketAddr,
+    /// The search target of the search.
+    pub st: String,
+    /// The USN of the device the response is about.
+    pub usn: String,
+    /// The number of bytes sent, 0 if sending failed.
+    pub bytes_sent: usize,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    capacity: usize,
+    responses: VecDeque<SentResponse>,
+}
+
+/// The most recently sent search responses of a server, see
+/// [`Server::response_history`](crate::Server::response_history).
+///
+/// Clones share the same state, so a `ResponseHistory` obtained before the server is
+/// started reflects the running server.
+#[derive(Debug, Clone, Default)]
+pub struct ResponseHistory {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl ResponseHistory {
+    /// Get the recently sent responses, oldest first.
+    pub fn recent_responses(&self) -> Vec<SentResponse> {
+        self.lock().responses.iter().cloned().collect()
+    }
+
+    pub(crate) fn set_capacity(&self, capacity: usize) {
+        let mut inner = self.lock();
+        inner.capacity = capacity;
+        while inner.responses.len() > capacity {
+            inner.responses.pop_front();
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.lock().capacity > 0
+    }
+
+    pub(crate) fn push(&self, response: SentResponse) {
+        let mut inner = self.lock();
+        if inner.capacity == 0 {
+            return;
+        }
+        while inner.responses.len() >= inner.capacity {
+            inner.responses.pop_front();
+        }
+        inner.responses.push_back(response);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,51 @@
+use std::{fmt, future::Future, net::SocketAddr, pin::Pin};
+
+use crate::SearchRequest;
+
+/// What to do with an `M-SEARCH` request, see [`Server::on_search`](crate::Server::on_search).
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum SearchDecision {
+    /// Answer the search.
+    #[default]
+    Answer,
+    /// Answer the search, adding the given headers to the responses. They replace the
+    /// server-level headers with the same names, and support the same placeholders. Line
+    /// breaks in their names and values are replaced by spaces.
+    AnswerWith(Vec<(String, String)>),
+    /// Do not answer the search.
+    Ignore,
+}
+
+/// Decides how to handle each `M-SEARCH` request, see
+/// [`Server::on_search`](crate::Server::on_search).
+///
+/// Implemented for closures taking the request and the address of its sender, and
+/// returning a future resolving to a [`SearchDecision`].
+pub trait SearchHook: Send + Sync {
+    /// Decide how to handle `request`, sent by `from`.
+    fn decide(
+        &self,
+        request: &SearchRequest,
+        from: SocketAddr,
+    ) -> Pin<Box<dyn Future<Output = SearchDecision> + Send>>;
+}
+
+impl<F, Fut> SearchHook for F
+where
+    F: Fn(&SearchRequest, SocketAddr) -> Fut + Send + Sync,
+    Fut: Future<Output = SearchDecision> + Send + 'static,
+{
+    fn decide(
+        &self,
+        request: &SearchRequest,
+        from: SocketAddr,
+    ) -> Pin<Box<dyn Future<Output = SearchDecision> + Send>> {
+        Box::pin(self(request, from))
+    }
+}
+
+impl fmt::Debug for dyn SearchHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SearchHook")
+    }
+}
@@ -0,0 +1,113 @@
+//! A minimal HTTP server for device descriptions.
+
+use std::{
+    fmt,
+    io::Result as IoResult,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use tokio::{net::TcpListener, sync::watch};
+
+use crate::logging::info;
+
+const DEFAULT_PATH: &str = "/desc.xml";
+
+/// A device description served over HTTP by the server, see
+/// [`Server::description`](crate::Server::description).
+#[derive(Clone)]
+pub struct Description {
+    render: Arc<dyn Fn() -> String + Send + Sync>,
+    path: String,
+    port: u16,
+}
+
+impl Description {
+    /// Serve the description `xml`.
+    ///
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{Description, Device, Server};
+    ///
+    /// Server::new([Device::new(
+    ///     "ad8782a0-9e28-422b-a6ae-670fe7c4c043",
+    ///     "upnp:rootdevice",
+    ///     "{description}",
+    /// )])
+    /// .description(Description::new("<root>...</root>").port(8080));
+    /// ```
+    pub fn new(xml: impl Into<String>) -> Self {
+        let xml: Arc<str> = xml.into().into();
+        Self::render(move || xml.to_string())
+    }
+
+    /// Serve the description rendered by `render` on each request.
+    pub fn render(render: impl Fn() -> String + Send + Sync + 'static) -> Self {
+        Self {
+            render: Arc::new(render),
+            path: DEFAULT_PATH.to_string(),
+            port: 0,
+        }
+    }
+
+    /// Serve the description at `path`, defaults to `/desc.xml`.
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = path.into();
+        self
+    }
+
+    /// Listen on `port`, defaults to an ephemeral port.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// The URL of the description on `host`.
+    pub(crate) fn url(&self, host: Ipv4Addr) -> String {
+        format!("http://{}:{}{}", host, self.port, self.path)
+    }
+
+    /// Bind the listener on `ip`, and switch to the port it was bound on.
+    pub(crate) fn bind(&mut self, ip: Ipv4Addr) -> IoResult<TcpListener> {
+        let listener = std::net::TcpListener::bind(SocketAddr::from((ip, self.port)))?;
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        self.port = listener.local_addr()?.port();
+        Ok(listener)
+    }
+
+    /// Serve the description on `listener` until `shutdown` changes.
+    pub(crate) async fn serve(
+        &self,
+        listener: TcpListener,
+        mut shutdown: watch::Receiver<()>,
+    ) -> IoResult<()> {
+        use axum::{http::header::CONTENT_TYPE, routing::get};
+
+        info!("Serving description on {}", listener.local_addr()?);
+
+        let render = Arc::clone(&self.render);
+        let router = axum::Router::new().route(
+            &self.path,
+            get(move || {
+                let xml = render();
+                async move { ([(CONTENT_TYPE, "text/xml; charset=\"utf-8\"")], xml) }
+            }),
+        );
+
+        axum::serve(listener, router)
+            .with_graceful_shutdown(async move {
+                let _ = shutdown.changed().await;
+            })
+            .await
+    }
+}
+
+impl fmt::Debug for Description {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Description")
+            .field("path", &self.path)
+            .field("port", &self.port)
+            .finish_non_exhaustive()
+    }
+}
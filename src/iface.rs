@@ -0,0 +1,128 @@
+use std::{io, net::Ipv4Addr};
+
+/// The IPv4 address of the interface named `name`, e.g. `eth0`.
+///
+/// Only supported on unix platforms.
+pub(crate) fn ipv4_addr(name: &str) -> io::Result<Ipv4Addr> {
+    imp::ipv4_addr(name)
+}
+
+/// Restrict `socket` to the interface named `name` with `SO_BINDTODEVICE`.
+///
+/// Does nothing on platforms without `SO_BINDTODEVICE`.
+pub(crate) fn bind_to_device(socket: &std::net::UdpSocket, name: &str) -> io::Result<()> {
+    imp::bind_to_device(socket, name)
+}
+
+/// Send multicast datagrams on `socket` from the interface with address `ip`, instead of
+/// the interface of the default route.
+///
+/// Does nothing on platforms other than unix.
+pub(crate) fn set_multicast_if(socket: &std::net::UdpSocket, ip: Ipv4Addr) -> io::Result<()> {
+    imp::set_multicast_if(socket, ip)
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::{ffi::CStr, io, mem::size_of, net::Ipv4Addr, os::unix::io::AsRawFd, ptr};
+
+    pub(super) fn ipv4_addr(name: &str) -> io::Result<Ipv4Addr> {
+        let mut addrs: *mut libc::ifaddrs = ptr::null_mut();
+        // SAFETY: `addrs` is only used after success, and freed below.
+        if unsafe { libc::getifaddrs(&mut addrs) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut found = None;
+        let mut cur = addrs;
+        // SAFETY: the list returned by `getifaddrs` is valid until freed, and the address
+        // is only read as `sockaddr_in` if its family is `AF_INET`.
+        unsafe {
+            while !cur.is_null() {
+                let ifa = &*cur;
+                if !ifa.ifa_addr.is_null()
+                    && i32::from((*ifa.ifa_addr).sa_family) == libc::AF_INET
+                    && CStr::from_ptr(ifa.ifa_name).to_bytes() == name.as_bytes()
+                {
+                    let addr: libc::sockaddr_in = ptr::read_unaligned(ifa.ifa_addr.cast());
+                    found = Some(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)));
+                    break;
+                }
+                cur = ifa.ifa_next;
+            }
+            libc::freeifaddrs(addrs);
+        }
+
+        found.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("interface {} not found, or without IPv4 address", name),
+            )
+        })
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(super) fn bind_to_device(socket: &std::net::UdpSocket, name: &str) -> io::Result<()> {
+        // SAFETY: the name outlives the call, and the length matches it.
+        let ret = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_BINDTODEVICE,
+                name.as_ptr().cast(),
+                name.len() as libc::socklen_t,
+            )
+        };
+
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub(super) fn bind_to_device(_socket: &std::net::UdpSocket, _name: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub(super) fn set_multicast_if(socket: &std::net::UdpSocket, ip: Ipv4Addr) -> io::Result<()> {
+        let addr = libc::in_addr {
+            s_addr: u32::from(ip).to_be(),
+        };
+        // SAFETY: `addr` outlives the call, and the length matches its type.
+        let ret = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::IPPROTO_IP,
+                libc::IP_MULTICAST_IF,
+                ptr::addr_of!(addr).cast(),
+                size_of::<libc::in_addr>() as libc::socklen_t,
+            )
+        };
+
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use std::{io, net::Ipv4Addr};
+
+    pub(super) fn ipv4_addr(name: &str) -> io::Result<Ipv4Addr> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("looking up interface {} is not supported", name),
+        ))
+    }
+
+    pub(super) fn bind_to_device(_socket: &std::net::UdpSocket, _name: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub(super) fn set_multicast_if(_socket: &std::net::UdpSocket, _ip: Ipv4Addr) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+use std::io;
+
+/// Notifications of changed network interfaces and addresses, see
+/// [`Server::watch_interfaces`](crate::Server::watch_interfaces).
+///
+/// Uses a netlink socket on Linux and Android, and a routing socket on macOS and the BSDs.
+#[derive(Debug)]
+pub(crate) struct InterfaceEvents {
+    inner: imp::Socket,
+}
+
+impl InterfaceEvents {
+    /// Start listening for changes. Must be called from within a tokio runtime.
+    pub(crate) fn new() -> io::Result<Self> {
+        Ok(Self {
+            inner: imp::Socket::open()?,
+        })
+    }
+
+    /// Wait for the next change of an interface or address. Changes which happened
+    /// since the last call are reported at once.
+    pub(crate) async fn changed(&mut self) -> io::Result<()> {
+        self.inner.changed().await
+    }
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+))]
+mod imp {
+    use std::{
+        io,
+        os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    };
+
+    use tokio::io::unix::AsyncFd;
+
+    #[derive(Debug)]
+    pub(super) struct Socket {
+        fd: AsyncFd<OwnedFd>,
+    }
+
+    impl Socket {
+        pub(super) fn open() -> io::Result<Self> {
+            let fd = open_raw()?;
+            // SAFETY: `fd` was just opened, and is owned by nothing else.
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+            // SAFETY: `fd` is a valid file descriptor.
+            let ret = unsafe {
+                let flags = libc::fcntl(fd.as_raw_fd(), libc::F_GETFL);
+                libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK)
+            };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(Self {
+                fd: AsyncFd::new(fd)?,
+            })
+        }
+
+        pub(super) async fn changed(&mut self) -> io::Result<()> {
+            let mut buf = [0u8; 4096];
+            loop {
+                let mut guard = self.fd.readable().await?;
+
+                // Drain all pending messages, any of them is a change
+                let mut changed = false;
+                loop {
+                    // SAFETY: `buf` outlives the call, and the length matches it.
+                    let n = unsafe {
+                        libc::recv(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0)
+                    };
+                    if n >= 0 {
+                        changed = true;
+                        continue;
+                    }
+
+                    let e = io::Error::last_os_error();
+                    match e.kind() {
+                        io::ErrorKind::WouldBlock => break,
+                        io::ErrorKind::Interrupted => continue,
+                        // Messages were dropped, so something changed
+                        _ if e.raw_os_error() == Some(libc::ENOBUFS) => changed = true,
+                        _ => return Err(e),
+                    }
+                }
+
+                guard.clear_ready();
+                if changed {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn open_raw() -> io::Result<libc::c_int> {
+        use std::mem::{size_of, zeroed};
+
+        // SAFETY: `addr` outlives the call, and the length matches its type. The socket
+        // is closed again if binding fails.
+        unsafe {
+            let fd = libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            );
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut addr: libc::sockaddr_nl = zeroed();
+            addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            addr.nl_groups = (libc::RTMGRP_LINK | libc::RTMGRP_IPV4_IFADDR) as u32;
+            let ret = libc::bind(
+                fd,
+                (&addr as *const libc::sockaddr_nl).cast(),
+                size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            );
+            if ret < 0 {
+                let e = io::Error::last_os_error();
+                libc::close(fd);
+                return Err(e);
+            }
+
+            Ok(fd)
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn open_raw() -> io::Result<libc::c_int> {
+        // SAFETY: plain socket creation, the descriptor is checked below.
+        let fd = unsafe { libc::socket(libc::AF_ROUTE, libc::SOCK_RAW, libc::AF_UNSPEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` is a valid file descriptor.
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        Ok(fd)
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+)))]
+mod imp {
+    use std::io;
+
+    #[derive(Debug)]
+    pub(super) struct Socket;
+
+    impl Socket {
+        pub(super) fn open() -> io::Result<Self> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "watching interfaces is not supported on this platform",
+            ))
+        }
+
+        pub(super) async fn changed(&mut self) -> io::Result<()> {
+            std::future::pending().await
+        }
+    }
+}
@@ -1,7 +1,129 @@
 //! A mininal SSDP device implementation using `tokio`.
 
+mod addr;
+pub use addr::{
+    Ipv4Subnet, SSDP_ADDR_V4, SSDP_ADDR_V6_GLOBAL, SSDP_ADDR_V6_LINK_LOCAL, SSDP_ADDR_V6_ORG_LOCAL,
+    SSDP_ADDR_V6_SITE_LOCAL, SSDP_PORT, ssdp_multicast_v4, ssdp_multicast_v6,
+};
+
+mod backoff;
+pub use backoff::Backoff;
+
+mod batch;
+
+mod bootid;
+pub use bootid::{BootIdStore, FileBootIdStore};
+
+mod cache;
+
+pub mod compat;
+
+mod client;
+pub use client::{Client, SearchResponse, SearchResponses, SocketStrategy};
+
+mod customize;
+pub use customize::{ResponseCustomizer, ResponseOverrides};
+
+mod description;
+pub use description::{DeviceDescription, RootDevice};
+
 mod device;
-pub use device::Device;
+pub use device::{Device, LocationProvider, UsnMode};
+
+mod dial;
+pub use dial::{DIAL_SEARCH_TARGET, Dial};
+
+mod error;
+pub use error::Error;
+
+mod event;
+pub use event::ServerEvent;
+
+mod eventlog;
+pub use eventlog::EventLog;
+
+mod fetch;
+pub use fetch::DescriptionFetcher;
+
+mod group;
+pub use group::DeviceGroup;
+
+mod handle;
+pub use handle::{ServerHandle, ShutdownGuard};
+
+mod header;
+pub use header::{Header, HeaderContext, HeaderProvider};
+
+mod health;
+pub use health::{Health, HealthReport};
+
+mod history;
+pub use history::{ResponseHistory, SentResponse};
+
+#[cfg(feature = "http")]
+mod http;
+#[cfg(feature = "http")]
+pub use http::Description;
+
+mod hook;
+pub use hook::{SearchDecision, SearchHook};
+
+mod iface;
+
+mod ifwatch;
+
+mod logging;
+
+#[cfg(feature = "mdns")]
+mod mdns;
+
+pub mod message;
+pub use message::{
+    ControlPoint, MessageOptions, NotificationSubtype, Nts, SearchRequest, UpnpVersion,
+    build_alive, build_byebye, build_search_response,
+};
+
+mod monitor;
+pub use monitor::{Monitor, Notification, Notifications};
+
+mod pktinfo;
+pub use pktinfo::PacketInfo;
+
+mod ratelimit;
+pub use ratelimit::SearchLimit;
+
+mod relay;
+pub use relay::Relay;
+
+mod registry;
+pub use registry::{Registry, RegistryEvent, RemoteDevice};
+
+mod retry;
+pub use retry::SendErrorClass;
+
+mod rng;
+
+mod stats;
+
+mod status;
+pub use status::Status;
+
+mod throttle;
+pub use throttle::Throttle;
+
+mod transport;
+pub use transport::Transport;
+
+#[cfg(feature = "wsd")]
+mod wsd;
+#[cfg(feature = "wsd")]
+pub use wsd::WsDiscovery;
 
 mod server;
-pub use server::Server;
+pub use server::{
+    AddrInUse, AddressChange, Advertiser, AliveCycle, AnsweredSearch, AnsweredSearches,
+    DuplicatePolicy, DuplicateResponder, IncomingSearch, MessageKind, MissingMx, OutgoingMessage,
+    Pacing, PendingResponses, Rejoin, ResponseDelay, RestartAttempt, SearchContext, Server,
+};
+
+mod shared;
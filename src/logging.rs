@@ -0,0 +1,85 @@
+//! The logging macros, forwarding to the `tracing` crate if the `tracing` feature is
+//! enabled, to the `log` crate if the `log` feature is enabled, and compiled out otherwise.
+//!
+//! With `tracing`, each received datagram is handled in a [`Span`] from [`datagram_span`].
+
+use std::net::SocketAddr;
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::{Instrument, Span, debug, error, info, trace, warn};
+
+#[cfg(all(feature = "log", not(feature = "tracing")))]
+pub(crate) use log::{debug, error, info, trace, warn};
+
+#[cfg(not(any(feature = "log", feature = "tracing")))]
+pub(crate) use disabled::{
+    disabled as debug, disabled as error, disabled as info, disabled as trace, disabled as warn,
+};
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use spans::{Instrument, Span};
+
+/// Create the span for handling a datagram from `peer`.
+pub(crate) fn datagram_span(peer: SocketAddr) -> Span {
+    #[cfg(feature = "tracing")]
+    return tracing::debug_span!(
+        "ssdp_datagram",
+        %peer,
+        st = tracing::field::Empty,
+        mx = tracing::field::Empty,
+    );
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = peer;
+        Span
+    }
+}
+
+/// Record the search target and `MX` of an `M-SEARCH` request in `span`.
+pub(crate) fn record_search(span: &Span, st: &str, mx: Option<u32>) {
+    #[cfg(feature = "tracing")]
+    {
+        span.record("st", st);
+        if let Some(mx) = mx {
+            span.record("mx", mx);
+        }
+    }
+    #[cfg(not(feature = "tracing"))]
+    let _ = (span, st, mx);
+}
+
+#[cfg(not(feature = "tracing"))]
+mod spans {
+    /// Stands in for `tracing::Span`.
+    #[derive(Debug, Clone)]
+    pub(crate) struct Span;
+
+    impl Span {
+        pub(crate) fn current() -> Self {
+            Span
+        }
+    }
+
+    /// Stands in for `tracing::Instrument`.
+    pub(crate) trait Instrument: Sized {
+        fn instrument(self, _span: Span) -> Self {
+            self
+        }
+    }
+
+    impl<T> Instrument for T {}
+}
+
+#[cfg(not(any(feature = "log", feature = "tracing")))]
+mod disabled {
+    /// Type check the arguments like the `log` macros, without logging anything.
+    macro_rules! disabled {
+        ($($arg:tt)*) => {
+            if false {
+                let _ = format_args!($($arg)*);
+            }
+        };
+    }
+
+    pub(crate) use disabled;
+}
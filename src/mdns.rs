@@ -0,0 +1,286 @@
+//! A minimal mDNS / DNS-SD responder announcing the devices of a server.
+
+use std::{
+    io::Result as IoResult,
+    net::{Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use crate::logging::{debug, trace, warn};
+use tokio::{net::UdpSocket, sync::watch};
+
+use crate::Device;
+
+const MDNS_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+const TTL: u32 = 120;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const CACHE_FLUSH: u16 = 0x8000;
+
+/// The DNS-SD records of one device.
+#[derive(Debug, Clone)]
+pub(crate) struct MdnsService {
+    /// e.g. `_http._tcp.local`
+    service: String,
+    /// e.g. `<uuid>._http._tcp.local`
+    instance: String,
+    /// e.g. `<uuid>.local`
+    host: String,
+    port: u16,
+    location: String,
+    addr: Ipv4Addr,
+}
+
+impl MdnsService {
+    /// The records for `device` as an instance of `service_type`, e.g. `_http._tcp`.
+    pub(crate) fn new(service_type: &str, device: &Device, location: &str, addr: Ipv4Addr) -> Self {
+        let name = device
+            .uuid
+            .as_deref()
+            .unwrap_or(&device.usn)
+            .replace('.', "-");
+
+        // LOCATION is `scheme://host[:port]/path`
+        let authority = location
+            .split_once("://")
+            .map_or(location, |(_, rest)| rest)
+            .split('/')
+            .next()
+            .unwrap_or_default();
+        let port = authority
+            .rsplit_once(':')
+            .and_then(|(_, port)| port.parse().ok())
+            .unwrap_or(if location.starts_with("https:") {
+                443
+            } else {
+                80
+            });
+
+        let service = format!("{}.local", service_type.trim_end_matches('.'));
+        Self {
+            instance: format!("{}.{}", name, service),
+            host: format!("{}.local", name),
+            service,
+            port,
+            location: location.to_string(),
+            addr,
+        }
+    }
+
+    fn answers(&self, name: &str) -> bool {
+        [&self.service, &self.instance, &self.host]
+            .iter()
+            .any(|own| own.eq_ignore_ascii_case(name.trim_end_matches('.')))
+    }
+
+    fn write_records(&self, out: &mut Vec<u8>, ttl: u32) -> u16 {
+        write_record(out, &self.service, TYPE_PTR, CLASS_IN, ttl, |rdata| {
+            write_name(rdata, &self.instance)
+        });
+
+        write_record(
+            out,
+            &self.instance,
+            TYPE_SRV,
+            CLASS_IN | CACHE_FLUSH,
+            ttl,
+            |rdata| {
+                rdata.extend_from_slice(&0u16.to_be_bytes()); // priority
+                rdata.extend_from_slice(&0u16.to_be_bytes()); // weight
+                rdata.extend_from_slice(&self.port.to_be_bytes());
+                write_name(rdata, &self.host);
+            },
+        );
+
+        write_record(
+            out,
+            &self.instance,
+            TYPE_TXT,
+            CLASS_IN | CACHE_FLUSH,
+            ttl,
+            |rdata| {
+                let entry = format!("location={}", self.location);
+                let entry = &entry.as_bytes()[..entry.len().min(255)];
+                rdata.push(entry.len() as u8);
+                rdata.extend_from_slice(entry);
+            },
+        );
+
+        write_record(
+            out,
+            &self.host,
+            TYPE_A,
+            CLASS_IN | CACHE_FLUSH,
+            ttl,
+            |rdata| rdata.extend_from_slice(&self.addr.octets()),
+        );
+
+        4
+    }
+}
+
+/// Announce `services` on `ip` and answer queries for them, until `shutdown` changes.
+pub(crate) async fn announce(
+    ip: Ipv4Addr,
+    services: Vec<MdnsService>,
+    mut shutdown: watch::Receiver<()>,
+) -> IoResult<()> {
+    let socket = bind_socket(ip)?;
+    let dest = SocketAddr::from((MDNS_ADDR, MDNS_PORT));
+
+    // Announce twice, one second apart, as required by RFC 6762 section 8.3
+    for _ in 0..2 {
+        socket.send_to(&response(&services, TTL), dest).await?;
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+            _ = shutdown.changed() => return goodbye(&socket, &services).await,
+        }
+    }
+
+    let mut buf = [0u8; 2048];
+    loop {
+        tokio::select! {
+            res = socket.recv_from(&mut buf) => {
+                let (n, addr) = res?;
+                let Some(names) = parse_query(&buf[..n]) else {
+                    continue;
+                };
+
+                let matching: Vec<_> = services
+                    .iter()
+                    .filter(|service| names.iter().any(|name| service.answers(name)))
+                    .cloned()
+                    .collect();
+
+                if !matching.is_empty() {
+                    trace!("Answering mDNS query from {} for {:?}", addr, names);
+                    socket.send_to(&response(&matching, TTL), dest).await?;
+                }
+            }
+            _ = shutdown.changed() => return goodbye(&socket, &services).await,
+        }
+    }
+}
+
+/// Withdraw the records by announcing them with a TTL of 0.
+async fn goodbye(socket: &UdpSocket, services: &[MdnsService]) -> IoResult<()> {
+    debug!("Sending mDNS goodbye");
+    socket
+        .send_to(&response(services, 0), (MDNS_ADDR, MDNS_PORT))
+        .await?;
+    Ok(())
+}
+
+fn bind_socket(ip: Ipv4Addr) -> IoResult<UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let s = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    s.set_reuse_address(true)?;
+    #[cfg(unix)]
+    if let Err(e) = s.set_reuse_port(true) {
+        warn!("Failed to set SO_REUSEPORT on mDNS socket: {}", e);
+    }
+    s.set_nonblocking(true)?;
+    s.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, MDNS_PORT)).into())?;
+    s.join_multicast_v4(&MDNS_ADDR, &ip)?;
+    s.set_multicast_ttl_v4(255)?;
+    if !ip.is_unspecified() {
+        s.set_multicast_if_v4(&ip)?;
+    }
+    UdpSocket::from_std(s.into())
+}
+
+/// An unsolicited response carrying the records of `services`.
+fn response(services: &[MdnsService], ttl: u32) -> Vec<u8> {
+    let mut out = vec![0; 12];
+    // Flags: response, authoritative answer
+    out[2] = 0x84;
+
+    let count: u16 = services
+        .iter()
+        .map(|service| service.write_records(&mut out, ttl))
+        .sum();
+    out[6..8].copy_from_slice(&count.to_be_bytes());
+    out
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        out.push(label.len() as u8);
+        out.extend_from_slice(label);
+    }
+    out.push(0);
+}
+
+fn write_record(
+    out: &mut Vec<u8>,
+    name: &str,
+    ty: u16,
+    class: u16,
+    ttl: u32,
+    rdata: impl FnOnce(&mut Vec<u8>),
+) {
+    write_name(out, name);
+    out.extend_from_slice(&ty.to_be_bytes());
+    out.extend_from_slice(&class.to_be_bytes());
+    out.extend_from_slice(&ttl.to_be_bytes());
+
+    let len_pos = out.len();
+    out.extend_from_slice(&[0, 0]);
+    rdata(out);
+    let len = (out.len() - len_pos - 2) as u16;
+    out[len_pos..len_pos + 2].copy_from_slice(&len.to_be_bytes());
+}
+
+/// The names asked for by a query, or `None` if `packet` is not a query.
+fn parse_query(packet: &[u8]) -> Option<Vec<String>> {
+    if packet.len() < 12 || packet[2] & 0x80 != 0 {
+        return None;
+    }
+
+    let qdcount = u16::from_be_bytes([packet[4], packet[5]]);
+    let mut pos = 12;
+    let mut names = vec![];
+    for _ in 0..qdcount {
+        let (name, end) = read_name(packet, pos)?;
+        let ty = u16::from_be_bytes([*packet.get(end)?, *packet.get(end + 1)?]);
+        pos = end + 4;
+        if matches!(ty, TYPE_PTR | TYPE_SRV | TYPE_TXT | TYPE_A | TYPE_ANY) {
+            names.push(name);
+        }
+    }
+
+    Some(names)
+}
+
+/// Read a possibly compressed name at `pos`, returning it and the position after it.
+fn read_name(packet: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels = vec![];
+    let mut end = None;
+
+    // Bound the number of pointers followed, to not loop forever on malicious packets
+    for _ in 0..128 {
+        let len = *packet.get(pos)? as usize;
+        match len {
+            0 => return Some((labels.join("."), end.unwrap_or(pos + 1))),
+            len if len & 0xc0 == 0xc0 => {
+                let target = (len & 0x3f) << 8 | *packet.get(pos + 1)? as usize;
+                end.get_or_insert(pos + 2);
+                pos = target;
+            }
+            len => {
+                let label = packet.get(pos + 1..pos + 1 + len)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + len;
+            }
+        }
+    }
+
+    None
+}
@@ -0,0 +1,1148 @@
+//! SSDP messages: typed `M-SEARCH` requests, search responses and `NOTIFY` messages
+//! with their parsers and serializers, and the formatting of the messages sent by a
+//! [`Server`](crate::Server).
+//!
+//! The parsers accept messages with up to 64 headers, as a server does by default, see
+//! [`Server::max_headers`](crate::Server::max_headers).
+
+use std::{
+    fmt,
+    io::{Error, ErrorKind, Result as IoResult},
+    net::{Ipv4Addr, SocketAddrV4},
+    time::SystemTime,
+};
+
+use crate::{
+    Device, Header, MessageKind, SSDP_ADDR_V4, SSDP_PORT,
+    compat::Profile,
+    header::{expand_placeholders, write_headers},
+};
+
+const DEFAULT_HTTP_VERSION: &str = "HTTP/1.1";
+
+/// The header slots tried first, on the stack.
+pub(crate) const INLINE_HEADERS: usize = 16;
+
+/// The most headers of a message, unless configured otherwise with
+/// [`Server::max_headers`](crate::Server::max_headers).
+pub(crate) const DEFAULT_MAX_HEADERS: usize = 64;
+
+/// A version of the UPnP Device Architecture, selecting a consistent set of headers and
+/// behaviors, see [`Server::upnp_version`](crate::Server::upnp_version).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UpnpVersion {
+    /// UPnP 1.0, the default.
+    #[default]
+    V1_0,
+    /// UPnP 1.1, adding `BOOTID.UPNP.ORG` and `CONFIGID.UPNP.ORG` to all messages.
+    V1_1,
+    /// UPnP 2.0, as UPnP 1.1, and control points identify themselves in searches, see
+    /// [`SearchRequest::control_point_name`].
+    V2_0,
+}
+
+impl UpnpVersion {
+    /// The version as used in the `SERVER` header, e.g. `1.1`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UpnpVersion::V1_0 => "1.0",
+            UpnpVersion::V1_1 => "1.1",
+            UpnpVersion::V2_0 => "2.0",
+        }
+    }
+
+    fn default_server_name(&self) -> &'static str {
+        match self {
+            UpnpVersion::V1_0 => "Tokio-SSDP/1.0 UPnP/1.0",
+            UpnpVersion::V1_1 => "Tokio-SSDP/1.0 UPnP/1.1",
+            UpnpVersion::V2_0 => "Tokio-SSDP/1.0 UPnP/2.0",
+        }
+    }
+}
+
+impl fmt::Display for UpnpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The notification subtype of a `NOTIFY` message, i.e. the value of the `NTS` header.
+///
+/// Received messages may carry any subtype, see [`Nts::subtype`] for the ones sent by
+/// servers.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Nts {
+    /// `ssdp:alive`
+    Alive,
+    /// `ssdp:byebye`
+    ByeBye,
+    /// `ssdp:update`
+    Update,
+    /// Any other value.
+    Other(String),
+}
+
+impl Nts {
+    /// The value of the `NTS` header.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Nts::Alive => NotificationSubtype::Alive.as_str(),
+            Nts::ByeBye => NotificationSubtype::ByeBye.as_str(),
+            Nts::Update => NotificationSubtype::Update.as_str(),
+            Nts::Other(other) => other,
+        }
+    }
+
+    /// The subtype sent by servers, `None` for any other value.
+    ///
+    /// # Examples
+    /// ```
+    /// # use tokio_ssdp::{NotificationSubtype, Nts};
+    /// assert_eq!(Nts::Update.subtype(), Some(NotificationSubtype::Update));
+    /// assert_eq!(Nts::from("upnp:propchange").subtype(), None);
+    /// ```
+    pub fn subtype(&self) -> Option<NotificationSubtype> {
+        match self {
+            Nts::Alive => Some(NotificationSubtype::Alive),
+            Nts::ByeBye => Some(NotificationSubtype::ByeBye),
+            Nts::Update => Some(NotificationSubtype::Update),
+            Nts::Other(_) => None,
+        }
+    }
+}
+
+impl From<&str> for Nts {
+    /// Parse the value of the `NTS` header.
+    ///
+    /// # Examples
+    /// ```
+    /// # use tokio_ssdp::Nts;
+    /// assert_eq!(Nts::from("ssdp:byebye"), Nts::ByeBye);
+    /// assert_eq!(Nts::from("upnp:propchange"), Nts::Other("upnp:propchange".into()));
+    /// ```
+    fn from(value: &str) -> Self {
+        let value = value.trim();
+        NotificationSubtype::ALL
+            .into_iter()
+            .find(|subtype| value.eq_ignore_ascii_case(subtype.as_str()))
+            .map_or_else(|| Nts::Other(value.to_string()), Nts::from)
+    }
+}
+
+impl fmt::Display for Nts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The subtype of notifications a server sends, see
+/// [`ServerHandle::notify`](crate::ServerHandle::notify).
+///
+/// These are the subtypes of [`Nts`] without [`Nts::Other`], so a server can't be asked
+/// to send an unknown subtype.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NotificationSubtype {
+    /// `ssdp:alive`
+    Alive,
+    /// `ssdp:byebye`
+    ByeBye,
+    /// `ssdp:update`
+    Update,
+}
+
+impl NotificationSubtype {
+    const ALL: [NotificationSubtype; 3] = [
+        NotificationSubtype::Alive,
+        NotificationSubtype::ByeBye,
+        NotificationSubtype::Update,
+    ];
+
+    /// The value of the `NTS` header.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NotificationSubtype::Alive => "ssdp:alive",
+            NotificationSubtype::ByeBye => "ssdp:byebye",
+            NotificationSubtype::Update => "ssdp:update",
+        }
+    }
+}
+
+impl From<NotificationSubtype> for Nts {
+    fn from(value: NotificationSubtype) -> Self {
+        match value {
+            NotificationSubtype::Alive => Nts::Alive,
+            NotificationSubtype::ByeBye => Nts::ByeBye,
+            NotificationSubtype::Update => Nts::Update,
+        }
+    }
+}
+
+impl fmt::Display for NotificationSubtype {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Serialized as the value of the `NTS` header.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Nts {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Nts {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        Ok(Nts::from(value.as_str()))
+    }
+}
+
+/// How a control point identified itself in a search, see
+/// [`SearchRequest::control_point`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ControlPoint {
+    /// The product of the control point (`USER-AGENT`), sent by UPnP 1.1 control points.
+    pub user_agent: Option<String>,
+    /// The friendly name of the control point (`CPFN.UPNP.ORG`).
+    pub name: Option<String>,
+    /// The UUID of the control point (`CPUUID.UPNP.ORG`).
+    pub uuid: Option<String>,
+}
+
+impl fmt::Display for ControlPoint {
+    /// Format as the friendly name, falling back to the user agent, then the UUID.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let id = self.name.as_deref().or(self.user_agent.as_deref());
+        f.write_str(id.or(self.uuid.as_deref()).unwrap_or("unknown"))
+    }
+}
+
+/// A validated `M-SEARCH` request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SearchRequest {
+    /// The search target.
+    pub st: String,
+    /// The maximum wait time in seconds, if present.
+    pub mx: Option<u32>,
+    /// All headers of the request, in order of appearance.
+    pub headers: Vec<(String, String)>,
+}
+
+impl SearchRequest {
+    /// Get the value of the first header named `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The friendly name of the control point (`CPFN.UPNP.ORG`), sent by UPnP 2.0
+    /// control points.
+    pub fn control_point_name(&self) -> Option<&str> {
+        self.header("CPFN.UPNP.ORG")
+    }
+
+    /// The UUID of the control point (`CPUUID.UPNP.ORG`), sent by UPnP 2.0 control points.
+    pub fn control_point_uuid(&self) -> Option<&str> {
+        self.header("CPUUID.UPNP.ORG")
+    }
+
+    /// The product of the control point (`USER-AGENT`), sent by UPnP 1.1 control points,
+    /// e.g. `Linux/6.1 UPnP/1.1 acme/1.0`.
+    pub fn user_agent(&self) -> Option<&str> {
+        self.header("USER-AGENT")
+    }
+
+    /// All identification of the control point sent with the search.
+    ///
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::message::SearchRequest;
+    ///
+    /// let request = SearchRequest::parse(
+    ///     b"M-SEARCH * HTTP/1.1\r\n\
+    ///       HOST: 239.255.255.250:1900\r\n\
+    ///       MAN: \"ssdp:discover\"\r\n\
+    ///       MX: 1\r\n\
+    ///       ST: ssdp:all\r\n\
+    ///       USER-AGENT: Linux/6.1 UPnP/2.0 acme/1.0\r\n\
+    ///       CPFN.UPNP.ORG: Living Room TV\r\n\r\n",
+    /// )?;
+    ///
+    /// let control_point = request.control_point();
+    /// assert_eq!(control_point.user_agent.as_deref(), Some("Linux/6.1 UPnP/2.0 acme/1.0"));
+    /// assert_eq!(control_point.to_string(), "Living Room TV");
+    /// assert_eq!(control_point.uuid, None);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn control_point(&self) -> ControlPoint {
+        ControlPoint {
+            user_agent: self.user_agent().map(String::from),
+            name: self.control_point_name().map(String::from),
+            uuid: self.control_point_uuid().map(String::from),
+        }
+    }
+
+    /// Parse and validate an `M-SEARCH` request.
+    ///
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::message::SearchRequest;
+    ///
+    /// let request = SearchRequest {
+    ///     st: "upnp:rootdevice".into(),
+    ///     mx: Some(2),
+    ///     headers: vec![("USER-AGENT".into(), "acme/1.0".into())],
+    /// };
+    ///
+    /// let parsed = SearchRequest::parse(&request.to_bytes())?;
+    /// assert_eq!(parsed.st, "upnp:rootdevice");
+    /// assert_eq!(parsed.mx, Some(2));
+    /// assert_eq!(parsed.header("user-agent"), Some("acme/1.0"));
+    ///
+    /// assert!(SearchRequest::parse(b"M-SEARCH * HTTP/1.1\r\nST: ssdp:all\r\n\r\n").is_err());
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn parse(buf: &[u8]) -> IoResult<Self> {
+        Self::parse_with(buf, &Profile::strict())
+    }
+
+    /// Parse and validate an `M-SEARCH` request, tolerating the malformations of broken
+    /// control points, see [`Server::lenient_parsing`](crate::Server::lenient_parsing).
+    ///
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::message::SearchRequest;
+    ///
+    /// let request = b"m-search * HTTP/1.1\nMAN: ssdp:discover\nMX: 1\nST: ssdp:all\n\0\0";
+    /// assert!(SearchRequest::parse(request).is_err());
+    ///
+    /// let parsed = SearchRequest::parse_lenient(request)?;
+    /// assert_eq!(parsed.st, "ssdp:all");
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn parse_lenient(buf: &[u8]) -> IoResult<Self> {
+        Self::parse_with(buf, &Profile::new().lf_line_endings(true))
+    }
+
+    /// Parse and validate an `M-SEARCH` request, tolerating the quirks of `profile`, see
+    /// [`Profile`].
+    pub fn parse_with(buf: &[u8], profile: &Profile) -> IoResult<Self> {
+        let buf = profile.prepare(buf);
+        with_request(&buf, DEFAULT_MAX_HEADERS, |res, req| {
+            match res {
+                Ok(httparse::Status::Complete(_))
+                    if req.method == Some("M-SEARCH") && req.path == Some("*") => {}
+                Ok(_) => return Err(invalid("not a complete M-SEARCH request")),
+                Err(e) => return Err(invalid(e)),
+            }
+
+            Self::from_request(req, profile)
+        })
+    }
+
+    /// Validate a parsed `M-SEARCH` request, tolerating the quirks of `profile`.
+    pub(crate) fn from_request(
+        req: &httparse::Request<'_, '_>,
+        profile: &Profile,
+    ) -> IoResult<Self> {
+        let headers = collect_headers(req.headers);
+
+        match find_header(&headers, "man") {
+            Some(man) if is_discover(man, profile.accepts_unquoted_man()) => {}
+            Some(man) => return Err(invalid(format!("MAN != \"ssdp:discover\" ({})", man))),
+            None if profile.missing_man => {}
+            None => return Err(invalid("MAN header not found")),
+        }
+
+        let mx = match find_header(&headers, "mx") {
+            Some(mx) => profile.parse_mx(mx).map_err(invalid)?,
+            None => None,
+        };
+
+        let Some(st) = find_header(&headers, "st") else {
+            return Err(invalid("ST header not found"));
+        };
+
+        Ok(Self {
+            st: st.to_string(),
+            mx,
+            headers,
+        })
+    }
+
+    /// Format the request, with `HOST` defaulting to the SSDP multicast address.
+    ///
+    /// `headers` are written in order, with the values of the fields of the request
+    /// replacing those of their headers. Fields without a header are written first.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let host = self
+            .header("host")
+            .map_or_else(|| format!("{}:{}", SSDP_ADDR_V4, SSDP_PORT), String::from);
+
+        let mut typed = vec![
+            (Header::Host.name(), host),
+            ("MAN", "\"ssdp:discover\"".to_string()),
+        ];
+        if let Some(mx) = self.mx {
+            typed.push(("MX", mx.to_string()));
+        }
+        typed.push((Header::St.name(), self.st.clone()));
+
+        serialize("M-SEARCH * HTTP/1.1", &typed, &self.headers)
+    }
+}
+
+/// A response to an `M-SEARCH` request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResponse {
+    /// The search target of the device.
+    pub st: String,
+    /// The unique service name of the device.
+    pub usn: String,
+    /// The URL of the description of the device.
+    pub location: String,
+    /// How long the response is valid, from `CACHE-CONTROL: max-age`.
+    pub max_age: Option<u64>,
+    /// All headers of the response, in order of appearance.
+    pub headers: Vec<(String, String)>,
+}
+
+impl SearchResponse {
+    /// Get the value of the first header named `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// Parse a search response.
+    ///
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::message::SearchResponse;
+    ///
+    /// let response = SearchResponse::parse(
+    ///     b"HTTP/1.1 200 OK\r\n\
+    ///       CACHE-CONTROL: max-age=1800\r\n\
+    ///       EXT:\r\n\
+    ///       LOCATION: http://192.168.1.100:8080/desc.xml\r\n\
+    ///       ST: upnp:rootdevice\r\n\
+    ///       USN: uuid:ad8782a0-9e28-422b-a6ae-670fe7c4c043::upnp:rootdevice\r\n\r\n",
+    /// )?;
+    /// assert_eq!(response.max_age, Some(1800));
+    /// assert_eq!(SearchResponse::parse(&response.to_bytes())?, response);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn parse(buf: &[u8]) -> IoResult<Self> {
+        let headers = with_response(buf, DEFAULT_MAX_HEADERS, |res, response| match res {
+            Ok(httparse::Status::Complete(_)) if response.code == Some(200) => {
+                Ok(collect_headers(response.headers))
+            }
+            Ok(_) => Err(invalid("not a complete 200 OK response")),
+            Err(e) => Err(invalid(e)),
+        })?;
+
+        let required = |name| {
+            find_header(&headers, name)
+                .map(String::from)
+                .ok_or_else(|| invalid(format!("{} header not found", name.to_uppercase())))
+        };
+
+        Ok(Self {
+            st: required("st")?,
+            usn: required("usn")?,
+            location: required("location")?,
+            max_age: find_header(&headers, "cache-control").and_then(parse_max_age),
+            headers,
+        })
+    }
+
+    /// Check that the response has the fields the specification requires, and that they
+    /// are well-formed: a `CACHE-CONTROL` header with a `max-age`, a non-empty `ST`, a
+    /// `USN` starting with `uuid:`, and a `LOCATION` which is an absolute `http` URL.
+    ///
+    /// [`SearchResponse::parse`] only requires `ST`, `USN` and `LOCATION` headers, to
+    /// accept the responses of broken devices.
+    ///
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::message::SearchResponse;
+    ///
+    /// let response = SearchResponse::parse(
+    ///     b"HTTP/1.1 200 OK\r\n\
+    ///       LOCATION: /desc.xml\r\n\
+    ///       ST: upnp:rootdevice\r\n\
+    ///       USN: uuid:ad8782a0-9e28-422b-a6ae-670fe7c4c043::upnp:rootdevice\r\n\r\n",
+    /// )?;
+    /// assert!(response.validate().is_err());
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn validate(&self) -> IoResult<()> {
+        if self.max_age.is_none() {
+            return Err(invalid("CACHE-CONTROL header with max-age not found"));
+        }
+        if self.st.trim().is_empty() {
+            return Err(invalid("empty ST header"));
+        }
+        if !self
+            .usn
+            .get(..5)
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case("uuid:"))
+            || self.usn.len() == 5
+        {
+            return Err(invalid(format!("invalid USN {}", self.usn)));
+        }
+
+        let host = ["http://", "https://"].iter().find_map(|scheme| {
+            self.location
+                .get(..scheme.len())
+                .filter(|prefix| prefix.eq_ignore_ascii_case(scheme))
+                .map(|_| &self.location[scheme.len()..])
+        });
+        if host.is_none_or(|rest| rest.is_empty() || rest.starts_with('/')) {
+            return Err(invalid(format!("invalid LOCATION {}", self.location)));
+        }
+        Ok(())
+    }
+
+    /// Format the response, ordering headers as [`SearchRequest::to_bytes`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut typed = vec![];
+        if let Some(max_age) = self.max_age {
+            typed.push((Header::CacheControl.name(), format!("max-age={}", max_age)));
+        }
+        typed.push((Header::Location.name(), self.location.clone()));
+        typed.push((Header::St.name(), self.st.clone()));
+        typed.push((Header::Usn.name(), self.usn.clone()));
+
+        serialize("HTTP/1.1 200 OK", &typed, &self.headers)
+    }
+}
+
+/// A `NOTIFY` message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notify {
+    /// The notification type, i.e. the search target of the device.
+    pub nt: String,
+    /// The notification subtype.
+    pub nts: Nts,
+    /// The unique service name of the device.
+    pub usn: String,
+    /// The URL of the description of the device, absent in `ssdp:byebye`.
+    pub location: Option<String>,
+    /// How long the announcement is valid, from `CACHE-CONTROL: max-age`.
+    pub max_age: Option<u64>,
+    /// All headers of the message, in order of appearance.
+    pub headers: Vec<(String, String)>,
+}
+
+impl Notify {
+    /// Get the value of the first header named `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// Parse a `NOTIFY` message.
+    ///
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{Nts, message::Notify};
+    ///
+    /// let notify = Notify {
+    ///     nt: "upnp:rootdevice".into(),
+    ///     nts: Nts::ByeBye,
+    ///     usn: "uuid:ad8782a0-9e28-422b-a6ae-670fe7c4c043::upnp:rootdevice".into(),
+    ///     location: None,
+    ///     max_age: None,
+    ///     headers: vec![],
+    /// };
+    ///
+    /// let parsed = Notify::parse(&notify.to_bytes())?;
+    /// assert_eq!(parsed.nts, Nts::ByeBye);
+    /// assert_eq!(parsed.usn, notify.usn);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn parse(buf: &[u8]) -> IoResult<Self> {
+        with_request(buf, DEFAULT_MAX_HEADERS, |res, req| {
+            match res {
+                Ok(httparse::Status::Complete(_))
+                    if req.method == Some("NOTIFY") && req.path == Some("*") => {}
+                Ok(_) => return Err(invalid("not a complete NOTIFY message")),
+                Err(e) => return Err(invalid(e)),
+            }
+
+            Self::from_request(req)
+        })
+    }
+
+    /// Validate a parsed `NOTIFY` message.
+    pub(crate) fn from_request(req: &httparse::Request<'_, '_>) -> IoResult<Self> {
+        let headers = collect_headers(req.headers);
+        let required = |name| {
+            find_header(&headers, name)
+                .map(String::from)
+                .ok_or_else(|| invalid(format!("{} header not found", name.to_uppercase())))
+        };
+
+        Ok(Self {
+            nt: required("nt")?,
+            nts: Nts::from(required("nts")?.as_str()),
+            usn: required("usn")?,
+            location: find_header(&headers, "location").map(String::from),
+            max_age: find_header(&headers, "cache-control").and_then(parse_max_age),
+            headers,
+        })
+    }
+
+    /// Format the message, with `HOST` defaulting to the SSDP multicast address, ordering
+    /// headers as [`SearchRequest::to_bytes`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let host = self
+            .header("host")
+            .map_or_else(|| format!("{}:{}", SSDP_ADDR_V4, SSDP_PORT), String::from);
+
+        let mut typed = vec![(Header::Host.name(), host)];
+        if let Some(max_age) = self.max_age {
+            typed.push((Header::CacheControl.name(), format!("max-age={}", max_age)));
+        }
+        if let Some(location) = &self.location {
+            typed.push((Header::Location.name(), location.clone()));
+        }
+        typed.push((Header::Nt.name(), self.nt.clone()));
+        typed.push((Header::Nts.name(), self.nts.to_string()));
+        typed.push((Header::Usn.name(), self.usn.clone()));
+
+        serialize("NOTIFY * HTTP/1.1", &typed, &self.headers)
+    }
+}
+
+fn invalid(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Error {
+    Error::new(ErrorKind::InvalidData, error)
+}
+
+/// Parse the request in `buf` with up to `max_headers` headers, and pass the result to `f`.
+fn with_request<T>(
+    buf: &[u8],
+    max_headers: usize,
+    f: impl FnOnce(httparse::Result<usize>, &httparse::Request<'_, '_>) -> T,
+) -> T {
+    // Most messages fit the headers on the stack, retry with more otherwise
+    let mut headers = [httparse::EMPTY_HEADER; INLINE_HEADERS];
+    let mut req = httparse::Request::new(&mut headers[..max_headers.min(INLINE_HEADERS)]);
+    match req.parse(buf) {
+        Err(httparse::Error::TooManyHeaders) if max_headers > INLINE_HEADERS => {
+            let mut headers = vec![httparse::EMPTY_HEADER; max_headers];
+            let mut req = httparse::Request::new(&mut headers);
+            let res = req.parse(buf);
+            f(res, &req)
+        }
+        res => f(res, &req),
+    }
+}
+
+/// Parse the response in `buf` with up to `max_headers` headers, and pass the result to
+/// `f`.
+fn with_response<T>(
+    buf: &[u8],
+    max_headers: usize,
+    f: impl FnOnce(httparse::Result<usize>, &httparse::Response<'_, '_>) -> T,
+) -> T {
+    let mut headers = [httparse::EMPTY_HEADER; INLINE_HEADERS];
+    let mut res = httparse::Response::new(&mut headers[..max_headers.min(INLINE_HEADERS)]);
+    match res.parse(buf) {
+        Err(httparse::Error::TooManyHeaders) if max_headers > INLINE_HEADERS => {
+            let mut headers = vec![httparse::EMPTY_HEADER; max_headers];
+            let mut response = httparse::Response::new(&mut headers);
+            let parsed = response.parse(buf);
+            f(parsed, &response)
+        }
+        parsed => f(parsed, &res),
+    }
+}
+
+fn collect_headers(headers: &[httparse::Header<'_>]) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|h| {
+            let value = String::from_utf8_lossy(h.value).trim().to_string();
+            (h.name.to_string(), value)
+        })
+        .collect()
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+/// Format a message from its start line, the values of its typed fields, and `headers`.
+///
+/// `headers` keep their order, with the values of typed fields replacing those of their
+/// headers. Typed fields without a header come first.
+fn serialize(start_line: &str, typed: &[(&str, String)], headers: &[(String, String)]) -> Vec<u8> {
+    let typed_value = |name: &str| {
+        typed
+            .iter()
+            .find(|(typed, _)| typed.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    };
+
+    let mut message = format!("{}\r\n", start_line);
+    for (name, value) in typed {
+        if find_header(headers, name).is_none() {
+            message.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    for (idx, (name, value)) in headers.iter().enumerate() {
+        let value = match typed_value(name) {
+            // Repeated typed headers are written once
+            Some(_)
+                if headers[..idx]
+                    .iter()
+                    .any(|(n, _)| n.eq_ignore_ascii_case(name)) =>
+            {
+                continue;
+            }
+            Some(typed) => typed,
+            None => value,
+        };
+        message.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    message.push_str("\r\n");
+    message.into_bytes()
+}
+
+/// Rewrite a request into the form expected by [`httparse`]: without NUL bytes, with
+/// `CRLF` line endings, an upper case method, and terminated by an empty line.
+pub(crate) fn normalize_request(buf: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(buf.len() + 8);
+    for &b in buf.iter().filter(|&&b| b != 0) {
+        if b == b'\n' && out.last() != Some(&b'\r') {
+            out.push(b'\r');
+        }
+        out.push(b);
+    }
+
+    let method_len = out.iter().position(|&b| b == b' ').unwrap_or(0);
+    out[..method_len].make_ascii_uppercase();
+
+    if !out.windows(4).any(|w| w == b"\r\n\r\n") {
+        if !out.ends_with(b"\r\n") {
+            out.extend_from_slice(b"\r\n");
+        }
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
+
+/// Whether `man` is `"ssdp:discover"`, also without quotes and with extra whitespace if
+/// `lenient` is set.
+fn is_discover(man: &str, lenient: bool) -> bool {
+    if man == "\"ssdp:discover\"" {
+        return true;
+    }
+
+    if !lenient {
+        return false;
+    }
+
+    let man = man.trim();
+    let man = man
+        .strip_prefix('"')
+        .and_then(|m| m.strip_suffix('"'))
+        .unwrap_or(man);
+
+    man.trim() == "ssdp:discover"
+}
+
+/// Options for formatting SSDP messages.
+///
+/// These are the same options used by [`Server`](crate::Server), for use with
+/// [`build_search_response`], [`build_alive`] and [`build_byebye`] by projects
+/// doing their own UDP handling.
+#[derive(Debug, Clone)]
+pub struct MessageOptions {
+    pub(crate) server_name: Option<String>,
+    pub(crate) http_version: Option<String>,
+    pub(crate) upnp_version: UpnpVersion,
+    pub(crate) max_age: u64,
+    pub(crate) content_length: bool,
+    pub(crate) header_order: Vec<Header>,
+    pub(crate) extra_headers: Vec<(String, String)>,
+    pub(crate) multicast: SocketAddrV4,
+}
+
+impl Default for MessageOptions {
+    fn default() -> Self {
+        Self {
+            server_name: None,
+            http_version: None,
+            upnp_version: UpnpVersion::default(),
+            max_age: 100,
+            content_length: false,
+            header_order: vec![],
+            extra_headers: vec![],
+            multicast: SocketAddrV4::new(SSDP_ADDR_V4, SSDP_PORT),
+        }
+    }
+}
+
+impl MessageOptions {
+    /// Create the default message options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the value of the `SERVER` header, defaults to `Tokio-SSDP/1.0 UPnP/<version>`.
+    pub fn server_name(mut self, server_name: impl Into<String>) -> Self {
+        self.server_name = Some(server_name.into());
+        self
+    }
+
+    /// Set the version token used in the status and request lines, defaults to `HTTP/1.1`.
+    pub fn http_version(mut self, http_version: impl Into<String>) -> Self {
+        self.http_version = Some(http_version.into());
+        self
+    }
+
+    /// Set the UPnP version in the default `SERVER` header, defaults to 1.0.
+    pub fn upnp_version(mut self, upnp_version: UpnpVersion) -> Self {
+        self.upnp_version = upnp_version;
+        self
+    }
+
+    /// Set the value of `Cache-Control: max-age=`, defaults to 100.
+    pub fn max_age(mut self, max_age: u64) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Add `CONTENT-LENGTH: 0` to messages.
+    pub fn content_length(mut self, content_length: bool) -> Self {
+        self.content_length = content_length;
+        self
+    }
+
+    /// Set the order of the standard headers, see [`Server::header_order`](crate::Server::header_order).
+    pub fn header_order(mut self, order: impl IntoIterator<Item = Header>) -> Self {
+        self.header_order = order.into_iter().collect();
+        self
+    }
+
+    /// Add an extra header, with `{uuid}` and `{location}` expanded for each device.
+    pub fn extra_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.extra_headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Set the multicast group and port named in the `HOST` header of notifications,
+    /// defaults to `239.255.255.250:1900`.
+    pub fn multicast_endpoint(mut self, addr: Ipv4Addr, port: u16) -> Self {
+        self.multicast = SocketAddrV4::new(addr, port);
+        self
+    }
+
+    pub(crate) fn server_header(&self) -> &str {
+        self.server_name
+            .as_deref()
+            .unwrap_or(self.upnp_version.default_server_name())
+    }
+
+    pub(crate) fn version_token(&self) -> &str {
+        self.http_version.as_deref().unwrap_or(DEFAULT_HTTP_VERSION)
+    }
+
+    fn expand_extra_headers(&self, kind: MessageKind, device: &Device) -> String {
+        let location = device.current_location();
+        let vars = [
+            ("uuid", device.uuid.as_deref().unwrap_or_default()),
+            ("location", &*location),
+        ];
+
+        let headers: String = self
+            .extra_headers
+            .iter()
+            .map(|(name, value)| format!("{}: {}\r\n", name, value))
+            .collect();
+        expand_placeholders(&device.merge_headers(kind, &headers), &vars).into_owned()
+    }
+}
+
+/// Format the response to an `M-SEARCH` request for `device`.
+///
+/// # Examples
+/// ```
+/// use tokio_ssdp::{Device, MessageOptions, build_search_response};
+///
+/// let device = Device::new("ad8782a0-9e28-422b-a6ae-670fe7c4c043", "upnp:rootdevice", "http://192.168.1.100:8080/desc.xml");
+/// let response = build_search_response(&device, &MessageOptions::new().max_age(1800));
+///
+/// assert!(response.starts_with(b"HTTP/1.1 200 OK\r\n"));
+/// ```
+pub fn build_search_response(device: &Device, opts: &MessageOptions) -> Vec<u8> {
+    let extra_headers = opts.expand_extra_headers(MessageKind::SearchResponse, device);
+    let max_age = device.max_age.unwrap_or(opts.max_age);
+    let date = http_date(SystemTime::now());
+    search_response(
+        opts,
+        device,
+        max_age,
+        &device.current_location(),
+        &date,
+        &extra_headers,
+    )
+    .into_bytes()
+}
+
+/// Format the `ssdp:alive` notification for `device`.
+pub fn build_alive(device: &Device, opts: &MessageOptions) -> Vec<u8> {
+    let extra_headers = opts.expand_extra_headers(MessageKind::Alive, device);
+    let max_age = device.max_age.unwrap_or(opts.max_age);
+    alive(
+        opts,
+        device,
+        max_age,
+        &device.current_location(),
+        &extra_headers,
+    )
+    .into_bytes()
+}
+
+/// Format the `ssdp:byebye` notification for `device`.
+pub fn build_byebye(device: &Device, opts: &MessageOptions) -> Vec<u8> {
+    let extra_headers = opts.expand_extra_headers(MessageKind::Byebye, device);
+    byebye(opts, device, &extra_headers).into_bytes()
+}
+
+pub(crate) fn search_response(
+    opts: &MessageOptions,
+    device: &Device,
+    max_age: u64,
+    location: &str,
+    date: &str,
+    extra_headers: &str,
+) -> String {
+    finish(
+        opts,
+        &format!("{} 200 OK", opts.version_token()),
+        device,
+        vec![
+            (Header::CacheControl, format!("max-age={}", max_age)),
+            (Header::Date, date.to_string()),
+            (Header::Ext, String::new()),
+            (Header::Location, location.to_string()),
+            (Header::Server, opts.server_header().to_string()),
+            (Header::St, device.search_target.clone()),
+            (Header::Usn, device.usn.clone()),
+        ],
+        extra_headers,
+    )
+}
+
+pub(crate) fn alive(
+    opts: &MessageOptions,
+    device: &Device,
+    max_age: u64,
+    location: &str,
+    extra_headers: &str,
+) -> String {
+    finish(
+        opts,
+        &format!("NOTIFY * {}", opts.version_token()),
+        device,
+        vec![
+            (Header::Host, opts.multicast.to_string()),
+            (Header::CacheControl, format!("max-age={}", max_age)),
+            (Header::Location, location.to_string()),
+            (Header::Nt, device.search_target.clone()),
+            (Header::Nts, Nts::Alive.to_string()),
+            (Header::Server, opts.server_header().to_string()),
+            (Header::Usn, device.usn.clone()),
+        ],
+        extra_headers,
+    )
+}
+
+pub(crate) fn byebye(opts: &MessageOptions, device: &Device, extra_headers: &str) -> String {
+    finish(
+        opts,
+        &format!("NOTIFY * {}", opts.version_token()),
+        device,
+        vec![
+            (Header::Host, opts.multicast.to_string()),
+            (Header::Nt, device.search_target.clone()),
+            (Header::Nts, Nts::ByeBye.to_string()),
+            (Header::Usn, device.usn.clone()),
+        ],
+        extra_headers,
+    )
+}
+
+pub(crate) fn update(
+    opts: &MessageOptions,
+    device: &Device,
+    location: &str,
+    next_boot_id: u32,
+    extra_headers: &str,
+) -> String {
+    finish(
+        opts,
+        &format!("NOTIFY * {}", opts.version_token()),
+        device,
+        vec![
+            (Header::Host, opts.multicast.to_string()),
+            (Header::Location, location.to_string()),
+            (Header::Nt, device.search_target.clone()),
+            (Header::Nts, Nts::Update.to_string()),
+            (Header::Usn, device.usn.clone()),
+        ],
+        &format!("{}NEXTBOOTID.UPNP.ORG: {}\r\n", extra_headers, next_boot_id),
+    )
+}
+
+/// The `max-age` directive of a `CACHE-CONTROL` header value.
+pub(crate) fn parse_max_age(cache_control: &str) -> Option<u64> {
+    cache_control.split(',').find_map(|directive| {
+        let (name, value) = directive.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("max-age")
+            .then(|| value.trim().trim_matches('"').parse().ok())?
+    })
+}
+
+/// A `412 Precondition Failed` response to an invalid `M-SEARCH` request, with the
+/// reason in an `X-SSDP-ERROR` header.
+pub(crate) fn error_response(opts: &MessageOptions, reason: &str) -> String {
+    let reason = reason.replace(['\r', '\n'], " ");
+    format!(
+        "{} 412 Precondition Failed\r\n{}: {}\r\n{}: 0\r\nX-SSDP-ERROR: {}\r\n\r\n",
+        opts.version_token(),
+        Header::Server,
+        opts.server_header(),
+        Header::ContentLength,
+        reason
+    )
+}
+
+fn finish(
+    opts: &MessageOptions,
+    start_line: &str,
+    device: &Device,
+    mut headers: Vec<(Header, String)>,
+    extra_headers: &str,
+) -> String {
+    if opts.content_length {
+        headers.push((Header::ContentLength, "0".to_string()));
+    }
+
+    let order = device.header_order.as_deref().unwrap_or(&opts.header_order);
+
+    let mut message = format!("{}\r\n", start_line);
+    write_headers(&mut message, &headers, order);
+    message.push_str(extra_headers);
+    message.push_str("\r\n");
+    message
+}
+
+/// Format `time` as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+#[cfg(feature = "httpdate")]
+pub(crate) fn http_date(time: SystemTime) -> String {
+    httpdate::fmt_http_date(time)
+}
+
+/// Format `time` as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+#[cfg(not(feature = "httpdate"))]
+pub(crate) fn http_date(time: SystemTime) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let secs = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_headers(start: &str, required: &str, count: usize) -> Vec<u8> {
+        let mut message = format!("{}\r\n{}", start, required);
+        for i in 0..count {
+            message.push_str(&format!("X-HEADER-{}: {}\r\n", i, i));
+        }
+        message.push_str("\r\n");
+        message.into_bytes()
+    }
+
+    #[test]
+    fn parses_up_to_max_headers() {
+        let search =
+            "HOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 1\r\nST: ssdp:all\r\n";
+        let request = SearchRequest::parse(&with_headers("M-SEARCH * HTTP/1.1", search, 40));
+        assert_eq!(request.unwrap().headers.len(), 44);
+        let request = SearchRequest::parse(&with_headers("M-SEARCH * HTTP/1.1", search, 61));
+        assert!(request.is_err());
+
+        let response = "CACHE-CONTROL: max-age=1800\r\nLOCATION: http://192.0.2.1/desc.xml\r\n\
+                        ST: upnp:rootdevice\r\nUSN: uuid:1::upnp:rootdevice\r\n";
+        let parsed = SearchResponse::parse(&with_headers("HTTP/1.1 200 OK", response, 40));
+        assert_eq!(parsed.unwrap().headers.len(), 44);
+
+        let notify = "HOST: 239.255.255.250:1900\r\nNT: upnp:rootdevice\r\nNTS: ssdp:byebye\r\n\
+                      USN: uuid:1::upnp:rootdevice\r\n";
+        let parsed = Notify::parse(&with_headers("NOTIFY * HTTP/1.1", notify, 40));
+        assert_eq!(parsed.unwrap().headers.len(), 44);
+    }
+
+    #[test]
+    fn parses_up_to_max_headers_below_inline() {
+        let search =
+            "HOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 1\r\nST: ssdp:all\r\n";
+        let parse = |extra, max_headers| {
+            let request = with_headers("M-SEARCH * HTTP/1.1", search, extra);
+            with_request(&request, max_headers, |res, req| {
+                res.map(|_| req.headers.len()).ok()
+            })
+        };
+        assert_eq!(parse(4, 8), Some(8));
+        assert_eq!(parse(5, 8), None);
+
+        let response = "CACHE-CONTROL: max-age=1800\r\nLOCATION: http://192.0.2.1/desc.xml\r\n\
+                        ST: upnp:rootdevice\r\nUSN: uuid:1::upnp:rootdevice\r\n";
+        let response = with_headers("HTTP/1.1 200 OK", response, 5);
+        let parsed = with_response(&response, 8, |res, _| res.is_ok());
+        assert!(!parsed);
+    }
+}
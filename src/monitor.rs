@@ -0,0 +1,163 @@
+use std::{
+    future::poll_fn,
+    io::Result as IoResult,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use futures_core::Stream;
+use tokio::{io::ReadBuf, net::UdpSocket};
+
+use crate::{Nts, SSDP_ADDR_V4, SSDP_PORT, Server, logging::trace, message};
+
+/// A `NOTIFY` message sent by a device on the network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Notification {
+    /// The address of the device which sent the notification.
+    pub remote_addr: SocketAddr,
+    /// The notification type, i.e. the search target of the device.
+    pub nt: String,
+    /// The notification subtype.
+    pub nts: Nts,
+    /// The unique service name of the device.
+    pub usn: String,
+    /// The URL of the description of the device, absent in `ssdp:byebye`.
+    pub location: Option<String>,
+    /// How long the announcement is valid, from `CACHE-CONTROL: max-age`.
+    pub max_age: Option<u64>,
+    /// All headers of the notification, in order of appearance.
+    pub headers: Vec<(String, String)>,
+}
+
+impl Notification {
+    /// Get the value of the first header named `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Parse a datagram received from `remote_addr`, returning `None` if it is not a
+    /// valid `NOTIFY` message.
+    pub(crate) fn parse(buf: &[u8], remote_addr: SocketAddr) -> Option<Self> {
+        let notify = message::Notify::parse(buf).ok()?;
+
+        Some(Self {
+            remote_addr,
+            nt: notify.nt,
+            nts: notify.nts,
+            usn: notify.usn,
+            location: notify.location,
+            max_age: notify.max_age,
+            headers: notify.headers,
+        })
+    }
+}
+
+/// A passive listener for the `NOTIFY` messages of devices on the network.
+///
+/// The SSDP port is shared, so a monitor can run next to a [`Server`] on the same host.
+#[derive(Debug, Clone)]
+pub struct Monitor {
+    ip: Ipv4Addr,
+    multicast: SocketAddrV4,
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self {
+            ip: Ipv4Addr::UNSPECIFIED,
+            multicast: SocketAddrV4::new(SSDP_ADDR_V4, SSDP_PORT),
+        }
+    }
+}
+
+impl Monitor {
+    /// Create a new monitor.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::{Monitor, Nts};
+    ///
+    /// let mut notifications = Monitor::new().listen()?;
+    /// while let Some(notification) = notifications.next().await {
+    ///     let notification = notification?;
+    ///     if notification.nts == Nts::ByeBye {
+    ///         println!("{} left", notification.usn);
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Listen on the interface with address `ip`, defaults to the default multicast
+    /// interface.
+    pub fn bind_addr(mut self, ip: Ipv4Addr) -> Self {
+        self.ip = ip;
+        self
+    }
+
+    /// Listen on the multicast group `addr` and port `port` instead of
+    /// `239.255.255.250:1900`, see [`Server::multicast_endpoint`].
+    pub fn multicast_endpoint(mut self, addr: Ipv4Addr, port: u16) -> Self {
+        self.multicast = SocketAddrV4::new(addr, port);
+        self
+    }
+
+    /// Join the SSDP multicast group, returning a stream of the received notifications.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn listen(&self) -> IoResult<Notifications> {
+        let socket = Server::new_rx_socket(self.multicast.port(), true)?;
+        socket.set_nonblocking(true)?;
+        socket.join_multicast_v4(self.multicast.ip(), &self.ip)?;
+        socket.set_multicast_loop_v4(true)?;
+
+        Ok(Notifications {
+            socket: UdpSocket::from_std(socket)?,
+            buf: vec![0; 2048],
+        })
+    }
+}
+
+/// The notifications received by a [`Monitor`].
+#[derive(Debug)]
+pub struct Notifications {
+    socket: UdpSocket,
+    buf: Vec<u8>,
+}
+
+impl Notifications {
+    /// Wait for the next notification.
+    pub async fn next(&mut self) -> Option<IoResult<Notification>> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl Stream for Notifications {
+    type Item = IoResult<Notification>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let mut buf = ReadBuf::new(&mut this.buf);
+            let remote_addr = match ready!(this.socket.poll_recv_from(cx, &mut buf)) {
+                Ok(remote_addr) => remote_addr,
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            };
+
+            // Searches from control points are received as well
+            match Notification::parse(buf.filled(), remote_addr) {
+                Some(notification) => return Poll::Ready(Some(Ok(notification))),
+                None => trace!("Ignoring datagram from {}", remote_addr),
+            }
+        }
+    }
+}
@@ -0,0 +1,598 @@
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr},
+};
+
+use crate::{Transport, transport};
+
+/// Where a datagram was received, as reported by `IP_PKTINFO` and `IP_RECVTTL` on Linux
+/// and Android, and by `IP_RECVDSTADDR`, `IP_RECVIF` and `IP_RECVTTL` on the BSDs and
+/// Apple platforms.
+///
+/// Not available on other platforms, such as Windows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketInfo {
+    /// The index of the interface the datagram was received on.
+    pub interface_index: u32,
+    /// The local address of the receiving interface.
+    pub local_addr: Ipv4Addr,
+    /// The destination address of the datagram, e.g. the SSDP multicast address.
+    pub destination: Ipv4Addr,
+    /// The remaining TTL of the datagram. Not available on NetBSD and OpenBSD.
+    pub ttl: Option<u8>,
+}
+
+/// Whether [`send_to`] can choose the source address of datagrams.
+pub(crate) const SENDS_FROM: bool = cfg!(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+));
+
+/// Ask the kernel to report the receiving interface and TTL of datagrams on `socket`.
+///
+/// Does nothing on platforms without `IP_PKTINFO` or `IP_RECVIF`.
+pub(crate) fn enable(socket: &std::net::UdpSocket) -> io::Result<()> {
+    imp::enable(socket)
+}
+
+/// Like [`UdpSocket::send_to`](tokio::net::UdpSocket::send_to), sending from the interface and address `from` was received
+/// on, e.g. to answer a search from the interface it arrived on.
+///
+/// Falls back to a plain send without `from`, on transports other than UDP sockets, and on
+/// platforms without `IP_PKTINFO` or `IP_SENDSRCADDR`, including Apple platforms. With
+/// `IP_SENDSRCADDR`, only the source address is chosen, not the interface.
+pub(crate) async fn send_to(
+    transport: &dyn Transport,
+    buf: &[u8],
+    target: SocketAddr,
+    from: Option<PacketInfo>,
+) -> io::Result<usize> {
+    match (transport.udp_socket(), from) {
+        (Some(socket), Some(from)) => imp::send_from(socket, buf, target, from).await,
+        _ => transport::send_to(transport, buf, target).await,
+    }
+}
+
+/// Like [`UdpSocket::recv_from`](tokio::net::UdpSocket::recv_from), also returning the receiving interface if available.
+pub(crate) async fn recv_from(
+    transport: &dyn Transport,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<PacketInfo>)> {
+    match transport.udp_socket() {
+        Some(socket) => imp::recv_from(socket, buf).await,
+        None => {
+            let (n, addr) = transport::recv_from(transport, buf).await?;
+            Ok((n, addr, None))
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod imp {
+    use std::{
+        io,
+        mem::{size_of, size_of_val},
+        net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+        os::unix::io::{AsRawFd, RawFd},
+        ptr,
+    };
+
+    use tokio::{io::Interest, net::UdpSocket};
+
+    use super::{PacketInfo, sys};
+
+    pub(super) fn enable(socket: &std::net::UdpSocket) -> io::Result<()> {
+        sys::set_option(socket, libc::IP_PKTINFO)?;
+        sys::set_option(socket, libc::IP_RECVTTL)
+    }
+
+    pub(super) async fn recv_from(
+        socket: &UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<PacketInfo>)> {
+        let fd = socket.as_raw_fd();
+        socket
+            .async_io(Interest::READABLE, || recvmsg(fd, buf))
+            .await
+    }
+
+    pub(super) async fn send_from(
+        socket: &UdpSocket,
+        buf: &[u8],
+        target: SocketAddr,
+        from: PacketInfo,
+    ) -> io::Result<usize> {
+        let SocketAddr::V4(target) = target else {
+            return socket.send_to(buf, target).await;
+        };
+        let fd = socket.as_raw_fd();
+        socket
+            .async_io(Interest::WRITABLE, || sendmsg(fd, buf, target, from))
+            .await
+    }
+
+    fn sendmsg(fd: RawFd, buf: &[u8], target: SocketAddrV4, from: PacketInfo) -> io::Result<usize> {
+        // u64 for the alignment required by `cmsghdr`
+        let mut control = [0u64; 4];
+
+        // SAFETY: all pointers in `msg` refer to locals which outlive the call, with
+        // lengths matching their sizes. The control message is written via the `CMSG_*`
+        // macros, within the space reserved with `CMSG_SPACE`.
+        unsafe {
+            let mut name = sys::sockaddr_in(target);
+
+            let mut iov = libc::iovec {
+                iov_base: buf.as_ptr().cast_mut().cast(),
+                iov_len: buf.len(),
+            };
+
+            let space = libc::CMSG_SPACE(size_of::<libc::in_pktinfo>() as u32) as usize;
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_name = ptr::addr_of_mut!(name).cast();
+            msg.msg_namelen = size_of_val(&name) as libc::socklen_t;
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr().cast();
+            msg.msg_controllen = space as _;
+
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::IPPROTO_IP;
+            (*cmsg).cmsg_type = libc::IP_PKTINFO;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<libc::in_pktinfo>() as u32) as _;
+            let pktinfo = libc::in_pktinfo {
+                ipi_ifindex: from.interface_index as _,
+                ipi_spec_dst: libc::in_addr {
+                    s_addr: u32::from(from.local_addr).to_be(),
+                },
+                ipi_addr: libc::in_addr { s_addr: 0 },
+            };
+            ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast(), pktinfo);
+
+            let n = libc::sendmsg(fd, &msg, 0);
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(n as usize)
+        }
+    }
+
+    fn recvmsg(fd: RawFd, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Option<PacketInfo>)> {
+        // u64 for the alignment required by `cmsghdr`
+        let mut control = [0u64; 8];
+
+        // SAFETY: all pointers in `msg` refer to locals which outlive the call, with
+        // lengths matching their sizes. Control messages are only read within the
+        // bounds reported by the kernel, via the `CMSG_*` macros.
+        unsafe {
+            let mut name: libc::sockaddr_in = std::mem::zeroed();
+            let mut iov = libc::iovec {
+                iov_base: buf.as_mut_ptr().cast(),
+                iov_len: buf.len(),
+            };
+
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_name = ptr::addr_of_mut!(name).cast();
+            msg.msg_namelen = size_of_val(&name) as libc::socklen_t;
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr().cast();
+            msg.msg_controllen = size_of_val(&control) as _;
+
+            let n = libc::recvmsg(fd, &mut msg, 0);
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let remote_addr = sys::socket_addr(&name);
+
+            let mut info = None;
+            let mut ttl = None;
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                    (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                        let pktinfo: libc::in_pktinfo =
+                            ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast());
+                        info = Some(PacketInfo {
+                            interface_index: pktinfo.ipi_ifindex as u32,
+                            local_addr: Ipv4Addr::from(u32::from_be(pktinfo.ipi_spec_dst.s_addr)),
+                            destination: Ipv4Addr::from(u32::from_be(pktinfo.ipi_addr.s_addr)),
+                            ttl: None,
+                        });
+                    }
+                    (libc::IPPROTO_IP, libc::IP_TTL) => {
+                        let value: libc::c_int = ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast());
+                        ttl = u8::try_from(value).ok();
+                    }
+                    _ => {}
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+
+            let info = info.map(|info| PacketInfo { ttl, ..info });
+            Ok((n as usize, remote_addr, info))
+        }
+    }
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "macos",
+    target_os = "ios"
+))]
+mod sys {
+    use std::{
+        io,
+        mem::size_of,
+        net::{Ipv4Addr, SocketAddr},
+        os::unix::io::AsRawFd,
+        ptr,
+    };
+
+    /// Enable the boolean `IPPROTO_IP` level `option` on `socket`.
+    pub(super) fn set_option(socket: &std::net::UdpSocket, option: libc::c_int) -> io::Result<()> {
+        let on: libc::c_int = 1;
+        // SAFETY: `on` outlives the call, and the length matches its type.
+        let ret = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::IPPROTO_IP,
+                option,
+                ptr::addr_of!(on).cast(),
+                size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    pub(super) fn sockaddr_in(addr: std::net::SocketAddrV4) -> libc::sockaddr_in {
+        // SAFETY: all-zero is a valid `sockaddr_in`.
+        let mut name: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+        name.sin_family = libc::AF_INET as libc::sa_family_t;
+        name.sin_port = addr.port().to_be();
+        name.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+        name
+    }
+
+    pub(super) fn socket_addr(name: &libc::sockaddr_in) -> SocketAddr {
+        SocketAddr::from((
+            Ipv4Addr::from(u32::from_be(name.sin_addr.s_addr)),
+            u16::from_be(name.sin_port),
+        ))
+    }
+}
+
+#[cfg(any(
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "macos",
+    target_os = "ios"
+))]
+mod imp {
+    use std::{
+        ffi::CStr,
+        io,
+        mem::size_of_val,
+        net::{Ipv4Addr, SocketAddr},
+        os::unix::io::{AsRawFd, RawFd},
+        ptr,
+    };
+
+    use tokio::{io::Interest, net::UdpSocket};
+
+    use super::{PacketInfo, sys};
+    use crate::iface;
+
+    pub(super) fn enable(socket: &std::net::UdpSocket) -> io::Result<()> {
+        sys::set_option(socket, libc::IP_RECVDSTADDR)?;
+        sys::set_option(socket, libc::IP_RECVIF)?;
+        #[cfg(not(any(target_os = "netbsd", target_os = "openbsd")))]
+        sys::set_option(socket, libc::IP_RECVTTL)?;
+        Ok(())
+    }
+
+    pub(super) async fn recv_from(
+        socket: &UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<PacketInfo>)> {
+        let fd = socket.as_raw_fd();
+        let (n, remote_addr, destination, index, ttl) = socket
+            .async_io(Interest::READABLE, || recvmsg(fd, buf))
+            .await?;
+
+        let info = destination
+            .zip(index)
+            .map(|(destination, interface_index)| {
+                // Only the destination is reported, which is the local address unless multicast
+                let local_addr = if destination.is_multicast() || destination.is_broadcast() {
+                    interface_addr(interface_index).unwrap_or(Ipv4Addr::UNSPECIFIED)
+                } else {
+                    destination
+                };
+                PacketInfo {
+                    interface_index,
+                    local_addr,
+                    destination,
+                    ttl,
+                }
+            });
+        Ok((n, remote_addr, info))
+    }
+
+    /// The IPv4 address of the interface with index `index`.
+    fn interface_addr(index: u32) -> Option<Ipv4Addr> {
+        let mut name = [0 as libc::c_char; libc::IF_NAMESIZE];
+        // SAFETY: `name` has the `IF_NAMESIZE` bytes required, and is NUL-terminated on
+        // success.
+        let name = unsafe {
+            if libc::if_indextoname(index, name.as_mut_ptr()).is_null() {
+                return None;
+            }
+            CStr::from_ptr(name.as_ptr())
+        };
+        iface::ipv4_addr(name.to_str().ok()?).ok()
+    }
+
+    type Received = (usize, SocketAddr, Option<Ipv4Addr>, Option<u32>, Option<u8>);
+
+    fn recvmsg(fd: RawFd, buf: &mut [u8]) -> io::Result<Received> {
+        // u64 for the alignment required by `cmsghdr`
+        let mut control = [0u64; 16];
+
+        // SAFETY: all pointers in `msg` refer to locals which outlive the call, with
+        // lengths matching their sizes. Control messages are only read within the
+        // bounds reported by the kernel, via the `CMSG_*` macros.
+        unsafe {
+            let mut name: libc::sockaddr_in = std::mem::zeroed();
+            let mut iov = libc::iovec {
+                iov_base: buf.as_mut_ptr().cast(),
+                iov_len: buf.len(),
+            };
+
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_name = ptr::addr_of_mut!(name).cast();
+            msg.msg_namelen = size_of_val(&name) as libc::socklen_t;
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr().cast();
+            msg.msg_controllen = size_of_val(&control) as _;
+
+            let n = libc::recvmsg(fd, &mut msg, 0);
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut destination = None;
+            let mut index = None;
+            let mut ttl = None;
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                    (libc::IPPROTO_IP, libc::IP_RECVDSTADDR) => {
+                        let addr: libc::in_addr = ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast());
+                        destination = Some(Ipv4Addr::from(u32::from_be(addr.s_addr)));
+                    }
+                    (libc::IPPROTO_IP, libc::IP_RECVIF) => {
+                        let addr: libc::sockaddr_dl =
+                            ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast());
+                        index = Some(u32::from(addr.sdl_index));
+                    }
+                    #[cfg(not(any(target_os = "netbsd", target_os = "openbsd")))]
+                    (libc::IPPROTO_IP, libc::IP_RECVTTL) => {
+                        ttl = Some(ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<u8>()));
+                    }
+                    _ => {}
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+
+            Ok((n as usize, sys::socket_addr(&name), destination, index, ttl))
+        }
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    pub(super) async fn send_from(
+        socket: &UdpSocket,
+        buf: &[u8],
+        target: SocketAddr,
+        _from: PacketInfo,
+    ) -> io::Result<usize> {
+        socket.send_to(buf, target).await
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    pub(super) async fn send_from(
+        socket: &UdpSocket,
+        buf: &[u8],
+        target: SocketAddr,
+        from: PacketInfo,
+    ) -> io::Result<usize> {
+        let SocketAddr::V4(target) = target else {
+            return socket.send_to(buf, target).await;
+        };
+        let fd = socket.as_raw_fd();
+        socket
+            .async_io(Interest::WRITABLE, || {
+                sendmsg(fd, buf, target, from.local_addr)
+            })
+            .await
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    fn sendmsg(
+        fd: RawFd,
+        buf: &[u8],
+        target: std::net::SocketAddrV4,
+        from: Ipv4Addr,
+    ) -> io::Result<usize> {
+        use std::mem::size_of;
+
+        // u64 for the alignment required by `cmsghdr`
+        let mut control = [0u64; 4];
+
+        // SAFETY: all pointers in `msg` refer to locals which outlive the call, with
+        // lengths matching their sizes. The control message is written via the `CMSG_*`
+        // macros, within the space reserved with `CMSG_SPACE`.
+        unsafe {
+            let mut name = sys::sockaddr_in(target);
+            let mut iov = libc::iovec {
+                iov_base: buf.as_ptr().cast_mut().cast(),
+                iov_len: buf.len(),
+            };
+
+            let space = libc::CMSG_SPACE(size_of::<libc::in_addr>() as u32) as usize;
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_name = ptr::addr_of_mut!(name).cast();
+            msg.msg_namelen = size_of_val(&name) as libc::socklen_t;
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr().cast();
+            msg.msg_controllen = space as _;
+
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::IPPROTO_IP;
+            (*cmsg).cmsg_type = libc::IP_SENDSRCADDR;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<libc::in_addr>() as u32) as _;
+            let addr = libc::in_addr {
+                s_addr: u32::from(from).to_be(),
+            };
+            ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast(), addr);
+
+            let n = libc::sendmsg(fd, &msg, 0);
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(n as usize)
+        }
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "macos",
+    target_os = "ios"
+)))]
+mod imp {
+    use std::{io, net::SocketAddr};
+
+    use tokio::net::UdpSocket;
+
+    use super::PacketInfo;
+
+    pub(super) fn enable(_socket: &std::net::UdpSocket) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub(super) async fn send_from(
+        socket: &UdpSocket,
+        buf: &[u8],
+        target: SocketAddr,
+        _from: PacketInfo,
+    ) -> io::Result<usize> {
+        socket.send_to(buf, target).await
+    }
+
+    pub(super) async fn recv_from(
+        socket: &UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<PacketInfo>)> {
+        let (n, addr) = socket.recv_from(buf).await?;
+        Ok((n, addr, None))
+    }
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "android")))]
+mod tests {
+    use std::{ffi::CString, net::Ipv4Addr};
+
+    use tokio::net::UdpSocket;
+
+    use super::*;
+
+    async fn receiver() -> UdpSocket {
+        let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        enable(&socket).unwrap();
+        UdpSocket::from_std(socket).unwrap()
+    }
+
+    #[tokio::test]
+    async fn replies_from_receiving_address() {
+        let rx = receiver().await;
+        let port = rx.local_addr().unwrap().port();
+        let tx = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+
+        // Any 127.0.0.0/8 address is local on the loopback interface
+        let destination = Ipv4Addr::new(127, 0, 0, 2);
+        tx.send_to(b"M-SEARCH", (destination, port)).await.unwrap();
+
+        let mut buf = [0; 64];
+        let (n, remote_addr, info) = recv_from(&rx, &mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"M-SEARCH");
+        assert_eq!(remote_addr, tx.local_addr().unwrap());
+
+        let info = info.unwrap();
+        let lo = CString::new("lo").unwrap();
+        // SAFETY: `lo` is a valid NUL-terminated string.
+        let lo_index = unsafe { libc::if_nametoindex(lo.as_ptr()) };
+        assert_eq!(info.interface_index, lo_index);
+        assert_eq!(info.destination, destination);
+        assert_eq!(info.local_addr, destination);
+        assert!(info.ttl.is_some());
+
+        // Without the packet info, the reply would come from 127.0.0.1
+        send_to(&rx, b"HTTP/1.1 200 OK", remote_addr, Some(info))
+            .await
+            .unwrap();
+        let (_, reply_from) = tx.recv_from(&mut buf).await.unwrap();
+        assert_eq!(reply_from, SocketAddr::from((destination, port)));
+    }
+
+    #[tokio::test]
+    async fn replies_from_chosen_address() {
+        let rx = receiver().await;
+        let port = rx.local_addr().unwrap().port();
+        let tx = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        tx.send_to(b"M-SEARCH", (Ipv4Addr::LOCALHOST, port))
+            .await
+            .unwrap();
+
+        let mut buf = [0; 64];
+        let (_, remote_addr, _) = recv_from(&rx, &mut buf).await.unwrap();
+
+        // As for `Server::response_source`, leaving the interface to the routing table
+        let source = Ipv4Addr::new(127, 0, 0, 3);
+        let from = PacketInfo {
+            interface_index: 0,
+            local_addr: source,
+            destination: source,
+            ttl: None,
+        };
+        send_to(&rx, b"HTTP/1.1 200 OK", remote_addr, Some(from))
+            .await
+            .unwrap();
+        let (_, reply_from) = tx.recv_from(&mut buf).await.unwrap();
+        assert_eq!(reply_from, SocketAddr::from((source, port)));
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+/// Above this many tracked sources, sources with full buckets are forgotten.
+const MAX_SOURCES: usize = 1024;
+
+#[derive(Debug, Clone, Copy)]
+struct Rate {
+    per_second: f64,
+    burst: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Refill the bucket until `now`, returning whether it is full.
+    fn refill(&mut self, rate: Rate, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate.per_second).min(rate.burst);
+        self.updated = now;
+        self.tokens >= rate.burst
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    global: Option<Bucket>,
+    sources: HashMap<IpAddr, Bucket>,
+}
+
+/// A limit on the rate of `M-SEARCH` requests a server answers, see
+/// [`Server::search_limit`](crate::Server::search_limit).
+///
+/// Searches are limited with token buckets, per source address and for all sources
+/// combined. Searches exceeding the limit are dropped before any response is scheduled,
+/// so misbehaving control points cannot make the server amplify their traffic.
+///
+/// Clones share the same state, so a `SearchLimit` can be shared by several servers.
+#[derive(Debug, Clone, Default)]
+pub struct SearchLimit {
+    per_source: Option<Rate>,
+    global: Option<Rate>,
+    state: Arc<Mutex<State>>,
+}
+
+impl SearchLimit {
+    /// Create a new, unlimited search limit.
+    ///
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{SearchLimit, Server};
+    ///
+    /// Server::new([]).search_limit(
+    ///     SearchLimit::new()
+    ///         .per_source(2, 10)
+    ///         .global(50, 100),
+    /// );
+    /// ```
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer at most `per_second` searches per second from each source address, with
+    /// bursts of up to `burst` searches.
+    pub fn per_source(mut self, per_second: u32, burst: u32) -> Self {
+        self.per_source = Some(Rate::new(per_second, burst));
+        self
+    }
+
+    /// Answer at most `per_second` searches per second from all sources combined, with
+    /// bursts of up to `burst` searches.
+    pub fn global(mut self, per_second: u32, burst: u32) -> Self {
+        self.global = Some(Rate::new(per_second, burst));
+        self
+    }
+
+    /// Take a token for a search from `source`, returning whether it may be answered.
+    pub(crate) fn allow(&self, source: IpAddr) -> bool {
+        if self.per_source.is_none() && self.global.is_none() {
+            return true;
+        }
+
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let State { global, sources } = &mut *state;
+
+        let global = self.global.map(|rate| {
+            let bucket = global.get_or_insert(Bucket {
+                tokens: rate.burst,
+                updated: now,
+            });
+            bucket.refill(rate, now);
+            bucket
+        });
+
+        let source = self.per_source.map(|rate| {
+            if sources.len() >= MAX_SOURCES {
+                sources.retain(|_, bucket| !bucket.refill(rate, now));
+            }
+            let bucket = sources.entry(source).or_insert(Bucket {
+                tokens: rate.burst,
+                updated: now,
+            });
+            bucket.refill(rate, now);
+            bucket
+        });
+
+        let allowed = [&global, &source]
+            .into_iter()
+            .flatten()
+            .all(|bucket| bucket.tokens >= 1.0);
+        if allowed {
+            for bucket in [global, source].into_iter().flatten() {
+                bucket.tokens -= 1.0;
+            }
+        }
+
+        allowed
+    }
+}
+
+impl Rate {
+    fn new(per_second: u32, burst: u32) -> Self {
+        Self {
+            per_second: per_second as f64,
+            burst: burst.max(1) as f64,
+        }
+    }
+}
@@ -0,0 +1,428 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::poll_fn,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_core::Stream;
+use tokio::time::{Instant, Sleep};
+
+use crate::{
+    Notification, Notifications, Nts, SearchResponse, SearchResponses,
+    client::Received,
+    logging::{trace, warn},
+};
+
+/// The `max-age` assumed for devices not sending `CACHE-CONTROL`, which is the minimum
+/// allowed by the specification.
+const DEFAULT_MAX_AGE: u64 = 1800;
+
+/// A device on the network, as tracked by a [`Registry`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteDevice {
+    /// The unique service name of the device.
+    pub usn: String,
+    /// The search target of the device.
+    pub st: String,
+    /// The URL of the description of the device.
+    pub location: String,
+    /// The address the device was last seen from.
+    pub remote_addr: SocketAddr,
+    /// How long an announcement of the device is valid.
+    pub max_age: Duration,
+    /// When the device expires, unless announced again.
+    pub expires: Instant,
+}
+
+/// A change in the devices tracked by a [`Registry`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistryEvent {
+    /// A device was seen for the first time.
+    Added(RemoteDevice),
+    /// The location or search target of a device changed, or it sent `ssdp:update`.
+    Updated(RemoteDevice),
+    /// A device was not announced again within its `max-age`.
+    Expired(RemoteDevice),
+    /// A device announced leaving with `ssdp:byebye`.
+    Removed(RemoteDevice),
+}
+
+/// A registry of the devices on the network, fed by the notifications of a [`Monitor`]
+/// and the responses to searches of a [`Client`].
+///
+/// The registry is a stream of [`RegistryEvent`]s, which ends once it has no sources
+/// and no devices left.
+///
+/// [`Monitor`]: crate::Monitor
+/// [`Client`]: crate::Client
+#[derive(Debug, Default)]
+pub struct Registry {
+    notifications: Option<Notifications>,
+    searches: Vec<SearchResponses>,
+    devices: HashMap<String, RemoteDevice>,
+    events: VecDeque<RegistryEvent>,
+    expiry: Option<Pin<Box<Sleep>>>,
+}
+
+impl Registry {
+    /// Create a new, empty registry.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::{Client, Monitor, Registry, RegistryEvent};
+    ///
+    /// let mut registry = Registry::new().listen(Monitor::new().listen()?);
+    /// registry.add_search(Client::new().search("ssdp:all").await?);
+    ///
+    /// while let Some(event) = registry.next().await {
+    ///     match event {
+    ///         RegistryEvent::Added(device) => println!("{} at {}", device.usn, device.location),
+    ///         RegistryEvent::Expired(device) | RegistryEvent::Removed(device) => {
+    ///             println!("{} is gone", device.usn)
+    ///         }
+    ///         RegistryEvent::Updated(_) => {}
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track the devices announcing themselves in `notifications`.
+    pub fn listen(mut self, notifications: Notifications) -> Self {
+        self.notifications = Some(notifications);
+        self
+    }
+
+    /// Track the devices responding to a search.
+    pub fn add_search(&mut self, responses: SearchResponses) {
+        self.searches.push(responses);
+    }
+
+    /// Track the devices of saved responses, e.g. deserialized with the `serde` feature,
+    /// which expire after their [remaining `max-age`](SearchResponse::remaining_max_age).
+    /// Expired responses are skipped.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # fn load() -> Vec<tokio_ssdp::SearchResponse> { vec![] }
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::{Client, Registry};
+    ///
+    /// let mut registry = Registry::new();
+    /// registry.restore(load());
+    /// registry.add_search(Client::new().search("ssdp:all").await?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn restore(&mut self, responses: impl IntoIterator<Item = SearchResponse>) {
+        for response in responses {
+            let remaining = response
+                .remaining_max_age()
+                .unwrap_or(Duration::from_secs(DEFAULT_MAX_AGE));
+            if remaining.is_zero() {
+                continue;
+            }
+
+            let usn = response.usn.clone();
+            let SearchResponse {
+                remote_addr,
+                st,
+                location,
+                max_age,
+                ..
+            } = response;
+            self.seen(usn.clone(), st, location, remote_addr, max_age, false);
+            if let Some(device) = self.devices.get_mut(&usn) {
+                device.expires = Instant::now() + remaining;
+            }
+        }
+    }
+
+    /// Get a device by its USN.
+    pub fn get(&self, usn: &str) -> Option<&RemoteDevice> {
+        self.devices.get(usn)
+    }
+
+    /// Iterate over the tracked devices.
+    pub fn devices(&self) -> impl Iterator<Item = &RemoteDevice> {
+        self.devices.values()
+    }
+
+    /// Wait for the next change, or `None` once the registry has no sources and no
+    /// devices left.
+    pub async fn next(&mut self) -> Option<RegistryEvent> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    fn notified(&mut self, notification: Notification) {
+        let max_age = notification.max_age;
+        let Notification {
+            remote_addr,
+            nt,
+            nts,
+            usn,
+            location,
+            ..
+        } = notification;
+
+        match nts {
+            Nts::ByeBye => {
+                if let Some(device) = self.devices.remove(&usn) {
+                    self.events.push_back(RegistryEvent::Removed(device));
+                }
+            }
+            Nts::Alive | Nts::Update => {
+                let Some(location) = location else {
+                    trace!("Ignoring {} without LOCATION from {}", nts, remote_addr);
+                    return;
+                };
+                self.seen(usn, nt, location, remote_addr, max_age, nts == Nts::Update);
+            }
+            Nts::Other(_) => {}
+        }
+    }
+
+    fn seen(
+        &mut self,
+        usn: String,
+        st: String,
+        location: String,
+        remote_addr: SocketAddr,
+        max_age: Option<u64>,
+        update: bool,
+    ) {
+        let max_age = Duration::from_secs(max_age.unwrap_or(DEFAULT_MAX_AGE));
+        let device = RemoteDevice {
+            usn: usn.clone(),
+            st,
+            location,
+            remote_addr,
+            max_age,
+            expires: Instant::now() + max_age,
+        };
+
+        let event = match self.devices.get(&usn) {
+            None => Some(RegistryEvent::Added(device.clone())),
+            Some(old) if update || old.location != device.location || old.st != device.st => {
+                Some(RegistryEvent::Updated(device.clone()))
+            }
+            Some(_) => None,
+        };
+
+        self.devices.insert(usn, device);
+        self.events.extend(event);
+    }
+
+    /// Remove the expired devices, and arm the timer for the next expiry.
+    fn expire(&mut self, cx: &mut Context<'_>) {
+        let now = Instant::now();
+        let expired: Vec<_> = self
+            .devices
+            .values()
+            .filter(|device| device.expires <= now)
+            .map(|device| device.usn.clone())
+            .collect();
+
+        for usn in expired {
+            if let Some(device) = self.devices.remove(&usn) {
+                self.events.push_back(RegistryEvent::Expired(device));
+            }
+        }
+
+        self.expiry = self
+            .devices
+            .values()
+            .map(|device| device.expires)
+            .min()
+            .map(|next| Box::pin(tokio::time::sleep_until(next)));
+
+        if let Some(expiry) = &mut self.expiry {
+            // Register the waker for the next expiry
+            let _ = expiry.as_mut().poll(cx);
+        }
+    }
+}
+
+impl Stream for Registry {
+    type Item = RegistryEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if let Some(event) = this.events.pop_front() {
+            return Poll::Ready(Some(event));
+        }
+
+        while let Some(notifications) = &mut this.notifications {
+            match Pin::new(notifications).poll_next(cx) {
+                Poll::Ready(Some(Ok(notification))) => this.notified(notification),
+                Poll::Ready(Some(Err(e))) => warn!("Failed to receive notification: {}", e),
+                Poll::Ready(None) => this.notifications = None,
+                Poll::Pending => break,
+            }
+        }
+
+        let mut idx = 0;
+        while idx < this.searches.len() {
+            match this.searches[idx].poll_message(cx) {
+                Poll::Ready(Some(Ok(Received::Notify(notification)))) => {
+                    this.notified(notification)
+                }
+                Poll::Ready(Some(Ok(Received::Response(response)))) => {
+                    let SearchResponse {
+                        remote_addr,
+                        st,
+                        usn,
+                        location,
+                        max_age,
+                        ..
+                    } = response;
+                    this.seen(usn, st, location, remote_addr, max_age, false);
+                }
+                Poll::Ready(Some(Err(e))) => warn!("Failed to receive search response: {}", e),
+                Poll::Ready(None) => {
+                    this.searches.swap_remove(idx);
+                }
+                Poll::Pending => idx += 1,
+            }
+        }
+
+        // Devices were added or removed, or the next one is due to expire
+        let expiry_due = this
+            .expiry
+            .as_mut()
+            .is_none_or(|expiry| expiry.as_mut().poll(cx).is_ready());
+        if !this.events.is_empty() || expiry_due {
+            this.expire(cx);
+        }
+
+        if let Some(event) = this.events.pop_front() {
+            return Poll::Ready(Some(event));
+        }
+
+        if this.notifications.is_none() && this.searches.is_empty() && this.devices.is_empty() {
+            return Poll::Ready(None);
+        }
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr() -> SocketAddr {
+        "192.0.2.1:1900".parse().unwrap()
+    }
+
+    fn see(registry: &mut Registry, usn: &str, location: &str, max_age: u64) {
+        registry.seen(
+            usn.to_string(),
+            "upnp:rootdevice".to_string(),
+            location.to_string(),
+            addr(),
+            Some(max_age),
+            false,
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tracks_devices_by_usn() {
+        let mut registry = Registry::new();
+
+        see(&mut registry, "uuid:a", "http://192.0.2.1/a.xml", 60);
+        assert!(
+            matches!(registry.next().await, Some(RegistryEvent::Added(d)) if d.usn == "uuid:a")
+        );
+
+        // Seen again unchanged, no event
+        see(&mut registry, "uuid:a", "http://192.0.2.1/a.xml", 60);
+        see(&mut registry, "uuid:b", "http://192.0.2.1/b.xml", 60);
+        assert!(
+            matches!(registry.next().await, Some(RegistryEvent::Added(d)) if d.usn == "uuid:b")
+        );
+
+        see(&mut registry, "uuid:a", "http://192.0.2.1/moved.xml", 60);
+        assert!(matches!(
+            registry.next().await,
+            Some(RegistryEvent::Updated(d)) if d.location == "http://192.0.2.1/moved.xml"
+        ));
+
+        registry.notified(Notification {
+            remote_addr: addr(),
+            nt: "upnp:rootdevice".to_string(),
+            nts: Nts::ByeBye,
+            usn: "uuid:b".to_string(),
+            location: None,
+            max_age: None,
+            headers: vec![],
+        });
+        assert!(
+            matches!(registry.next().await, Some(RegistryEvent::Removed(d)) if d.usn == "uuid:b")
+        );
+        assert_eq!(registry.devices().count(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expires_devices_after_max_age() {
+        let mut registry = Registry::new();
+        see(&mut registry, "uuid:a", "http://192.0.2.1/a.xml", 60);
+        see(&mut registry, "uuid:b", "http://192.0.2.1/b.xml", 120);
+        let start = Instant::now();
+
+        for _ in 0..2 {
+            assert!(matches!(
+                registry.next().await,
+                Some(RegistryEvent::Added(_))
+            ));
+        }
+
+        // Announced again, extending its expiry
+        tokio::time::advance(Duration::from_secs(50)).await;
+        see(&mut registry, "uuid:a", "http://192.0.2.1/a.xml", 60);
+
+        let Some(RegistryEvent::Expired(device)) = registry.next().await else {
+            panic!("expected an expiry");
+        };
+        assert_eq!(device.usn, "uuid:a");
+        assert_eq!(start.elapsed(), Duration::from_secs(110));
+
+        assert!(
+            matches!(registry.next().await, Some(RegistryEvent::Expired(d)) if d.usn == "uuid:b")
+        );
+        assert_eq!(start.elapsed(), Duration::from_secs(120));
+
+        // No sources and no devices left
+        assert_eq!(registry.next().await, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restores_saved_responses() {
+        let response = |usn: &str, age| SearchResponse {
+            remote_addr: addr(),
+            st: "upnp:rootdevice".to_string(),
+            usn: usn.to_string(),
+            location: "http://192.0.2.1/desc.xml".to_string(),
+            max_age: Some(60),
+            headers: vec![],
+            latency: Duration::ZERO,
+            conforming: true,
+            received: std::time::SystemTime::now() - Duration::from_secs(age),
+        };
+
+        let mut registry = Registry::new();
+        registry.restore([response("uuid:a", 50), response("uuid:b", 70)]);
+
+        assert!(registry.get("uuid:b").is_none());
+        let remaining = registry.get("uuid:a").unwrap().expires - Instant::now();
+        assert!(remaining <= Duration::from_secs(10) && remaining > Duration::from_secs(9));
+    }
+}
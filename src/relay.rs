@@ -0,0 +1,224 @@
+use std::{
+    future::Future,
+    io::Result as IoResult,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::net::UdpSocket;
+
+use crate::{
+    SSDP_ADDR_V4, SSDP_PORT, SearchRequest, Server,
+    compat::Profile,
+    logging::{debug, trace, warn},
+    message::{DEFAULT_MAX_HEADERS, INLINE_HEADERS, Notify},
+    pktinfo,
+};
+
+/// The header marking relayed messages, so they are never relayed again.
+const RELAY_HEADER: &str = "X-SSDP-RELAY";
+
+/// How long responses to a relayed search are forwarded, beyond its `MX`.
+const RESPONSE_GRACE: Duration = Duration::from_secs(1);
+
+/// The `MX` cap advised by the UPnP device architecture.
+const MAX_MX: u32 = 5;
+
+/// Relays SSDP traffic from one interface to another, e.g. between routed VLANs that
+/// multicast does not cross.
+///
+/// `NOTIFY` messages received on the source interface are re-sent to the multicast group
+/// on the target interface. `M-SEARCH` requests are re-sent as well, and the responses
+/// forwarded to the searching control point until its `MX` has passed.
+///
+/// Relayed messages are marked with an `X-SSDP-RELAY` header, and marked messages are
+/// never relayed, so two relays in opposite directions do not loop.
+#[derive(Debug, Clone)]
+pub struct Relay {
+    from: Ipv4Addr,
+    to: Ipv4Addr,
+    search_targets: Vec<String>,
+}
+
+impl Relay {
+    /// Relay from the interface with address `from` to the interface with address `to`.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use std::net::Ipv4Addr;
+    /// use tokio_ssdp::Relay;
+    ///
+    /// let clients = Ipv4Addr::new(192, 168, 1, 1);
+    /// let devices = Ipv4Addr::new(192, 168, 20, 1);
+    ///
+    /// tokio::try_join!(
+    ///     Relay::new(clients, devices).serve()?,
+    ///     Relay::new(devices, clients).serve()?,
+    /// )?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(from: Ipv4Addr, to: Ipv4Addr) -> Self {
+        Self {
+            from,
+            to,
+            search_targets: vec![],
+        }
+    }
+
+    /// Only relay searches for `st`, and notifications of type `st`. Can be called more
+    /// than once to relay several types, defaults to relaying everything.
+    pub fn search_target(mut self, st: impl Into<String>) -> Self {
+        self.search_targets.push(st.into());
+        self
+    }
+
+    fn relays(&self, st: &str) -> bool {
+        self.search_targets.is_empty() || self.search_targets.iter().any(|own| own == st)
+    }
+
+    /// Start relaying, returns a future that needs to be `await`ed to keep the relay running.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn serve(self) -> IoResult<impl Future<Output = IoResult<()>>> {
+        let rx_socket = Server::new_rx_socket(SSDP_PORT, true)?;
+        rx_socket.set_nonblocking(true)?;
+        rx_socket.join_multicast_v4(&SSDP_ADDR_V4, &self.from)?;
+        rx_socket.set_multicast_loop_v4(false)?;
+        pktinfo::enable(&rx_socket)?;
+        let rx_socket = UdpSocket::from_std(rx_socket)?;
+
+        let tx_socket = Arc::new(bind_sending(self.to)?);
+        let reply_socket = Arc::new(bind_sending(self.from)?);
+
+        Ok(async move {
+            let mut buf = [0u8; 2048];
+            loop {
+                let (n, addr, info) = pktinfo::recv_from(&rx_socket, &mut buf).await?;
+                if info.is_some_and(|info| info.local_addr != self.from) {
+                    // Received on another interface
+                    continue;
+                }
+                if addr.ip() == self.from || addr.ip() == self.to {
+                    continue;
+                }
+
+                // Most messages fit the headers on the stack, retry with more otherwise
+                let mut headers = [httparse::EMPTY_HEADER; INLINE_HEADERS];
+                let mut more_headers;
+                let mut req = httparse::Request::new(&mut headers);
+                let mut res = req.parse(&buf[..n]);
+                if matches!(res, Err(httparse::Error::TooManyHeaders)) {
+                    more_headers = vec![httparse::EMPTY_HEADER; DEFAULT_MAX_HEADERS];
+                    req = httparse::Request::new(&mut more_headers);
+                    res = req.parse(&buf[..n]);
+                }
+                if !matches!(res, Ok(httparse::Status::Complete(_))) {
+                    continue;
+                }
+                if req
+                    .headers
+                    .iter()
+                    .any(|h| h.name.eq_ignore_ascii_case(RELAY_HEADER))
+                {
+                    trace!("Not relaying message from {}, already relayed", addr);
+                    continue;
+                }
+
+                match (req.method, req.path) {
+                    (Some("M-SEARCH"), Some("*")) => {
+                        let Ok(search) =
+                            SearchRequest::from_request(&req, &Profile::new().unquoted_man(true))
+                        else {
+                            continue;
+                        };
+                        if !self.relays(&search.st) {
+                            continue;
+                        }
+
+                        debug!("Relaying M-SEARCH ST={} from {}", search.st, addr);
+                        let window = Duration::from_secs(search.mx.unwrap_or(1).min(MAX_MX) as u64)
+                            + RESPONSE_GRACE;
+                        let message = mark(&buf[..n]);
+                        let reply_socket = Arc::clone(&reply_socket);
+                        let to = self.to;
+                        tokio::spawn(async move {
+                            if let Err(e) =
+                                relay_search(to, &message, addr, &reply_socket, window).await
+                            {
+                                warn!("Relaying M-SEARCH from {} failed: {}", addr, e);
+                            }
+                        });
+                    }
+                    (Some("NOTIFY"), Some("*")) => {
+                        let Ok(notify) = Notify::from_request(&req) else {
+                            continue;
+                        };
+                        if !self.relays(&notify.nt) {
+                            continue;
+                        }
+
+                        trace!(
+                            "Relaying NOTIFY {} for {} from {}",
+                            notify.nts, notify.usn, addr
+                        );
+                        let message = mark(&buf[..n]);
+                        if let Err(e) = tx_socket.send_to(&message, (SSDP_ADDR_V4, SSDP_PORT)).await
+                        {
+                            warn!("Relaying NOTIFY from {} failed: {}", addr, e);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        })
+    }
+}
+
+/// Send `message` on the interface `to`, and forward the responses to `searcher` until
+/// `window` has passed.
+async fn relay_search(
+    to: Ipv4Addr,
+    message: &[u8],
+    searcher: SocketAddr,
+    reply_socket: &UdpSocket,
+    window: Duration,
+) -> IoResult<()> {
+    let socket = bind_sending(to)?;
+    socket.send_to(message, (SSDP_ADDR_V4, SSDP_PORT)).await?;
+
+    let mut buf = [0u8; 2048];
+    let deadline = tokio::time::Instant::now() + window;
+    while let Ok(res) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let (n, addr) = res?;
+        trace!("Forwarding search response from {} to {}", addr, searcher);
+        reply_socket.send_to(&buf[..n], searcher).await?;
+    }
+
+    Ok(())
+}
+
+/// A socket sending from the interface with address `ip`.
+fn bind_sending(ip: Ipv4Addr) -> IoResult<UdpSocket> {
+    let socket = std::net::UdpSocket::bind((ip, 0))?;
+    socket.set_nonblocking(true)?;
+    socket.set_multicast_loop_v4(false)?;
+    UdpSocket::from_std(socket)
+}
+
+/// `message` with the relay header added after the start line.
+fn mark(message: &[u8]) -> Vec<u8> {
+    let start_line = message
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .map_or(message.len(), |pos| pos + 2);
+
+    let mut out = Vec::with_capacity(message.len() + RELAY_HEADER.len() + 5);
+    out.extend_from_slice(&message[..start_line]);
+    out.extend_from_slice(RELAY_HEADER.as_bytes());
+    out.extend_from_slice(b": 1\r\n");
+    out.extend_from_slice(&message[start_line..]);
+    out
+}
@@ -0,0 +1,24 @@
+use std::io::{Error, ErrorKind};
+
+/// The class of a failure to send a datagram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendErrorClass {
+    /// The failure is likely to go away by itself, e.g. `ENOBUFS` or `EAGAIN`.
+    Temporary,
+    /// The failure is unlikely to go away by retrying, e.g. `ENETUNREACH`.
+    Persistent,
+}
+
+impl SendErrorClass {
+    /// Classify a send error.
+    pub fn of(error: &Error) -> Self {
+        match error.kind() {
+            ErrorKind::WouldBlock | ErrorKind::Interrupted | ErrorKind::TimedOut => {
+                SendErrorClass::Temporary
+            }
+            #[cfg(unix)]
+            _ if error.raw_os_error() == Some(libc::ENOBUFS) => SendErrorClass::Temporary,
+            _ => SendErrorClass::Persistent,
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! Random numbers for jitter, from `rand` if the `rand` feature is enabled, and from
+//! the randomly seeded hasher of std otherwise.
+
+/// A uniformly distributed number in `0.0..1.0`.
+pub(crate) fn unit() -> f64 {
+    (next_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// A random (version 4) UUID.
+pub(crate) fn uuid() -> String {
+    let (hi, lo) = (next_u64(), next_u64());
+    let hi = (hi & !0xf000) | 0x4000;
+    let lo = (lo & !(0xc << 60)) | (0x8 << 60);
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        hi >> 32,
+        (hi >> 16) & 0xffff,
+        hi & 0xffff,
+        lo >> 48,
+        lo & 0xffff_ffff_ffff
+    )
+}
+
+#[cfg(feature = "rand")]
+fn next_u64() -> u64 {
+    use rand::RngCore;
+    rand::thread_rng().next_u64()
+}
+
+#[cfg(not(feature = "rand"))]
+fn next_u64() -> u64 {
+    use std::{
+        cell::Cell,
+        hash::{BuildHasher, RandomState},
+    };
+
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new(RandomState::new().hash_one(0u64) | 1);
+    }
+
+    // xorshift64*
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    })
+}
@@ -1,30 +1,642 @@
 use std::{
+    collections::HashMap,
+    fmt,
     future::Future,
-    net::{Ipv4Addr, SocketAddr},
-    sync::Arc,
-    time::{Duration, SystemTime},
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::{
+        Arc, Mutex, RwLock,
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use log::{debug, error, info, trace};
-use rand::Rng;
+use crate::logging::{self, Instrument, Span, debug, error, info, trace, warn};
 use std::io::Result as IoResult;
-use tokio::{net::UdpSocket, sync::oneshot};
+use tokio::{
+    net::UdpSocket,
+    sync::{Semaphore, broadcast, mpsc, oneshot, watch},
+};
+
+use crate::{
+    Backoff, BootIdStore, Client, ControlPoint, Device, DeviceGroup, Error, EventLog, Header,
+    HeaderContext, HeaderProvider, Health, Ipv4Subnet, MessageOptions, NotificationSubtype, Nts,
+    PacketInfo, ResponseCustomizer, ResponseHistory, SSDP_ADDR_V4, SSDP_PORT, SearchDecision,
+    SearchHook, SearchLimit, SearchRequest, SendErrorClass, SentResponse, ServerEvent,
+    ServerHandle, Status, Throttle, Transport, UpnpVersion, batch,
+    cache::{CacheContext, MessageCache},
+    compat::Profile,
+    error,
+    header::{expand_placeholders, merge_headers},
+    iface,
+    ifwatch::InterfaceEvents,
+    message::{self, DEFAULT_MAX_HEADERS, INLINE_HEADERS},
+    pktinfo, rng,
+    shared::SharedLoop,
+    stats,
+    status::ScheduledGroup,
+    transport,
+};
+
+#[cfg(feature = "http")]
+use crate::Description;
+#[cfg(feature = "mdns")]
+use crate::mdns::{self, MdnsService};
+#[cfg(feature = "wsd")]
+use crate::{
+    WsDiscovery,
+    wsd::{self, WsdTarget},
+};
+#[cfg(feature = "tokio-util")]
+use tokio_util::sync::CancellationToken;
+
+#[cfg(feature = "tower")]
+type SearchService =
+    tower::util::BoxCloneSyncService<IncomingSearch, Option<IncomingSearch>, tower::BoxError>;
+
+const FAST_ADVERTISE_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_PACING: Duration = Duration::from_millis(50);
+
+/// How long to wait for more changes after a network interface changed.
+const INTERFACE_SETTLE: Duration = Duration::from_millis(500);
+const DEFAULT_NOTIFY_TTL: u32 = 2;
+
+/// The `MX` cap advised by the UPnP device architecture.
+const DEFAULT_MAX_MX: u32 = 5;
+
+const DEFAULT_RECEIVE_BUFFER: usize = 2048;
+
+const DEFAULT_ANNOUNCE_RETRY: Backoff =
+    Backoff::new(Duration::from_millis(20), Duration::from_millis(500)).max_attempts(3);
+
+/// How to handle `M-SEARCH` requests without an `MX` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MissingMx {
+    /// Answer immediately.
+    #[default]
+    Immediate,
+    /// Answer as if the request had the given `MX` value.
+    Assume(u32),
+    /// Reject the request, as required by the specification.
+    Reject,
+}
+
+/// How the delay before answering an `M-SEARCH` request is chosen, see [`Server::response_delay`].
+///
+/// The delay is chosen for each matching device, so the responses to a search are spread
+/// over the window. It never exceeds the `MX` value of the request, capped by
+/// [`Server::max_mx`].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ResponseDelay {
+    /// Uniformly distributed over the whole window from zero to `MX`.
+    #[default]
+    Uniform,
+    /// No delay, e.g. for low-latency LANs with few devices.
+    Immediate,
+    /// Always the given delay.
+    Fixed(Duration),
+    /// Uniformly distributed below `MX`, squared, so most responses are sent early while
+    /// some are still spread over the whole window.
+    EarlyBiased,
+    /// Exponentially distributed with the given mean, truncated at `MX`.
+    Exponential(Duration),
+}
+
+impl ResponseDelay {
+    /// Pick a delay for a request with the given (capped) `MX` value.
+    fn sample(&self, mx: u32) -> Duration {
+        let max = Duration::from_secs(mx as u64);
+        let delay = match *self {
+            ResponseDelay::Uniform => max.mul_f64(rng::unit()),
+            ResponseDelay::Immediate => Duration::ZERO,
+            ResponseDelay::Fixed(delay) => delay,
+            ResponseDelay::EarlyBiased => max.mul_f64(rng::unit().powi(2)),
+            ResponseDelay::Exponential(mean) => {
+                let u = rng::unit();
+                Duration::try_from_secs_f64(-mean.as_secs_f64() * (1.0 - u).ln()).unwrap_or(max)
+            }
+        };
+
+        delay.min(max)
+    }
+}
+
+/// How the messages of an announcement are spread out, see [`Server::pacing`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pacing {
+    /// The given delay after each message.
+    Fixed(Duration),
+    /// The first message right away, the others at random times within the given window,
+    /// e.g. to keep large announcements from bursting onto the network.
+    Spread(Duration),
+}
+
+impl Default for Pacing {
+    fn default() -> Self {
+        Pacing::Fixed(DEFAULT_PACING)
+    }
+}
+
+impl Pacing {
+    /// The delays after each of `count` messages.
+    fn delays(&self, count: usize) -> Vec<Duration> {
+        match *self {
+            Pacing::Fixed(delay) => vec![delay; count],
+            Pacing::Spread(window) => {
+                let mut times: Vec<_> = (1..count).map(|_| window.mul_f64(rng::unit())).collect();
+                times.sort();
+
+                let mut last = Duration::ZERO;
+                let mut delays: Vec<_> = times
+                    .into_iter()
+                    .map(|time| {
+                        let delay = time - last;
+                        last = time;
+                        delay
+                    })
+                    .collect();
+                delays.push(Duration::ZERO);
+                delays
+            }
+        }
+    }
+}
+
+/// What to do when the SSDP port is already in use, see [`Server::addr_in_use`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AddrInUse {
+    /// Fail with an error describing the problem.
+    #[default]
+    Fail,
+    /// Keep trying to bind the port in the background, announcing the devices meanwhile.
+    /// Attempts are reported as [`RestartAttempt`]s, and the server terminates once no
+    /// attempts are left.
+    Retry(Backoff),
+    /// Bind the port with `SO_REUSEPORT` as well, which lets the port be shared with other
+    /// processes doing the same, and fail otherwise. Requires the `socket2` feature, and a
+    /// unix platform. See [`Server::reuse_port`] to combine sharing with another policy.
+    Shared,
+    /// Only announce the devices, without answering searches.
+    AnnounceOnly,
+}
+
+/// The kind of a message sent by the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum MessageKind {
+    /// Response to an `M-SEARCH` request.
+    SearchResponse,
+    /// `ssdp:alive` notification.
+    Alive,
+    /// `ssdp:byebye` notification.
+    Byebye,
+}
+
+/// What to do with delayed search responses when the server shuts down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PendingResponses {
+    /// Drop responses which have not been sent yet.
+    #[default]
+    Cancel,
+    /// Send responses which have not been sent yet immediately, giving up
+    /// on those which are not sent before the deadline.
+    Flush(Duration),
+}
+
+/// The devices in `groups`, with the group of each.
+fn devices(groups: &[DeviceGroup]) -> impl Iterator<Item = (&DeviceGroup, &Device)> {
+    groups
+        .iter()
+        .flat_map(|group| group.devices.iter().map(move |device| (group, device)))
+}
+
+/// Whether the `HOST` header `host` names `ip` and `port`, the port being optional.
+fn is_host(host: &str, ip: Ipv4Addr, port: u16) -> bool {
+    match host.trim().rsplit_once(':') {
+        Some((addr, p)) => addr.parse() == Ok(ip) && p.parse() == Ok(port),
+        None => host.trim().parse() == Ok(ip),
+    }
+}
+
+/// Describe `cause`, the failure to bind the SSDP port `port` because it is in use.
+fn addr_in_use_error(port: u16, cause: std::io::Error) -> std::io::Error {
+    std::io::Error::new(
+        cause.kind(),
+        format!(
+            "UDP port {} is in use by a process which doesn't share it ({}). \
+             Stop the other SSDP stack (e.g. minissdpd, or a media server), \
+             make it set SO_REUSEADDR, or see Server::addr_in_use",
+            port, cause
+        ),
+    )
+}
+
+/// Wait until `availability` changes to `true`, never returning if it is `None` or closed.
+async fn availability_changed(availability: Option<&mut watch::Receiver<bool>>) {
+    let Some(rx) = availability else {
+        return std::future::pending().await;
+    };
+
+    loop {
+        if rx.changed().await.is_err() {
+            return std::future::pending().await;
+        }
+        if *rx.borrow_and_update() {
+            return;
+        }
+    }
+}
+
+/// Triggers announcements of a running server, see [`Server::advertiser`].
+///
+/// Clones share the same state, so an `Advertiser` obtained before the server is
+/// started controls the running server.
+#[derive(Debug, Clone)]
+pub struct Advertiser {
+    fast_until: Arc<watch::Sender<Option<Instant>>>,
+}
+
+impl Advertiser {
+    /// Announce all devices now, and then every 5 seconds for `duration`, e.g. after
+    /// the user pressed a "pair" button, so control points find the devices instantly.
+    ///
+    /// The announcement interval of a group is never increased, and calling this again
+    /// restarts the period.
+    pub fn fast_advertise(&self, duration: Duration) {
+        self.fast_until
+            .send_replace(Some(Instant::now() + duration));
+    }
+
+    /// The interval between announcements with a configured interval of `interval`.
+    fn interval(&self, interval: Duration) -> Duration {
+        match *self.fast_until.borrow() {
+            Some(until) if Instant::now() < until => interval.min(FAST_ADVERTISE_INTERVAL),
+            _ => interval,
+        }
+    }
+}
+
+/// What a standby instance knows about the active instance, see [`Server::standby`].
+#[derive(Debug)]
+struct Standby {
+    /// When the active instance last announced one of the devices.
+    last_seen: Instant,
+    /// The last `BOOTID.UPNP.ORG` of the active instance.
+    active_boot_id: Option<u32>,
+}
+
+/// Shutdown signal and completion tracking for delayed search responses.
+///
+/// Every response task holds a clone of `done`, so the receiving end is closed
+/// once all of them have finished.
+#[derive(Debug, Clone)]
+struct InFlight {
+    shutdown: watch::Receiver<()>,
+    done: mpsc::Sender<()>,
+}
+
+/// Where a server receives, see `Server::serve_with`.
+#[derive(Debug)]
+enum Receiver {
+    /// Bind the SSDP port.
+    Bind,
+    /// A socket set up by the application.
+    Socket(UdpSocket),
+    /// A transport set up by the application, also sending everything.
+    Transport(Arc<dyn Transport>),
+}
+
+/// The outcome of one `ssdp:alive` broadcast of a group of devices.
+///
+/// See [`Server::alive_cycles`].
+#[derive(Debug, Clone)]
+pub struct AliveCycle {
+    /// The USN of each device in the group, and the result of sending its `ssdp:alive` message.
+    pub results: Vec<(String, Result<(), Arc<std::io::Error>>)>,
+}
+
+impl AliveCycle {
+    /// Returns `true` if the `ssdp:alive` message of every device was sent.
+    pub fn is_ok(&self) -> bool {
+        self.results.iter().all(|(_, res)| res.is_ok())
+    }
+}
+
+/// An attempt to re-create the receiving socket after a fatal error.
+///
+/// See [`Server::restart_on_error`] and [`Server::restarts`].
+#[derive(Debug, Clone)]
+pub struct RestartAttempt {
+    /// The number of the attempt since the error, starting at 0.
+    pub attempt: u32,
+    /// The error which caused the restart.
+    pub cause: Arc<std::io::Error>,
+    /// The result of the attempt.
+    pub result: Result<(), Arc<std::io::Error>>,
+}
+
+/// The multicast group had to be re-joined, see [`Server::membership_watchdog`].
+#[derive(Debug, Clone)]
+pub struct Rejoin {
+    /// The interface the group was re-joined on.
+    pub interface: Ipv4Addr,
+}
+
+/// The address of the host changed, see [`Server::watch_address`].
+#[derive(Debug, Clone)]
+pub struct AddressChange {
+    /// The previous address.
+    pub old: Ipv4Addr,
+    /// The new address.
+    pub new: Ipv4Addr,
+}
+
+/// What to do when another host announces one of our USNs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// Log a warning, and keep announcing the device.
+    #[default]
+    Warn,
+    /// Keep announcing the device without logging a warning.
+    KeepAnnouncing,
+    /// Stop announcing the device and answering searches for it, until no duplicate
+    /// has been seen for the given duration.
+    BackOff(Duration),
+}
+
+/// Another host announced one of our USNs, see [`Server::duplicate_policy`].
+#[derive(Debug, Clone)]
+pub struct DuplicateResponder {
+    /// The duplicated USN.
+    pub usn: String,
+    /// The address of the other host.
+    pub addr: SocketAddr,
+}
+
+/// Where an `M-SEARCH` request came from, see [`Server::answer_search`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchContext {
+    /// The address of the control point which sent the search.
+    pub remote_addr: SocketAddr,
+    /// The interface the search was received on, if known.
+    pub received_on: Option<PacketInfo>,
+    /// Whether the search was sent to the server directly instead of to the multicast
+    /// group. Unicast searches are answered immediately, regardless of `MX`.
+    pub unicast: bool,
+}
+
+impl SearchContext {
+    /// Create the context of a multicast search sent by `remote_addr`.
+    pub fn new(remote_addr: SocketAddr) -> Self {
+        Self {
+            remote_addr,
+            received_on: None,
+            unicast: false,
+        }
+    }
+}
+
+/// An `M-SEARCH` request received by the server, see [`Server::search_service`].
+#[derive(Debug, Clone)]
+pub struct IncomingSearch {
+    /// The request.
+    pub request: SearchRequest,
+    /// Where the request came from.
+    pub context: SearchContext,
+}
+
+/// A message to be sent, see [`Server::answer_search`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutgoingMessage {
+    /// The address to send the message to.
+    pub dest: SocketAddr,
+    /// How long to wait before sending the message.
+    pub delay: Duration,
+    /// The message.
+    pub data: Vec<u8>,
+}
+
+/// The responses to a search, in the order they are due.
+struct Answer {
+    mx: u32,
+    /// The delay before the first response.
+    delay: Duration,
+    responses: Vec<(Device, String, Duration)>,
+}
+
+/// A change requested through a [`ServerHandle`] while the server is running.
+#[derive(Debug)]
+pub(crate) enum Control {
+    AddDevice(Device),
+    RemoveDevice(String),
+    /// Announce the device with the given USN, or all devices.
+    Announce(Option<String>),
+    /// Announce the next boot id, and the new configuration id, if any.
+    Update(Option<u32>),
+    /// Send notifications of the given subtype for the devices with the given USNs.
+    Notify {
+        nts: NotificationSubtype,
+        usns: Vec<String>,
+    },
+    /// Send `ssdp:alive`, or `ssdp:byebye`, for the device with the given USN to one
+    /// address only.
+    Unicast {
+        target: SocketAddr,
+        usn: String,
+        alive: bool,
+    },
+}
+
+/// How to announce a [`Control`].
+enum Announcement {
+    Alive(DeviceGroup, Device),
+    AliveAll,
+    Byebye(Device),
+    /// Send notifications of one subtype for several devices.
+    Notify(NotificationSubtype, Vec<(DeviceGroup, Device)>),
+    Update(Option<u32>),
+    AliveTo(SocketAddr, DeviceGroup, Device),
+    ByebyeTo(SocketAddr, Device),
+}
+
+/// A search answered by the server, see [`ServerEvent::SearchAnswered`].
+#[derive(Debug, Clone)]
+pub struct AnsweredSearch {
+    /// The address of the control point which sent the search.
+    pub remote_addr: SocketAddr,
+    /// The search target of the search.
+    pub st: String,
+    /// The `MX` value of the search.
+    pub mx: u32,
+    /// The devices matching the search.
+    pub devices: Vec<Device>,
+    /// The delay before the first response is sent.
+    pub delay: Duration,
+    /// The interface the search was received on, if supported by the platform.
+    pub received_on: Option<PacketInfo>,
+    /// How the control point identified itself.
+    pub control_point: ControlPoint,
+}
+
+impl fmt::Display for AnsweredSearch {
+    /// Format as a single line summary. The alternate form (`{:#}`) also lists the
+    /// matching devices, one per line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "M-SEARCH ST={} MX={} from {}: {} devices after {:?}",
+            self.st,
+            self.mx,
+            self.remote_addr,
+            self.devices.len(),
+            self.delay
+        )?;
+
+        if f.alternate() {
+            for device in &self.devices {
+                write!(f, "\n  {}", device)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Receives the searches answered by a server, see [`Server::answered_searches`].
+#[derive(Debug)]
+pub struct AnsweredSearches {
+    events: broadcast::Receiver<ServerEvent>,
+}
+
+impl AnsweredSearches {
+    /// Receive the next answered search, as [`broadcast::Receiver::recv`].
+    pub async fn recv(&mut self) -> Result<AnsweredSearch, broadcast::error::RecvError> {
+        loop {
+            if let ServerEvent::SearchAnswered(search) = self.events.recv().await? {
+                return Ok(search);
+            }
+        }
+    }
+}
+
+impl fmt::Display for AliveCycle {
+    /// Format as a single line summary. The alternate form (`{:#}`) also lists the
+    /// result for every device, one per line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sent = self.results.iter().filter(|(_, res)| res.is_ok()).count();
+        write!(
+            f,
+            "ssdp:alive sent for {}/{} devices",
+            sent,
+            self.results.len()
+        )?;
+
+        if f.alternate() {
+            for (usn, res) in &self.results {
+                match res {
+                    Ok(()) => write!(f, "\n  {}: ok", usn)?,
+                    Err(e) => write!(f, "\n  {}: {}", usn, e)?,
+                }
+            }
+        }
 
-use crate::Device;
+        Ok(())
+    }
+}
 
-const SSDP_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
-const SSDP_PORT: u16 = 1900;
-const DEFAULT_SERVER_NAME: &str = "Tokio-SSDP/1.0 UPnP/1.0";
+/// Pre-concatenated extra headers for each kind of message.
+#[derive(Debug)]
+struct ExtraHeaders {
+    search_response: String,
+    alive: String,
+    byebye: String,
+}
 
 /// A server providing SSDP functionalities.
 /// The server will respond to `M-SEARCH` requests, send `alive` and `byebye` messages when needed.
 #[derive(Debug, Clone)]
 pub struct Server {
-    server_name: Option<String>,
-    max_age: u64,
-    devices: Vec<Device>,
-    headers: Vec<(String, String)>,
-    partial_req_workaround: bool,
+    options: MessageOptions,
+    groups: Arc<RwLock<Arc<Vec<DeviceGroup>>>>,
+    headers: Vec<(Option<MessageKind>, String, String)>,
+    compat: Profile,
+    receive_buffer: usize,
+    max_headers: usize,
+    missing_mx: MissingMx,
+    response_delay: ResponseDelay,
+    max_mx: u32,
+    header_providers: Vec<Arc<dyn HeaderProvider>>,
+    search_hook: Option<Arc<dyn SearchHook>>,
+    response_customizer: Option<Arc<dyn ResponseCustomizer>>,
+    diagnostic_responses: bool,
+    event_log: Option<EventLog>,
+    status: Option<Status>,
+    #[cfg(feature = "mdns")]
+    mdns_service_type: Option<String>,
+    #[cfg(feature = "http")]
+    description: Option<Description>,
+    #[cfg(feature = "wsd")]
+    ws_discovery: Option<WsDiscovery>,
+    boot_id: Option<u32>,
+    nls: Option<String>,
+    boot_id_store: Option<Arc<dyn BootIdStore>>,
+    config_id: u32,
+    byebye_deadline: Option<Duration>,
+    pending_responses: PendingResponses,
+    max_pending_responses: Option<usize>,
+    response_slots: Option<Arc<Semaphore>>,
+    alive_cycle_tx: broadcast::Sender<AliveCycle>,
+    errors_tx: broadcast::Sender<Error>,
+    events_tx: broadcast::Sender<ServerEvent>,
+    health: Health,
+    response_history: ResponseHistory,
+    shared: SharedLoop,
+    advertiser: Advertiser,
+    availability: Option<watch::Receiver<bool>>,
+    ready_tx: Arc<watch::Sender<bool>>,
+    per_interface: bool,
+    addr_in_use: AddrInUse,
+    reuse_port: bool,
+    standby: Option<Duration>,
+    active_tx: Arc<watch::Sender<bool>>,
+    standby_state: Arc<Mutex<Standby>>,
+    current_boot_id: Arc<AtomicU32>,
+    current_config_id: Arc<AtomicU32>,
+    restart: Option<Backoff>,
+    restart_tx: broadcast::Sender<RestartAttempt>,
+    announce_retry: Option<Backoff>,
+    watchdog_interval: Option<Duration>,
+    rejoin_tx: broadcast::Sender<Rejoin>,
+    host_addr: Arc<RwLock<Ipv4Addr>>,
+    address_watch: Option<Duration>,
+    interface_watch: Option<bool>,
+    address_change_tx: broadcast::Sender<AddressChange>,
+    duplicate_policy: DuplicatePolicy,
+    duplicate_tx: broadcast::Sender<DuplicateResponder>,
+    backed_off: Arc<Mutex<HashMap<String, Instant>>>,
+    message_cache: MessageCache,
+    response_sources: Vec<Ipv4Subnet>,
+    search_port: Option<u16>,
+    min_ttl: Option<u8>,
+    strict_destination: bool,
+    response_ttl: Option<u32>,
+    notify_ttl: u32,
+    multicast_loop: bool,
+    throttle: Throttle,
+    search_limit: SearchLimit,
+    allowed: Vec<Ipv4Subnet>,
+    denied: Vec<Ipv4Subnet>,
+    notify_interval: Option<Duration>,
+    notify_jitter: Duration,
+    repeat: u32,
+    repeat_spacing: Duration,
+    pacing: Pacing,
+    batch_size: usize,
+    #[cfg(feature = "tower")]
+    search_service: Option<SearchService>,
+    interface: Option<String>,
+    #[cfg(feature = "tokio-util")]
+    cancellation_token: Option<CancellationToken>,
 }
 
 impl Server {
@@ -43,15 +655,97 @@ impl Server {
     /// ```
     pub fn new(devices: impl IntoIterator<Item = Device>) -> Self {
         Self {
-            server_name: None,
-            max_age: 100,
-            devices: devices.into_iter().collect(),
+            options: MessageOptions::default(),
+            groups: Arc::new(RwLock::new(Arc::new(vec![DeviceGroup::new(devices)]))),
             headers: vec![],
-            partial_req_workaround: false,
+            compat: Profile::default(),
+            receive_buffer: DEFAULT_RECEIVE_BUFFER,
+            max_headers: DEFAULT_MAX_HEADERS,
+            missing_mx: MissingMx::default(),
+            response_delay: ResponseDelay::default(),
+            max_mx: DEFAULT_MAX_MX,
+            header_providers: vec![],
+            search_hook: None,
+            response_customizer: None,
+            diagnostic_responses: false,
+            event_log: None,
+            status: None,
+            #[cfg(feature = "mdns")]
+            mdns_service_type: None,
+            #[cfg(feature = "http")]
+            description: None,
+            #[cfg(feature = "wsd")]
+            ws_discovery: None,
+            boot_id: None,
+            nls: None,
+            boot_id_store: None,
+            config_id: 1,
+            byebye_deadline: None,
+            pending_responses: PendingResponses::default(),
+            max_pending_responses: None,
+            response_slots: None,
+            alive_cycle_tx: broadcast::channel(16).0,
+            errors_tx: broadcast::channel(16).0,
+            events_tx: broadcast::channel(64).0,
+            health: Health::default(),
+            response_history: ResponseHistory::default(),
+            shared: SharedLoop::default(),
+            advertiser: Advertiser {
+                fast_until: Arc::new(watch::channel(None).0),
+            },
+            availability: None,
+            ready_tx: Arc::new(watch::channel(false).0),
+            per_interface: false,
+            addr_in_use: AddrInUse::default(),
+            reuse_port: false,
+            standby: None,
+            active_tx: Arc::new(watch::channel(true).0),
+            standby_state: Arc::new(Mutex::new(Standby {
+                last_seen: Instant::now(),
+                active_boot_id: None,
+            })),
+            current_boot_id: Arc::default(),
+            current_config_id: Arc::default(),
+            restart: None,
+            restart_tx: broadcast::channel(16).0,
+            announce_retry: Some(DEFAULT_ANNOUNCE_RETRY),
+            watchdog_interval: None,
+            rejoin_tx: broadcast::channel(16).0,
+            host_addr: Arc::new(RwLock::new(Ipv4Addr::UNSPECIFIED)),
+            address_watch: None,
+            interface_watch: None,
+            address_change_tx: broadcast::channel(16).0,
+            duplicate_policy: DuplicatePolicy::default(),
+            duplicate_tx: broadcast::channel(16).0,
+            backed_off: Arc::default(),
+            message_cache: MessageCache::default(),
+            response_sources: vec![],
+            search_port: None,
+            response_ttl: None,
+            notify_ttl: DEFAULT_NOTIFY_TTL,
+            multicast_loop: true,
+            min_ttl: None,
+            strict_destination: false,
+            throttle: Throttle::new(),
+            search_limit: SearchLimit::new(),
+            allowed: vec![],
+            denied: vec![],
+            notify_interval: None,
+            notify_jitter: Duration::ZERO,
+            repeat: 1,
+            repeat_spacing: Duration::ZERO,
+            pacing: Pacing::default(),
+            batch_size: 1,
+            #[cfg(feature = "tower")]
+            search_service: None,
+            interface: None,
+            #[cfg(feature = "tokio-util")]
+            cancellation_token: None,
         }
     }
 
-    /// Set the name of the `Server` response header, defaults to `Tokio-SSDP/1.0 UPnP/1.0`.
+    /// Set the name of the `Server` response header, defaults to `Tokio-SSDP/1.0 UPnP/<version>`,
+    /// see [`Server::upnp_version`].
     /// # Examples
     /// ```
     /// use tokio_ssdp::Server;
@@ -60,13 +754,105 @@ impl Server {
     ///   .server_name("SomeRandomDevice/1.0 UPnP/1.0");
     /// ```
     pub fn server_name(mut self, server_name: impl Into<String>) -> Self {
-        self.server_name = Some(server_name.into());
+        self.options.server_name = Some(server_name.into());
+        self
+    }
+
+    /// Set the version token used in the status and request lines, defaults to `HTTP/1.1`.
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::Server;
+    ///
+    /// Server::new([])
+    ///   .http_version("HTTP/1.0");
+    /// ```
+    pub fn http_version(mut self, http_version: impl Into<String>) -> Self {
+        self.options.http_version = Some(http_version.into());
         self
     }
 
     /// Set the value of `Cache-Control: max-age=`, which is the valid time for the message, defaults to 100.
     pub fn max_age(mut self, max_age: u64) -> Self {
-        self.max_age = max_age;
+        self.options.max_age = max_age;
+        self
+    }
+
+    /// Set the interval between `ssdp:alive` announcements, defaults to a third of the
+    /// max age, so control points keep the devices when an announcement is lost.
+    ///
+    /// The specification requires announcing more often than every half max age.
+    /// Groups can override the interval, see [`DeviceGroup::notify_interval`].
+    /// # Examples
+    /// ```
+    /// use std::time::Duration;
+    /// use tokio_ssdp::Server;
+    ///
+    /// Server::new([])
+    ///   .max_age(1800)
+    ///   .notify_interval(Duration::from_secs(600))
+    ///   .notify_jitter(Duration::from_secs(60));
+    /// ```
+    pub fn notify_interval(mut self, notify_interval: Duration) -> Self {
+        self.notify_interval = Some(notify_interval);
+        self
+    }
+
+    /// Shorten every interval between `ssdp:alive` announcements by a random duration
+    /// up to `jitter`, so announcements of many devices don't synchronize, defaults to none.
+    pub fn notify_jitter(mut self, jitter: Duration) -> Self {
+        self.notify_jitter = jitter;
+        self
+    }
+
+    /// Add a group of devices with its own announcement schedule, see [`DeviceGroup`].
+    pub fn group(self, group: DeviceGroup) -> Self {
+        let mut groups = self.groups().to_vec();
+        groups.push(group);
+        *self.groups.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(groups);
+        self
+    }
+
+    /// Set a deadline for sending `ssdp:byebye` messages on shutdown.
+    ///
+    /// Pacing between messages is compressed to fit the deadline, root devices are
+    /// sent first, and messages which still don't fit are dropped.
+    pub fn byebye_deadline(mut self, deadline: Duration) -> Self {
+        self.byebye_deadline = Some(deadline);
+        self
+    }
+
+    /// Set what to do with delayed search responses on shutdown, defaults to cancelling them.
+    ///
+    /// `ssdp:byebye` messages are only sent once all pending responses have been
+    /// sent or cancelled.
+    /// # Examples
+    /// ```
+    /// use std::time::Duration;
+    /// use tokio_ssdp::{PendingResponses, Server};
+    ///
+    /// Server::new([])
+    ///   .pending_responses(PendingResponses::Flush(Duration::from_millis(500)));
+    /// ```
+    pub fn pending_responses(mut self, pending_responses: PendingResponses) -> Self {
+        self.pending_responses = pending_responses;
+        self
+    }
+
+    /// Limit the number of search responses waiting for their delay, unlimited by default.
+    ///
+    /// Searches arriving while the limit is reached are ignored, as with
+    /// [`Server::search_limit`], which keeps a burst of searches from piling up tasks and
+    /// memory. A search matching more devices than the limit takes all of it, and a search
+    /// waiting for the [`Server::on_search`] hook takes one. When serving multiple
+    /// interfaces, each has its own limit. A limit of 0 lifts the limit again.
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::Server;
+    ///
+    /// Server::new([]).max_pending_responses(256);
+    /// ```
+    pub fn max_pending_responses(mut self, limit: usize) -> Self {
+        self.max_pending_responses = (limit > 0).then_some(limit);
         self
     }
 
@@ -76,361 +862,6692 @@ impl Server {
     ///
     /// To work with these clients, enable `partial_req_workaround`.
     pub fn partial_request_workaround(mut self, partial_req_workaround: bool) -> Self {
-        self.partial_req_workaround = partial_req_workaround;
+        self.compat.partial_request = partial_req_workaround;
         self
     }
 
-    /// Add an extra header to search responses
+    /// Set the size of the receive buffer in bytes, defaults to 2048. Larger datagrams
+    /// are truncated, and dropped as incomplete.
     /// # Examples
     /// ```
     /// use tokio_ssdp::Server;
     ///
     /// Server::new([])
-    ///   .extra_header("CONFIGID.UPNP.ORG", "1");
+    ///   .receive_buffer(8192)
+    ///   .max_headers(128);
     /// ```
-    pub fn extra_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
-        self.headers.push((name.into(), value.into()));
+    pub fn receive_buffer(mut self, receive_buffer: usize) -> Self {
+        self.receive_buffer = receive_buffer;
         self
     }
 
-    /// Start serving on all interfaces, see `serve_addr` for details.
-    pub fn serve(self) -> IoResult<impl Future<Output = IoResult<()>>> {
-        self.serve_addr(Ipv4Addr::new(0, 0, 0, 0))
+    /// Set the maximum number of headers of a request, defaults to 64. Requests with
+    /// more headers are dropped.
+    pub fn max_headers(mut self, max_headers: usize) -> Self {
+        self.max_headers = max_headers;
+        self
     }
 
-    /// Start serving on `ip`, returns a future that needs to be `await`ed to keep the server running.
+    /// Some clients send `MAN: ssdp:discover` without the mandatory quotes,
+    /// or with surplus whitespace, which is rejected by default.
+    ///
+    /// To work with these clients, enable `unquoted_man_workaround`.
+    pub fn unquoted_man_workaround(mut self, unquoted_man_workaround: bool) -> Self {
+        self.compat.unquoted_man = unquoted_man_workaround;
+        self
+    }
+
+    /// Tolerate the malformed requests of broken control points, such as cheap IoT
+    /// devices, which are dropped by default.
+    ///
+    /// Requests are cleaned up before parsing: NUL bytes are removed, `LF` line endings
+    /// and lower case methods are accepted, and a missing empty line at the end is
+    /// added. Implies [`Server::partial_request_workaround`] and
+    /// [`Server::unquoted_man_workaround`].
     /// # Examples
-    /// ```no_run
+    /// ```
     /// use tokio_ssdp::Server;
-    /// use std::net::Ipv4Addr;
     ///
-    /// Server::new([])
-    ///   .serve_addr(Ipv4Addr::new(192, 168, 1, 100));
+    /// Server::new([]).lenient_parsing(true);
     /// ```
-    pub fn serve_addr(self, ip: Ipv4Addr) -> IoResult<impl Future<Output = IoResult<()>>> {
-        let this = Arc::new(self);
+    pub fn lenient_parsing(mut self, lenient_parsing: bool) -> Self {
+        self.compat.normalize = lenient_parsing;
+        self
+    }
 
-        let s = {
-            use socket2::{Domain, Protocol, Socket, Type};
-            let s = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
-            s.set_reuse_address(true)?;
-            s.set_nonblocking(true)?;
-            s.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, SSDP_PORT)).into())?;
-            s.join_multicast_v4(&SSDP_ADDR, &ip)?;
-            s.set_multicast_loop_v4(true)?;
-            s
-        };
-        let rx_socket = Arc::new(UdpSocket::from_std(s.into())?);
+    /// Tolerate the quirks of broken control points in `profile`, replacing the quirks
+    /// enabled with [`Server::partial_request_workaround`],
+    /// [`Server::unquoted_man_workaround`] and [`Server::lenient_parsing`].
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{Server, compat::Profile};
+    ///
+    /// Server::new([])
+    ///   .compat(Profile::new().unquoted_man(true).lenient_mx(true));
+    /// ```
+    pub fn compat(mut self, profile: Profile) -> Self {
+        self.compat = profile;
+        self
+    }
 
-        let s = {
-            use socket2::{Domain, Protocol, Socket, Type};
-            let s = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
-            s.set_nonblocking(true)?;
-            s.bind(&SocketAddr::from((ip, 0)).into())?;
-            s
-        };
+    /// Answer invalid or rejected `M-SEARCH` requests with `412 Precondition Failed`,
+    /// with the reason in an `X-SSDP-ERROR` header, instead of ignoring them.
+    ///
+    /// This is not allowed by the specification, but helps diagnosing why a control
+    /// point isn't answered. Don't enable it in production.
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::Server;
+    ///
+    /// Server::new([])
+    ///   .diagnostic_responses(cfg!(debug_assertions));
+    /// ```
+    pub fn diagnostic_responses(mut self, diagnostic_responses: bool) -> Self {
+        self.diagnostic_responses = diagnostic_responses;
+        self
+    }
 
-        let tx_socket = Arc::new(UdpSocket::from_std(s.into())?);
+    /// Set how to handle `M-SEARCH` requests without an `MX` header, defaults to answering immediately.
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{MissingMx, Server};
+    ///
+    /// Server::new([])
+    ///   .missing_mx(MissingMx::Assume(1));
+    /// ```
+    pub fn missing_mx(mut self, missing_mx: MissingMx) -> Self {
+        self.missing_mx = missing_mx;
+        self
+    }
 
-        info!("Listening on {}", rx_socket.local_addr()?);
+    /// Set how the delay before answering `M-SEARCH` requests is chosen, defaults to
+    /// uniformly distributed between zero and `MX` seconds.
+    ///
+    /// Use a short delay where answering quickly matters, e.g. for casting, and spread
+    /// the responses where many devices answer the same search.
+    /// # Examples
+    /// ```
+    /// use std::time::Duration;
+    /// use tokio_ssdp::{ResponseDelay, Server};
+    ///
+    /// Server::new([])
+    ///   .response_delay(ResponseDelay::Exponential(Duration::from_millis(200)));
+    /// ```
+    pub fn response_delay(mut self, response_delay: ResponseDelay) -> Self {
+        self.response_delay = response_delay;
+        self
+    }
 
-        // Pre-concat headers
-        let extra_headers = Arc::new(
-            this.headers
-                .iter()
-                .map(|(name, value)| format!("{}: {}\r\n", name, value))
-                .collect::<Vec<_>>()
-                .join(""),
-        );
+    /// Cap the `MX` value of `M-SEARCH` requests at `max_mx` seconds when choosing the
+    /// response delay, defaults to 5 as advised by the specification.
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::Server;
+    ///
+    /// Server::new([])
+    ///   .max_mx(3);
+    /// ```
+    pub fn max_mx(mut self, max_mx: u32) -> Self {
+        self.max_mx = max_mx;
+        self
+    }
 
-        let server_fut = async move {
-            let mut buf = [0u8; 2048];
+    /// Set the order of the standard headers in search responses and notifications.
+    ///
+    /// Headers listed here are emitted first, in the given order, followed by the
+    /// remaining headers in their default order. Individual devices can override
+    /// this with [`Device::header_order`].
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{Header, Server};
+    ///
+    /// Server::new([])
+    ///   .header_order([Header::St, Header::Usn, Header::Ext]);
+    /// ```
+    pub fn header_order(mut self, order: impl IntoIterator<Item = Header>) -> Self {
+        self.options.header_order = order.into_iter().collect();
+        self
+    }
 
-            let (_notify_alive_tx, mut notify_alive_rx) = oneshot::channel::<()>();
-            tokio::spawn({
-                let this = Arc::clone(&this);
+    /// Some UPnP stacks refuse messages without an explicit `CONTENT-LENGTH: 0`.
+    ///
+    /// To work with these stacks, enable `content_length`, which adds the header
+    /// to search responses and notifications.
+    pub fn content_length(mut self, content_length: bool) -> Self {
+        self.options.content_length = content_length;
+        self
+    }
+
+    /// Windows Media Player and other legacy DLNA clients ignore devices whose messages
+    /// lack the `OPT` and `01-NLS` headers of the Windows SSDP stack.
+    ///
+    /// To work with these clients, enable `nls`, which adds
+    /// `OPT: "http://schemas.upnp.org/upnp/1/0/"; ns=01` and an `01-NLS` header to search
+    /// responses and notifications. The `01-NLS` value is a random UUID, generated anew
+    /// for every server, i.e. every boot.
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{Device, SearchContext, SearchRequest, Server};
+    ///
+    /// let server = Server::new([
+    ///     Device::new("ad8782a0-9e28-422b-a6ae-670fe7c4c043", "upnp:rootdevice", "http://192.168.1.100:8080/desc.xml"),
+    /// ])
+    /// .nls(true);
+    /// let request = SearchRequest {
+    ///     st: "ssdp:all".into(),
+    ///     mx: Some(1),
+    ///     headers: vec![],
+    /// };
+    /// let ctx = SearchContext::new("192.168.1.20:50000".parse().unwrap());
+    /// let response = String::from_utf8(server.answer_search(&request, &ctx)?.remove(0).data).unwrap();
+    /// assert!(response.contains("OPT: \"http://schemas.upnp.org/upnp/1/0/\"; ns=01\r\n01-NLS: "));
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn nls(mut self, nls: bool) -> Self {
+        self.nls = nls.then(rng::uuid);
+        self
+    }
+
+    /// Set the boot id available as `{bootid}` in extra headers, defaults to the
+    /// number of seconds since the UNIX epoch when the server is started, or to one more
+    /// than the stored boot id with [`Server::boot_id_store`].
+    pub fn boot_id(mut self, boot_id: u32) -> Self {
+        self.boot_id = Some(boot_id);
+        self
+    }
+
+    /// Keep the boot id in `store`, so it increases across restarts.
+    ///
+    /// Unless set with [`Server::boot_id`], the boot id is one more than the stored boot
+    /// id when the server starts. The store is updated whenever the boot id changes.
+    pub fn boot_id_store(mut self, store: impl BootIdStore + 'static) -> Self {
+        self.boot_id_store = Some(Arc::new(store));
+        self
+    }
+
+    /// Set the configuration id (`CONFIGID.UPNP.ORG`) sent with UPnP 1.1 and later, defaults to 1.
+    ///
+    /// The specification requires it to change whenever the device or service
+    /// descriptions change.
+    pub fn config_id(mut self, config_id: u32) -> Self {
+        self.config_id = config_id;
+        self
+    }
+
+    /// Set the UPnP version to follow, defaults to 1.0.
+    ///
+    /// From UPnP 1.1, `BOOTID.UPNP.ORG` (see [`Server::boot_id`]) and `CONFIGID.UPNP.ORG`
+    /// (see [`Server::config_id`]) are added to all messages, so they must not be added
+    /// with [`Server::extra_header`]. The version is also reflected in the default
+    /// `SERVER` header.
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{Server, UpnpVersion};
+    ///
+    /// Server::new([])
+    ///   .upnp_version(UpnpVersion::V1_1)
+    ///   .config_id(7);
+    /// ```
+    pub fn upnp_version(mut self, upnp_version: UpnpVersion) -> Self {
+        self.options.upnp_version = upnp_version;
+        self
+    }
+
+    /// Add an extra header to search responses and notifications
+    ///
+    /// Extra headers are emitted in the order they were added. Adding a header
+    /// with the same name more than once emits each of them.
+    ///
+    /// The following placeholders are expanded in `value`:
+    ///  - `{uuid}`: the UUID of the device
+    ///  - `{location}`: the location of the device
+    ///  - `{addr}`: the address of the host, see [`Server::watch_address`]
+    ///  - `{local_addr}`: the address a search was received on, see [`Server::serve_interfaces`]
+    ///  - `{bootid}`: the boot id, see [`Server::boot_id`]
+    ///  - `{configid}`: the configuration id, see [`Server::config_id`]
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::Server;
+    ///
+    /// Server::new([])
+    ///   .extra_header("CONFIGID.UPNP.ORG", "1")
+    ///   .extra_header("X-User-Agent", "redsonic")
+    ///   .extra_header("X-User-Agent", "acme")
+    ///   .extra_header("X-BRIDGE-ID", "{uuid}");
+    /// ```
+    pub fn extra_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((None, name.into(), value.into()));
+        self
+    }
+
+    /// Add an extra header to one kind of message only
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{MessageKind, Server};
+    ///
+    /// Server::new([])
+    ///   .extra_header_for(MessageKind::Alive, "BOOTID.UPNP.ORG", "1")
+    ///   .extra_header_for(MessageKind::SearchResponse, "X-VENDOR", "acme");
+    /// ```
+    pub fn extra_header_for(
+        mut self,
+        kind: MessageKind,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        self.headers.push((Some(kind), name.into(), value.into()));
+        self
+    }
+
+    /// Add headers computed by `provider` every time a message is built, e.g. for
+    /// values which change over time. They follow the headers added with
+    /// [`Server::extra_header`].
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{HeaderContext, MessageKind, Server};
+    ///
+    /// Server::new([]).header_provider(|ctx: &HeaderContext| match ctx.kind {
+    ///     MessageKind::SearchResponse => vec![("X-LOAD".to_string(), "0.5".to_string())],
+    ///     _ => vec![],
+    /// });
+    /// ```
+    pub fn header_provider(mut self, provider: impl HeaderProvider + 'static) -> Self {
+        self.header_providers.push(Arc::new(provider));
+        self
+    }
+
+    /// Record searches, responses and announcements in `event_log`, e.g. as an audit trail.
+    /// # Examples
+    /// ```
+    /// use tokio::sync::mpsc;
+    /// use tokio_ssdp::{EventLog, Server};
+    ///
+    /// let (tx, rx) = mpsc::unbounded_channel();
+    /// Server::new([]).event_log(EventLog::channel(tx));
+    /// ```
+    pub fn event_log(mut self, event_log: EventLog) -> Self {
+        self.event_log = Some(event_log);
+        self
+    }
+
+    /// Reflect the devices, health and recent events of the server in `status`, e.g.
+    /// to be served on a debug endpoint.
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{Server, Status};
+    ///
+    /// let status = Status::new().recent_events(100);
+    /// Server::new([]).status(status.clone());
+    /// ```
+    pub fn status(mut self, status: Status) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    /// Subscribe to the outcome of `ssdp:alive` broadcasts.
+    ///
+    /// An [`AliveCycle`] is received every time the `ssdp:alive` messages of a group of
+    /// devices have been sent, so supervisors can verify the server is still advertising.
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::Server;
+    ///
+    /// let server = Server::new([]);
+    /// let mut cycles = server.alive_cycles();
+    /// tokio::spawn(server.serve()?);
+    ///
+    /// while let Ok(cycle) = cycles.recv().await {
+    ///     if !cycle.is_ok() {
+    ///         eprintln!("Some alive messages were not sent: {:?}", cycle.results);
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn alive_cycles(&self) -> broadcast::Receiver<AliveCycle> {
+        self.alive_cycle_tx.subscribe()
+    }
+
+    /// Subscribe to the failures of the running server, e.g. to count or alert on them.
+    ///
+    /// The server logs and survives these failures, the receiver gets them as typed
+    /// [`Error`]s. Receivers lagging behind miss the oldest failures.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::{Error, Server};
+    ///
+    /// let server = Server::new([]);
+    /// let mut errors = server.errors();
+    /// tokio::spawn(server.serve()?);
+    ///
+    /// while let Ok(error) = errors.recv().await {
+    ///     if let Error::Send { usn, .. } = &error {
+    ///         eprintln!("Device {} is not reachable: {}", usn, error);
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn errors(&self) -> broadcast::Receiver<Error> {
+        self.errors_tx.subscribe()
+    }
+
+    /// Subscribe to what the running server does, e.g. to show which control points are
+    /// discovering the devices.
+    ///
+    /// Events are only made while there are receivers. Receivers lagging behind miss the
+    /// oldest events.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::{Server, ServerEvent};
+    ///
+    /// let server = Server::new([]);
+    /// let mut events = server.events();
+    /// tokio::spawn(server.serve()?);
+    ///
+    /// while let Ok(event) = events.recv().await {
+    ///     if let ServerEvent::SearchReceived { st, control_point, .. } = event {
+    ///         println!("{} is looking for {}", control_point, st);
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn events(&self) -> broadcast::Receiver<ServerEvent> {
+        self.events_tx.subscribe()
+    }
+
+    /// Get the liveness information of the server, e.g. to be reflected by a health endpoint.
+    /// # Examples
+    /// ```no_run
+    /// # fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::Server;
+    ///
+    /// let server = Server::new([]);
+    /// let health = server.health();
+    /// tokio::spawn(server.serve()?);
+    ///
+    /// let report = health.report();
+    /// println!("Last notify: {:?}", report.last_notify);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn health(&self) -> Health {
+        self.health.clone()
+    }
+
+    /// Keep the last `capacity` sent search responses, e.g. to check after the fact
+    /// whether a control point was answered. Nothing is kept by default.
+    /// # Examples
+    /// ```no_run
+    /// # fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::Server;
+    ///
+    /// let server = Server::new([]).keep_responses(100);
+    /// let history = server.response_history();
+    /// tokio::spawn(server.serve()?);
+    ///
+    /// for response in history.recent_responses() {
+    ///     println!("{:?}: {} for {}", response.time, response.remote_addr, response.usn);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn keep_responses(self, capacity: usize) -> Self {
+        self.response_history.set_capacity(capacity);
+        self
+    }
+
+    /// Get the recently sent search responses of the server, see [`Server::keep_responses`].
+    pub fn response_history(&self) -> ResponseHistory {
+        self.response_history.clone()
+    }
+
+    /// Get a client sharing the socket and receive loop of the server, so devices can be
+    /// discovered without binding the SSDP port a second time.
+    ///
+    /// Searches of the client are sent from the socket of the server while it runs, and
+    /// fail with [`ErrorKind::NotConnected`](std::io::ErrorKind::NotConnected) otherwise.
+    /// The search responses and `NOTIFY` messages received by the server are passed to
+    /// the client, so a [`Registry`](crate::Registry) from [`Client::discover`] also
+    /// tracks the devices announcing themselves. The socket settings of the client, like
+    /// [`Client::bind_addr`] and [`Client::socket_strategy`], are not used, and searches go
+    /// to the [multicast endpoint](Server::multicast_endpoint) set when calling this.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::{RegistryEvent, Server};
+    ///
+    /// let server = Server::new([]);
+    /// let client = server.client();
+    /// tokio::spawn(server.serve()?);
+    ///
+    /// let mut registry = client.discover("ssdp:all").await?;
+    /// while let Some(event) = registry.next().await {
+    ///     if let RegistryEvent::Added(device) = event {
+    ///         println!("{} at {}", device.usn, device.location);
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn client(&self) -> Client {
+        let multicast = self.options.multicast;
+        Client::new()
+            .multicast_endpoint(*multicast.ip(), multicast.port())
+            .server(self.shared.clone())
+    }
+
+    /// Only advertise the devices while `availability` is `true`.
+    ///
+    /// When it becomes `false`, `ssdp:byebye` is sent for all devices, and searches are
+    /// ignored until it becomes `true` again, at which point the devices are announced.
+    /// Drive it from a scheduler to make devices discoverable during e.g. business hours only.
+    /// # Examples
+    /// ```no_run
+    /// # fn run() -> std::io::Result<()> {
+    /// use tokio::sync::watch;
+    /// use tokio_ssdp::Server;
+    ///
+    /// let (open_tx, open_rx) = watch::channel(true);
+    /// tokio::spawn(Server::new([]).availability(open_rx).serve()?);
+    ///
+    /// // Closing time
+    /// open_tx.send_replace(false);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn availability(mut self, availability: watch::Receiver<bool>) -> Self {
+        self.availability = Some(availability);
+        self
+    }
+
+    /// Get a future resolving once the server is ready, i.e. the multicast group has
+    /// been joined and the devices have been announced for the first time.
+    ///
+    /// Resolves to `false` if the server stopped before becoming ready. When serving
+    /// multiple interfaces, see [`Server::serve_interfaces`], the server is ready once
+    /// all of them are.
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::Server;
+    ///
+    /// let server = Server::new([]);
+    /// let ready = server.ready();
+    /// tokio::spawn(server.serve()?);
+    ///
+    /// if ready.await {
+    ///     println!("Discoverable");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn ready(&self) -> impl Future<Output = bool> + Send + 'static {
+        let mut rx = self.ready_tx.subscribe();
+        async move { rx.wait_for(|ready| *ready).await.is_ok() }
+    }
+
+    /// Get a handle to trigger announcements of the server while it is running.
+    /// # Examples
+    /// ```no_run
+    /// # fn run() -> std::io::Result<()> {
+    /// use std::time::Duration;
+    /// use tokio_ssdp::Server;
+    ///
+    /// let server = Server::new([]);
+    /// let advertiser = server.advertiser();
+    /// tokio::spawn(server.serve()?);
+    ///
+    /// // The "pair" button was pressed
+    /// advertiser.fast_advertise(Duration::from_secs(60));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn advertiser(&self) -> Advertiser {
+        self.advertiser.clone()
+    }
+
+    /// Re-create the receiving socket and re-join the multicast group when receiving fails,
+    /// instead of terminating the server.
+    ///
+    /// Attempts are delayed according to `backoff`, and the server terminates with the
+    /// original error once no attempts are left.
+    /// # Examples
+    /// ```
+    /// use std::time::Duration;
+    /// use tokio_ssdp::{Backoff, Server};
+    ///
+    /// Server::new([])
+    ///   .restart_on_error(Backoff::new(Duration::from_millis(500), Duration::from_secs(60)));
+    /// ```
+    pub fn restart_on_error(mut self, backoff: Backoff) -> Self {
+        self.restart = Some(backoff);
+        self
+    }
+
+    /// Run as the standby of another instance advertising the same devices, e.g. a
+    /// redundant gateway.
+    ///
+    /// The server stays silent while the other instance announces the devices, and takes
+    /// over once it hasn't done so for `timeout`, which must be longer than the
+    /// announcement interval of the other instance. When taking over, the boot id is
+    /// raised above the one announced by the other instance, so control points notice
+    /// the change.
+    /// # Examples
+    /// ```
+    /// use std::time::Duration;
+    /// use tokio_ssdp::Server;
+    ///
+    /// Server::new([])
+    ///   .standby(Duration::from_secs(150));
+    /// ```
+    pub fn standby(mut self, timeout: Duration) -> Self {
+        self.standby = Some(timeout);
+        self
+    }
+
+    /// Set what to do when the SSDP port is already in use by another process which
+    /// doesn't share it, defaults to failing.
+    /// # Examples
+    /// ```
+    /// use std::time::Duration;
+    /// use tokio_ssdp::{AddrInUse, Backoff, Server};
+    ///
+    /// Server::new([])
+    ///   .addr_in_use(AddrInUse::Retry(Backoff::new(Duration::from_secs(1), Duration::from_secs(60))));
+    /// ```
+    pub fn addr_in_use(mut self, addr_in_use: AddrInUse) -> Self {
+        self.addr_in_use = addr_in_use;
+        self
+    }
+
+    /// Bind the SSDP port with `SO_REUSEPORT` in addition to `SO_REUSEADDR`, disabled by
+    /// default. Requires the `socket2` feature, and a unix platform.
+    ///
+    /// This lets the port be shared with other SSDP daemons and instances doing the same,
+    /// whatever [`Server::addr_in_use`] says about daemons which don't. All of them receive
+    /// the multicast searches, but searches sent to the port by unicast reach only one.
+    /// # Examples
+    /// ```
+    /// use std::time::Duration;
+    /// use tokio_ssdp::{AddrInUse, Backoff, Server};
+    ///
+    /// Server::new([])
+    ///   .reuse_port(true)
+    ///   .addr_in_use(AddrInUse::Retry(Backoff::new(Duration::from_secs(1), Duration::from_secs(60))));
+    /// ```
+    pub fn reuse_port(mut self, reuse_port: bool) -> Self {
+        self.reuse_port = reuse_port;
+        self
+    }
+
+    /// Use the multicast group `addr` and port `port` instead of `239.255.255.250:1900`.
+    ///
+    /// The server joins the group, listens for searches on the port, and sends
+    /// notifications there, with a matching `HOST` header. Standard control points won't
+    /// find the devices, so this is meant for tests which can't bind the SSDP port, and
+    /// for vendor specific discovery protocols built on SSDP.
+    /// # Examples
+    /// ```
+    /// use std::net::Ipv4Addr;
+    /// use tokio_ssdp::Server;
+    ///
+    /// Server::new([])
+    ///   .multicast_endpoint(Ipv4Addr::new(239, 255, 255, 251), 21900);
+    /// ```
+    pub fn multicast_endpoint(mut self, addr: Ipv4Addr, port: u16) -> Self {
+        self.options.multicast = SocketAddrV4::new(addr, port);
+        self
+    }
+
+    /// Subscribe to attempts to re-create the receiving socket, see [`Server::restart_on_error`].
+    pub fn restarts(&self) -> broadcast::Receiver<RestartAttempt> {
+        self.restart_tx.subscribe()
+    }
+
+    /// Set the retry policy for `ssdp:alive` and `ssdp:byebye` messages which failed to
+    /// send with a [temporary](SendErrorClass::Temporary) error, or `None` to never retry.
+    ///
+    /// Defaults to 3 attempts, starting at 20 ms.
+    /// # Examples
+    /// ```
+    /// use std::time::Duration;
+    /// use tokio_ssdp::{Backoff, Server};
+    ///
+    /// Server::new([])
+    ///   .announce_retry(Some(Backoff::new(Duration::from_millis(50), Duration::from_secs(1)).max_attempts(5)));
+    /// ```
+    pub fn announce_retry(mut self, backoff: Option<Backoff>) -> Self {
+        self.announce_retry = backoff;
+        self
+    }
+
+    /// Periodically verify the multicast group membership, and re-join the group if it was lost.
+    ///
+    /// Some drivers silently drop multicast memberships after an interface reset, after
+    /// which searches are no longer received.
+    /// # Examples
+    /// ```
+    /// use std::time::Duration;
+    /// use tokio_ssdp::Server;
+    ///
+    /// Server::new([])
+    ///   .membership_watchdog(Duration::from_secs(30));
+    /// ```
+    pub fn membership_watchdog(mut self, interval: Duration) -> Self {
+        self.watchdog_interval = Some(interval);
+        self
+    }
+
+    /// Subscribe to re-joins of the multicast group, see [`Server::membership_watchdog`].
+    pub fn rejoins(&self) -> broadcast::Receiver<Rejoin> {
+        self.rejoin_tx.subscribe()
+    }
+
+    /// Periodically check the address of the host, and re-announce devices when it changes.
+    ///
+    /// The address of the host is the address the server is serving on, or when serving
+    /// on all interfaces, the address of the interface used to reach the multicast group.
+    /// It is available as `{addr}` in device locations and extra headers.
+    ///
+    /// When the address changes, an `ssdp:byebye` is sent for every device with a location
+    /// containing `{addr}`, followed by an `ssdp:alive` with the new location.
+    /// # Examples
+    /// ```
+    /// use std::time::Duration;
+    /// use tokio_ssdp::{Device, Server};
+    ///
+    /// let uuid = "ad8782a0-9e28-422b-a6ae-670fe7c4c043";
+    ///
+    /// Server::new([Device::new(uuid, "upnp:rootdevice", "http://{addr}:8080/desc.xml")])
+    ///   .watch_address(Duration::from_secs(10));
+    /// ```
+    pub fn watch_address(mut self, interval: Duration) -> Self {
+        self.address_watch = Some(interval);
+        self
+    }
+
+    /// Re-join the multicast group and re-check the host address as soon as a network
+    /// interface or address changes, e.g. when Wi-Fi reconnects. With `announce`, all
+    /// devices are re-announced as well.
+    ///
+    /// Unlike [`Server::membership_watchdog`] and [`Server::watch_address`], changes are
+    /// reported by the OS, with netlink on Linux and Android, and with a routing socket on
+    /// macOS and the BSDs. Elsewhere a warning is logged and nothing is watched.
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{Device, Server};
+    ///
+    /// let uuid = "ad8782a0-9e28-422b-a6ae-670fe7c4c043";
+    ///
+    /// Server::new([Device::new(uuid, "upnp:rootdevice", "http://{addr}:8080/desc.xml")])
+    ///   .watch_interfaces(true);
+    /// ```
+    #[cfg(feature = "interface-events")]
+    pub fn watch_interfaces(mut self, announce: bool) -> Self {
+        self.interface_watch = Some(announce);
+        self
+    }
+
+    /// Subscribe to changes of the host address, see [`Server::watch_address`].
+    pub fn address_changes(&self) -> broadcast::Receiver<AddressChange> {
+        self.address_change_tx.subscribe()
+    }
+
+    /// Set what to do when another host announces one of our USNs, e.g. a cloned virtual
+    /// machine or a misconfigured second instance. Defaults to logging a warning.
+    /// # Examples
+    /// ```
+    /// use std::time::Duration;
+    /// use tokio_ssdp::{DuplicatePolicy, Server};
+    ///
+    /// Server::new([])
+    ///   .duplicate_policy(DuplicatePolicy::BackOff(Duration::from_secs(300)));
+    /// ```
+    pub fn duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = policy;
+        self
+    }
+
+    /// Subscribe to announcements of our USNs by other hosts, see [`Server::duplicate_policy`].
+    pub fn duplicates(&self) -> broadcast::Receiver<DuplicateResponder> {
+        self.duplicate_tx.subscribe()
+    }
+
+    /// Subscribe to the searches answered by the server, e.g. to find out which
+    /// control points are discovering the devices.
+    ///
+    /// This is [`Server::events`] keeping only [`ServerEvent::SearchAnswered`].
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::Server;
+    ///
+    /// let server = Server::new([]);
+    /// let mut searches = server.answered_searches();
+    /// tokio::spawn(server.serve()?);
+    ///
+    /// while let Ok(search) = searches.recv().await {
+    ///     println!("{} searched for {}", search.remote_addr, search.st);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn answered_searches(&self) -> AnsweredSearches {
+        AnsweredSearches {
+            events: self.events(),
+        }
+    }
+
+    /// Send search responses to control points in `source`'s subnet from `source`'s address.
+    ///
+    /// Some control points discard responses originating from an address outside their own
+    /// subnet, which can happen on hosts with multiple interfaces. Responses to control
+    /// points outside all configured subnets are sent from the address the search was
+    /// received on where the receiving interface is reported, see [`PacketInfo`], and the
+    /// source address of a datagram can be chosen (not on Apple platforms), and from the
+    /// default address elsewhere.
+    ///
+    /// The source address is chosen per response with `IP_PKTINFO` or `IP_SENDSRCADDR`
+    /// where supported, and with a socket bound to `source`'s address otherwise.
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::Server;
+    ///
+    /// Server::new([])
+    ///   .response_source("192.168.1.10/24".parse().unwrap())
+    ///   .response_source("10.0.0.5/8".parse().unwrap());
+    /// ```
+    pub fn response_source(mut self, source: Ipv4Subnet) -> Self {
+        self.response_sources.push(source);
+        self
+    }
+
+    /// Also listen for unicast `M-SEARCH` on `port`, as allowed from UPnP 1.1.
+    ///
+    /// The port is advertised as `SEARCHPORT.UPNP.ORG` in `ssdp:alive` and `ssdp:update`
+    /// with UPnP 1.1 and later, see [`Server::upnp_version`]. The specification requires
+    /// it to be in the range 49152-65535. Unicast searches, on this port or on the SSDP
+    /// port, are answered immediately.
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{Server, UpnpVersion};
+    ///
+    /// Server::new([])
+    ///   .upnp_version(UpnpVersion::V1_1)
+    ///   .search_port(49152);
+    /// ```
+    pub fn search_port(mut self, port: u16) -> Self {
+        self.search_port = Some(port);
+        self
+    }
+
+    /// Only handle messages from peers in `subnet`, e.g. to not advertise the devices to
+    /// an untrusted network the host is connected to.
+    ///
+    /// Without allowed subnets, messages from all peers are handled unless denied with
+    /// [`Server::deny_subnet`]. Messages from other peers are ignored without logging
+    /// beyond the `trace` level, and never answered, nor passed on to the clients from
+    /// [`Server::client`].
+    /// # Examples
+    /// ```
+    /// use std::net::Ipv4Addr;
+    /// use tokio_ssdp::Server;
+    ///
+    /// Server::new([])
+    ///   .allow_subnet("192.168.1.0/24".parse().unwrap())
+    ///   .deny_addr(Ipv4Addr::new(192, 168, 1, 13));
+    /// ```
+    pub fn allow_subnet(mut self, subnet: Ipv4Subnet) -> Self {
+        self.allowed.push(subnet);
+        self
+    }
+
+    /// Only handle messages from `addr`, see [`Server::allow_subnet`].
+    pub fn allow_addr(self, addr: Ipv4Addr) -> Self {
+        self.allow_subnet(Ipv4Subnet::from(addr))
+    }
+
+    /// Ignore messages from peers in `subnet`, even if allowed with
+    /// [`Server::allow_subnet`].
+    pub fn deny_subnet(mut self, subnet: Ipv4Subnet) -> Self {
+        self.denied.push(subnet);
+        self
+    }
+
+    /// Ignore messages from `addr`, see [`Server::deny_subnet`].
+    pub fn deny_addr(self, addr: Ipv4Addr) -> Self {
+        self.deny_subnet(Ipv4Subnet::from(addr))
+    }
+
+    /// Ignore searches received with a TTL below `min_ttl`.
+    ///
+    /// Control points on the local link send searches with a TTL of at least 1 (the
+    /// specification recommends 2), so a low remaining TTL indicates the search was routed
+    /// from another network, which is a sign of reflection attacks. The TTL is only known
+    /// on platforms supporting `IP_RECVTTL`; elsewhere no searches are ignored.
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::Server;
+    ///
+    /// Server::new([]).min_ttl(2);
+    /// ```
+    pub fn min_ttl(mut self, min_ttl: u8) -> Self {
+        self.min_ttl = Some(min_ttl);
+        self
+    }
+
+    /// Only answer searches addressed to the SSDP multicast group, disabled by default.
+    ///
+    /// Searches must carry `HOST: 239.255.255.250:1900`, and must have been sent to the
+    /// multicast group rather than to the address of the host, which rejects searches
+    /// injected by unicast, e.g. with a spoofed sender for reflection attacks. Searches on
+    /// the [`Server::search_port`] must name the address and port they were sent to
+    /// instead. The destination of a search is only known on platforms reporting it, see
+    /// [`PacketInfo`]; elsewhere only `HOST` is checked.
+    ///
+    /// Rejected searches are reported as [`Error::InvalidSearch`], without a diagnostic
+    /// response.
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::Server;
+    ///
+    /// Server::new([]).strict_destination(true);
+    /// ```
+    pub fn strict_destination(mut self, strict: bool) -> Self {
+        self.strict_destination = strict;
+        self
+    }
+
+    /// Set the IP TTL of unicast search responses, defaults to the TTL of the host.
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::Server;
+    ///
+    /// Server::new([])
+    ///   .response_ttl(64)
+    ///   .notify_ttl(4);
+    /// ```
+    pub fn response_ttl(mut self, ttl: u32) -> Self {
+        self.response_ttl = Some(ttl);
+        self
+    }
+
+    /// Set the IP TTL of multicast notifications, defaults to 2 as recommended by the
+    /// UPnP device architecture.
+    pub fn notify_ttl(mut self, ttl: u32) -> Self {
+        self.notify_ttl = ttl;
+        self
+    }
+
+    /// Whether multicast messages sent by the server are looped back to the host,
+    /// defaults to `true`.
+    ///
+    /// Disabling loopback hides the announcements from other SSDP clients on the same
+    /// host, such as a [`Monitor`](crate::Monitor).
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::Server;
+    ///
+    /// Server::new([])
+    ///   .notify_ttl(1)
+    ///   .multicast_loop(false);
+    /// ```
+    pub fn multicast_loop(mut self, multicast_loop: bool) -> Self {
+        self.multicast_loop = multicast_loop;
+        self
+    }
+
+    /// Limit the outbound traffic of the server, unlimited by default.
+    ///
+    /// The limit is applied on top of the pacing of announcements, see [`Throttle`].
+    pub fn throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    /// Limit the rate of searches the server answers, unlimited by default, see
+    /// [`SearchLimit`].
+    pub fn search_limit(mut self, search_limit: SearchLimit) -> Self {
+        self.search_limit = search_limit;
+        self
+    }
+
+    /// Send every announcement and search response `count` times, with a random delay
+    /// of up to `spacing` between the copies, defaults to once.
+    ///
+    /// The UPnP device architecture recommends sending messages more than once, as UDP
+    /// is unreliable and some control points miss single announcements. Copies are sent
+    /// on a best effort basis, only failures of the first one are reported.
+    /// # Examples
+    /// ```
+    /// use std::time::Duration;
+    /// use tokio_ssdp::Server;
+    ///
+    /// Server::new([]).repeat(3, Duration::from_millis(100));
+    /// ```
+    pub fn repeat(mut self, count: u32, spacing: Duration) -> Self {
+        self.repeat = count.max(1);
+        self.repeat_spacing = spacing;
+        self
+    }
+
+    /// Set how the messages of an announcement are spread out, defaults to 50 ms after
+    /// each message. Overridden by [`DeviceGroup::pacing`].
+    ///
+    /// Applies to `ssdp:alive`, `ssdp:byebye` and `ssdp:update` announcements of several
+    /// devices, but not to the delay before search responses, see [`ResponseDelay`].
+    /// # Examples
+    /// ```
+    /// use std::time::Duration;
+    /// use tokio_ssdp::{Pacing, Server};
+    ///
+    /// Server::new([]).pacing(Pacing::Spread(Duration::from_millis(500)));
+    /// ```
+    pub fn pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// Send up to `size` `ssdp:alive` messages of an announcement at once, with a single
+    /// `sendmmsg` call on Linux, defaults to 1.
+    ///
+    /// Only messages which aren't paced apart are batched, so this is meant for servers
+    /// with many devices and no [`Server::pacing`]. Batches are repeated and throttled as
+    /// single messages are, see [`Server::repeat`] and [`Server::throttle`].
+    /// # Examples
+    /// ```
+    /// use std::time::Duration;
+    /// use tokio_ssdp::{Pacing, Server};
+    ///
+    /// Server::new([])
+    ///   .pacing(Pacing::Fixed(Duration::ZERO))
+    ///   .batch_size(64);
+    /// ```
+    pub fn batch_size(mut self, size: usize) -> Self {
+        self.batch_size = size.max(1);
+        self
+    }
+
+    /// Pass incoming searches through `service` before answering them.
+    ///
+    /// The service can drop a search by responding with `None`, or modify it before it is
+    /// matched against the devices. Standard `tower` middleware can be layered on top, e.g.
+    /// for logging or rate limiting. Searches are passed through the service one at a
+    /// time, so a service which is not ready delays receiving; add a `load_shed` layer to
+    /// drop searches instead. Searches for which the service fails are dropped.
+    /// # Examples
+    /// ```
+    /// use std::convert::Infallible;
+    /// use tokio_ssdp::{IncomingSearch, Server};
+    /// use tower::service_fn;
+    ///
+    /// Server::new([]).search_service(service_fn(|search: IncomingSearch| async move {
+    ///     // Don't answer searches for everything
+    ///     Ok::<_, Infallible>((search.request.st != "ssdp:all").then_some(search))
+    /// }));
+    /// ```
+    #[cfg(feature = "tower")]
+    pub fn search_service<S>(mut self, service: S) -> Self
+    where
+        S: tower::Service<IncomingSearch, Response = Option<IncomingSearch>>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        S::Error: Into<tower::BoxError>,
+        S::Future: Send + 'static,
+    {
+        use tower::ServiceExt;
+        self.search_service = Some(SearchService::new(service.map_err(Into::into)));
+        self
+    }
+
+    /// Shut the server down when `token` is cancelled, after sending `ssdp:byebye` for
+    /// all devices, like [`ServerHandle::shutdown`].
+    ///
+    /// This ties the server to the task tree of the application, so cancelling it stops
+    /// the server cleanly instead of aborting it mid-announcement.
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::Server;
+    /// use tokio_util::sync::CancellationToken;
+    ///
+    /// let token = CancellationToken::new();
+    /// let server = tokio::spawn(Server::new([]).cancellation_token(token.child_token()).serve()?);
+    ///
+    /// tokio::signal::ctrl_c().await?;
+    /// token.cancel();
+    /// server.await??;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "tokio-util")]
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.cancellation_token = Some(token);
+        self
+    }
+
+    /// Decide how to handle each `M-SEARCH` request with `hook`, e.g. to ignore some
+    /// control points, or to tag the responses depending on who is asking.
+    ///
+    /// The hook runs after the search was validated and before it is matched against the
+    /// devices. Each search waits for its decision in a task of its own, so a slow hook
+    /// delays the responses to that search without holding up the others. Searches still
+    /// waiting for a decision on shutdown are dropped.
+    /// # Examples
+    /// ```
+    /// use std::net::SocketAddr;
+    /// use tokio_ssdp::{SearchDecision, SearchRequest, Server};
+    ///
+    /// Server::new([]).on_search(|request: &SearchRequest, from: SocketAddr| {
+    ///     let is_vendor_app = request
+    ///         .header("USER-AGENT")
+    ///         .is_some_and(|agent| agent.contains("acme"));
+    ///
+    ///     async move {
+    ///         if from.ip().is_loopback() {
+    ///             SearchDecision::Ignore
+    ///         } else if is_vendor_app {
+    ///             SearchDecision::AnswerWith(vec![("X-ACME-CLIENT".into(), from.ip().to_string())])
+    ///         } else {
+    ///             SearchDecision::Answer
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn on_search(mut self, hook: impl SearchHook + 'static) -> Self {
+        self.search_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Customize the response of each matching device to a search with `customizer`,
+    /// e.g. to add a session token, or to send another location to guests.
+    ///
+    /// Unlike [`Server::on_search`], the customizer runs once per response, with the
+    /// device it is about. Search responses are not cached while a customizer is set.
+    /// # Examples
+    /// ```
+    /// use std::net::IpAddr;
+    /// use tokio_ssdp::{Device, Ipv4Subnet, ResponseOverrides, SearchContext, SearchRequest, Server};
+    ///
+    /// let guests: Ipv4Subnet = "192.168.100.0/24".parse().unwrap();
+    ///
+    /// let server = Server::new([Device::new(
+    ///     "ad8782a0-9e28-422b-a6ae-670fe7c4c043",
+    ///     "upnp:rootdevice",
+    ///     "http://192.168.1.100:8080/desc.xml",
+    /// )])
+    /// .customize_responses(
+    ///     move |_: &SearchRequest, _: &Device, ctx: &SearchContext| match ctx.remote_addr.ip() {
+    ///         IpAddr::V4(ip) if guests.contains(ip) => {
+    ///             ResponseOverrides::default().location("http://192.168.100.1:8080/guest.xml")
+    ///         }
+    ///         _ => ResponseOverrides::default().header("X-SESSION", "6f1c2e"),
+    ///     },
+    /// );
+    ///
+    /// let request = SearchRequest {
+    ///     st: "ssdp:all".into(),
+    ///     mx: Some(1),
+    ///     headers: vec![],
+    /// };
+    /// let guest = SearchContext::new("192.168.100.20:50000".parse().unwrap());
+    /// let response = &server.answer_search(&request, &guest)?[0].data;
+    /// assert!(String::from_utf8_lossy(response).contains("LOCATION: http://192.168.100.1:8080/guest.xml\r\n"));
+    ///
+    /// let member = SearchContext::new("192.168.1.20:50000".parse().unwrap());
+    /// let response = &server.answer_search(&request, &member)?[0].data;
+    /// assert!(String::from_utf8_lossy(response).contains("X-SESSION: 6f1c2e\r\n"));
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn customize_responses(mut self, customizer: impl ResponseCustomizer + 'static) -> Self {
+        self.response_customizer = Some(Arc::new(customizer));
+        self
+    }
+
+    /// Also announce the root devices over mDNS / DNS-SD, as instances of `service_type`
+    /// (e.g. `_http._tcp`) with the `LOCATION` in a `location` TXT entry.
+    ///
+    /// The records are announced when the server starts, and withdrawn when it stops.
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{Device, Server};
+    ///
+    /// Server::new([Device::new(
+    ///     "ad8782a0-9e28-422b-a6ae-670fe7c4c043",
+    ///     "upnp:rootdevice",
+    ///     "http://192.168.1.100:8080/desc.xml",
+    /// )])
+    /// .mdns("_http._tcp");
+    /// ```
+    #[cfg(feature = "mdns")]
+    pub fn mdns(mut self, service_type: impl Into<String>) -> Self {
+        self.mdns_service_type = Some(service_type.into());
+        self
+    }
+
+    /// Also announce the root devices over WS-Discovery, e.g. to be found by ONVIF
+    /// clients and Windows network discovery.
+    ///
+    /// The devices are announced with `Hello` when the server starts and `Bye` when it
+    /// stops, and `Probe` requests matching `ws_discovery` are answered.
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{Device, Server, WsDiscovery};
+    ///
+    /// Server::new([Device::new(
+    ///     "ad8782a0-9e28-422b-a6ae-670fe7c4c043",
+    ///     "upnp:rootdevice",
+    ///     "http://192.168.1.100:8080/desc.xml",
+    /// )])
+    /// .ws_discovery(WsDiscovery::new());
+    /// ```
+    #[cfg(feature = "wsd")]
+    pub fn ws_discovery(mut self, ws_discovery: WsDiscovery) -> Self {
+        self.ws_discovery = Some(ws_discovery);
+        self
+    }
+
+    /// Serve `description` over HTTP while the server runs.
+    ///
+    /// `{description}` in device locations is replaced by the URL of the description,
+    /// on the address a search was received on, so the `LOCATION` header always matches
+    /// the port the description is served on.
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{Description, Device, Server};
+    ///
+    /// let uuid = "ad8782a0-9e28-422b-a6ae-670fe7c4c043";
+    /// Server::new([Device::new(uuid, "upnp:rootdevice", "{description}")])
+    ///     .description(Description::render(move || {
+    ///         format!("<root><device><UDN>uuid:{uuid}</UDN></device></root>")
+    ///     }));
+    /// ```
+    #[cfg(feature = "http")]
+    pub fn description(mut self, description: Description) -> Self {
+        self.description = Some(description);
+        self
+    }
+
+    /// Start serving on all interfaces, see `serve_addr` for details.
+    pub fn serve(self) -> IoResult<impl Future<Output = IoResult<()>>> {
+        self.serve_addr(Ipv4Addr::new(0, 0, 0, 0))
+    }
+
+    /// Start serving on each of `ips`, e.g. the addresses of all interfaces of a
+    /// multi-homed host, returns a future that needs to be `await`ed to keep the server
+    /// running.
+    ///
+    /// Each interface is served separately, so `{addr}` in device locations and extra
+    /// headers is the address of the interface a message is sent on. Searches are only
+    /// answered on the interface they were received on, which requires the receiving
+    /// interface to be reported, see [`PacketInfo`]; elsewhere each interface answers
+    /// every search.
+    ///
+    /// If serving one interface fails, the others are shut down, sending `ssdp:byebye`,
+    /// before the future returns the error.
+    ///
+    /// When serving on all interfaces, `{local_addr}` serves the same purpose for
+    /// search responses.
+    /// # Examples
+    /// ```no_run
+    /// use tokio_ssdp::{Device, Server};
+    /// use std::net::Ipv4Addr;
+    ///
+    /// Server::new([Device::new(
+    ///     "ad8782a0-9e28-422b-a6ae-670fe7c4c043",
+    ///     "upnp:rootdevice",
+    ///     "http://{addr}:8080/desc.xml",
+    /// )])
+    /// .serve_interfaces([Ipv4Addr::new(192, 168, 1, 100), Ipv4Addr::new(10, 0, 0, 1)]);
+    /// ```
+    pub fn serve_interfaces(
+        mut self,
+        ips: impl IntoIterator<Item = Ipv4Addr>,
+    ) -> IoResult<impl Future<Output = IoResult<()>>> {
+        // All interfaces share one boot id
+        self.initial_boot_id();
+        self.per_interface = true;
+
+        // Each interface becomes ready on its own, the server once all of them are
+        self.ready_tx.send_replace(false);
+        let mut ready_rxs = vec![];
+        let shutdown_tx: Arc<watch::Sender<bool>> = Arc::default();
+        let servers = ips
+            .into_iter()
+            .map(|ip| {
+                let mut server = self.clone();
+                server.ready_tx = Arc::new(watch::channel(false).0);
+                ready_rxs.push(server.ready_tx.subscribe());
+                server.serve_with(ip, Receiver::Bind, None, Arc::clone(&shutdown_tx))
+            })
+            .collect::<IoResult<Vec<_>>>()?;
+        let ready_tx = Arc::clone(&self.ready_tx);
+
+        Ok(async move {
+            let mut tasks = tokio::task::JoinSet::new();
+            for server in servers {
+                tasks.spawn(server);
+            }
+            let ready = tasks.spawn(async move {
+                for mut rx in ready_rxs {
+                    if rx.wait_for(|ready| *ready).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                debug!("All interfaces ready");
+                ready_tx.send_replace(true);
+                Ok(())
+            });
+            while let Some(res) = tasks.join_next().await {
+                let Err(e) = res.map_err(std::io::Error::other).and_then(|res| res) else {
+                    continue;
+                };
+
+                // Stop the other interfaces cleanly, so they still send byebye
+                error!("Serving an interface failed, shutting down: {}", e);
+                ready.abort();
+                shutdown_tx.send_replace(true);
+                while tasks.join_next().await.is_some() {}
+                return Err(e);
+            }
+            Ok(())
+        })
+    }
+
+    /// Start serving on the interface named `name`, e.g. `eth0`, returns a future that
+    /// needs to be `await`ed to keep the server running.
+    ///
+    /// Like [`Server::serve_addr`] with the address of the interface, and on Linux and
+    /// Android the sockets are also bound to the interface with `SO_BINDTODEVICE`, so
+    /// nothing is received or sent on other interfaces. Looking up the interface is only
+    /// supported on unix platforms.
+    /// # Examples
+    /// ```no_run
+    /// use tokio_ssdp::Server;
+    ///
+    /// Server::new([]).serve_interface("eth0");
+    /// ```
+    pub fn serve_interface(
+        mut self,
+        name: &str,
+    ) -> IoResult<impl Future<Output = IoResult<()>> + use<>> {
+        let ip = iface::ipv4_addr(name)?;
+        info!("Serving on interface {} ({})", name, ip);
+
+        self.interface = Some(name.to_string());
+        self.serve_with(ip, Receiver::Bind, None, Arc::default())
+    }
+
+    /// Start serving on all interfaces in a background task, see `spawn_addr` for details.
+    pub fn spawn(self) -> IoResult<ServerHandle> {
+        self.spawn_addr(Ipv4Addr::new(0, 0, 0, 0))
+    }
+
+    /// Start serving on `ip` in a background task, returning a handle to shut the
+    /// server down cleanly. Must be called from within a tokio runtime.
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio_ssdp::Server;
+    /// use std::net::Ipv4Addr;
+    ///
+    /// let handle = Server::new([]).spawn_addr(Ipv4Addr::new(192, 168, 1, 100))?;
+    ///
+    /// tokio::signal::ctrl_c().await?;
+    /// handle.shutdown().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn spawn_addr(self, ip: Ipv4Addr) -> IoResult<ServerHandle> {
+        let shutdown_tx = Arc::new(watch::channel(false).0);
+        let health = self.health();
+        let advertiser = self.advertiser();
+        let response_history = self.response_history();
+        let ready_tx = Arc::clone(&self.ready_tx);
+
+        let (control_tx, control_rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(self.serve_with(
+            ip,
+            Receiver::Bind,
+            Some(control_rx),
+            Arc::clone(&shutdown_tx),
+        )?);
+        Ok(ServerHandle {
+            shutdown_tx,
+            control_tx,
+            task,
+            health,
+            advertiser,
+            response_history,
+            ready_tx,
+        })
+    }
+
+    /// Start serving on `ip`, returns a future that needs to be `await`ed to keep the server running.
+    /// # Examples
+    /// ```no_run
+    /// use tokio_ssdp::Server;
+    /// use std::net::Ipv4Addr;
+    ///
+    /// Server::new([])
+    ///   .serve_addr(Ipv4Addr::new(192, 168, 1, 100));
+    /// ```
+    pub fn serve_addr(self, ip: Ipv4Addr) -> IoResult<impl Future<Output = IoResult<()>>> {
+        self.serve_with(ip, Receiver::Bind, None, Arc::default())
+    }
+
+    /// Start serving on a socket set up by the application, e.g. bound to a device with
+    /// `SO_BINDTODEVICE`, returns a future that needs to be `await`ed to keep the server
+    /// running.
+    ///
+    /// The socket must be bound to the SSDP port and joined to the multicast group; its
+    /// options are left as they are. Announcements and responses are sent from the address
+    /// the socket is bound to. The socket cannot be re-created, so receive errors stop the
+    /// server regardless of [`Server::restart_on_error`], and
+    /// [`Server::membership_watchdog`] does not apply.
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tokio::net::UdpSocket;
+    /// use tokio_ssdp::{SSDP_ADDR_V4, Server};
+    /// use std::net::Ipv4Addr;
+    ///
+    /// let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 1900)).await?;
+    /// socket.join_multicast_v4(SSDP_ADDR_V4, Ipv4Addr::new(192, 168, 1, 100))?;
+    ///
+    /// Server::new([]).serve_socket(socket)?.await
+    /// # }
+    /// ```
+    pub fn serve_socket(self, socket: UdpSocket) -> IoResult<impl Future<Output = IoResult<()>>> {
+        let ip = match socket.local_addr()?.ip() {
+            IpAddr::V4(ip) if !ip.is_multicast() => ip,
+            IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED,
+            IpAddr::V6(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "SSDP over IPv6 is not supported",
+                ));
+            }
+        };
+
+        self.serve_with(ip, Receiver::Socket(socket), None, Arc::default())
+    }
+
+    /// Start serving on `transport`, e.g. a stand-in for the network in tests, returns a
+    /// future that needs to be `await`ed to keep the server running.
+    ///
+    /// Everything is received and sent on `transport`, including announcements and
+    /// responses, so [`Server::search_port`] and [`Server::response_source`] do not apply.
+    /// As with [`Server::serve_socket`], receive errors stop the server. The interface a
+    /// search was received on is only known if `transport` is a UDP socket.
+    /// # Examples
+    /// ```
+    /// use std::{
+    ///     collections::VecDeque,
+    ///     io,
+    ///     net::SocketAddr,
+    ///     sync::Mutex,
+    ///     task::{Context, Poll},
+    /// };
+    /// use tokio::{io::ReadBuf, sync::mpsc};
+    /// use tokio_ssdp::{Device, ResponseDelay, Server, Transport};
+    ///
+    /// /// Receives canned datagrams, and passes on everything sent.
+    /// struct MockTransport {
+    ///     incoming: Mutex<VecDeque<(Vec<u8>, SocketAddr)>>,
+    ///     sent: mpsc::UnboundedSender<(String, SocketAddr)>,
+    /// }
+    ///
+    /// impl Transport for MockTransport {
+    ///     fn poll_send_to(
+    ///         &self,
+    ///         _cx: &mut Context<'_>,
+    ///         buf: &[u8],
+    ///         target: SocketAddr,
+    ///     ) -> Poll<io::Result<usize>> {
+    ///         let _ = self.sent.send((String::from_utf8_lossy(buf).into_owned(), target));
+    ///         Poll::Ready(Ok(buf.len()))
+    ///     }
+    ///
+    ///     fn poll_recv_from(
+    ///         &self,
+    ///         _cx: &mut Context<'_>,
+    ///         buf: &mut ReadBuf<'_>,
+    ///     ) -> Poll<io::Result<SocketAddr>> {
+    ///         match self.incoming.lock().unwrap().pop_front() {
+    ///             Some((data, addr)) => {
+    ///                 buf.put_slice(&data);
+    ///                 Poll::Ready(Ok(addr))
+    ///             }
+    ///             None => Poll::Pending,
+    ///         }
+    ///     }
+    ///
+    ///     fn local_addr(&self) -> io::Result<SocketAddr> {
+    ///         Ok("192.168.1.100:1900".parse().unwrap())
+    ///     }
+    /// }
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let control_point: SocketAddr = "192.168.1.20:50000".parse().unwrap();
+    /// let search = "M-SEARCH * HTTP/1.1\r\n\
+    ///               HOST: 239.255.255.250:1900\r\n\
+    ///               MAN: \"ssdp:discover\"\r\n\
+    ///               MX: 1\r\n\
+    ///               ST: upnp:rootdevice\r\n\r\n";
+    /// let (sent, mut outgoing) = mpsc::unbounded_channel();
+    /// let transport = MockTransport {
+    ///     incoming: Mutex::new(VecDeque::from([(search.as_bytes().to_vec(), control_point)])),
+    ///     sent,
+    /// };
+    ///
+    /// let server = Server::new([Device::new(
+    ///     "ad8782a0-9e28-422b-a6ae-670fe7c4c043",
+    ///     "upnp:rootdevice",
+    ///     "http://{addr}:8080/desc.xml",
+    /// )])
+    /// .response_delay(ResponseDelay::Immediate);
+    /// tokio::spawn(server.serve_transport(transport).unwrap());
+    ///
+    /// loop {
+    ///     let (message, target) = outgoing.recv().await.unwrap();
+    ///     if target == control_point {
+    ///         assert!(message.starts_with("HTTP/1.1 200 OK\r\n"));
+    ///         assert!(message.contains("LOCATION: http://192.168.1.100:8080/desc.xml\r\n"));
+    ///         break;
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn serve_transport(
+        self,
+        transport: impl Transport,
+    ) -> IoResult<impl Future<Output = IoResult<()>>> {
+        let ip = match transport.local_addr()?.ip() {
+            IpAddr::V4(ip) if !ip.is_multicast() => ip,
+            IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED,
+            IpAddr::V6(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "SSDP over IPv6 is not supported",
+                ));
+            }
+        };
+
+        self.serve_with(
+            ip,
+            Receiver::Transport(Arc::new(transport)),
+            None,
+            Arc::default(),
+        )
+    }
+
+    fn serve_with(
+        mut self,
+        ip: Ipv4Addr,
+        receiver: Receiver,
+        mut control_rx: Option<mpsc::UnboundedReceiver<Control>>,
+        shutdown_tx: Arc<watch::Sender<bool>>,
+    ) -> IoResult<impl Future<Output = IoResult<()>> + use<>> {
+        let boot_id = self.initial_boot_id();
+        self.current_boot_id = Arc::new(AtomicU32::new(boot_id));
+        self.current_config_id = Arc::new(AtomicU32::new(self.config_id));
+
+        // Devices added at runtime must not leak into clones of this server
+        self.groups = Arc::new(RwLock::new(self.groups()));
+
+        if let Some(status) = &self.status {
+            self.attach_status(status);
+
+            let status_log = EventLog::status(status.clone());
+            self.event_log = Some(match self.event_log.take() {
+                Some(event_log) => event_log.tee(status_log),
+                None => status_log,
+            });
+        }
+
+        self.active_tx.send_replace(self.standby.is_none());
+        *self.standby_state.lock().unwrap_or_else(|e| e.into_inner()) = Standby {
+            last_seen: Instant::now(),
+            active_boot_id: None,
+        };
+
+        self.host_addr = Arc::new(RwLock::new(Self::detect_host_addr(ip).unwrap_or(ip)));
+        self.backed_off = Arc::default();
+        self.message_cache = MessageCache::default();
+        self.response_slots = self
+            .max_pending_responses
+            .map(|limit| Arc::new(Semaphore::new(limit)));
+
+        #[cfg(feature = "http")]
+        let description_listener = match &mut self.description {
+            Some(description) => Some((description.bind(ip)?, description.clone())),
+            None => None,
+        };
+
+        let this = Arc::new(self);
+
+        let own_socket = matches!(receiver, Receiver::Bind);
+        let (bound, transport) = match receiver {
+            Receiver::Bind => (this.bind_rx_socket(ip).map(Self::transport), None),
+            Receiver::Socket(socket) => (Ok(Self::transport(socket)), None),
+            Receiver::Transport(transport) => (Ok(Arc::clone(&transport)), Some(transport)),
+        };
+        let (mut rx_socket, retry_bind) = match bound {
+            Ok(socket) => (socket, None),
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => match this.addr_in_use {
+                AddrInUse::Retry(backoff) => {
+                    warn!("SSDP port in use, retrying in the background: {}", e);
+                    (
+                        Self::transport(Self::bind_announce_only(ip)?),
+                        Some((backoff, e)),
+                    )
+                }
+                AddrInUse::AnnounceOnly => {
+                    warn!("SSDP port in use, only announcing: {}", e);
+                    (Self::transport(Self::bind_announce_only(ip)?), None)
+                }
+                AddrInUse::Fail | AddrInUse::Shared => {
+                    return Err(addr_in_use_error(this.options.multicast.port(), e));
+                }
+            },
+            Err(e) => return Err(e),
+        };
+        let announce_only = rx_socket.local_addr()?.port() != this.options.multicast.port();
+        this.health.set_multicast_joined(!announce_only);
+
+        let tx_socket = match &transport {
+            Some(transport) => Arc::clone(transport),
+            None => {
+                let s = std::net::UdpSocket::bind((ip, 0))?;
+                s.set_nonblocking(true)?;
+                this.configure_sending(&s)?;
+                if !ip.is_unspecified() {
+                    // Binding the address alone leaves multicast on the interface of the
+                    // default route
+                    iface::set_multicast_if(&s, ip)?;
+                }
+                Self::transport(UdpSocket::from_std(s)?)
+            }
+        };
+        let tx_port = tx_socket.local_addr()?.port();
+
+        // Where the source address can't be chosen per datagram, responses are sent from
+        // sockets bound to the configured sources
+        let response_sockets = this
+            .response_sources
+            .iter()
+            .filter(|_| transport.is_none() && !(pktinfo::SENDS_FROM && ip.is_unspecified()))
+            .map(|source| {
+                let socket = std::net::UdpSocket::bind((source.addr(), 0))?;
+                socket.set_nonblocking(true)?;
+                this.configure_sending(&socket)?;
+                Ok((source.addr(), Self::transport(UdpSocket::from_std(socket)?)))
+            })
+            .collect::<IoResult<Vec<_>>>()?;
+
+        let search_socket = match this.search_port.filter(|_| transport.is_none()) {
+            Some(port) => {
+                let socket = std::net::UdpSocket::bind((ip, port))?;
+                socket.set_nonblocking(true)?;
+                this.bind_to_interface(&socket)?;
+                if let Err(e) = pktinfo::enable(&socket) {
+                    warn!("Failed to enable packet info on search port: {}", e);
+                }
+                info!("Listening for unicast searches on {}", socket.local_addr()?);
+                Some(UdpSocket::from_std(socket)?)
+            }
+            None => None,
+        };
+
+        info!("Listening on {}", rx_socket.local_addr()?);
+
+        // Pre-concat headers
+        let extra_headers = Arc::new(ExtraHeaders {
+            search_response: this.concat_headers(MessageKind::SearchResponse),
+            alive: this.concat_headers(MessageKind::Alive),
+            byebye: this.concat_headers(MessageKind::Byebye),
+        });
+
+        this.ready_tx.send_replace(false);
+        let group_count = this.groups().len();
+        let unannounced = Arc::new(AtomicUsize::new(group_count));
+
+        let server_fut = async move {
+            let mut buf = vec![0u8; this.receive_buffer];
+            let mut search_buf = vec![0u8; this.receive_buffer];
+            #[cfg(feature = "tower")]
+            let mut search_service = this.search_service.clone();
+
+            let mut shutdown_rx = shutdown_tx.subscribe();
+            let (notify_alive_tx, notify_alive_rx) = watch::channel(());
+            for idx in 0..group_count {
+                tokio::spawn({
+                    let this = Arc::clone(&this);
+                    let socket = Arc::clone(&tx_socket);
+                    let extra_headers = Arc::clone(&extra_headers);
+                    let mut notify_alive_rx = notify_alive_rx.clone();
+                    let mut fast_rx = this.advertiser.fast_until.subscribe();
+                    let mut availability = this.availability.clone();
+                    let mut active_rx = this.active_tx.subscribe();
+                    let mut unannounced = Some(Arc::clone(&unannounced));
+
+                    async move {
+                        let interval = this.group_notify_interval(&this.groups()[idx]);
+
+                        loop {
+                            if this.is_available() {
+                                let groups = this.groups();
+                                let cycle = this
+                                    .broadcast_alive(&groups[idx], &socket, &extra_headers.alive)
+                                    .await;
+                                for (usn, res) in &cycle.results {
+                                    if let Err(e) = res {
+                                        error!("Send alive message for {} failed: {}", usn, e);
+                                    }
+                                }
+                                let _ = this.alive_cycle_tx.send(cycle);
+                            }
+
+                            if let Some(unannounced) = unannounced.take()
+                                && unannounced.fetch_sub(1, Ordering::AcqRel) == 1
+                            {
+                                debug!("Initial announcements sent, ready");
+                                this.ready_tx.send_replace(true);
+                            }
+
+                            tokio::select! {
+                                _ = tokio::time::sleep(this.advertiser.interval(this.jittered(interval))) => {
+                                    // It's time to send alive messages
+                                }
+                                Ok(()) = fast_rx.changed() => {
+                                    debug!("Fast advertising requested");
+                                }
+                                _ = availability_changed(availability.as_mut()) => {
+                                    // Announce immediately when becoming available
+                                }
+                                _ = availability_changed(Some(&mut active_rx)) => {
+                                    // Announce immediately when taking over from the active instance
+                                }
+                                _ = notify_alive_rx.changed() => {
+                                    // We should shut down
+                                    debug!("notify_alive shutdown");
+                                    return IoResult::Ok(());
+                                }
+                            }
+                        }
+                    }
+                });
+            }
+
+            #[cfg(feature = "tokio-util")]
+            if let Some(token) = this.cancellation_token.clone() {
+                let shutdown_tx = Arc::clone(&shutdown_tx);
+                let mut stopped = notify_alive_rx.clone();
+
+                tokio::spawn(async move {
+                    tokio::select! {
+                        _ = token.cancelled() => {
+                            info!("Cancelled");
+                            shutdown_tx.send_replace(true);
+                        }
+                        _ = stopped.changed() => {}
+                    }
+                });
+            }
+
+            if let Some(period) = this.address_watch {
+                tokio::spawn({
+                    let this = Arc::clone(&this);
+                    let socket = Arc::clone(&tx_socket);
+                    let extra_headers = Arc::clone(&extra_headers);
+                    let mut shutdown = notify_alive_rx.clone();
+
+                    async move {
+                        let start = tokio::time::Instant::now() + period;
+                        let mut interval = tokio::time::interval_at(start, period);
+                        loop {
+                            tokio::select! {
+                                _ = interval.tick() => {
+                                    this.check_host_addr(ip, &socket, &extra_headers).await;
+                                }
+                                _ = shutdown.changed() => return,
+                            }
+                        }
+                    }
+                });
+            }
+
+            #[cfg(feature = "mdns")]
+            if let Some(service_type) = &this.mdns_service_type {
+                let services = devices(&this.groups())
+                    .filter(|(_, device)| device.is_root())
+                    .map(|(_, device)| {
+                        MdnsService::new(
+                            service_type,
+                            device,
+                            &this.location(device, None),
+                            this.host_addr(),
+                        )
+                    })
+                    .collect();
+                let shutdown = notify_alive_rx.clone();
+                let errors_tx = this.errors_tx.clone();
+
+                tokio::spawn(async move {
+                    if let Err(e) = mdns::announce(ip, services, shutdown).await {
+                        error!("mDNS announcer failed: {}", e);
+                        let _ = errors_tx.send(Error::Service {
+                            service: "mdns",
+                            error: Arc::new(e),
+                        });
+                    }
+                });
+            }
+
+            #[cfg(feature = "wsd")]
+            if let Some(config) = &this.ws_discovery {
+                let targets = devices(&this.groups())
+                    .filter(|(_, device)| device.is_root())
+                    .map(|(_, device)| WsdTarget {
+                        address: format!(
+                            "urn:uuid:{}",
+                            device.uuid.as_deref().unwrap_or(&device.usn)
+                        ),
+                        xaddrs: match &config.xaddrs {
+                            Some(xaddrs) => expand_placeholders(
+                                xaddrs,
+                                &[
+                                    ("uuid", device.uuid.as_deref().unwrap_or_default()),
+                                    ("location", &this.location(device, None)),
+                                    ("addr", &this.host_addr().to_string()),
+                                ],
+                            )
+                            .into_owned(),
+                            None => this.location(device, None),
+                        },
+                    })
+                    .collect();
+                let config = config.clone();
+                let instance_id = this.current_boot_id.load(Ordering::Acquire);
+                let shutdown = notify_alive_rx.clone();
+                let errors_tx = this.errors_tx.clone();
+
+                tokio::spawn(async move {
+                    if let Err(e) = wsd::announce(ip, config, targets, instance_id, shutdown).await
+                    {
+                        error!("WS-Discovery responder failed: {}", e);
+                        let _ = errors_tx.send(Error::Service {
+                            service: "wsd",
+                            error: Arc::new(e),
+                        });
+                    }
+                });
+            }
+
+            #[cfg(feature = "http")]
+            if let Some((listener, description)) = description_listener {
+                let shutdown = notify_alive_rx.clone();
+                let errors_tx = this.errors_tx.clone();
+
+                tokio::spawn(async move {
+                    if let Err(e) = description.serve(listener, shutdown).await {
+                        error!("Description server failed: {}", e);
+                        let _ = errors_tx.send(Error::Service {
+                            service: "description",
+                            error: Arc::new(e),
+                        });
+                    }
+                });
+            }
+
+            if let Some(timeout) = this.standby {
+                tokio::spawn({
+                    let this = Arc::clone(&this);
+                    let mut shutdown = notify_alive_rx.clone();
+
+                    async move {
+                        info!("Standing by");
+                        loop {
+                            let last_seen = this.standby_state().last_seen;
+                            tokio::select! {
+                                _ = tokio::time::sleep_until((last_seen + timeout).into()) => {}
+                                _ = shutdown.changed() => return,
+                            }
+
+                            if this.standby_state().last_seen + timeout <= Instant::now() {
+                                this.take_over();
+                                return;
+                            }
+                        }
+                    }
+                });
+            }
+
+            if let Some(mut availability) = this.availability.clone() {
+                tokio::spawn({
+                    let this = Arc::clone(&this);
+                    let socket = Arc::clone(&tx_socket);
+                    let extra_headers = Arc::clone(&extra_headers);
+                    let mut shutdown = notify_alive_rx.clone();
+
+                    async move {
+                        let mut available = *availability.borrow_and_update();
+                        loop {
+                            tokio::select! {
+                                res = availability.changed() => {
+                                    if res.is_err() {
+                                        return;
+                                    }
+                                }
+                                _ = shutdown.changed() => return,
+                            }
+
+                            let now = *availability.borrow_and_update();
+                            if available && !now {
+                                info!("Devices became unavailable");
+                                let res =
+                                    this.broadcast_byebye(&socket, &extra_headers.byebye).await;
+                                if let Err(e) = res {
+                                    error!("Send byebye messages failed: {}", e);
+                                }
+                            } else if !available && now {
+                                info!("Devices became available");
+                            }
+                            available = now;
+                        }
+                    }
+                });
+            }
+
+            let (in_flight_tx, mut in_flight_rx) = mpsc::channel::<()>(1);
+            let in_flight = InFlight {
+                shutdown: notify_alive_rx,
+                done: in_flight_tx,
+            };
+
+            let (notify_byebye_tx, notify_byebye_rx) = oneshot::channel::<()>();
+            let byebye_task = tokio::spawn({
+                let this = Arc::clone(&this);
                 let socket = Arc::clone(&tx_socket);
                 let extra_headers = Arc::clone(&extra_headers);
 
-                async move {
-                    loop {
-                        if let Err(e) = this.broadcast_alive(&socket, &extra_headers).await {
-                            error!("Send alive messages failed: {}", e);
-                        }
+                async move {
+                    let _ = notify_byebye_rx.await;
+
+                    // Wait for pending search responses to be flushed or cancelled
+                    let quiescent = in_flight_rx.recv();
+                    match this.pending_responses {
+                        PendingResponses::Cancel => {
+                            quiescent.await;
+                        }
+                        PendingResponses::Flush(deadline) => {
+                            if tokio::time::timeout(deadline, quiescent).await.is_err() {
+                                debug!("Gave up flushing pending search responses");
+                            }
+                        }
+                    }
+
+                    if !this.is_available() {
+                        // Byebye was sent when becoming unavailable
+                        return;
+                    }
+
+                    if let Err(e) = this.broadcast_byebye(&socket, &extra_headers.byebye).await {
+                        error!("Send byebye messages failed: {}", e);
+                    }
+                }
+            });
+
+            if let Some((backoff, cause)) = retry_bind {
+                rx_socket = Self::transport(this.rebind_rx_socket(ip, backoff, cause).await?);
+                this.health.set_multicast_joined(true);
+                info!("Listening on {}", rx_socket.local_addr()?);
+            }
+
+            let mut watchdog = this.watchdog_interval.map(|period| {
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period)
+            });
+            if !own_socket || announce_only && this.addr_in_use == AddrInUse::AnnounceOnly {
+                watchdog = None;
+            }
+            let mut interface_events = match this.interface_watch {
+                Some(_) => InterfaceEvents::new()
+                    .inspect_err(|e| warn!("Watching network interfaces failed: {}", e))
+                    .ok(),
+                None => None,
+            };
+
+            let mut searches = this.shared.serve();
+
+            loop {
+                let (res, on_search_port) = tokio::select! {
+                    res = pktinfo::recv_from(&*rx_socket, &mut buf) => (res, false),
+                    res = async {
+                        match &search_socket {
+                            Some(socket) => pktinfo::recv_from(socket, &mut search_buf).await,
+                            None => std::future::pending().await,
+                        }
+                    } => match res {
+                        Ok(res) => (Ok(res), true),
+                        Err(e) => {
+                            error!("Receive on search port failed: {}", e);
+                            let _ = this.errors_tx.send(Error::Receive(Arc::new(e)));
+                            continue;
+                        }
+                    },
+                    _ = async { drop(shutdown_rx.wait_for(|shutdown| *shutdown).await) } => {
+                        info!("Shutting down");
+                        drop(notify_alive_tx);
+                        drop(in_flight);
+                        drop(notify_byebye_tx);
+                        let _ = byebye_task.await;
+                        return Ok(());
+                    }
+                    Some(control) = async {
+                        match &mut control_rx {
+                            Some(control_rx) => control_rx.recv().await,
+                            None => std::future::pending().await,
+                        }
+                    } => {
+                        if let Some(announcement) = this.control(control) {
+                            tokio::spawn({
+                                let this = Arc::clone(&this);
+                                let socket = Arc::clone(&tx_socket);
+                                let extra_headers = Arc::clone(&extra_headers);
+                                async move {
+                                    this.announce(announcement, &socket, &extra_headers).await
+                                }
+                            });
+                        }
+                        continue;
+                    }
+                    Some(request) = searches.recv() => {
+                        trace!("Sending M-SEARCH of a client");
+                        let target = this.options.multicast.into();
+                        let res = transport::send_to(&*rx_socket, &request, target).await;
+                        if let Err(e) = res {
+                            warn!("Send M-SEARCH of a client failed: {}", e);
+                        }
+                        continue;
+                    }
+                    _ = async {
+                        match &mut watchdog {
+                            Some(watchdog) => watchdog.tick().await,
+                            None => std::future::pending().await,
+                        }
+                    } => {
+                        this.check_membership(&*rx_socket, ip);
+                        continue;
+                    }
+                    res = async {
+                        match &mut interface_events {
+                            Some(events) => events.changed().await,
+                            None => std::future::pending().await,
+                        }
+                    } => {
+                        if let Err(e) = res {
+                            error!("Watching network interfaces failed: {}", e);
+                            interface_events = None;
+                            continue;
+                        }
+
+                        debug!("Network interfaces changed");
+                        if own_socket && !announce_only {
+                            this.check_membership(&*rx_socket, ip);
+                        }
+                        tokio::spawn({
+                            let this = Arc::clone(&this);
+                            let socket = Arc::clone(&tx_socket);
+                            let extra_headers = Arc::clone(&extra_headers);
+                            async move {
+                                // Changes come in bursts, e.g. link up followed by the address
+                                tokio::time::sleep(INTERFACE_SETTLE).await;
+                                this.check_host_addr(ip, &socket, &extra_headers).await;
+                                if this.interface_watch == Some(true) && this.is_available() {
+                                    this.announce_all(&socket, &extra_headers.alive).await;
+                                }
+                            }
+                        });
+                        continue;
+                    }
+                };
+
+                let (n, addr, received_on) = match res {
+                    Ok((_, _, Some(info))) if this.per_interface && info.local_addr != ip => {
+                        // Received on another interface, served by another instance
+                        continue;
+                    }
+                    Ok(res) => res,
+                    Err(e) if !own_socket && !on_search_port => return Err(e),
+                    Err(e) if !on_search_port => {
+                        this.health.set_multicast_joined(false);
+                        rx_socket = Self::transport(this.restart_rx_socket(ip, e).await?);
+                        this.health.set_multicast_joined(true);
+                        continue;
+                    }
+                    Err(_) => continue,
+                };
+                if on_search_port {
+                    buf[..n].copy_from_slice(&search_buf[..n]);
+                }
+                this.health.received();
+
+                let span = logging::datagram_span(addr);
+                async {
+                    if !this.is_allowed(addr.ip()) {
+                        trace!("Ignoring message from {}, not allowed", addr);
+                        return;
+                    }
+
+                    if !buf[..n].starts_with(b"M-SEARCH") {
+                        // Search responses and NOTIFY messages for the clients of the server
+                        this.shared.received(&buf[..n], addr);
+                    }
+
+                    // Fix up the requests of broken clients, if enabled, so we can parse them
+                    let data = this.compat.prepare(&buf[..n]);
+
+                    // Most requests fit the headers on the stack, retry with more otherwise
+                    let mut headers = [httparse::EMPTY_HEADER; INLINE_HEADERS];
+                    let mut more_headers;
+                    let inline = this.max_headers.min(INLINE_HEADERS);
+                    let mut req = httparse::Request::new(&mut headers[..inline]);
+                    let mut res = req.parse(&data);
+                    if matches!(res, Err(httparse::Error::TooManyHeaders))
+                        && this.max_headers > INLINE_HEADERS
+                    {
+                        more_headers = vec![httparse::EMPTY_HEADER; this.max_headers];
+                        req = httparse::Request::new(&mut more_headers);
+                        res = req.parse(&data);
+                    }
+                    if let Err(e) = &res {
+                        debug!("Invalid request from {}: {}", addr, e);
+                        stats::parse_failed();
+                        let _ = this.errors_tx.send(Error::Parse {
+                            remote_addr: addr,
+                            reason: e.to_string(),
+                        });
+                        this.emit(|| ServerEvent::ParseFailed {
+                            remote_addr: addr,
+                            reason: e.to_string(),
+                        });
+                    }
+
+                    if let Ok(httparse::Status::Complete(_)) = res {
+                        let method = if let Some(m) = req.method {
+                            m
+                        } else {
+                            return;
+                        };
+
+                        let path = if let Some(m) = req.path {
+                            m
+                        } else {
+                            return;
+                        };
+
+                        match (method, path) {
+                            ("M-SEARCH", "*") if !this.is_available() => {
+                                trace!("Ignoring M-SEARCH from {}, unavailable", addr);
+                            }
+                            ("M-SEARCH", "*") => {
+                                let received = Instant::now();
+                                stats::search_received();
+
+                                let ttl = received_on.and_then(|info| info.ttl);
+                                if let (Some(min), Some(ttl)) = (this.min_ttl, ttl)
+                                    && ttl < min
+                                {
+                                    debug!("Ignoring M-SEARCH from {} with TTL {}", addr, ttl);
+                                    return;
+                                }
+
+                                if !this.search_limit.allow(addr.ip()) {
+                                    trace!("Ignoring M-SEARCH from {}, rate limited", addr);
+                                    this.record("search_limited", &[("remote_addr", &addr)]);
+                                    return;
+                                }
+
+                                let search = match SearchRequest::from_request(&req, &this.compat) {
+                                    Ok(request) => IncomingSearch {
+                                        request,
+                                        context: SearchContext {
+                                            remote_addr: addr,
+                                            received_on,
+                                            unicast: on_search_port
+                                                || received_on.is_some_and(|info| {
+                                                    !info.destination.is_multicast()
+                                                }),
+                                        },
+                                    },
+                                    Err(e) => {
+                                        debug!("Invalid M-SEARCH from {}: {}", addr, e);
+                                        stats::parse_failed();
+                                        this.emit(|| ServerEvent::ParseFailed {
+                                            remote_addr: addr,
+                                            reason: e.to_string(),
+                                        });
+                                        this.reject_search(&tx_socket, addr, &e).await;
+                                        return;
+                                    }
+                                };
+
+                                if this.strict_destination
+                                    && let Err(reason) = this.check_destination(
+                                        &search.request,
+                                        received_on,
+                                        on_search_port,
+                                    )
+                                {
+                                    debug!("Ignoring M-SEARCH from {}: {}", addr, reason);
+                                    this.record(
+                                        "search_rejected",
+                                        &[("remote_addr", &addr), ("reason", &reason)],
+                                    );
+                                    let _ = this.errors_tx.send(Error::InvalidSearch {
+                                        remote_addr: addr,
+                                        error: Arc::new(std::io::Error::new(
+                                            std::io::ErrorKind::InvalidData,
+                                            reason,
+                                        )),
+                                    });
+                                    return;
+                                }
+
+                                logging::record_search(
+                                    &span,
+                                    &search.request.st,
+                                    search.request.mx,
+                                );
+                                this.emit(|| ServerEvent::SearchReceived {
+                                    st: search.request.st.clone(),
+                                    remote_addr: addr,
+                                    control_point: search.request.control_point(),
+                                });
+
+                                #[cfg(feature = "tower")]
+                                let search = match &mut search_service {
+                                    Some(service) => {
+                                        match Self::filter_search(service, search).await {
+                                            Some(search) => search,
+                                            None => return,
+                                        }
+                                    }
+                                    None => search,
+                                };
+
+                                let source = this.reply_source(&search.context);
+                                let socket = source
+                                    .and_then(|source| {
+                                        response_sockets
+                                            .iter()
+                                            .find(|(addr, _)| *addr == source.local_addr)
+                                    })
+                                    .map_or(&tx_socket, |(_, socket)| socket);
+                                // Searches waiting for the hook count as pending responses
+                                let mut permit = None;
+                                if this.search_hook.is_some()
+                                    && let Some(slots) = &this.response_slots
+                                {
+                                    match Arc::clone(slots).try_acquire_owned() {
+                                        Ok(acquired) => permit = Some(acquired),
+                                        Err(_) => {
+                                            this.search_limited(addr);
+                                            return;
+                                        }
+                                    }
+                                }
+                                let answer = {
+                                    let this = Arc::clone(&this);
+                                    let socket = Arc::clone(socket);
+                                    let extra_headers = Arc::clone(&extra_headers);
+                                    let in_flight = in_flight.clone();
+                                    async move {
+                                        let headers = match &this.search_hook {
+                                            Some(hook) => {
+                                                let mut shutdown = in_flight.shutdown.clone();
+                                                let decision = tokio::select! {
+                                                    decision = hook.decide(
+                                                        &search.request,
+                                                        search.context.remote_addr,
+                                                    ) => decision,
+                                                    _ = shutdown.changed() => {
+                                                        debug!(
+                                                            "Dropping M-SEARCH from {}, shutting down",
+                                                            addr
+                                                        );
+                                                        return;
+                                                    }
+                                                };
+                                                match decision {
+                                                    SearchDecision::Answer => vec![],
+                                                    SearchDecision::AnswerWith(headers) => headers,
+                                                    SearchDecision::Ignore => {
+                                                        debug!(
+                                                            "Ignoring M-SEARCH from {}, vetoed",
+                                                            addr
+                                                        );
+                                                        return;
+                                                    }
+                                                }
+                                            }
+                                            None => vec![],
+                                        };
+                                        let headers: Vec<_> = headers
+                                            .iter()
+                                            .map(|(name, value)| (name.as_str(), value.as_str()))
+                                            .collect();
+
+                                        let remote_addr = search.context.remote_addr;
+                                        // The responses take their own slots
+                                        drop(permit);
+                                        let res = this
+                                            .handle_search(
+                                                search,
+                                                Arc::clone(&socket),
+                                                source,
+                                                &merge_headers(
+                                                    &extra_headers.search_response,
+                                                    &headers,
+                                                ),
+                                                in_flight,
+                                                received,
+                                            )
+                                            .await;
+                                        if let Err(e) = res {
+                                            debug!("Invalid M-SEARCH from {}: {}", remote_addr, e);
+                                            stats::parse_failed();
+                                            this.emit(|| ServerEvent::ParseFailed {
+                                                remote_addr,
+                                                reason: e.to_string(),
+                                            });
+                                            this.reject_search(&socket, remote_addr, &e).await;
+                                        }
+                                    }
+                                };
+                                // The hook may take a while, don't hold up the receive loop
+                                if this.search_hook.is_some() {
+                                    tokio::spawn(answer.instrument(Span::current()));
+                                } else {
+                                    answer.await;
+                                }
+                            }
+                            ("NOTIFY", "*") => {
+                                trace!("NOTIFY * from {}", addr);
+                                match message::Notify::from_request(&req) {
+                                    Ok(notify) => this.handle_notify(&notify, addr, tx_port),
+                                    Err(e) => {
+                                        debug!("Invalid NOTIFY from {}: {}", addr, e);
+                                        stats::parse_failed();
+                                        let _ = this.errors_tx.send(Error::Parse {
+                                            remote_addr: addr,
+                                            reason: e.to_string(),
+                                        });
+                                        this.emit(|| ServerEvent::ParseFailed {
+                                            remote_addr: addr,
+                                            reason: e.to_string(),
+                                        });
+                                    }
+                                }
+                            }
+                            _ => debug!("Unknown SSDP request {} {} from {}", method, path, addr),
+                        }
+                    }
+                }
+                .instrument(span.clone())
+                .await;
+            }
+        };
+
+        Ok(server_fut)
+    }
+
+    /// Share `socket` as a transport.
+    fn transport(socket: UdpSocket) -> Arc<dyn Transport> {
+        Arc::new(socket)
+    }
+
+    fn bind_rx_socket(&self, ip: Ipv4Addr) -> IoResult<UdpSocket> {
+        let multicast = self.options.multicast;
+        let s = Self::new_rx_socket(
+            multicast.port(),
+            self.reuse_port || self.addr_in_use == AddrInUse::Shared,
+        )?;
+        s.set_nonblocking(true)?;
+        self.bind_to_interface(&s)?;
+        s.join_multicast_v4(multicast.ip(), &ip)?;
+        s.set_multicast_loop_v4(self.multicast_loop)?;
+        pktinfo::enable(&s)?;
+        UdpSocket::from_std(s)
+    }
+
+    /// A socket on an ephemeral port, which never receives anything, standing in for the
+    /// receiving socket while the SSDP port is in use.
+    fn bind_announce_only(ip: Ipv4Addr) -> IoResult<UdpSocket> {
+        let s = std::net::UdpSocket::bind((ip, 0))?;
+        s.set_nonblocking(true)?;
+        UdpSocket::from_std(s)
+    }
+
+    /// Bind `port`, usually the SSDP port, shared with other SSDP stacks on the host.
+    #[cfg(feature = "socket2")]
+    pub(crate) fn new_rx_socket(port: u16, shared: bool) -> IoResult<std::net::UdpSocket> {
+        use socket2::{Domain, Protocol, Socket, Type};
+        let s = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        s.set_reuse_address(true)?;
+        #[cfg(unix)]
+        if shared {
+            s.set_reuse_port(true)?;
+        }
+        #[cfg(not(unix))]
+        let _ = shared;
+        s.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)).into())?;
+        Ok(s.into())
+    }
+
+    /// Bind `port`. Without `socket2`, `SO_REUSEADDR` can not be set before binding, so
+    /// this fails if another SSDP stack is running on the host.
+    #[cfg(not(feature = "socket2"))]
+    pub(crate) fn new_rx_socket(port: u16, _shared: bool) -> IoResult<std::net::UdpSocket> {
+        std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))
+    }
+
+    /// Re-issue the multicast group join. Joining a group which is still joined fails with
+    /// `AddrInUse`, so success means the membership had been lost. Does nothing for
+    /// transports other than UDP sockets.
+    fn check_membership(&self, socket: &dyn Transport, ip: Ipv4Addr) {
+        let Some(socket) = socket.udp_socket() else {
+            return;
+        };
+
+        match socket.join_multicast_v4(*self.options.multicast.ip(), ip) {
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+                trace!("Multicast membership on {} is intact", ip);
+            }
+            Ok(()) => {
+                info!("Multicast membership on {} was lost, re-joined", ip);
+                self.health.set_multicast_joined(true);
+                let _ = self.rejoin_tx.send(Rejoin { interface: ip });
+            }
+            Err(e) => {
+                error!("Re-joining multicast group on {} failed: {}", ip, e);
+                self.health.set_multicast_joined(false);
+            }
+        }
+    }
+
+    /// Re-create the receiving socket after `cause`, if enabled by [`Server::restart_on_error`].
+    async fn restart_rx_socket(&self, ip: Ipv4Addr, cause: std::io::Error) -> IoResult<UdpSocket> {
+        let Some(backoff) = self.restart else {
+            return Err(cause);
+        };
+
+        error!("Receiving failed, restarting: {}", cause);
+        let _ = self.errors_tx.send(Error::Receive(error::shared(&cause)));
+        self.rebind_rx_socket(ip, backoff, cause).await
+    }
+
+    /// Re-create the receiving socket after `cause`, with attempts delayed according to
+    /// `backoff`, reported as [`RestartAttempt`]s.
+    async fn rebind_rx_socket(
+        &self,
+        ip: Ipv4Addr,
+        backoff: Backoff,
+        cause: std::io::Error,
+    ) -> IoResult<UdpSocket> {
+        let cause = Arc::new(cause);
+
+        let mut attempt = 0;
+        while let Some(delay) = backoff.delay(attempt) {
+            tokio::time::sleep(delay).await;
+
+            let res = self.bind_rx_socket(ip);
+            let _ = self.restart_tx.send(RestartAttempt {
+                attempt,
+                cause: Arc::clone(&cause),
+                result: res
+                    .as_ref()
+                    .map(drop)
+                    .map_err(|e| Arc::new(std::io::Error::new(e.kind(), e.to_string()))),
+            });
+
+            match res {
+                Ok(socket) => {
+                    info!("Restarted after {} attempts", attempt + 1);
+                    return Ok(socket);
+                }
+                Err(e) => error!("Restart attempt {} failed: {}", attempt, e),
+            }
+
+            attempt += 1;
+        }
+
+        Err(Arc::try_unwrap(cause)
+            .unwrap_or_else(|cause| std::io::Error::new(cause.kind(), cause.to_string())))
+    }
+
+    /// Check whether a `NOTIFY` from another host announces one of our USNs.
+    fn handle_notify(&self, notify: &message::Notify, remote_addr: SocketAddr, tx_port: u16) {
+        if remote_addr.port() == tx_port && remote_addr.ip() == self.host_addr() {
+            // Our own announcement, looped back
+            return;
+        }
+
+        if notify.nts != Nts::Alive {
+            return;
+        }
+
+        let usn = &notify.usn;
+        if !devices(&self.groups()).any(|(_, device)| device.usn == *usn) {
+            return;
+        }
+
+        if !*self.active_tx.borrow() {
+            trace!("{} announced by active instance {}", usn, remote_addr);
+            let mut standby = self.standby_state();
+            standby.last_seen = Instant::now();
+            if let Some(boot_id) = notify
+                .header("bootid.upnp.org")
+                .and_then(|b| b.parse().ok())
+            {
+                standby.active_boot_id = Some(boot_id);
+            }
+            return;
+        }
+
+        match self.duplicate_policy {
+            DuplicatePolicy::Warn => {
+                warn!("{} is also announced by {}", usn, remote_addr);
+            }
+            DuplicatePolicy::KeepAnnouncing => {
+                debug!("{} is also announced by {}", usn, remote_addr);
+            }
+            DuplicatePolicy::BackOff(duration) => {
+                warn!(
+                    "{} is also announced by {}, backing off for {:?}",
+                    usn, remote_addr, duration
+                );
+                self.backed_off
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(usn.clone(), Instant::now() + duration);
+            }
+        }
+
+        let _ = self.duplicate_tx.send(DuplicateResponder {
+            usn: usn.clone(),
+            addr: remote_addr,
+        });
+    }
+
+    /// Whether `device` is currently backed off, see [`DuplicatePolicy::BackOff`].
+    fn is_backed_off(&self, device: &Device) -> bool {
+        let mut backed_off = self.backed_off.lock().unwrap_or_else(|e| e.into_inner());
+        match backed_off.get(&device.usn) {
+            Some(until) if *until > Instant::now() => true,
+            Some(_) => {
+                backed_off.remove(&device.usn);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Pass `search` through the search service, returning it unless it was dropped.
+    #[cfg(feature = "tower")]
+    async fn filter_search(
+        service: &mut SearchService,
+        search: IncomingSearch,
+    ) -> Option<IncomingSearch> {
+        use tower::{Service, ServiceExt};
+
+        let remote_addr = search.context.remote_addr;
+        let res = match service.ready().await {
+            Ok(service) => service.call(search).await,
+            Err(e) => Err(e),
+        };
+
+        match res {
+            Ok(Some(search)) => Some(search),
+            Ok(None) => {
+                debug!("Search from {} dropped by search service", remote_addr);
+                None
+            }
+            Err(e) => {
+                error!("Search service failed for {}: {}", remote_addr, e);
+                None
+            }
+        }
+    }
+
+    /// Send a diagnostic response for a rejected search, if enabled by
+    /// [`Server::diagnostic_responses`].
+    async fn reject_search(
+        &self,
+        socket: &dyn Transport,
+        remote_addr: SocketAddr,
+        cause: &std::io::Error,
+    ) {
+        self.record(
+            "search_rejected",
+            &[("remote_addr", &remote_addr), ("reason", cause)],
+        );
+        let _ = self.errors_tx.send(Error::InvalidSearch {
+            remote_addr,
+            error: error::shared(cause),
+        });
+
+        if !self.diagnostic_responses {
+            return;
+        }
+
+        let response = message::error_response(&self.options, &cause.to_string());
+        self.throttle.acquire(response.len()).await;
+        if let Err(e) = transport::send_to(socket, response.as_bytes(), remote_addr).await {
+            error!("Failed to send diagnostic response: {}", e);
+        }
+    }
+
+    /// Match `request` against the devices, and build the responses.
+    fn answer(
+        &self,
+        request: &SearchRequest,
+        ctx: &SearchContext,
+        extra_headers: &str,
+    ) -> IoResult<Answer> {
+        let st = &request.st;
+        let mx = match (request.mx, self.missing_mx) {
+            (Some(mx), _) => mx,
+            // Unicast searches need no MX, as they are answered immediately
+            (None, _) if ctx.unicast => 0,
+            (None, MissingMx::Immediate) => 0,
+            (None, MissingMx::Assume(mx)) => mx,
+            (None, MissingMx::Reject) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "MX header not found",
+                ));
+            }
+        };
+
+        trace!("ST={:?}, MX={:?}", st, mx);
+
+        let groups = self.groups();
+        let mut matching: Vec<_> = devices(&groups)
+            .filter(|(_, device)| !self.is_backed_off(device))
+            .filter_map(|(group, device)| {
+                let target = device.matched_target(st)?;
+                let mut device = device.clone();
+                if target != device.search_target {
+                    // Answer searches for lower versions with the version searched for
+                    device.search_target = target.to_string();
+                }
+                Some((group, device))
+            })
+            .collect();
+
+        // `uuid:X` matches the device with UUID X, whichever search targets it registered,
+        // and is answered once with USN `uuid:X`
+        if matching.is_empty()
+            && let Some(uuid) = st.strip_prefix("uuid:")
+            && let Some((group, device)) = devices(&groups).find(|(_, device)| {
+                !self.is_backed_off(device)
+                    && device
+                        .uuid
+                        .as_deref()
+                        .is_some_and(|own| own.eq_ignore_ascii_case(uuid))
+            })
+        {
+            let mut device = device.clone();
+            device.usn = format!("uuid:{}", device.uuid.as_deref().unwrap_or(uuid));
+            device.search_target = device.usn.clone();
+            matching.push((group, device));
+        }
+
+        let local_addr = ctx.received_on.map(|info| info.local_addr);
+        let mut responses: Vec<_> = matching
+            .into_iter()
+            .map(|(group, mut device)| {
+                let mut own_headers = vec![];
+                if let Some(customizer) = &self.response_customizer {
+                    let overrides = customizer.customize(request, &device, ctx);
+                    if let Some(location) = overrides.location {
+                        device.set_location(location);
+                    }
+                    if let Some(max_age) = overrides.max_age {
+                        device.max_age = Some(max_age);
+                    }
+                    own_headers = overrides.headers;
+                }
+                let own_headers: Vec<_> = own_headers
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.as_str()))
+                    .collect();
+                let extra_headers = merge_headers(extra_headers, &own_headers);
+
+                let build = |date: &str| {
+                    message::search_response(
+                        &self.options,
+                        &device,
+                        self.device_max_age(group, &device),
+                        &self.location(&device, local_addr),
+                        date,
+                        &self.expand_extra_headers(
+                            MessageKind::SearchResponse,
+                            &device,
+                            Some(ctx.remote_addr),
+                            local_addr,
+                            &extra_headers,
+                        ),
+                    )
+                };
+                let response = self.cached_message(
+                    MessageKind::SearchResponse,
+                    &device,
+                    local_addr,
+                    &extra_headers,
+                    build,
+                );
+                // Each device answers at its own time, so the control point is not flooded
+                let delay = if mx > 0 && !ctx.unicast {
+                    // upnp specification advises to use a number less than 5 if it is bigger than 5
+                    self.response_delay.sample(mx.min(self.max_mx))
+                } else {
+                    Duration::ZERO
+                };
+                (device, response, delay)
+            })
+            .collect();
+        responses.sort_by_key(|(_, _, delay)| *delay);
+
+        Ok(Answer {
+            mx,
+            delay: responses
+                .first()
+                .map_or(Duration::ZERO, |(_, _, delay)| *delay),
+            responses,
+        })
+    }
+
+    /// Match `request` against the devices, and build the responses, without sending
+    /// them. For applications doing their own UDP handling.
+    ///
+    /// Placeholders are expanded as for a running server, but `{addr}` and `{bootid}`
+    /// are only known once the server has been started.
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{Device, SearchContext, SearchRequest, Server};
+    ///
+    /// let server = Server::new([Device::new(
+    ///     "ad8782a0-9e28-422b-a6ae-670fe7c4c043",
+    ///     "upnp:rootdevice",
+    ///     "http://192.168.1.100:8080/desc.xml",
+    /// )]);
+    ///
+    /// let request = SearchRequest {
+    ///     st: "ssdp:all".into(),
+    ///     mx: Some(2),
+    ///     headers: vec![],
+    /// };
+    /// let ctx = SearchContext::new("192.168.1.20:50000".parse().unwrap());
+    ///
+    /// let messages = server.answer_search(&request, &ctx)?;
+    /// assert_eq!(messages.len(), 1);
+    /// assert!(messages[0].delay.as_secs() < 2);
+    ///
+    /// // Devices are found by UUID, and answer with USN `uuid:X`
+    /// let request = SearchRequest {
+    ///     st: "uuid:ad8782a0-9e28-422b-a6ae-670fe7c4c043".into(),
+    ///     mx: None,
+    ///     headers: vec![],
+    /// };
+    /// let messages = server.answer_search(&request, &ctx)?;
+    /// let response = String::from_utf8_lossy(&messages[0].data);
+    /// assert!(response.contains("\r\nUSN: uuid:ad8782a0-9e28-422b-a6ae-670fe7c4c043\r\n"));
+    ///
+    /// // Unicast searches are answered immediately
+    /// let ctx = SearchContext { unicast: true, ..ctx };
+    /// let messages = server.answer_search(&request, &ctx)?;
+    /// assert!(messages[0].delay.is_zero());
+    ///
+    /// // Every matching device answers, each after its own delay
+    /// let st = "urn:schemas-upnp-org:service:ContentDirectory:1";
+    /// let server = Server::new([
+    ///     Device::new("ad8782a0-9e28-422b-a6ae-670fe7c4c043", st, "http://192.168.1.100:8080/a.xml"),
+    ///     Device::new("0e5a3ac0-0a24-4d9b-8b3b-6e5e0b2cf5a1", st, "http://192.168.1.100:8080/b.xml"),
+    /// ]);
+    /// let request = SearchRequest {
+    ///     st: st.into(),
+    ///     mx: Some(2),
+    ///     headers: vec![],
+    /// };
+    /// let ctx = SearchContext::new("192.168.1.20:50000".parse().unwrap());
+    /// let messages = server.answer_search(&request, &ctx)?;
+    /// assert_eq!(messages.len(), 2);
+    /// assert!(messages[0].delay <= messages[1].delay);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn answer_search(
+        &self,
+        request: &SearchRequest,
+        ctx: &SearchContext,
+    ) -> IoResult<Vec<OutgoingMessage>> {
+        let extra_headers = self.concat_headers(MessageKind::SearchResponse);
+        let answer = self.answer(request, ctx, &extra_headers)?;
+
+        Ok(answer
+            .responses
+            .into_iter()
+            .map(|(_, response, delay)| OutgoingMessage {
+                dest: ctx.remote_addr,
+                delay,
+                data: response.into_bytes(),
+            })
+            .collect())
+    }
+
+    fn search_limited(&self, remote_addr: SocketAddr) {
+        debug!(
+            "Ignoring M-SEARCH from {}, too many pending responses",
+            remote_addr
+        );
+        self.record("search_limited", &[("remote_addr", &remote_addr)]);
+    }
+
+    async fn handle_search(
+        &self,
+        search: IncomingSearch,
+        socket: Arc<dyn Transport>,
+        source: Option<PacketInfo>,
+        extra_headers: &str,
+        in_flight: InFlight,
+        received: Instant,
+    ) -> std::io::Result<()> {
+        let Answer {
+            mx,
+            delay,
+            responses,
+        } = self.answer(&search.request, &search.context, extra_headers)?;
+
+        if responses.is_empty() {
+            return Ok(());
+        }
+
+        let mut permit = None;
+        if let (Some(slots), Some(limit)) = (&self.response_slots, self.max_pending_responses) {
+            let wanted = responses.len().min(limit).max(1) as u32;
+            match Arc::clone(slots).try_acquire_many_owned(wanted) {
+                Ok(acquired) => permit = Some(acquired),
+                Err(_) => {
+                    self.search_limited(search.context.remote_addr);
+                    return Ok(());
+                }
+            }
+        }
+
+        trace!("Responses: {responses:?}");
+
+        let SearchContext {
+            remote_addr,
+            received_on,
+            ..
+        } = search.context;
+
+        if self.options.upnp_version >= UpnpVersion::V2_0
+            && let Some(name) = search.request.control_point_name()
+        {
+            debug!(
+                "Search from control point {:?} ({}) at {}",
+                name,
+                search
+                    .request
+                    .control_point_uuid()
+                    .unwrap_or("unknown UUID"),
+                remote_addr
+            );
+        }
+
+        self.record(
+            "search",
+            &[
+                ("remote_addr", &remote_addr),
+                ("st", &search.request.st),
+                ("mx", &mx),
+                ("devices", &responses.len()),
+                ("delay_ms", &delay.as_millis()),
+            ],
+        );
+
+        let st = self
+            .response_history
+            .is_enabled()
+            .then(|| search.request.st.clone());
+
+        self.emit(|| {
+            ServerEvent::SearchAnswered(AnsweredSearch {
+                remote_addr,
+                control_point: search.request.control_point(),
+                st: search.request.st,
+                mx,
+                devices: responses
+                    .iter()
+                    .map(|(device, _, _)| device.clone())
+                    .collect(),
+                delay,
+                received_on,
+            })
+        });
+
+        let responses: Vec<_> = responses
+            .into_iter()
+            .map(|(device, response, delay)| (device.usn.clone(), response, delay))
+            .collect();
+
+        let cancel = self.pending_responses == PendingResponses::Cancel;
+        let InFlight { mut shutdown, done } = in_flight;
+        let health = self.health.clone();
+        let throttle = self.throttle.clone();
+        let event_log = self.event_log.clone();
+        let errors_tx = self.errors_tx.clone();
+        let events_tx = self.events_tx.clone();
+        let history = self.response_history.clone();
+        let (repeat, repeat_spacing) = (self.repeat, self.repeat_spacing);
+        // Unless the socket is bound to an address already
+        let from = source.filter(|_| {
+            socket
+                .local_addr()
+                .is_ok_and(|addr| addr.ip().is_unspecified())
+        });
+
+        // Keep the responses in the span of the received datagram.
+        tokio::spawn(
+            async move {
+                let _done = done;
+                let _permit = permit;
+                let start = tokio::time::Instant::now();
+                let mut flushing = false;
+
+                for (usn, response, delay) in responses {
+                    if !delay.is_zero() && !flushing {
+                        tokio::select! {
+                            _ = tokio::time::sleep_until(start + delay) => {}
+                            _ = shutdown.changed() => {
+                                if cancel {
+                                    debug!("Cancelled search response to {}", remote_addr);
+                                    return;
+                                }
+                                flushing = true;
+                            }
+                        }
+                    }
+                    throttle.acquire(response.len()).await;
+                    let res =
+                        pktinfo::send_to(&socket, response.as_bytes(), remote_addr, from).await;
+                    match &res {
+                        Ok(_) => {
+                            trace!("Sent search response for {} to {}", usn, remote_addr);
+                            health.sent();
+                            stats::response_sent(received);
+                            if events_tx.receiver_count() > 0 {
+                                let _ = events_tx.send(ServerEvent::ResponseSent {
+                                    usn: usn.clone(),
+                                    remote_addr,
+                                });
+                            }
+                        }
+                        Err(e) => {
+                            health.send_failed();
+                            stats::send_failed();
+                            error!("Failed to send search response: {}", e);
+                            let _ = errors_tx.send(Error::Send {
+                                event: "response",
+                                usn: usn.clone(),
+                                remote_addr: Some(remote_addr),
+                                error: error::shared(e),
+                            });
+                        }
+                    }
+                    if let Some(event_log) = &event_log {
+                        Self::record_send(event_log, "response", &usn, Some(remote_addr), &res);
+                    }
+                    if let Some(st) = &st {
+                        history.push(SentResponse {
+                            time: SystemTime::now(),
+                            remote_addr,
+                            st: st.clone(),
+                            usn,
+                            bytes_sent: *res.as_ref().unwrap_or(&0),
+                        });
+                    }
+
+                    for _ in 1..repeat {
+                        tokio::time::sleep(repeat_spacing.mul_f64(rng::unit())).await;
+                        throttle.acquire(response.len()).await;
+                        if let Err(e) =
+                            pktinfo::send_to(&socket, response.as_bytes(), remote_addr, from).await
+                        {
+                            debug!("Sending repeated search response failed: {}", e);
+                        }
+                    }
+                }
+            }
+            .instrument(Span::current()),
+        );
+
+        Ok(())
+    }
+
+    /// Where to answer the search described by `ctx` from: the address configured with
+    /// [`Server::response_source`] for the subnet of the control point, or else the
+    /// interface the search was received on, if known.
+    fn reply_source(&self, ctx: &SearchContext) -> Option<PacketInfo> {
+        let source = match ctx.remote_addr.ip() {
+            IpAddr::V4(ip) => self
+                .response_sources
+                .iter()
+                .find(|source| source.contains(ip)),
+            IpAddr::V6(_) => None,
+        };
+
+        match source {
+            // Leave the interface to the routing table
+            Some(source) => Some(PacketInfo {
+                interface_index: 0,
+                local_addr: source.addr(),
+                destination: source.addr(),
+                ttl: None,
+            }),
+            None => ctx.received_on,
+        }
+    }
+
+    /// Concatenate the extra headers for `kind`. The placeholders are expanded in
+    /// [`Server::expand_extra_headers`].
+    fn concat_headers(&self, kind: MessageKind) -> String {
+        let mut headers = String::new();
+        if self.options.upnp_version >= UpnpVersion::V1_1 {
+            headers.push_str("BOOTID.UPNP.ORG: {bootid}\r\n");
+            headers.push_str("CONFIGID.UPNP.ORG: {configid}\r\n");
+            if let Some(port) = self.search_port
+                && kind == MessageKind::Alive
+            {
+                headers.push_str(&format!("SEARCHPORT.UPNP.ORG: {}\r\n", port));
+            }
+        }
+        if let Some(nls) = &self.nls {
+            headers.push_str("OPT: \"http://schemas.upnp.org/upnp/1/0/\"; ns=01\r\n");
+            headers.push_str(&format!("01-NLS: {}\r\n", nls));
+        }
+
+        for (_, name, value) in self
+            .headers
+            .iter()
+            .filter(|(k, _, _)| k.is_none_or(|k| k == kind))
+        {
+            headers.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+        headers
+    }
+
+    /// Expand the per-device placeholders in `extra_headers`
+    fn expand_extra_headers(
+        &self,
+        kind: MessageKind,
+        device: &Device,
+        remote_addr: Option<SocketAddr>,
+        local_addr: Option<Ipv4Addr>,
+        extra_headers: &str,
+    ) -> String {
+        let mut headers = expand_placeholders(
+            &device.merge_headers(kind, extra_headers),
+            &[
+                ("uuid", device.uuid.as_deref().unwrap_or_default()),
+                ("location", &self.location(device, local_addr)),
+                ("addr", &self.host_addr().to_string()),
+                (
+                    "local_addr",
+                    &local_addr.unwrap_or_else(|| self.host_addr()).to_string(),
+                ),
+                (
+                    "bootid",
+                    &self.current_boot_id.load(Ordering::Acquire).to_string(),
+                ),
+                (
+                    "configid",
+                    &self.current_config_id.load(Ordering::Acquire).to_string(),
+                ),
+            ],
+        )
+        .into_owned();
+
+        let ctx = HeaderContext {
+            kind,
+            device,
+            remote_addr,
+        };
+        for provider in &self.header_providers {
+            for (name, value) in provider.headers(&ctx) {
+                headers.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+
+        headers
+    }
+
+    fn alive_message(&self, group: &DeviceGroup, device: &Device, extra_headers: &str) -> String {
+        let build = |_: &str| {
+            message::alive(
+                &self.options,
+                device,
+                self.device_max_age(group, device),
+                &self.location(device, None),
+                &self.expand_extra_headers(MessageKind::Alive, device, None, None, extra_headers),
+            )
+        };
+        self.cached_message(MessageKind::Alive, device, None, extra_headers, build)
+    }
+
+    /// Build a message with `build`, or take it from the cache. Messages are not cached
+    /// with [`Server::header_provider`]s, which may add different headers each time, and
+    /// search responses not with [`Server::customize_responses`].
+    fn cached_message(
+        &self,
+        kind: MessageKind,
+        device: &Device,
+        local_addr: Option<Ipv4Addr>,
+        extra_headers: &str,
+        build: impl FnOnce(&str) -> String,
+    ) -> String {
+        let date = || message::http_date(SystemTime::now());
+        if !self.header_providers.is_empty()
+            || kind == MessageKind::SearchResponse && self.response_customizer.is_some()
+        {
+            return build(&date());
+        }
+
+        let ctx = CacheContext {
+            kind,
+            device,
+            local_addr,
+            host_addr: self.host_addr(),
+            boot_id: self.current_boot_id.load(Ordering::Acquire),
+            config_id: self.current_config_id.load(Ordering::Acquire),
+            extra_headers,
+        };
+        self.message_cache.get_or_build(ctx, date, build)
+    }
+
+    fn byebye_message(&self, device: &Device, extra_headers: &str) -> String {
+        message::byebye(
+            &self.options,
+            device,
+            &self.expand_extra_headers(MessageKind::Byebye, device, None, None, extra_headers),
+        )
+    }
+
+    /// Apply [`Server::response_ttl`], [`Server::notify_ttl`],
+    /// [`Server::multicast_loop`] and [`Server::serve_interface`] to a sending socket.
+    fn configure_sending(&self, socket: &std::net::UdpSocket) -> IoResult<()> {
+        if let Some(ttl) = self.response_ttl {
+            socket.set_ttl(ttl)?;
+        }
+        socket.set_multicast_ttl_v4(self.notify_ttl)?;
+        socket.set_multicast_loop_v4(self.multicast_loop)?;
+        self.bind_to_interface(socket)
+    }
+
+    /// Restrict `socket` to the interface passed to [`Server::serve_interface`], if any.
+    fn bind_to_interface(&self, socket: &std::net::UdpSocket) -> IoResult<()> {
+        let Some(name) = &self.interface else {
+            return Ok(());
+        };
+
+        match iface::bind_to_device(socket, name) {
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                // Before Linux 5.7, this requires CAP_NET_RAW
+                warn!("Binding to interface {} not permitted: {}", name, e);
+                Ok(())
+            }
+            res => res,
+        }
+    }
+
+    /// Check that a search was addressed to the multicast group, or to the search port,
+    /// see [`Server::strict_destination`].
+    fn check_destination(
+        &self,
+        request: &SearchRequest,
+        received_on: Option<PacketInfo>,
+        on_search_port: bool,
+    ) -> Result<(), String> {
+        let Some(host) = request.header("host") else {
+            return Err("HOST header not found".to_string());
+        };
+
+        if on_search_port {
+            // Unicast searches name the address of the device
+            let port = self.search_port.unwrap_or(self.options.multicast.port());
+            return match received_on {
+                Some(info) if !is_host(host, info.destination, port) => {
+                    Err(format!("HOST is {}, sent to {}", host, info.destination))
+                }
+                _ => Ok(()),
+            };
+        }
+
+        let multicast = self.options.multicast;
+        if let Some(info) = received_on
+            && info.destination != *multicast.ip()
+        {
+            return Err(format!(
+                "sent to {} instead of the multicast group",
+                info.destination
+            ));
+        }
+        if !is_host(host, *multicast.ip(), multicast.port()) {
+            return Err(format!("HOST is {}", host));
+        }
+        Ok(())
+    }
+
+    /// Whether messages from `addr` are handled, see [`Server::allow_subnet`] and
+    /// [`Server::deny_subnet`].
+    fn is_allowed(&self, addr: IpAddr) -> bool {
+        let IpAddr::V4(addr) = addr else {
+            return self.allowed.is_empty();
+        };
+
+        (self.allowed.is_empty() || self.allowed.iter().any(|subnet| subnet.contains(addr)))
+            && !self.denied.iter().any(|subnet| subnet.contains(addr))
+    }
+
+    /// Whether the devices are currently advertised, see [`Server::availability`] and
+    /// [`Server::standby`].
+    fn is_available(&self) -> bool {
+        *self.active_tx.borrow() && self.availability.as_ref().is_none_or(|rx| *rx.borrow())
+    }
+
+    fn standby_state(&self) -> std::sync::MutexGuard<'_, Standby> {
+        self.standby_state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The boot id to start with, see [`Server::boot_id`] and [`Server::boot_id_store`].
+    fn initial_boot_id(&mut self) -> u32 {
+        if let Some(boot_id) = self.boot_id {
+            return boot_id;
+        }
+
+        let stored = self.boot_id_store.as_ref().and_then(|store| {
+            store
+                .load()
+                .inspect_err(|e| warn!("Loading boot id failed: {}", e))
+                .ok()
+                .flatten()
+        });
+        let boot_id = match stored {
+            Some(stored) => stored.wrapping_add(1),
+            None => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs() as u32),
+        };
+
+        self.boot_id = Some(boot_id);
+        self.save_boot_id(boot_id);
+        boot_id
+    }
+
+    /// Save `boot_id` in the [`Server::boot_id_store`], if any.
+    fn save_boot_id(&self, boot_id: u32) {
+        if let Some(store) = &self.boot_id_store
+            && let Err(e) = store.store(boot_id)
+        {
+            warn!("Storing boot id {} failed: {}", boot_id, e);
+        }
+    }
+
+    /// Take over from the active instance, see [`Server::standby`].
+    fn take_over(&self) {
+        if let Some(active) = self.standby_state().active_boot_id {
+            self.current_boot_id
+                .fetch_max(active.wrapping_add(1), Ordering::AcqRel);
+            self.save_boot_id(self.current_boot_id.load(Ordering::Acquire));
+        }
+
+        warn!(
+            "Active instance went silent, taking over with boot id {}",
+            self.current_boot_id.load(Ordering::Acquire)
+        );
+        self.active_tx.send_replace(true);
+    }
+
+    fn host_addr(&self) -> Ipv4Addr {
+        *self.host_addr.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The location of `device`, with `{addr}` expanded to the current host address,
+    /// `{local_addr}` to `local_addr`, the address a search was received on, and
+    /// `{description}` to the URL of the description on `local_addr`.
+    fn location(&self, device: &Device, local_addr: Option<Ipv4Addr>) -> String {
+        let addr = self.host_addr();
+        let local_addr = local_addr.unwrap_or(addr);
+        let location = device.current_location();
+        let location = expand_placeholders(
+            &location,
+            &[
+                ("addr", &addr.to_string()),
+                ("local_addr", &local_addr.to_string()),
+            ],
+        );
+
+        #[cfg(feature = "http")]
+        if let Some(description) = &self.description {
+            let url = description.url(local_addr);
+            return expand_placeholders(&location, &[("description", &url)]).into_owned();
+        }
+
+        location.into_owned()
+    }
+
+    /// The address of the host on `ip`. If `ip` is unspecified, this is the address of
+    /// the interface used to reach the multicast group.
+    fn detect_host_addr(ip: Ipv4Addr) -> IoResult<Ipv4Addr> {
+        if !ip.is_unspecified() {
+            return Ok(ip);
+        }
+
+        let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.connect((SSDP_ADDR_V4, SSDP_PORT))?;
+        match socket.local_addr()?.ip() {
+            IpAddr::V4(addr) => Ok(addr),
+            IpAddr::V6(_) => Ok(ip),
+        }
+    }
+
+    /// Re-detect the host address, and re-announce the devices with templated
+    /// locations if it changed.
+    async fn check_host_addr(
+        &self,
+        ip: Ipv4Addr,
+        socket: &dyn Transport,
+        extra_headers: &ExtraHeaders,
+    ) {
+        let new = match Self::detect_host_addr(ip) {
+            Ok(addr) => addr,
+            Err(e) => {
+                debug!("Detecting host address failed: {}", e);
+                return;
+            }
+        };
+
+        let old = self.host_addr();
+        if new == old {
+            return;
+        }
+
+        info!("Host address changed from {} to {}", old, new);
+
+        let groups = self.groups();
+        let affected: Vec<_> = devices(&groups)
+            .filter(|(_, device)| {
+                let location = device.current_location();
+                location.contains("{addr}") || location.contains("{description}")
+            })
+            .collect();
+
+        for ((group, device), delay) in affected.iter().zip(self.pacing.delays(affected.len())) {
+            let message = self.byebye_message(device, &extra_headers.byebye);
+            let res = self.send_announcement(socket, &message).await;
+            self.sent_notification("ssdp:byebye", &device.usn, &res);
+            if let Err(e) = res {
+                error!("Send byebye message for {} failed: {}", device.usn, e);
+            }
+            tokio::time::sleep(group.pacing.unwrap_or(delay)).await;
+        }
+
+        *self.host_addr.write().unwrap_or_else(|e| e.into_inner()) = new;
+
+        for ((group, device), delay) in affected.iter().zip(self.pacing.delays(affected.len())) {
+            let message = self.alive_message(group, device, &extra_headers.alive);
+            let res = self.send_announcement(socket, &message).await;
+            self.sent_notification("ssdp:alive", &device.usn, &res);
+            if let Err(e) = res {
+                error!("Send alive message for {} failed: {}", device.usn, e);
+            }
+            tokio::time::sleep(group.pacing.unwrap_or(delay)).await;
+        }
+
+        let _ = self.address_change_tx.send(AddressChange { old, new });
+    }
+
+    /// The current device groups.
+    fn groups(&self) -> Arc<Vec<DeviceGroup>> {
+        Arc::clone(&self.groups.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Reflect the current device groups in `status`.
+    fn attach_status(&self, status: &Status) {
+        let groups = self
+            .groups()
+            .iter()
+            .map(|group| ScheduledGroup {
+                max_age: self.group_max_age(group),
+                notify_interval: self.group_notify_interval(group),
+                devices: group.devices.clone(),
+            })
+            .collect();
+        status.attach(groups, self.health.clone());
+    }
+
+    /// Apply a change requested through a [`ServerHandle`], returning how to announce it.
+    fn control(&self, control: Control) -> Option<Announcement> {
+        match control {
+            Control::AddDevice(device) => self.modify_groups(|groups| {
+                info!("Adding {}", device.usn);
+                for group in groups.iter_mut() {
+                    group.devices.retain(|d| d.usn != device.usn);
+                }
+                // Servers always have at least the group of `Server::new`
+                groups[0].devices.push(device.clone());
+                Some(Announcement::Alive(groups[0].clone(), device))
+            }),
+            Control::RemoveDevice(usn) => self.modify_groups(|groups| {
+                let Some((idx, pos)) = groups.iter().enumerate().find_map(|(idx, group)| {
+                    let pos = group.devices.iter().position(|d| d.usn == usn)?;
+                    Some((idx, pos))
+                }) else {
+                    warn!("Not removing unknown device {}", usn);
+                    return None;
+                };
+                info!("Removing {}", usn);
+                Some(Announcement::Byebye(groups[idx].devices.remove(pos)))
+            }),
+            Control::Announce(None) => Some(Announcement::AliveAll),
+            Control::Announce(Some(usn)) => {
+                let groups = self.groups();
+                let Some((group, device)) = devices(&groups).find(|(_, d)| d.usn == usn) else {
+                    warn!("Not announcing unknown device {}", usn);
+                    return None;
+                };
+                Some(Announcement::Alive(group.clone(), device.clone()))
+            }
+            Control::Update(config_id) => Some(Announcement::Update(config_id)),
+            Control::Notify { nts, usns } => {
+                let groups = self.groups();
+                let selected: Vec<_> = usns
+                    .iter()
+                    .filter_map(|usn| {
+                        let found = devices(&groups).find(|(_, d)| d.usn == *usn);
+                        if found.is_none() {
+                            warn!("Not notifying unknown device {}", usn);
+                        }
+                        found
+                    })
+                    .map(|(group, device)| (group.clone(), device.clone()))
+                    .collect();
+                (!selected.is_empty()).then_some(Announcement::Notify(nts, selected))
+            }
+            Control::Unicast { target, usn, alive } => {
+                let groups = self.groups();
+                let Some((group, device)) = devices(&groups).find(|(_, d)| d.usn == usn) else {
+                    warn!("Not notifying {} of unknown device {}", target, usn);
+                    return None;
+                };
+                if alive {
+                    Some(Announcement::AliveTo(target, group.clone(), device.clone()))
+                } else {
+                    Some(Announcement::ByebyeTo(target, device.clone()))
+                }
+            }
+        }
+    }
+
+    /// Change the device groups with `modify`, keeping the status up to date.
+    fn modify_groups<T>(
+        &self,
+        modify: impl FnOnce(&mut Vec<DeviceGroup>) -> Option<T>,
+    ) -> Option<T> {
+        let mut guard = self.groups.write().unwrap_or_else(|e| e.into_inner());
+        let res = modify(Arc::make_mut(&mut guard));
+        drop(guard);
+
+        if res.is_some() {
+            self.message_cache.clear();
+            if let Some(status) = &self.status {
+                self.attach_status(status);
+            }
+        }
+
+        res
+    }
+
+    /// Announce a change requested through a [`ServerHandle`].
+    async fn announce(
+        &self,
+        announcement: Announcement,
+        socket: &dyn Transport,
+        extra_headers: &ExtraHeaders,
+    ) {
+        let (event, nts, device, message, target) = match announcement {
+            Announcement::Alive(group, device) => {
+                let message = self.alive_message(&group, &device, &extra_headers.alive);
+                ("alive", "ssdp:alive", device, message, None)
+            }
+            Announcement::Byebye(device) => {
+                let message = self.byebye_message(&device, &extra_headers.byebye);
+                ("byebye", "ssdp:byebye", device, message, None)
+            }
+            Announcement::AliveTo(target, group, device) => {
+                let message = self.alive_message(&group, &device, &extra_headers.alive);
+                ("alive", "ssdp:alive", device, message, Some(target))
+            }
+            Announcement::ByebyeTo(target, device) => {
+                let message = self.byebye_message(&device, &extra_headers.byebye);
+                ("byebye", "ssdp:byebye", device, message, Some(target))
+            }
+            Announcement::AliveAll => {
+                if self.is_available() {
+                    self.announce_all(socket, &extra_headers.alive).await;
+                }
+                return;
+            }
+            Announcement::Notify(NotificationSubtype::Update, _) => {
+                // The boot id is shared, so all devices are updated
+                return self.broadcast_update(None, socket, extra_headers).await;
+            }
+            Announcement::Notify(nts, devices) => {
+                if self.is_available() {
+                    self.notify_devices(nts, &devices, socket, extra_headers)
+                        .await;
+                }
+                return;
+            }
+            Announcement::Update(config_id) => {
+                return self
+                    .broadcast_update(config_id, socket, extra_headers)
+                    .await;
+            }
+        };
+
+        if !self.is_available() {
+            return;
+        }
+
+        self.notify_device(socket, event, nts, &device, &message, target)
+            .await;
+    }
+
+    /// Send the notification `message` about `device`, to `target` or the multicast
+    /// group, and record the result.
+    async fn notify_device(
+        &self,
+        socket: &dyn Transport,
+        event: &'static str,
+        nts: &'static str,
+        device: &Device,
+        message: &str,
+        target: Option<SocketAddr>,
+    ) {
+        let res = match target {
+            Some(target) => {
+                self.throttle.acquire(message.len()).await;
+                transport::send_to(socket, message.as_bytes(), target).await
+            }
+            None => self.send_announcement(socket, message).await,
+        };
+        self.sent_notification(nts, &device.usn, &res);
+        if let Some(event_log) = &self.event_log {
+            Self::record_send(event_log, event, &device.usn, target, &res);
+        }
+        if let Err(e) = res {
+            error!("Send {} message for {} failed: {}", event, device.usn, e);
+        }
+    }
+
+    /// Send notifications of subtype `nts` for `devices`, paced as other announcements.
+    ///
+    /// `ssdp:update` announces the next boot id, without switching to it.
+    async fn notify_devices(
+        &self,
+        nts: NotificationSubtype,
+        devices: &[(DeviceGroup, Device)],
+        socket: &dyn Transport,
+        extra_headers: &ExtraHeaders,
+    ) {
+        let next_boot_id = self.current_boot_id.load(Ordering::Acquire).wrapping_add(1);
+        let event = nts.as_str().trim_start_matches("ssdp:");
+        let delays = self.pacing.delays(devices.len());
+        for ((group, device), delay) in devices.iter().zip(delays) {
+            let message = match nts {
+                NotificationSubtype::Alive => {
+                    self.alive_message(group, device, &extra_headers.alive)
+                }
+                NotificationSubtype::ByeBye => self.byebye_message(device, &extra_headers.byebye),
+                NotificationSubtype::Update => {
+                    let message = message::update(
+                        &self.options,
+                        device,
+                        &self.location(device, None),
+                        next_boot_id,
+                        &self.expand_extra_headers(
+                            MessageKind::Alive,
+                            device,
+                            None,
+                            None,
+                            &extra_headers.alive,
+                        ),
+                    );
+                    trace!("Update message: {}", message);
+                    message
+                }
+            };
+            self.notify_device(socket, event, nts.as_str(), device, &message, None)
+                .await;
+            tokio::time::sleep(group.pacing.unwrap_or(delay)).await;
+        }
+    }
+
+    /// Broadcast `ssdp:update` announcing the next boot id, switch to it and to
+    /// `config_id`, and re-announce all devices.
+    async fn broadcast_update(
+        &self,
+        config_id: Option<u32>,
+        socket: &dyn Transport,
+        extra_headers: &ExtraHeaders,
+    ) {
+        let next_boot_id = self.current_boot_id.load(Ordering::Acquire).wrapping_add(1);
+        let available = self.is_available();
+
+        if available {
+            debug!("Sending update messages");
+            let devices: Vec<_> = devices(&self.groups())
+                .map(|(group, device)| (group.clone(), device.clone()))
+                .collect();
+            self.notify_devices(NotificationSubtype::Update, &devices, socket, extra_headers)
+                .await;
+        }
+
+        info!("Boot id is now {}", next_boot_id);
+        self.current_boot_id.store(next_boot_id, Ordering::Release);
+        self.save_boot_id(next_boot_id);
+        if let Some(config_id) = config_id {
+            self.current_config_id.store(config_id, Ordering::Release);
+        }
+
+        if available {
+            self.announce_all(socket, &extra_headers.alive).await;
+        }
+    }
+
+    /// Broadcast `ssdp:alive` for all groups, outside of their schedule.
+    async fn announce_all(&self, socket: &dyn Transport, extra_headers: &str) {
+        for group in self.groups().iter() {
+            let cycle = self.broadcast_alive(group, socket, extra_headers).await;
+            for (usn, res) in &cycle.results {
+                if let Err(e) = res {
+                    error!("Send alive message for {} failed: {}", usn, e);
+                }
+            }
+            let _ = self.alive_cycle_tx.send(cycle);
+        }
+    }
+
+    fn group_max_age(&self, group: &DeviceGroup) -> u64 {
+        group.max_age.unwrap_or(self.options.max_age)
+    }
+
+    fn group_notify_interval(&self, group: &DeviceGroup) -> Duration {
+        group
+            .notify_interval
+            .or(self.notify_interval)
+            .unwrap_or_else(|| Duration::from_secs(self.group_max_age(group)) / 3)
+    }
+
+    /// `interval`, shortened by a random duration up to [`Server::notify_jitter`].
+    fn jittered(&self, interval: Duration) -> Duration {
+        interval.saturating_sub(self.notify_jitter.mul_f64(rng::unit()))
+    }
+
+    fn device_max_age(&self, group: &DeviceGroup, device: &Device) -> u64 {
+        device.max_age.unwrap_or_else(|| self.group_max_age(group))
+    }
+
+    /// Send an announcement to the multicast group, retrying temporary failures, and
+    /// repeat it as configured with [`Server::repeat`]
+    async fn send_announcement(&self, socket: &dyn Transport, message: &str) -> IoResult<usize> {
+        let res = self.send_announcement_once(socket, message).await;
+
+        for _ in 1..self.repeat {
+            tokio::time::sleep(self.repeat_spacing.mul_f64(rng::unit())).await;
+            if let Err(e) = self.send_announcement_once(socket, message).await {
+                debug!("Sending repeated announcement failed: {}", e);
+            }
+        }
+
+        res
+    }
+
+    /// Send `messages` as [`Server::send_announcement`], batched if there are several.
+    async fn send_announcements(
+        &self,
+        socket: &dyn Transport,
+        messages: &[&str],
+    ) -> Vec<IoResult<usize>> {
+        match messages {
+            [] => return vec![],
+            [message] => return vec![self.send_announcement(socket, message).await],
+            _ => {}
+        }
+
+        let res = self.send_batch_once(socket, messages).await;
+
+        for _ in 1..self.repeat {
+            tokio::time::sleep(self.repeat_spacing.mul_f64(rng::unit())).await;
+            let repeated = self.send_batch_once(socket, messages).await;
+            if let Some(Err(e)) = repeated.iter().find(|res| res.is_err()) {
+                debug!("Sending repeated announcements failed: {}", e);
+            }
+        }
+
+        res
+    }
+
+    /// Send `messages` with as few system calls as possible. Messages after a failed one
+    /// are sent one by one, retrying temporary failures.
+    async fn send_batch_once(
+        &self,
+        socket: &dyn Transport,
+        messages: &[&str],
+    ) -> Vec<IoResult<usize>> {
+        for message in messages {
+            self.throttle.acquire(message.len()).await;
+        }
+
+        let bufs: Vec<&[u8]> = messages.iter().map(|message| message.as_bytes()).collect();
+        let target = SocketAddr::V4(self.options.multicast);
+        let sent = match batch::send_to(socket, &bufs, target).await {
+            Ok(sent) => sent,
+            Err(e) => {
+                debug!("Sending batch of announcements failed: {}", e);
+                0
+            }
+        };
+
+        let mut results: Vec<_> = bufs[..sent].iter().map(|buf| Ok(buf.len())).collect();
+        for message in &messages[sent..] {
+            results.push(self.send_announcement_once(socket, message).await);
+        }
+        results
+    }
+
+    async fn send_announcement_once(
+        &self,
+        socket: &dyn Transport,
+        message: &str,
+    ) -> IoResult<usize> {
+        self.throttle.acquire(message.len()).await;
+
+        let mut attempt = 0;
+        loop {
+            let target = SocketAddr::V4(self.options.multicast);
+            match transport::send_to(socket, message.as_bytes(), target).await {
+                Err(e) if SendErrorClass::of(&e) == SendErrorClass::Temporary => {
+                    let Some(delay) = self.announce_retry.and_then(|b| b.delay(attempt)) else {
+                        return Err(e);
+                    };
+                    debug!(
+                        "Sending announcement failed, retrying in {:?}: {}",
+                        delay, e
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Record an event in the event log, if enabled by [`Server::event_log`].
+    fn record(&self, event: &str, fields: &[(&str, &dyn fmt::Display)]) {
+        if let Some(event_log) = &self.event_log {
+            event_log.record(event, fields);
+        }
+    }
+
+    /// Send the event made by `event` to the subscribers of [`Server::events`], if any.
+    fn emit(&self, event: impl FnOnce() -> ServerEvent) {
+        if self.events_tx.receiver_count() > 0 {
+            let _ = self.events_tx.send(event());
+        }
+    }
+
+    /// Count sending a notification with the `NTS` subtype `nts` about `usn` in the
+    /// metrics, and report it if it failed.
+    fn sent_notification(&self, nts: &'static str, usn: &str, res: &IoResult<usize>) {
+        match res {
+            Ok(_) => {
+                stats::notification_sent(nts);
+                if let Some(event) = ServerEvent::notification(nts, usn) {
+                    self.emit(|| event);
+                }
+            }
+            Err(e) => {
+                stats::send_failed();
+                let _ = self.errors_tx.send(Error::Send {
+                    event: nts.trim_start_matches("ssdp:"),
+                    usn: usn.to_string(),
+                    remote_addr: None,
+                    error: error::shared(e),
+                });
+            }
+        }
+    }
+
+    /// Record sending a message about `usn` in `event_log`.
+    fn record_send(
+        event_log: &EventLog,
+        event: &str,
+        usn: &str,
+        remote_addr: Option<SocketAddr>,
+        res: &IoResult<usize>,
+    ) {
+        let mut fields: Vec<(&str, &dyn fmt::Display)> = vec![("usn", &usn)];
+        if let Some(remote_addr) = &remote_addr {
+            fields.push(("remote_addr", remote_addr));
+        }
+        if let Err(e) = res {
+            fields.push(("error", e));
+        }
+        event_log.record(event, &fields);
+    }
+
+    /// Broadcast `ssdp:alive` for the devices in `group`
+    async fn broadcast_alive(
+        &self,
+        group: &DeviceGroup,
+        socket: &dyn Transport,
+        extra_headers: &str,
+    ) -> AliveCycle {
+        debug!("Sending alive messages");
+
+        let mut results = Vec::with_capacity(group.devices.len());
+        let delays = match group.pacing {
+            Some(pacing) => vec![pacing; group.devices.len()],
+            None => self.pacing.delays(group.devices.len()),
+        };
+
+        let mut batch = Vec::with_capacity(self.batch_size);
+        for (device, delay) in group.devices.iter().zip(delays) {
+            if self.is_backed_off(device) {
+                trace!("Not announcing {}, backed off", device.usn);
+                continue;
+            }
+
+            let message = self.alive_message(group, device, extra_headers);
+
+            trace!("Alive message: {}", message);
+
+            batch.push((device, message));
+            if delay.is_zero() && batch.len() < self.batch_size {
+                continue;
+            }
+            self.send_alive_batch(socket, &mut batch, &mut results)
+                .await;
+
+            // Avoid congestion
+            tokio::time::sleep(delay).await;
+        }
+        self.send_alive_batch(socket, &mut batch, &mut results)
+            .await;
+
+        AliveCycle { results }
+    }
+
+    /// Send the alive messages in `batch`, leaving it empty for reuse, and add the
+    /// results to `results`.
+    async fn send_alive_batch(
+        &self,
+        socket: &dyn Transport,
+        batch: &mut Vec<(&Device, String)>,
+        results: &mut Vec<(String, Result<(), Arc<std::io::Error>>)>,
+    ) {
+        let messages: Vec<&str> = batch.iter().map(|(_, message)| message.as_str()).collect();
+        let sent = self.send_announcements(socket, &messages).await;
+
+        for ((device, _), res) in batch.drain(..).zip(sent) {
+            self.sent_notification("ssdp:alive", &device.usn, &res);
+            match res {
+                Ok(_) => self.health.notified(),
+                Err(_) => self.health.send_failed(),
+            }
+            if let Some(event_log) = &self.event_log {
+                Self::record_send(event_log, "alive", &device.usn, None, &res);
+            }
+            results.push((device.usn.clone(), res.map(drop).map_err(Arc::new)));
+        }
+    }
+
+    /// Broadcast `ssdp:byebye` for all devices, returning the first error after trying
+    /// every device.
+    async fn broadcast_byebye(&self, socket: &dyn Transport, extra_headers: &str) -> IoResult<()> {
+        debug!("Sending byebye messages");
+
+        let groups = self.groups();
+        let mut devices: Vec<_> = devices(&groups).collect();
+        let deadline = self.byebye_deadline.map(|d| Instant::now() + d);
+        if deadline.is_some() {
+            devices.sort_by_key(|(_, device)| !device.is_root());
+        }
+
+        let count = devices.len();
+        let delays = self.pacing.delays(count);
+        let mut first_err = None;
+        for (idx, ((group, device), delay)) in devices.into_iter().zip(delays).enumerate() {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                debug!("Byebye deadline reached, dropping {} messages", count - idx);
+                break;
+            }
+
+            let message = self.byebye_message(device, extra_headers);
+
+            trace!("Byebye message: {}", message);
+
+            let res = self.send_announcement(socket, &message).await;
+            self.sent_notification("ssdp:byebye", &device.usn, &res);
+            if let Some(event_log) = &self.event_log {
+                Self::record_send(event_log, "byebye", &device.usn, None, &res);
+            }
+            match res {
+                Ok(_) => self.health.sent(),
+                Err(e) => {
+                    // Keep going, so the other devices still say goodbye
+                    self.health.send_failed();
+                    warn!("Send byebye message for {} failed: {}", device.usn, e);
+                    first_err.get_or_insert(e);
+                    continue;
+                }
+            }
+
+            // Avoid congestion
+            let mut pacing = group.pacing.unwrap_or(delay);
+            if let Some(deadline) = deadline {
+                let left = (count - idx) as u32;
+                pacing = pacing.min(deadline.saturating_duration_since(Instant::now()) / left);
+            }
+            tokio::time::sleep(pacing).await;
+        }
+
+        first_err.map_or(Ok(()), Err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::task::{Context, Poll};
+
+    use tokio::{io::ReadBuf, task::JoinHandle};
+
+    use super::*;
+
+    const UUID: &str = "ad8782a0-9e28-422b-a6ae-670fe7c4c043";
+    const CONTROL_POINT: &str = "192.168.1.20:50000";
+
+    /// Stands in for the network: receives what a test feeds it, and passes on
+    /// everything the server sends. Sending messages containing one of `failing` fails,
+    /// and the first `temporary_failures` sends fail temporarily.
+    struct MockTransport {
+        incoming: Mutex<mpsc::UnboundedReceiver<(Vec<u8>, SocketAddr)>>,
+        sent: mpsc::UnboundedSender<(String, SocketAddr)>,
+        failing: Vec<String>,
+        temporary_failures: AtomicUsize,
+    }
+
+    impl Transport for MockTransport {
+        fn poll_send_to(
+            &self,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+            target: SocketAddr,
+        ) -> Poll<std::io::Result<usize>> {
+            let message = String::from_utf8_lossy(buf).into_owned();
+            if self.failing.iter().any(|failing| message.contains(failing)) {
+                return Poll::Ready(Err(std::io::ErrorKind::PermissionDenied.into()));
+            }
+            let temporary_failure =
+                self.temporary_failures
+                    .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1));
+            if temporary_failure.is_ok() {
+                return Poll::Ready(Err(std::io::ErrorKind::WouldBlock.into()));
+            }
+            let _ = self.sent.send((message, target));
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_recv_from(
+            &self,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<SocketAddr>> {
+            match self.incoming.lock().unwrap().poll_recv(cx) {
+                Poll::Ready(Some((data, addr))) => {
+                    buf.put_slice(&data);
+                    Poll::Ready(Ok(addr))
+                }
+                _ => Poll::Pending,
+            }
+        }
+
+        fn local_addr(&self) -> std::io::Result<SocketAddr> {
+            Ok("192.168.1.100:1900".parse().unwrap())
+        }
+    }
+
+    /// A server running on a [`MockTransport`].
+    struct Running {
+        incoming: mpsc::UnboundedSender<(Vec<u8>, SocketAddr)>,
+        sent: mpsc::UnboundedReceiver<(String, SocketAddr)>,
+        control: mpsc::UnboundedSender<Control>,
+        shutdown: Arc<watch::Sender<bool>>,
+        task: JoinHandle<IoResult<()>>,
+    }
+
+    impl Running {
+        fn start(server: Server) -> Self {
+            Self::start_failing(server, &[])
+        }
+
+        fn start_failing(server: Server, failing: &[&str]) -> Self {
+            Self::start_with(server, failing, 0)
+        }
+
+        fn start_with(server: Server, failing: &[&str], temporary_failures: usize) -> Self {
+            let (incoming, incoming_rx) = mpsc::unbounded_channel();
+            let (sent_tx, sent) = mpsc::unbounded_channel();
+            let transport = MockTransport {
+                incoming: Mutex::new(incoming_rx),
+                sent: sent_tx,
+                failing: failing.iter().map(|s| s.to_string()).collect(),
+                temporary_failures: AtomicUsize::new(temporary_failures),
+            };
+            let (control, control_rx) = mpsc::unbounded_channel();
+            let shutdown: Arc<watch::Sender<bool>> = Arc::default();
+            let ip = Ipv4Addr::new(192, 168, 1, 100);
+            let receiver = Receiver::Transport(Arc::new(transport));
+            let server = server
+                .serve_with(ip, receiver, Some(control_rx), Arc::clone(&shutdown))
+                .unwrap();
+
+            Self {
+                incoming,
+                sent,
+                control,
+                shutdown,
+                task: tokio::spawn(server),
+            }
+        }
+
+        /// Feed `data` to the server, as sent by `from`.
+        fn receive(&self, data: &str, from: &str) {
+            let from = from.parse().unwrap();
+            self.incoming
+                .send((data.as_bytes().to_vec(), from))
+                .unwrap();
+        }
+
+        /// Feed the server an `M-SEARCH` for `st` from [`CONTROL_POINT`].
+        fn search(&self, st: &str, mx: u32) {
+            self.receive(&search(st, mx), CONTROL_POINT);
+        }
+
+        /// The next message sent, failing the test if none is sent for a while.
+        async fn next(&mut self) -> (String, SocketAddr) {
+            tokio::time::timeout(Duration::from_secs(10), self.sent.recv())
+                .await
+                .expect("no message sent")
+                .expect("server stopped")
+        }
+
+        /// The next message sent to `target`, skipping others.
+        async fn next_to(&mut self, target: &str) -> String {
+            let target: SocketAddr = target.parse().unwrap();
+            loop {
+                let (message, to) = self.next().await;
+                if to == target {
+                    return message;
+                }
+            }
+        }
+
+        /// The next notification with `NTS: nts`, skipping other messages.
+        async fn next_notify(&mut self, nts: &str) -> String {
+            loop {
+                let (message, _) = self.next().await;
+                if header(&message, "NTS") == Some(nts) {
+                    return message;
+                }
+            }
+        }
+
+        /// The messages sent within `duration`.
+        async fn sent_within(&mut self, duration: Duration) -> Vec<(String, SocketAddr)> {
+            let mut sent = vec![];
+            let deadline = tokio::time::Instant::now() + duration;
+            while let Ok(Some(message)) = tokio::time::timeout_at(deadline, self.sent.recv()).await
+            {
+                sent.push(message);
+            }
+            sent
+        }
+
+        /// Request `control` like a [`ServerHandle`].
+        fn control(&self, control: Control) {
+            self.control.send(control).unwrap();
+        }
+
+        /// Shut the server down, returning the messages sent until it stopped.
+        async fn shutdown(mut self) -> Vec<(String, SocketAddr)> {
+            self.shutdown.send_replace(true);
+            self.task.await.unwrap().unwrap();
+
+            let mut sent = vec![];
+            while let Ok(message) = self.sent.try_recv() {
+                sent.push(message);
+            }
+            sent
+        }
+    }
+
+    /// A server answering right away, without pacing its announcements.
+    fn server(devices: impl IntoIterator<Item = Device>) -> Server {
+        Server::new(devices)
+            .response_delay(ResponseDelay::Immediate)
+            .pacing(Pacing::Fixed(Duration::ZERO))
+    }
+
+    fn root_device() -> Device {
+        Device::new(UUID, "upnp:rootdevice", "http://{addr}:8080/desc.xml")
+    }
+
+    fn service(service: &str) -> Device {
+        let st = format!("urn:schemas-upnp-org:service:{}", service);
+        Device::new(UUID, st, "http://{addr}:8080/desc.xml")
+    }
+
+    fn search(st: &str, mx: u32) -> String {
+        format!(
+            "M-SEARCH * HTTP/1.1\r\n\
+             HOST: 239.255.255.250:1900\r\n\
+             MAN: \"ssdp:discover\"\r\n\
+             MX: {}\r\n\
+             ST: {}\r\n\r\n",
+            mx, st
+        )
+    }
+
+    /// The value of the header `name` in `message`.
+    fn header<'a>(message: &'a str, name: &str) -> Option<&'a str> {
+        message.split("\r\n").skip(1).find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim()
+                .eq_ignore_ascii_case(name)
+                .then_some(value.trim())
+        })
+    }
+
+    /// The USNs of the messages in `sent` with `NTS: nts`.
+    fn usns(sent: &[(String, SocketAddr)], nts: &str) -> Vec<String> {
+        sent.iter()
+            .filter(|(message, _)| header(message, "NTS") == Some(nts))
+            .filter_map(|(message, _)| header(message, "USN"))
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// The names of the headers in `message`, in order.
+    fn header_names(message: &str) -> Vec<&str> {
+        message
+            .split("\r\n")
+            .skip(1)
+            .filter_map(|line| Some(line.split_once(':')?.0))
+            .collect()
+    }
+
+    /// A port which is free on all addresses, at least right now.
+    fn free_port() -> u16 {
+        let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        socket.local_addr().unwrap().port()
+    }
+
+    /// A server receiving on a loopback socket of its own, on a port of its own.
+    struct Bound {
+        port: u16,
+        shutdown: Arc<watch::Sender<bool>>,
+        task: JoinHandle<IoResult<()>>,
+    }
+
+    impl Bound {
+        fn start(server: Server) -> Self {
+            let port = free_port();
+            let shutdown: Arc<watch::Sender<bool>> = Arc::default();
+            let server = server
+                .multicast_endpoint(SSDP_ADDR_V4, port)
+                .serve_with(
+                    Ipv4Addr::LOCALHOST,
+                    Receiver::Bind,
+                    None,
+                    Arc::clone(&shutdown),
+                )
+                .unwrap();
+
+            Self {
+                port,
+                shutdown,
+                task: tokio::spawn(server),
+            }
+        }
+
+        /// Send `data` to the server from `socket`.
+        async fn send(&self, socket: &UdpSocket, data: &str) {
+            socket
+                .send_to(data.as_bytes(), (Ipv4Addr::LOCALHOST, self.port))
+                .await
+                .unwrap();
+        }
+
+        async fn shutdown(self) {
+            self.shutdown.send_replace(true);
+            self.task.await.unwrap().unwrap();
+        }
+    }
+
+    /// A socket on loopback, e.g. a control point.
+    async fn loopback_socket() -> UdpSocket {
+        UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap()
+    }
+
+    /// The next datagram received by `socket` within `timeout`.
+    async fn recv_within(socket: &UdpSocket, timeout: Duration) -> Option<String> {
+        let mut buf = [0; 2048];
+        let n = tokio::time::timeout(timeout, socket.recv_from(&mut buf))
+            .await
+            .ok()?
+            .unwrap()
+            .0;
+        Some(String::from_utf8_lossy(&buf[..n]).into_owned())
+    }
+
+    #[tokio::test]
+    async fn sends_byebye_on_shutdown() {
+        let devices = [root_device(), service("ContentDirectory:1")];
+        let mut running = Running::start(server(devices.clone()));
+        running.next_notify("ssdp:alive").await;
+
+        let sent = running.shutdown().await;
+        let expected: Vec<_> = devices.iter().map(|device| device.usn.clone()).collect();
+        assert_eq!(usns(&sent, "ssdp:byebye"), expected);
+        for (message, target) in &sent {
+            assert_eq!(
+                *target,
+                SocketAddr::V4(SocketAddrV4::new(SSDP_ADDR_V4, SSDP_PORT))
+            );
+            assert!(message.starts_with("NOTIFY * HTTP/1.1\r\n"));
+        }
+    }
+
+    #[tokio::test]
+    async fn announces_update_with_next_boot_id() {
+        let devices = [root_device(), service("ContentDirectory:1")];
+        let server = server(devices.clone())
+            .upnp_version(UpnpVersion::V1_1)
+            .boot_id(7);
+        let mut running = Running::start(server);
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_eq!(header(&alive, "BOOTID.UPNP.ORG"), Some("7"));
+        assert_eq!(header(&alive, "CONFIGID.UPNP.ORG"), Some("1"));
+
+        running.control(Control::Update(Some(2)));
+        for device in &devices {
+            let update = running.next_notify("ssdp:update").await;
+            assert_eq!(header(&update, "USN"), Some(device.usn.as_str()));
+            assert_eq!(header(&update, "BOOTID.UPNP.ORG"), Some("7"));
+            assert_eq!(header(&update, "NEXTBOOTID.UPNP.ORG"), Some("8"));
+        }
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_eq!(header(&alive, "BOOTID.UPNP.ORG"), Some("8"));
+        assert_eq!(header(&alive, "CONFIGID.UPNP.ORG"), Some("2"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn answers_search_within_mx() {
+        let mut running =
+            Running::start(server([root_device()]).response_delay(ResponseDelay::Uniform));
+        running.next_notify("ssdp:alive").await;
+
+        let start = tokio::time::Instant::now();
+        running.search("upnp:rootdevice", 2);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert!(start.elapsed() <= Duration::from_secs(2));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(header(&response, "ST"), Some("upnp:rootdevice"));
+        assert_eq!(
+            header(&response, "LOCATION"),
+            Some("http://192.168.1.100:8080/desc.xml")
+        );
+
+        // Capped by `Server::max_mx`
+        let delay = ResponseDelay::Fixed(Duration::from_secs(60));
+        let mut running = Running::start(server([root_device()]).response_delay(delay));
+        running.next_notify("ssdp:alive").await;
+
+        let start = tokio::time::Instant::now();
+        running.search("upnp:rootdevice", 120);
+        running.next_to(CONTROL_POINT).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(DEFAULT_MAX_MX as u64));
+    }
+
+    #[tokio::test]
+    async fn ignores_searches_from_denied_peers() {
+        let server = server([root_device()])
+            .allow_subnet("192.168.1.0/24".parse().unwrap())
+            .deny_addr(Ipv4Addr::new(192, 168, 1, 13));
+        let mut running = Running::start(server);
+        running.next_notify("ssdp:alive").await;
+
+        let search = search("ssdp:all", 1);
+        running.receive(&search, "10.0.0.20:50000");
+        running.receive(&search, "192.168.1.13:50000");
+        running.receive(&search, CONTROL_POINT);
+
+        let (response, target) = running.next().await;
+        assert_eq!(target, CONTROL_POINT.parse().unwrap());
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(
+            running
+                .sent_within(Duration::from_millis(100))
+                .await
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn orders_headers() {
+        let order = [Header::St, Header::Usn, Header::Ext, Header::Location];
+        let mut running = Running::start(server([root_device()]).header_order(order));
+        running.next_notify("ssdp:alive").await;
+
+        running.search("upnp:rootdevice", 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert_eq!(
+            header_names(&response)[..4],
+            ["ST", "USN", "EXT", "LOCATION"]
+        );
+
+        let device = root_device().header_order([Header::Usn, Header::St]);
+        let mut running = Running::start(server([device]).header_order(order));
+        running.next_notify("ssdp:alive").await;
+
+        running.search("upnp:rootdevice", 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert_eq!(header_names(&response)[..2], ["USN", "ST"]);
+    }
+
+    #[tokio::test]
+    async fn adds_content_length() {
+        let mut running = Running::start(server([root_device()]).content_length(true));
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_eq!(header(&alive, "CONTENT-LENGTH"), Some("0"));
+
+        running.search("upnp:rootdevice", 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert_eq!(header(&response, "CONTENT-LENGTH"), Some("0"));
+
+        let sent = running.shutdown().await;
+        let (byebye, _) = &sent[0];
+        assert_eq!(header(byebye, "CONTENT-LENGTH"), Some("0"));
+    }
+
+    #[tokio::test]
+    async fn uses_http_version() {
+        let mut running = Running::start(server([root_device()]).http_version("HTTP/1.0"));
+        let alive = running.next_notify("ssdp:alive").await;
+        assert!(alive.starts_with("NOTIFY * HTTP/1.0\r\n"));
+
+        running.search("upnp:rootdevice", 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+    }
+
+    #[tokio::test]
+    async fn uses_custom_usn() {
+        let custom = root_device().usn("uuid:CUSTOM::upnp:rootdevice");
+        let formatted =
+            service("ContentDirectory:1").usn_format(|uuid, st| format!("{}/{}", st, uuid));
+        let mut running = Running::start(server([custom, formatted]));
+        running.next_notify("ssdp:alive").await;
+
+        running.search("upnp:rootdevice", 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert_eq!(
+            header(&response, "USN"),
+            Some("uuid:CUSTOM::upnp:rootdevice")
+        );
+
+        running.search("urn:schemas-upnp-org:service:ContentDirectory:1", 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert_eq!(
+            header(&response, "USN"),
+            Some(
+                "urn:schemas-upnp-org:service:ContentDirectory:1/ad8782a0-9e28-422b-a6ae-670fe7c4c043"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn accepts_unquoted_man_when_enabled() {
+        let unquoted = search("upnp:rootdevice", 1).replace("\"ssdp:discover\"", " ssdp:discover ");
+
+        let mut running = Running::start(server([root_device()]));
+        running.next_notify("ssdp:alive").await;
+        running.receive(&unquoted, CONTROL_POINT);
+        assert!(
+            running
+                .sent_within(Duration::from_millis(100))
+                .await
+                .is_empty()
+        );
+
+        let mut running = Running::start(server([root_device()]).unquoted_man_workaround(true));
+        running.next_notify("ssdp:alive").await;
+        running.receive(&unquoted, CONTROL_POINT);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn handles_missing_mx() {
+        let no_mx = search("upnp:rootdevice", 1).replace("MX: 1\r\n", "");
+        let delay = ResponseDelay::Fixed(Duration::from_secs(3));
+
+        let mut running = Running::start(server([root_device()]).response_delay(delay));
+        running.next_notify("ssdp:alive").await;
+        let start = tokio::time::Instant::now();
+        running.receive(&no_mx, CONTROL_POINT);
+        running.next_to(CONTROL_POINT).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        let assume = server([root_device()])
+            .response_delay(delay)
+            .missing_mx(MissingMx::Assume(1));
+        let mut running = Running::start(assume);
+        running.next_notify("ssdp:alive").await;
+        let start = tokio::time::Instant::now();
+        running.receive(&no_mx, CONTROL_POINT);
+        running.next_to(CONTROL_POINT).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+
+        let mut running = Running::start(server([root_device()]).missing_mx(MissingMx::Reject));
+        running.next_notify("ssdp:alive").await;
+        running.receive(&no_mx, CONTROL_POINT);
+        assert!(running.sent_within(Duration::from_secs(2)).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn adds_extra_headers_per_message_kind() {
+        let server = server([root_device()])
+            .extra_header("X-ALL", "1")
+            .extra_header_for(MessageKind::Alive, "X-ALIVE", "1")
+            .extra_header_for(MessageKind::SearchResponse, "X-RESPONSE", "1")
+            .extra_header_for(MessageKind::Byebye, "X-BYEBYE", "1");
+        let mut running = Running::start(server);
+
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_eq!(header_names(&alive)[7..], ["X-ALL", "X-ALIVE"]);
+
+        running.search("upnp:rootdevice", 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert_eq!(header_names(&response)[7..], ["X-ALL", "X-RESPONSE"]);
+
+        let sent = running.shutdown().await;
+        let (byebye, _) = &sent[0];
+        assert_eq!(header_names(byebye)[4..], ["X-ALL", "X-BYEBYE"]);
+    }
+
+    #[tokio::test]
+    async fn repeats_extra_headers() {
+        let server = server([root_device()])
+            .extra_header("X-User-Agent", "redsonic")
+            .extra_header("X-User-Agent", "acme");
+        let mut running = Running::start(server);
+        running.next_notify("ssdp:alive").await;
+
+        running.search("upnp:rootdevice", 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert!(response.ends_with("X-User-Agent: redsonic\r\nX-User-Agent: acme\r\n\r\n"));
+    }
+
+    #[tokio::test]
+    async fn expands_placeholders_in_extra_headers() {
+        let server = server([root_device()])
+            .upnp_version(UpnpVersion::V1_1)
+            .boot_id(7)
+            .extra_header("X-BRIDGE-ID", "{uuid}")
+            .extra_header("X-DESCRIPTION", "{location}")
+            .extra_header("X-HOST", "{addr}")
+            .extra_header("X-BOOT", "{bootid}");
+        let mut running = Running::start(server);
+
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_eq!(header(&alive, "X-BRIDGE-ID"), Some(UUID));
+        assert_eq!(
+            header(&alive, "X-DESCRIPTION"),
+            Some("http://192.168.1.100:8080/desc.xml")
+        );
+        assert_eq!(header(&alive, "X-HOST"), Some("192.168.1.100"));
+        assert_eq!(header(&alive, "X-BOOT"), Some("7"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn announces_groups_on_their_own_schedule() {
+        let light = Device::new(
+            "0c3ab6d2-4f3e-4b1a-9a0e-2b8f6f2a1f10",
+            "urn:schemas-upnp-org:device:DimmableLight:1",
+            "http://{addr}:8080/light.xml",
+        );
+        let group = DeviceGroup::new([light.clone()])
+            .max_age(30)
+            .notify_interval(Duration::from_secs(10));
+        let server = server([root_device()])
+            .notify_interval(Duration::from_secs(60))
+            .group(group);
+        let mut running = Running::start(server);
+
+        let sent = running.sent_within(Duration::from_secs(35)).await;
+        let alive = usns(&sent, "ssdp:alive");
+        assert_eq!(alive.iter().filter(|usn| **usn == light.usn).count(), 4);
+        assert_eq!(
+            alive
+                .iter()
+                .filter(|usn| **usn == root_device().usn)
+                .count(),
+            1
+        );
+        for (message, _) in &sent {
+            if header(message, "USN") == Some(light.usn.as_str()) {
+                assert_eq!(header(message, "CACHE-CONTROL"), Some("max-age=30"));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_remaining_byebyes_after_failure() {
+        let devices = [
+            root_device(),
+            service("ContentDirectory:1"),
+            service("ConnectionManager:1"),
+        ];
+        let failing = format!("NTS: ssdp:byebye\r\nUSN: {}", devices[1].usn);
+        let mut running = Running::start_failing(server(devices.clone()), &[&failing]);
+        running.next_notify("ssdp:alive").await;
+
+        let sent = running.shutdown().await;
+        assert_eq!(
+            usns(&sent, "ssdp:byebye"),
+            [devices[0].usn.clone(), devices[2].usn.clone()]
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_root_byebye_first_within_deadline() {
+        let devices = [service("ContentDirectory:1"), root_device()];
+        let server = Server::new(devices.clone())
+            .pacing(Pacing::Fixed(Duration::from_secs(1)))
+            .byebye_deadline(Duration::from_millis(100));
+        let mut running = Running::start(server);
+        running.next_notify("ssdp:alive").await;
+
+        let sent = running.shutdown().await;
+        assert_eq!(
+            usns(&sent, "ssdp:byebye"),
+            [devices[1].usn.clone(), devices[0].usn.clone()]
+        );
+    }
+
+    #[tokio::test]
+    async fn flushes_pending_responses_on_shutdown() {
+        for (pending, flushed) in [
+            (PendingResponses::Cancel, false),
+            (PendingResponses::Flush(Duration::from_secs(1)), true),
+        ] {
+            let server = server([root_device()])
+                .response_delay(ResponseDelay::Fixed(Duration::from_secs(30)))
+                .pending_responses(pending);
+            let mut answered = server.answered_searches();
+            let mut running = Running::start(server);
+            running.next_notify("ssdp:alive").await;
+
+            running.search("upnp:rootdevice", 5);
+            answered.recv().await.unwrap();
+            let sent = running.shutdown().await;
+            let control_point = CONTROL_POINT.parse().unwrap();
+            let responses = sent.iter().filter(|(_, target)| *target == control_point);
+            assert_eq!(responses.count(), flushed as usize, "{:?}", pending);
+            // The responses go out before the byebye
+            assert_eq!(usns(&sent, "ssdp:byebye"), [root_device().usn]);
+            assert_eq!(sent.first().unwrap().1 == control_point, flushed);
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_alive_cycles() {
+        let devices = [root_device(), service("ContentDirectory:1")];
+        let failing = format!("USN: {}\r\n", devices[1].usn);
+        let server = server(devices.clone());
+        let mut cycles = server.alive_cycles();
+        let mut running = Running::start_failing(server, &[&failing]);
+
+        let cycle = cycles.recv().await.unwrap();
+        assert!(!cycle.is_ok());
+        assert_eq!(cycle.results.len(), 2);
+        assert_eq!(cycle.results[0].0, devices[0].usn);
+        assert!(cycle.results[0].1.is_ok());
+        assert_eq!(cycle.results[1].0, devices[1].usn);
+        assert!(cycle.results[1].1.is_err());
+
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_eq!(header(&alive, "USN"), Some(devices[0].usn.as_str()));
+    }
+
+    #[tokio::test]
+    async fn reports_health() {
+        let healthy = server([root_device()]);
+        let health = healthy.health();
+        assert_eq!(health.report(), crate::HealthReport::default());
+        let mut running = Running::start(healthy);
+
+        running.next_notify("ssdp:alive").await;
+        let report = health.report();
+        assert!(report.last_notify.is_some());
+        assert!(report.multicast_joined);
+        assert_eq!(report.last_receive, None);
+
+        running.search("upnp:rootdevice", 1);
+        running.next_to(CONTROL_POINT).await;
+        assert!(health.report().last_receive.is_some());
+
+        // Failures count until a send succeeds
+        let failing = server([root_device()]).announce_retry(None);
+        let health = failing.health();
+        let mut cycles = failing.alive_cycles();
+        let mut running = Running::start_failing(failing, &["ssdp:alive"]);
+        assert!(!cycles.recv().await.unwrap().is_ok());
+        assert_eq!(health.report().consecutive_send_failures, 1);
+        assert_eq!(health.report().last_notify, None);
+
+        running.search("upnp:rootdevice", 1);
+        running.next_to(CONTROL_POINT).await;
+        assert_eq!(health.report().consecutive_send_failures, 0);
+    }
+
+    #[tokio::test]
+    async fn restarts_receiving_socket() {
+        let port = free_port();
+        let backoff = Backoff::new(Duration::from_millis(50), Duration::from_millis(50));
+        let server = Server::new([root_device()])
+            .multicast_endpoint(SSDP_ADDR_V4, port)
+            .restart_on_error(backoff.max_attempts(10));
+        let mut restarts = server.restarts();
+
+        // Held by another process, which doesn't share it, for the first attempt
+        let blocker = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)).unwrap();
+        let cause = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        let restart =
+            tokio::spawn(async move { server.restart_rx_socket(Ipv4Addr::LOCALHOST, cause).await });
+
+        let attempt = restarts.recv().await.unwrap();
+        assert_eq!(attempt.attempt, 0);
+        assert_eq!(attempt.cause.kind(), std::io::ErrorKind::ConnectionReset);
+        assert!(attempt.result.is_err());
+        drop(blocker);
+        let attempt = restarts.recv().await.unwrap();
+        assert!(attempt.result.is_ok());
+
+        let socket = restart.await.unwrap().unwrap();
+        let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let search = search("upnp:rootdevice", 1);
+        client
+            .send_to(search.as_bytes(), (Ipv4Addr::LOCALHOST, port))
+            .await
+            .unwrap();
+        let mut buf = [0; 512];
+        let (n, _) = socket.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], search.as_bytes());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_temporary_send_failures() {
+        let retry = Backoff::new(Duration::from_millis(20), Duration::from_millis(20));
+        let retrying = server([root_device()]).announce_retry(Some(retry.max_attempts(2)));
+        let mut cycles = retrying.alive_cycles();
+        let mut running = Running::start_with(retrying, &[], 2);
+        let start = tokio::time::Instant::now();
+        running.next_notify("ssdp:alive").await;
+        assert_eq!(start.elapsed(), Duration::from_millis(40));
+        assert!(cycles.recv().await.unwrap().is_ok());
+
+        let failing = server([root_device()]).announce_retry(None);
+        let mut cycles = failing.alive_cycles();
+        let mut errors = failing.errors();
+        let _running = Running::start_with(failing, &[], 1);
+        assert!(!cycles.recv().await.unwrap().is_ok());
+        match errors.recv().await.unwrap() {
+            Error::Send { event, usn, .. } => {
+                assert_eq!(event, "alive");
+                assert_eq!(usn, root_device().usn);
+            }
+            error => panic!("unexpected {:?}", error),
+        }
+    }
+
+    #[tokio::test]
+    async fn rejoins_lost_multicast_group() {
+        let port = free_port();
+        let server = Server::new([]).multicast_endpoint(SSDP_ADDR_V4, port);
+        let mut rejoins = server.rejoins();
+        let socket = Server::new_rx_socket(port, false).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let socket = UdpSocket::from_std(socket).unwrap();
+
+        // Never joined, as if the membership was lost
+        server.check_membership(&socket, Ipv4Addr::LOCALHOST);
+        assert_eq!(rejoins.try_recv().unwrap().interface, Ipv4Addr::LOCALHOST);
+        assert!(server.health().report().multicast_joined);
+        server.check_membership(&socket, Ipv4Addr::LOCALHOST);
+        assert!(rejoins.try_recv().is_err());
+
+        let client = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        crate::iface::set_multicast_if(&client, Ipv4Addr::LOCALHOST).unwrap();
+        let search = search("ssdp:all", 1);
+        client
+            .send_to(search.as_bytes(), (SSDP_ADDR_V4, port))
+            .unwrap();
+        let mut buf = [0; 512];
+        let (n, _) = socket.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], search.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn reannounces_devices_when_address_changes() {
+        let fixed = Device::new(
+            UUID,
+            "urn:schemas-upnp-org:service:ContentDirectory:1",
+            "http://192.168.1.100:8080/cd.xml",
+        );
+        let mut server = server([root_device(), fixed]);
+        let mut changes = server.address_changes();
+        server.host_addr = Arc::new(RwLock::new(Ipv4Addr::new(10, 0, 0, 1)));
+
+        let (_incoming, incoming_rx) = mpsc::unbounded_channel();
+        let (sent_tx, mut sent) = mpsc::unbounded_channel();
+        let transport = MockTransport {
+            incoming: Mutex::new(incoming_rx),
+            sent: sent_tx,
+            failing: vec![],
+            temporary_failures: AtomicUsize::new(0),
+        };
+        let extra_headers = ExtraHeaders {
+            search_response: String::new(),
+            alive: String::new(),
+            byebye: String::new(),
+        };
+        let ip = Ipv4Addr::new(192, 168, 1, 100);
+        server.check_host_addr(ip, &transport, &extra_headers).await;
+
+        let (byebye, _) = sent.try_recv().unwrap();
+        assert_eq!(header(&byebye, "NTS"), Some("ssdp:byebye"));
+        assert_eq!(header(&byebye, "USN"), Some(root_device().usn.as_str()));
+        let (alive, _) = sent.try_recv().unwrap();
+        assert_eq!(header(&alive, "NTS"), Some("ssdp:alive"));
+        assert_eq!(
+            header(&alive, "LOCATION"),
+            Some("http://192.168.1.100:8080/desc.xml")
+        );
+        assert!(sent.try_recv().is_err());
+
+        let change = changes.try_recv().unwrap();
+        assert_eq!(change.old, Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(change.new, ip);
+    }
+
+    #[tokio::test]
+    async fn backs_off_from_duplicate_responders() {
+        let device = root_device();
+        let server = server([device.clone()])
+            .duplicate_policy(DuplicatePolicy::BackOff(Duration::from_secs(60)));
+        let mut duplicates = server.duplicates();
+        let mut running = Running::start(server);
+        running.next_notify("ssdp:alive").await;
+
+        let notify = format!(
+            "NOTIFY * HTTP/1.1\r\n\
+             HOST: 239.255.255.250:1900\r\n\
+             CACHE-CONTROL: max-age=1800\r\n\
+             LOCATION: http://192.168.1.50:8080/desc.xml\r\n\
+             NT: upnp:rootdevice\r\n\
+             NTS: ssdp:alive\r\n\
+             USN: {}\r\n\r\n",
+            device.usn
+        );
+        running.receive(&notify, "192.168.1.50:1900");
+        let duplicate = duplicates.recv().await.unwrap();
+        assert_eq!(duplicate.usn, device.usn);
+        assert_eq!(duplicate.addr, "192.168.1.50:1900".parse().unwrap());
+
+        running.search("upnp:rootdevice", 1);
+        assert!(
+            running
+                .sent_within(Duration::from_millis(100))
+                .await
+                .is_empty()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reports_answered_searches() {
+        let server = server([root_device(), service("ContentDirectory:1")])
+            .response_delay(ResponseDelay::Fixed(Duration::from_millis(300)));
+        let mut searches = server.answered_searches();
+        let mut running = Running::start(server);
+        running.next_notify("ssdp:alive").await;
+
+        running.search("upnp:rootdevice", 2);
+        let answered = searches.recv().await.unwrap();
+        assert_eq!(answered.remote_addr, CONTROL_POINT.parse().unwrap());
+        assert_eq!(answered.st, "upnp:rootdevice");
+        assert_eq!(answered.mx, 2);
+        assert_eq!(answered.delay, Duration::from_millis(300));
+        let usns: Vec<_> = answered.devices.iter().map(|d| d.usn.as_str()).collect();
+        assert_eq!(usns, [root_device().usn.as_str()]);
+
+        let response = running.next_to(CONTROL_POINT).await;
+        assert_eq!(header(&response, "ST"), Some("upnp:rootdevice"));
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[tokio::test]
+    async fn ignores_searches_below_min_ttl() {
+        let bound = Bound::start(server([root_device()]).min_ttl(2));
+        let client = loopback_socket().await;
+        let search = search("upnp:rootdevice", 1);
+
+        client.set_ttl(1).unwrap();
+        bound.send(&client, &search).await;
+        assert_eq!(recv_within(&client, Duration::from_millis(200)).await, None);
+
+        client.set_ttl(2).unwrap();
+        bound.send(&client, &search).await;
+        let response = recv_within(&client, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(header(&response, "ST"), Some("upnp:rootdevice"));
+
+        bound.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn throttles_outbound_traffic() {
+        let devices = (0..15).map(|i| service(&format!("Service{}:1", i)));
+        let throttled = server(devices).throttle(Throttle::new().packets_per_second(10));
+        let start = Instant::now();
+        let mut running = Running::start(throttled);
+
+        // A one second burst, followed by the limit
+        let burst = running.sent_within(Duration::from_millis(50)).await;
+        assert_eq!(burst.len(), 11);
+        for _ in burst.len()..15 {
+            running.next_notify("ssdp:alive").await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(400));
+    }
+
+    #[cfg(feature = "tower")]
+    #[tokio::test]
+    async fn passes_searches_through_search_service() {
+        use std::convert::Infallible;
+        use tower::service_fn;
+
+        let service = service_fn(|mut search: IncomingSearch| async move {
+            if search.request.st == "ssdp:all" {
+                return Ok::<_, Infallible>(None);
+            }
+            if search.request.st == "legacy:root" {
+                search.request.st = "upnp:rootdevice".to_string();
+            }
+            Ok(Some(search))
+        });
+        let mut running = Running::start(server([root_device()]).search_service(service));
+        running.next_notify("ssdp:alive").await;
+
+        running.search("ssdp:all", 1);
+        assert!(
+            running
+                .sent_within(Duration::from_millis(100))
+                .await
+                .is_empty()
+        );
+
+        running.search("legacy:root", 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert_eq!(header(&response, "ST"), Some("upnp:rootdevice"));
+    }
+
+    #[tokio::test]
+    async fn hands_back_device_metadata() {
+        #[derive(Debug, PartialEq)]
+        struct Renderer(u32);
+
+        let server = server([root_device().metadata(Renderer(7))]);
+        let mut searches = server.answered_searches();
+        let mut running = Running::start(server);
+        running.next_notify("ssdp:alive").await;
+
+        running.search("upnp:rootdevice", 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert_eq!(header(&response, "USN"), Some(root_device().usn.as_str()));
+        let answered = searches.recv().await.unwrap();
+        assert_eq!(answered.devices[0].get_metadata(), Some(&Renderer(7)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn delays_responses_as_configured() {
+        let fixed = server([root_device()])
+            .response_delay(ResponseDelay::Fixed(Duration::from_millis(300)));
+        let mut running = Running::start(fixed);
+        running.next_notify("ssdp:alive").await;
+        let start = tokio::time::Instant::now();
+        running.search("upnp:rootdevice", 2);
+        running.next_to(CONTROL_POINT).await;
+        assert_eq!(start.elapsed(), Duration::from_millis(300));
+
+        // Never later than MX
+        let capped = server([root_device()])
+            .response_delay(ResponseDelay::Exponential(Duration::from_secs(60)));
+        let mut running = Running::start(capped);
+        running.next_notify("ssdp:alive").await;
+        let start = tokio::time::Instant::now();
+        running.search("upnp:rootdevice", 1);
+        running.next_to(CONTROL_POINT).await;
+        assert!(start.elapsed() <= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn computes_headers_per_message() {
+        let counter = AtomicUsize::new(0);
+        let provider = move |ctx: &HeaderContext<'_>| {
+            let token = counter.fetch_add(1, Ordering::Relaxed);
+            let mut headers = vec![("X-TOKEN".to_string(), token.to_string())];
+            if let Some(addr) = ctx.remote_addr {
+                headers.push(("X-PEER".to_string(), addr.to_string()));
+            }
+            headers
+        };
+        let mut running = Running::start(server([root_device()]).header_provider(provider));
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_eq!(header(&alive, "X-TOKEN"), Some("0"));
+        assert_eq!(header(&alive, "X-PEER"), None);
+
+        running.search("upnp:rootdevice", 1);
+        let first = running.next_to(CONTROL_POINT).await;
+        running.search("upnp:rootdevice", 1);
+        let second = running.next_to(CONTROL_POINT).await;
+        assert_eq!(header(&first, "X-TOKEN"), Some("1"));
+        assert_eq!(header(&second, "X-TOKEN"), Some("2"));
+        assert_eq!(header(&second, "X-PEER"), Some(CONTROL_POINT));
+    }
+
+    #[tokio::test]
+    async fn answers_invalid_searches_when_diagnosing() {
+        let invalid = search("upnp:rootdevice", 1).replace("ssdp:discover", "ssdp:unknown");
+
+        let mut running = Running::start(server([root_device()]));
+        running.next_notify("ssdp:alive").await;
+        running.receive(&invalid, CONTROL_POINT);
+        assert!(
+            running
+                .sent_within(Duration::from_millis(100))
+                .await
+                .is_empty()
+        );
+
+        let mut running = Running::start(server([root_device()]).diagnostic_responses(true));
+        running.next_notify("ssdp:alive").await;
+        running.receive(&invalid, CONTROL_POINT);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert!(response.starts_with("HTTP/1.1 412 Precondition Failed\r\n"));
+        assert!(header(&response, "X-SSDP-ERROR").is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn advertises_fast_on_demand() {
+        let server = server([root_device()]);
+        let advertiser = server.advertiser();
+        let mut running = Running::start(server);
+        running.next_notify("ssdp:alive").await;
+        assert!(
+            running
+                .sent_within(Duration::from_secs(10))
+                .await
+                .is_empty()
+        );
+
+        advertiser.fast_advertise(Duration::from_secs(60));
+        running.next_notify("ssdp:alive").await;
+        let start = tokio::time::Instant::now();
+        running.next_notify("ssdp:alive").await;
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn answers_searches_without_io_like_the_server() {
+        let device = Device::new(
+            UUID,
+            "upnp:rootdevice",
+            "http://192.168.1.100:8080/desc.xml",
+        );
+        let request = SearchRequest {
+            st: "upnp:rootdevice".into(),
+            mx: Some(1),
+            headers: vec![],
+        };
+        let ctx = SearchContext::new(CONTROL_POINT.parse().unwrap());
+        let messages = server([device.clone()])
+            .answer_search(&request, &ctx)
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].dest, CONTROL_POINT.parse().unwrap());
+        assert_eq!(messages[0].delay, Duration::ZERO);
+
+        let mut running = Running::start(server([device]));
+        running.next_notify("ssdp:alive").await;
+        running.search("upnp:rootdevice", 1);
+        let response = running.next_to(CONTROL_POINT).await;
+
+        // Everything but the date, which may have ticked over
+        let without_date = |msg: &str| {
+            msg.lines()
+                .filter(|line| !line.starts_with("DATE:"))
+                .collect::<Vec<_>>()
+                .join("\r\n")
+        };
+        let answered = String::from_utf8(messages[0].data.clone()).unwrap();
+        assert_eq!(without_date(&answered), without_date(&response));
+    }
+
+    #[tokio::test]
+    async fn sends_headers_of_upnp_version() {
+        let mut running = Running::start(server([root_device()]));
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_eq!(header(&alive, "SERVER"), Some("Tokio-SSDP/1.0 UPnP/1.0"));
+        assert_eq!(header(&alive, "BOOTID.UPNP.ORG"), None);
+        assert_eq!(header(&alive, "CONFIGID.UPNP.ORG"), None);
+
+        let v1_1 = server([root_device()])
+            .upnp_version(UpnpVersion::V1_1)
+            .boot_id(3)
+            .config_id(7);
+        let mut running = Running::start(v1_1);
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_eq!(header(&alive, "SERVER"), Some("Tokio-SSDP/1.0 UPnP/1.1"));
+        assert_eq!(header(&alive, "BOOTID.UPNP.ORG"), Some("3"));
+        assert_eq!(header(&alive, "CONFIGID.UPNP.ORG"), Some("7"));
+
+        running.search("upnp:rootdevice", 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert_eq!(header(&response, "BOOTID.UPNP.ORG"), Some("3"));
+        assert_eq!(header(&response, "CONFIGID.UPNP.ORG"), Some("7"));
+    }
+
+    #[tokio::test]
+    async fn advertises_only_while_available() {
+        let (available, availability) = watch::channel(true);
+        let mut running = Running::start(server([root_device()]).availability(availability));
+        running.next_notify("ssdp:alive").await;
+
+        available.send_replace(false);
+        running.next_notify("ssdp:byebye").await;
+        running.search("upnp:rootdevice", 1);
+        assert!(
+            running
+                .sent_within(Duration::from_millis(100))
+                .await
+                .is_empty()
+        );
+
+        available.send_replace(true);
+        running.next_notify("ssdp:alive").await;
+        running.search("upnp:rootdevice", 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert_eq!(header(&response, "ST"), Some("upnp:rootdevice"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn becomes_ready_after_initial_announcements() {
+        let server = Server::new([root_device(), service("ContentDirectory:1")])
+            .pacing(Pacing::Fixed(Duration::from_secs(1)));
+        let ready = tokio::spawn(server.ready());
+        let mut running = Running::start(server);
+
+        running.next_notify("ssdp:alive").await;
+        tokio::task::yield_now().await;
+        assert!(!ready.is_finished());
+        running.next_notify("ssdp:alive").await;
+        assert!(ready.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn handles_ssdp_port_in_use() {
+        let port = free_port();
+        let serve = |server: Server| {
+            server.multicast_endpoint(SSDP_ADDR_V4, port).serve_with(
+                Ipv4Addr::LOCALHOST,
+                Receiver::Bind,
+                None,
+                Arc::default(),
+            )
+        };
+
+        // Held by another process, which doesn't share it
+        let blocker = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)).unwrap();
+        blocker
+            .join_multicast_v4(&SSDP_ADDR_V4, &Ipv4Addr::LOCALHOST)
+            .unwrap();
+        blocker.set_nonblocking(true).unwrap();
+        let blocker = UdpSocket::from_std(blocker).unwrap();
+
+        let Err(e) = serve(Server::new([root_device()])) else {
+            panic!("served on a port in use");
+        };
+        assert_eq!(e.kind(), std::io::ErrorKind::AddrInUse);
+        assert!(e.to_string().contains("Server::addr_in_use"));
+
+        let announce_only = Server::new([root_device()]).addr_in_use(AddrInUse::AnnounceOnly);
+        let health = announce_only.health();
+        let task = tokio::spawn(serve(announce_only).unwrap());
+        let alive = recv_within(&blocker, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(header(&alive, "NTS"), Some("ssdp:alive"));
+        assert!(!health.report().multicast_joined);
+        task.abort();
+
+        let backoff = Backoff::new(Duration::from_millis(50), Duration::from_millis(50));
+        let retrying = Server::new([root_device()])
+            .response_delay(ResponseDelay::Immediate)
+            .addr_in_use(AddrInUse::Retry(backoff.max_attempts(20)));
+        let mut restarts = retrying.restarts();
+        let task = tokio::spawn(serve(retrying).unwrap());
+        assert!(restarts.recv().await.unwrap().result.is_err());
+        drop(blocker);
+        while restarts.recv().await.unwrap().result.is_err() {}
+
+        let client = loopback_socket().await;
+        client
+            .send_to(
+                search("upnp:rootdevice", 1).as_bytes(),
+                (Ipv4Addr::LOCALHOST, port),
+            )
+            .await
+            .unwrap();
+        let response = recv_within(&client, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(header(&response, "ST"), Some("upnp:rootdevice"));
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn takes_over_when_active_instance_goes_silent() {
+        let device = root_device();
+        let standby = server([device.clone()])
+            .upnp_version(UpnpVersion::V1_1)
+            .boot_id(3)
+            .standby(Duration::from_millis(300));
+        let mut running = Running::start(standby);
+        let notify = format!(
+            "NOTIFY * HTTP/1.1\r\n\
+             HOST: 239.255.255.250:1900\r\n\
+             CACHE-CONTROL: max-age=1800\r\n\
+             LOCATION: http://192.168.1.50:8080/desc.xml\r\n\
+             NT: upnp:rootdevice\r\n\
+             NTS: ssdp:alive\r\n\
+             USN: {}\r\n\
+             BOOTID.UPNP.ORG: 5\r\n\r\n",
+            device.usn
+        );
+
+        // Silent while the active instance announces the devices
+        for _ in 0..4 {
+            running.receive(&notify, "192.168.1.50:1900");
+            running.search("upnp:rootdevice", 1);
+            assert!(
+                running
+                    .sent_within(Duration::from_millis(100))
+                    .await
+                    .is_empty()
+            );
+        }
+
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_eq!(header(&alive, "BOOTID.UPNP.ORG"), Some("6"));
+        running.search("upnp:rootdevice", 1);
+        running.next_to(CONTROL_POINT).await;
+    }
+
+    #[tokio::test]
+    async fn reports_status() {
+        let status = Status::new();
+        let mut running = Running::start(server([root_device()]).status(status.clone()));
+        running.next_notify("ssdp:alive").await;
+        running.search("upnp:rootdevice", 1);
+        running.next_to(CONTROL_POINT).await;
+
+        let json = status.to_json();
+        assert!(json.contains("\"multicast_joined\":true"));
+        assert!(json.contains(&format!(
+            "\"devices\":[{{\"usn\":\"{}\",\"st\":\"upnp:rootdevice\"",
+            root_device().usn
+        )));
+        assert!(json.contains("\"events\":{\"alive\":1,\"response\":1,\"search\":1}"));
+        assert!(json.contains(&format!("\"remote_addr\":\"{}\"", CONTROL_POINT)));
+    }
+
+    #[cfg(all(feature = "socket2", any(target_os = "linux", target_os = "android")))]
+    #[tokio::test]
+    async fn sends_with_separate_ttls() {
+        let server = server([root_device()]).response_ttl(7).notify_ttl(3);
+        let advertiser = server.advertiser();
+        let bound = Bound::start(server);
+        let socket_with_ttl = |socket: std::net::UdpSocket| {
+            pktinfo::enable(&socket).unwrap();
+            socket.set_nonblocking(true).unwrap();
+            UdpSocket::from_std(socket).unwrap()
+        };
+
+        let listener = Server::new_rx_socket(bound.port, false).unwrap();
+        listener
+            .join_multicast_v4(&SSDP_ADDR_V4, &Ipv4Addr::LOCALHOST)
+            .unwrap();
+        let listener = socket_with_ttl(listener);
+        advertiser.fast_advertise(Duration::from_secs(60));
+        let mut buf = [0; 2048];
+        let (_, _, info) = pktinfo::recv_from(&listener, &mut buf).await.unwrap();
+        assert_eq!(info.unwrap().ttl, Some(3));
+        // Unicast datagrams to the port only reach one of the sockets bound to it
+        drop(listener);
+
+        let client = socket_with_ttl(std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap());
+        bound.send(&client, &search("upnp:rootdevice", 1)).await;
+        let (n, _, info) = pktinfo::recv_from(&client, &mut buf).await.unwrap();
+        assert!(buf[..n].starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert_eq!(info.unwrap().ttl, Some(7));
+
+        bound.shutdown().await;
+    }
+
+    #[cfg(feature = "socket2")]
+    #[tokio::test]
+    async fn handle_sends_byebye_on_shutdown() {
+        let port = free_port();
+        let listener = Server::new_rx_socket(port, false).unwrap();
+        listener
+            .join_multicast_v4(&SSDP_ADDR_V4, &Ipv4Addr::LOCALHOST)
+            .unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let listener = UdpSocket::from_std(listener).unwrap();
+
+        let devices = [root_device(), service("ContentDirectory:1")];
+        let handle = server(devices.clone())
+            .multicast_endpoint(SSDP_ADDR_V4, port)
+            .spawn_addr(Ipv4Addr::LOCALHOST)
+            .unwrap();
+        for _ in &devices {
+            let alive = recv_within(&listener, Duration::from_secs(5))
+                .await
+                .unwrap();
+            assert_eq!(header(&alive, "NTS"), Some("ssdp:alive"));
+        }
+
+        // All sent by the time shutdown completes
+        handle.shutdown().await.unwrap();
+        let mut buf = [0; 2048];
+        for device in &devices {
+            let n = listener.try_recv(&mut buf).unwrap();
+            let byebye = String::from_utf8_lossy(&buf[..n]);
+            assert_eq!(header(&byebye, "NTS"), Some("ssdp:byebye"));
+            assert_eq!(header(&byebye, "USN"), Some(device.usn.as_str()));
+        }
+    }
+
+    #[tokio::test]
+    async fn adds_and_removes_devices_at_runtime() {
+        let added = service("ContentDirectory:1");
+        let mut running = Running::start(server([root_device()]));
+        running.next_notify("ssdp:alive").await;
+
+        running.control(Control::AddDevice(added.clone()));
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_eq!(header(&alive, "USN"), Some(added.usn.as_str()));
+        running.search(&added.search_target, 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert_eq!(header(&response, "USN"), Some(added.usn.as_str()));
+
+        running.control(Control::RemoveDevice(added.usn.clone()));
+        let byebye = running.next_notify("ssdp:byebye").await;
+        assert_eq!(header(&byebye, "USN"), Some(added.usn.as_str()));
+        running.search(&added.search_target, 1);
+        assert!(
+            running
+                .sent_within(Duration::from_millis(100))
+                .await
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn answers_uuid_searches() {
+        let cd = service("ContentDirectory:1");
+        let mut running = Running::start(server([root_device(), cd.clone()]));
+        running.next_notify("ssdp:alive").await;
+        running.next_notify("ssdp:alive").await;
+
+        // Once per device, not per search target of the device
+        let uuid = format!("uuid:{}", UUID);
+        running.search(&uuid, 1);
+        let sent = running.sent_within(Duration::from_millis(100)).await;
+        assert_eq!(sent.len(), 1);
+        assert_eq!(header(&sent[0].0, "ST"), Some(uuid.as_str()));
+        assert_eq!(header(&sent[0].0, "USN"), Some(uuid.as_str()));
+
+        running.search(&cd.search_target, 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        let usn = format!("{}::{}", uuid, cd.search_target);
+        assert_eq!(header(&response, "USN"), Some(usn.as_str()));
+    }
+
+    #[cfg(all(feature = "socket2", any(target_os = "linux", target_os = "android")))]
+    #[tokio::test]
+    async fn uses_address_of_interface_in_location() {
+        let port = free_port();
+        let listener = Server::new_rx_socket(port, false).unwrap();
+        listener
+            .join_multicast_v4(&SSDP_ADDR_V4, &Ipv4Addr::LOCALHOST)
+            .unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let listener = UdpSocket::from_std(listener).unwrap();
+
+        let multihomed = server([root_device()]).multicast_endpoint(SSDP_ADDR_V4, port);
+        let ready = multihomed.ready();
+        let task = tokio::spawn(multihomed.serve_interfaces([Ipv4Addr::LOCALHOST]).unwrap());
+        let alive = recv_within(&listener, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(
+            header(&alive, "LOCATION"),
+            Some("http://127.0.0.1:8080/desc.xml")
+        );
+        assert!(ready.await);
+        task.abort();
+
+        // Any 127.0.0.0/8 address is local on the loopback interface
+        let device = Device::new(UUID, "upnp:rootdevice", "http://{local_addr}:8080/desc.xml");
+        let bound = Bound::start(server([device]));
+        let client = loopback_socket().await;
+        client
+            .send_to(
+                search("upnp:rootdevice", 1).as_bytes(),
+                (Ipv4Addr::new(127, 0, 0, 2), bound.port),
+            )
+            .await
+            .unwrap();
+        let response = recv_within(&client, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(
+            header(&response, "LOCATION"),
+            Some("http://127.0.0.2:8080/desc.xml")
+        );
+        bound.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn keeps_boot_id_in_store() {
+        #[derive(Clone)]
+        struct Memory(Arc<Mutex<Option<u32>>>);
+
+        impl BootIdStore for Memory {
+            fn load(&self) -> IoResult<Option<u32>> {
+                Ok(*self.0.lock().unwrap())
+            }
+
+            fn store(&self, boot_id: u32) -> IoResult<()> {
+                *self.0.lock().unwrap() = Some(boot_id);
+                Ok(())
+            }
+        }
+
+        let store = Memory(Arc::new(Mutex::new(Some(41))));
+        let server = server([root_device()])
+            .upnp_version(UpnpVersion::V1_1)
+            .boot_id_store(store.clone());
+        let mut running = Running::start(server);
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_eq!(header(&alive, "BOOTID.UPNP.ORG"), Some("42"));
+        assert_eq!(store.load().unwrap(), Some(42));
+
+        running.control(Control::Update(None));
+        let update = running.next_notify("ssdp:update").await;
+        assert_eq!(header(&update, "NEXTBOOTID.UPNP.ORG"), Some("43"));
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_eq!(header(&alive, "BOOTID.UPNP.ORG"), Some("43"));
+        assert_eq!(header(&alive, "CONFIGID.UPNP.ORG"), Some("1"));
+        assert_eq!(store.load().unwrap(), Some(43));
+    }
+
+    #[tokio::test]
+    async fn answers_unicast_searches_on_search_port() {
+        let search_port = free_port();
+        let v1_1 = server([root_device()])
+            .upnp_version(UpnpVersion::V1_1)
+            .search_port(search_port);
+        let mut running = Running::start(v1_1);
+        let alive = running.next_notify("ssdp:alive").await;
+        let advertised = search_port.to_string();
+        assert_eq!(
+            header(&alive, "SEARCHPORT.UPNP.ORG"),
+            Some(advertised.as_str())
+        );
+
+        // Not delayed, unlike multicast searches
+        let delayed = server([root_device()])
+            .response_delay(ResponseDelay::Fixed(Duration::from_secs(3)))
+            .search_port(search_port);
+        let bound = Bound::start(delayed);
+        let client = loopback_socket().await;
+        client
+            .send_to(
+                search("upnp:rootdevice", 5).as_bytes(),
+                (Ipv4Addr::LOCALHOST, search_port),
+            )
+            .await
+            .unwrap();
+        let response = recv_within(&client, Duration::from_secs(1)).await.unwrap();
+        assert_eq!(header(&response, "ST"), Some("upnp:rootdevice"));
+        bound.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn uses_device_headers_and_max_age() {
+        let custom = service("ContentDirectory:1")
+            .max_age(1800)
+            .extra_header("X-VENDOR", "acme");
+        let server = server([root_device(), custom.clone()])
+            .extra_header("X-VENDOR", "generic")
+            .extra_header("X-BACKEND", "media");
+        let mut running = Running::start(server);
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_eq!(header(&alive, "CACHE-CONTROL"), Some("max-age=100"));
+        assert_eq!(header(&alive, "X-VENDOR"), Some("generic"));
+
+        running.search(&custom.search_target, 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert_eq!(header(&response, "CACHE-CONTROL"), Some("max-age=1800"));
+        assert_eq!(header(&response, "X-VENDOR"), Some("acme"));
+        assert_eq!(header(&response, "X-BACKEND"), Some("media"));
+        assert_eq!(response.matches("X-VENDOR").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn advertises_full_set_of_root_device() {
+        let device_type = "urn:schemas-upnp-org:device:MediaServer:1";
+        let service_type = "urn:schemas-upnp-org:service:ContentDirectory:1";
+        let root = crate::RootDevice::new(UUID, device_type, "http://{addr}:8080/desc.xml")
+            .service(service_type);
+        let mut running = Running::start(server(root));
+
+        let uuid = format!("uuid:{}", UUID);
+        let expected = [
+            (
+                "upnp:rootdevice".to_string(),
+                format!("{}::upnp:rootdevice", uuid),
+            ),
+            (uuid.clone(), uuid.clone()),
+            (
+                device_type.to_string(),
+                format!("{}::{}", uuid, device_type),
+            ),
+            (
+                service_type.to_string(),
+                format!("{}::{}", uuid, service_type),
+            ),
+        ];
+        let mut alive = vec![];
+        for _ in &expected {
+            let msg = running.next_notify("ssdp:alive").await;
+            alive.push((
+                header(&msg, "NT").unwrap().to_string(),
+                header(&msg, "USN").unwrap().to_string(),
+            ));
+        }
+        alive.sort();
+        let mut sorted = expected.clone();
+        sorted.sort();
+        assert_eq!(alive, sorted);
+
+        running.search("ssdp:all", 1);
+        let sent = running.sent_within(Duration::from_millis(100)).await;
+        assert_eq!(sent.len(), expected.len());
+    }
+
+    #[tokio::test]
+    async fn sends_messages_of_message_model() {
+        let device = root_device();
+        let mut running = Running::start(server([device.clone()]));
+        let alive = running.next_notify("ssdp:alive").await;
+        let notify = message::Notify::parse(alive.as_bytes()).unwrap();
+        assert_eq!(notify.nt, "upnp:rootdevice");
+        assert_eq!(notify.nts, Nts::Alive);
+        assert_eq!(notify.usn, device.usn);
+        assert_eq!(
+            notify.location.as_deref(),
+            Some("http://192.168.1.100:8080/desc.xml")
+        );
+        assert_eq!(notify.max_age, Some(100));
+        assert_eq!(notify.to_bytes(), alive.as_bytes());
+
+        let request = SearchRequest {
+            st: "upnp:rootdevice".into(),
+            mx: Some(1),
+            headers: vec![],
+        };
+        running.receive(
+            &String::from_utf8(request.to_bytes()).unwrap(),
+            CONTROL_POINT,
+        );
+        let response = running.next_to(CONTROL_POINT).await;
+        let response = message::SearchResponse::parse(response.as_bytes()).unwrap();
+        assert_eq!(response.st, "upnp:rootdevice");
+        assert_eq!(response.usn, device.usn);
+        assert_eq!(response.location, "http://192.168.1.100:8080/desc.xml");
+        assert_eq!(response.max_age, Some(100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn decides_on_searches_with_hook() {
+        let slow: SocketAddr = "192.168.1.30:50000".parse().unwrap();
+        let hook = move |_: &SearchRequest, from: SocketAddr| async move {
+            if from.ip() == Ipv4Addr::new(192, 168, 1, 13) {
+                return SearchDecision::Ignore;
+            }
+            if from == slow {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+            SearchDecision::AnswerWith(vec![("X-PEER".into(), from.ip().to_string())])
+        };
+        let mut running = Running::start(server([root_device()]).on_search(hook));
+        running.next_notify("ssdp:alive").await;
+
+        let search = search("upnp:rootdevice", 1);
+        running.receive(&search, "192.168.1.13:50000");
+        running.receive(&search, &slow.to_string());
+        running.receive(&search, CONTROL_POINT);
+        let (response, target) = running.next().await;
+        assert_eq!(target, CONTROL_POINT.parse().unwrap());
+        assert_eq!(header(&response, "X-PEER"), Some("192.168.1.20"));
+        let (response, target) = running.next().await;
+        assert_eq!(target, slow);
+        assert_eq!(header(&response, "X-PEER"), Some("192.168.1.30"));
+        assert!(running.sent_within(Duration::from_secs(1)).await.is_empty());
+    }
 
-                        tokio::select! {
-                            _ = tokio::time::sleep(Duration::from_secs(this.max_age)) => {
-                                // It's time to send alive messages
-                            }
-                            _ = Pin::new(&mut notify_alive_rx) => {
-                                // We should shut down
-                                debug!("notify_alive shutdown");
-                                return IoResult::Ok(());
-                            }
-                        }
-                    }
-                }
-            });
+    #[test]
+    fn configures_multicast_ttl_and_loopback() {
+        // Multicast sent on loopback reaches the host regardless of the loopback option,
+        // so check the sending socket itself
+        let socket = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        Server::new([]).configure_sending(&socket).unwrap();
+        assert_eq!(socket.multicast_ttl_v4().unwrap(), DEFAULT_NOTIFY_TTL);
+        assert!(socket.multicast_loop_v4().unwrap());
 
-            let (_notify_byebye_tx, notify_byebye_rx) = oneshot::channel::<()>();
-            tokio::spawn({
-                let this = Arc::clone(&this);
-                let socket = Arc::clone(&tx_socket);
-                let extra_headers = Arc::clone(&extra_headers);
+        let socket = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        Server::new([])
+            .notify_ttl(1)
+            .multicast_loop(false)
+            .configure_sending(&socket)
+            .unwrap();
+        assert_eq!(socket.multicast_ttl_v4().unwrap(), 1);
+        assert!(!socket.multicast_loop_v4().unwrap());
+    }
 
-                async move {
-                    let _ = notify_byebye_rx.await;
+    #[tokio::test]
+    async fn serves_socket_of_application() {
+        let port = free_port();
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, port)).await.unwrap();
+        let task = tokio::spawn(
+            server([root_device()])
+                .multicast_endpoint(SSDP_ADDR_V4, port)
+                .serve_socket(socket)
+                .unwrap(),
+        );
 
-                    if let Err(e) = this.broadcast_byebye(&socket, &extra_headers).await {
-                        error!("Send byebye messages failed: {}", e);
-                    }
-                }
-            });
+        let client = loopback_socket().await;
+        client
+            .send_to(
+                search("upnp:rootdevice", 1).as_bytes(),
+                (Ipv4Addr::LOCALHOST, port),
+            )
+            .await
+            .unwrap();
+        let response = recv_within(&client, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(
+            header(&response, "LOCATION"),
+            Some("http://127.0.0.1:8080/desc.xml")
+        );
+        task.abort();
 
-            loop {
-                let (mut n, addr) = rx_socket.recv_from(&mut buf).await?;
-
-                // Some broken clients only end the request in `\r\n`, not `\r\n\r\n`.
-                // If workaround is enabled, fixup these requests so we can parse them.
-                if this.partial_req_workaround {
-                    let slice = &buf[..n];
-                    if slice.ends_with(b"\r\n")
-                        && !slice.ends_with(b"\r\n\r\n")
-                        && (n < buf.len() - 2)
-                    {
-                        buf[n] = b'\r';
-                        buf[n + 1] = b'\n';
-                        n += 2;
-                    }
-                }
+        let socket = UdpSocket::bind((std::net::Ipv6Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let Err(e) = Server::new([]).serve_socket(socket) else {
+            panic!("served an IPv6 socket");
+        };
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    }
 
-                let mut headers = [httparse::EMPTY_HEADER; 16];
-                let mut req = httparse::Request::new(&mut headers);
+    #[tokio::test(start_paused = true)]
+    async fn repeats_messages() {
+        let spacing = Duration::from_millis(100);
+        let repeating = server([root_device()]).repeat(3, spacing);
+        let mut running = Running::start(repeating);
+        let alive = running.next_notify("ssdp:alive").await;
+        let start = tokio::time::Instant::now();
+        for _ in 0..2 {
+            assert_eq!(running.next_notify("ssdp:alive").await, alive);
+        }
+        assert!(start.elapsed() <= spacing * 2);
 
-                if let Ok(httparse::Status::Complete(_)) = req.parse(&buf[..n]) {
-                    let method = if let Some(m) = req.method {
-                        m
-                    } else {
-                        continue;
-                    };
+        running.search("upnp:rootdevice", 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        for _ in 0..2 {
+            // The DATE may have ticked over
+            let copy = running.next_to(CONTROL_POINT).await;
+            assert_eq!(header(&copy, "USN"), header(&response, "USN"));
+        }
 
-                    let path = if let Some(m) = req.path {
-                        m
-                    } else {
-                        continue;
-                    };
+        let byebyes = usns(&running.shutdown().await, "ssdp:byebye");
+        assert_eq!(byebyes, vec![root_device().usn; 3]);
+    }
 
-                    match (method, path) {
-                        ("M-SEARCH", "*") => {
-                            let socket = Arc::clone(&tx_socket);
-                            let res = this.handle_search(&req, socket, addr, &extra_headers).await;
-                            if let Err(e) = res {
-                                error!("Handle search failed: {}", e);
-                            }
-                        }
-                        ("NOTIFY", "*") => trace!("NOTIFY * from {}", addr),
-                        _ => debug!("Unknown SSDP request {} {} from {}", method, path, addr),
-                    }
-                }
-            }
+    #[tokio::test(start_paused = true)]
+    async fn announces_at_notify_interval() {
+        // A third of the max age by default
+        let mut running = Running::start(server([root_device()]).max_age(90));
+        running.next_notify("ssdp:alive").await;
+        let start = tokio::time::Instant::now();
+        let (alive, _) = running.sent.recv().await.unwrap();
+        assert_eq!(header(&alive, "NTS"), Some("ssdp:alive"));
+        assert_eq!(start.elapsed(), Duration::from_secs(30));
+
+        let jittered = server([root_device()])
+            .max_age(90)
+            .notify_interval(Duration::from_secs(20))
+            .notify_jitter(Duration::from_secs(5));
+        let mut running = Running::start(jittered);
+        running.next_notify("ssdp:alive").await;
+        let start = tokio::time::Instant::now();
+        running.sent.recv().await.unwrap();
+        assert!(start.elapsed() >= Duration::from_secs(15));
+        assert!(start.elapsed() <= Duration::from_secs(20));
+    }
+
+    #[tokio::test]
+    async fn announces_on_demand() {
+        let devices = [root_device(), service("ContentDirectory:1")];
+        let mut running = Running::start(server(devices.clone()));
+        for _ in &devices {
+            running.next_notify("ssdp:alive").await;
+        }
+
+        running.control(Control::Announce(Some(devices[1].usn.clone())));
+        let sent = running.sent_within(Duration::from_millis(100)).await;
+        assert_eq!(usns(&sent, "ssdp:alive"), [devices[1].usn.clone()]);
+
+        running.control(Control::Announce(None));
+        let sent = running.sent_within(Duration::from_millis(100)).await;
+        let all: Vec<_> = devices.iter().map(|d| d.usn.clone()).collect();
+        assert_eq!(usns(&sent, "ssdp:alive"), all);
+    }
+
+    #[tokio::test]
+    async fn rate_limits_searches() {
+        let other = "192.168.1.30:50000";
+        let limited = server([root_device()]).search_limit(SearchLimit::new().per_source(1, 2));
+        let mut running = Running::start(limited);
+        running.next_notify("ssdp:alive").await;
+        for _ in 0..3 {
+            running.search("upnp:rootdevice", 1);
+        }
+        running.receive(&search("upnp:rootdevice", 1), other);
+        let sent = running.sent_within(Duration::from_millis(100)).await;
+        let to = |addr: &str| {
+            let addr: SocketAddr = addr.parse().unwrap();
+            sent.iter().filter(|(_, target)| *target == addr).count()
         };
+        assert_eq!(to(CONTROL_POINT), 2);
+        assert_eq!(to(other), 1);
 
-        Ok(server_fut)
+        let limited = server([root_device()]).search_limit(SearchLimit::new().global(1, 1));
+        let mut running = Running::start(limited);
+        running.next_notify("ssdp:alive").await;
+        running.search("upnp:rootdevice", 1);
+        running.receive(&search("upnp:rootdevice", 1), other);
+        let sent = running.sent_within(Duration::from_millis(100)).await;
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].1, CONTROL_POINT.parse().unwrap());
     }
 
-    async fn handle_search(
-        &self,
-        req: &httparse::Request<'_, '_>,
-        socket: Arc<UdpSocket>,
-        remote_addr: SocketAddr,
-        extra_headers: &str,
-    ) -> std::io::Result<()> {
-        let mut st = None;
-        let mut mx = 0u32;
-        let mut man_found = false;
+    #[tokio::test]
+    async fn ignores_notifications_from_denied_peers() {
+        let device = root_device();
+        let server = server([device.clone()])
+            .deny_subnet("10.0.0.0/8".parse().unwrap())
+            .duplicate_policy(DuplicatePolicy::BackOff(Duration::from_secs(60)));
+        let mut duplicates = server.duplicates();
+        let mut running = Running::start(server);
+        running.next_notify("ssdp:alive").await;
 
-        for header in req.headers.iter() {
-            let v = Some(String::from_utf8_lossy(header.value));
+        let notify = format!(
+            "NOTIFY * HTTP/1.1\r\n\
+             HOST: 239.255.255.250:1900\r\n\
+             CACHE-CONTROL: max-age=1800\r\n\
+             LOCATION: http://10.0.0.50:8080/desc.xml\r\n\
+             NT: upnp:rootdevice\r\n\
+             NTS: ssdp:alive\r\n\
+             USN: {}\r\n\r\n",
+            device.usn
+        );
+        running.receive(&notify, "10.0.0.50:1900");
 
-            if header.name.eq_ignore_ascii_case("st") {
-                st = v;
-                continue;
-            }
+        // Neither noticed as a duplicate, nor backing off
+        running.search("upnp:rootdevice", 1);
+        running.next_to(CONTROL_POINT).await;
+        assert!(duplicates.try_recv().is_err());
+    }
 
-            if header.name.eq_ignore_ascii_case("mx") {
-                let val = String::from_utf8_lossy(header.value);
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn records_metrics() {
+        use std::sync::atomic::AtomicU64;
 
-                mx = match val.parse() {
-                    Ok(v) => v,
-                    Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
-                };
+        use metrics::{
+            Counter, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit,
+        };
 
-                continue;
+        /// Counts in memory, ignoring gauges and histograms.
+        #[derive(Default)]
+        struct Counters(Mutex<HashMap<String, Arc<AtomicU64>>>);
+
+        impl Counters {
+            fn get(&self, name: &str) -> u64 {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .get(name)
+                    .map_or(0, |count| count.load(Ordering::Relaxed))
             }
+        }
 
-            if header.name.eq_ignore_ascii_case("man") {
-                if header.value != b"\"ssdp:discover\"" {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::InvalidData,
-                        format!(
-                            "MAN != \"ssdp:discover\" ({})",
-                            String::from_utf8_lossy(header.value)
-                        ),
-                    ));
+        impl Recorder for Counters {
+            fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+            fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+                let mut name = key.name().to_string();
+                for label in key.labels() {
+                    name += &format!(",{}={}", label.key(), label.value());
                 }
-                man_found = true;
+                let count = self.0.lock().unwrap().entry(name).or_default().clone();
+                Counter::from_arc(count)
             }
-        }
 
-        if !man_found {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "MAN header not found",
-            ));
+            fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+                Gauge::noop()
+            }
+
+            fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+                Histogram::noop()
+            }
         }
 
-        let st = if let Some(st) = st {
-            st
-        } else {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "ST header not found",
-            ));
+        // Recorded on this thread only, so other tests don't interfere
+        let counters = Counters::default();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        metrics::with_local_recorder(&counters, || {
+            runtime.block_on(async {
+                let mut running = Running::start(server([root_device()]));
+                running.next_notify("ssdp:alive").await;
+                running.receive("M-SEARCH\r\nST\r\n\r\n", CONTROL_POINT);
+                running.search("upnp:rootdevice", 1);
+                running.next_to(CONTROL_POINT).await;
+                running.shutdown().await;
+            })
+        });
+
+        assert_eq!(counters.get("ssdp_searches_received_total"), 1);
+        assert_eq!(counters.get("ssdp_responses_sent_total"), 1);
+        assert_eq!(counters.get("ssdp_parse_failures_total"), 1);
+        assert_eq!(
+            counters.get("ssdp_notifications_sent_total,nts=ssdp:alive"),
+            1
+        );
+        assert_eq!(
+            counters.get("ssdp_notifications_sent_total,nts=ssdp:byebye"),
+            1
+        );
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn records_datagram_spans() {
+        use tracing::{
+            Event, Metadata, Subscriber,
+            field::{Field, Visit},
+            span::{Attributes, Id, Record},
         };
 
-        trace!("ST={:?}, MX={:?}", st, mx);
+        /// The name and fields of a span.
+        struct Fields(Vec<(String, String)>);
 
-        let mut responses = vec![];
-
-        for device in &self.devices {
-            if st == "ssdp:all" || device.search_target.eq_ignore_ascii_case(&st) {
-                let response = format!(
-                    concat!(
-                        "HTTP/1.1 200 OK\r\n",
-                        "CACHE-CONTROL: max-age={max_age}\r\n",
-                        "DATE: {date}\r\n",
-                        "EXT:\r\n",
-                        "LOCATION: {loc}\r\n",
-                        "SERVER: {server}\r\n",
-                        "ST: {st}\r\n",
-                        "USN: {usn}\r\n",
-                        "{headers}",
-                        "\r\n"
-                    ),
-                    max_age = self.max_age,
-                    date = httpdate::fmt_http_date(SystemTime::now()),
-                    loc = device.location,
-                    server = self.server_name.as_deref().unwrap_or(DEFAULT_SERVER_NAME),
-                    st = device.search_target,
-                    usn = device.usn,
-                    headers = extra_headers
-                );
-                responses.push(response);
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+                self.0
+                    .push((field.name().to_string(), format!("{:?}", value)));
             }
         }
 
-        if responses.is_empty() {
-            return Ok(());
-        }
+        /// Collects the fields of all spans.
+        #[derive(Default)]
+        struct Spans(Mutex<Vec<Fields>>);
 
-        trace!("Responses: {responses:?}");
+        impl Subscriber for Spans {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
 
-        tokio::spawn(async move {
-            if mx > 0 {
-                // upnp specification advises to use a number less than 5 if it is bigger than 5
-                //
-                // we cap the waiting time at 3, for user convenience
-                mx = mx.min(3);
-                // wait a random time up to mx
-                let wait = {
-                    let mut rng = rand::thread_rng();
-                    rng.gen_range(0..mx)
-                };
-                tokio::time::sleep(Duration::from_secs(wait as u64)).await;
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut spans = self.0.lock().unwrap();
+                let mut fields = Fields(vec![(
+                    "name".to_string(),
+                    span.metadata().name().to_string(),
+                )]);
+                span.record(&mut fields);
+                spans.push(fields);
+                Id::from_u64(spans.len() as u64)
             }
-            for response in responses {
-                if let Err(e) = socket.send_to(response.as_bytes(), remote_addr).await {
-                    error!("Failed to send search response: {}", e);
-                }
+
+            fn record(&self, span: &Id, values: &Record<'_>) {
+                let mut spans = self.0.lock().unwrap();
+                values.record(&mut spans[span.into_u64() as usize - 1]);
             }
+
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, _: &Event<'_>) {}
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let spans = Arc::new(Spans::default());
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        tracing::subscriber::with_default(Arc::clone(&spans), || {
+            runtime.block_on(async {
+                let mut running = Running::start(server([root_device()]));
+                running.next_notify("ssdp:alive").await;
+                running.search("upnp:rootdevice", 1);
+                running.next_to(CONTROL_POINT).await;
+            })
         });
 
-        Ok(())
+        let spans = spans.0.lock().unwrap();
+        let datagram = spans
+            .iter()
+            .find(|fields| fields.0[0].1 == "ssdp_datagram")
+            .unwrap();
+        let field = |name: &str| {
+            datagram
+                .0
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(field("peer"), Some(CONTROL_POINT));
+        assert_eq!(field("st"), Some("\"upnp:rootdevice\""));
+        assert_eq!(field("mx"), Some("1"));
     }
 
-    /// Broadcast `ssdp:alive`
-    async fn broadcast_alive(&self, socket: &UdpSocket, extra_headers: &str) -> IoResult<()> {
-        debug!("Sending alive messages");
+    #[tokio::test(start_paused = true)]
+    async fn spreads_responses_over_mx() {
+        let uniform = server([root_device()]).response_delay(ResponseDelay::Uniform);
+        let mut searches = uniform.answered_searches();
+        let mut running = Running::start(uniform);
+        running.next_notify("ssdp:alive").await;
 
-        for device in self.devices.iter() {
-            let message = format!(
-                concat!(
-                    "NOTIFY * HTTP/1.1\r\n",
-                    "HOST: {ssdp_addr}:{ssdp_port}\r\n",
-                    "CACHE-CONTROL: max-age={max_age}\r\n",
-                    "LOCATION: {loc}\r\n",
-                    "NT: {st}\r\n",
-                    "NTS: ssdp:alive\r\n",
-                    "SERVER: {server}\r\n",
-                    "USN: {usn}\r\n",
-                    "{headers}",
-                    "\r\n"
-                ),
-                max_age = self.max_age,
-                ssdp_addr = SSDP_ADDR,
-                ssdp_port = SSDP_PORT,
-                loc = device.location,
-                server = self.server_name.as_deref().unwrap_or(DEFAULT_SERVER_NAME),
-                st = device.search_target,
-                usn = device.usn,
-                headers = extra_headers
+        // Over the whole window, also for MX 1
+        let mut delays = vec![];
+        for _ in 0..20 {
+            running.search("upnp:rootdevice", 1);
+            delays.push(searches.recv().await.unwrap().delay);
+        }
+        assert!(delays.iter().all(|delay| *delay <= Duration::from_secs(1)));
+        assert!(
+            delays
+                .iter()
+                .any(|delay| *delay > Duration::from_millis(500))
+        );
+
+        let capped = server([root_device()])
+            .max_mx(2)
+            .response_delay(ResponseDelay::Fixed(Duration::from_secs(60)));
+        let mut running = Running::start(capped);
+        running.next_notify("ssdp:alive").await;
+        let start = tokio::time::Instant::now();
+        running.search("upnp:rootdevice", 5);
+        running.next_to(CONTROL_POINT).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn answers_with_all_matching_devices() {
+        let st = "urn:schemas-upnp-org:service:ContentDirectory:1";
+        let devices = [
+            Device::new(UUID, st, "http://{addr}:8080/a.xml"),
+            Device::new(
+                "0e5a3ac0-0a24-4d9b-8b3b-6e5e0b2cf5a1",
+                st,
+                "http://{addr}:8080/b.xml",
+            ),
+        ];
+        let mut running = Running::start(server(devices.clone()));
+        for _ in &devices {
+            running.next_notify("ssdp:alive").await;
+        }
+
+        running.search(st, 1);
+        let sent = running.sent_within(Duration::from_millis(100)).await;
+        let usns: Vec<_> = sent
+            .iter()
+            .filter_map(|(response, _)| header(response, "USN"))
+            .collect();
+        assert_eq!(usns, [devices[0].usn.as_str(), devices[1].usn.as_str()]);
+    }
+
+    #[tokio::test]
+    async fn matches_lower_versions_when_enabled() {
+        let strict = service("ContentDirectory:2");
+        let lenient = Device::new(
+            "0e5a3ac0-0a24-4d9b-8b3b-6e5e0b2cf5a1",
+            "urn:schemas-upnp-org:service:ContentDirectory:2",
+            "http://{addr}:8080/b.xml",
+        )
+        .match_lower_versions(true);
+        let mut running = Running::start(server([strict.clone(), lenient.clone()]));
+        running.next_notify("ssdp:alive").await;
+        running.next_notify("ssdp:alive").await;
+
+        // Answered with the searched version
+        let v1 = "urn:schemas-upnp-org:service:ContentDirectory:1";
+        running.search(v1, 1);
+        let sent = running.sent_within(Duration::from_millis(100)).await;
+        assert_eq!(sent.len(), 1);
+        assert_eq!(header(&sent[0].0, "ST"), Some(v1));
+        assert!(
+            header(&sent[0].0, "USN")
+                .unwrap()
+                .starts_with("uuid:0e5a3ac0-0a24-4d9b-8b3b-6e5e0b2cf5a1::")
+        );
+
+        running.search("urn:schemas-upnp-org:service:ContentDirectory:3", 1);
+        assert!(
+            running
+                .sent_within(Duration::from_millis(100))
+                .await
+                .is_empty()
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serves_interface_by_name() {
+        assert!(server([]).serve_interface("no-such-interface0").is_err());
+
+        let port = free_port();
+        let task = tokio::spawn(
+            server([root_device()])
+                .multicast_endpoint(SSDP_ADDR_V4, port)
+                .serve_interface("lo")
+                .unwrap(),
+        );
+
+        let socket = loopback_socket().await;
+        socket
+            .send_to(
+                search("upnp:rootdevice", 1).as_bytes(),
+                (Ipv4Addr::LOCALHOST, port),
+            )
+            .await
+            .unwrap();
+        let response = recv_within(&socket, Duration::from_secs(1)).await.unwrap();
+        assert_eq!(
+            header(&response, "LOCATION"),
+            Some("http://127.0.0.1:8080/desc.xml")
+        );
+        task.abort();
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[tokio::test]
+    async fn replies_from_receiving_address() {
+        let port = free_port();
+        let shutdown: Arc<watch::Sender<bool>> = Arc::default();
+        let device = Device::new(UUID, "upnp:rootdevice", "http://{local_addr}:8080/desc.xml");
+        let task = tokio::spawn(
+            server([device])
+                .multicast_endpoint(SSDP_ADDR_V4, port)
+                .serve_with(
+                    Ipv4Addr::UNSPECIFIED,
+                    Receiver::Bind,
+                    None,
+                    Arc::clone(&shutdown),
+                )
+                .unwrap(),
+        );
+
+        let client = loopback_socket().await;
+        for addr in [Ipv4Addr::LOCALHOST, Ipv4Addr::new(127, 0, 0, 2)] {
+            client
+                .send_to(search("upnp:rootdevice", 1).as_bytes(), (addr, port))
+                .await
+                .unwrap();
+            let mut buf = [0; 2048];
+            let (n, from) =
+                tokio::time::timeout(Duration::from_secs(5), client.recv_from(&mut buf))
+                    .await
+                    .unwrap()
+                    .unwrap();
+            assert_eq!(from.ip(), addr);
+            let response = String::from_utf8_lossy(&buf[..n]);
+            assert_eq!(
+                header(&response, "LOCATION"),
+                Some(format!("http://{}:8080/desc.xml", addr).as_str())
             );
+        }
 
-            trace!("Alive message: {}", message);
+        shutdown.send_replace(true);
+        task.await.unwrap().unwrap();
+    }
 
-            socket
-                .send_to(message.as_bytes(), (SSDP_ADDR, SSDP_PORT))
-                .await?;
+    #[cfg(all(
+        feature = "interface-events",
+        any(target_os = "linux", target_os = "android")
+    ))]
+    #[tokio::test]
+    async fn reannounces_when_interfaces_change() {
+        let ip = |args: &[&str]| {
+            std::process::Command::new("ip")
+                .args(args)
+                .status()
+                .is_ok_and(|status| status.success())
+        };
 
-            // Avoid congestion
-            tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut running = Running::start(server([root_device()]).watch_interfaces(true));
+        running.next_notify("ssdp:alive").await;
+        // Skip the rest of the initial announcements
+        running.sent_within(Duration::from_secs(1)).await;
+
+        // Changing addresses needs CAP_NET_ADMIN
+        if !ip(&["addr", "add", "127.0.0.3/32", "dev", "lo"]) {
+            return;
         }
+        ip(&["addr", "del", "127.0.0.3/32", "dev", "lo"]);
 
-        Ok(())
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_eq!(header(&alive, "USN"), Some(root_device().usn.as_str()));
     }
 
-    /// Broadcast `ssdp:byebye`
-    async fn broadcast_byebye(&self, socket: &UdpSocket, extra_headers: &str) -> IoResult<()> {
-        debug!("Sending byebye messages");
+    #[tokio::test]
+    async fn sends_secure_location() {
+        let device = root_device().secure_location("https://{addr}:8443/desc.xml");
+        let mut running = Running::start(server([device]));
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_eq!(
+            header(&alive, "SECURELOCATION.UPNP.ORG"),
+            Some("https://192.168.1.100:8443/desc.xml")
+        );
+        assert_eq!(
+            header(&alive, "LOCATION"),
+            Some("http://192.168.1.100:8080/desc.xml")
+        );
 
-        for device in self.devices.iter() {
-            let message = format!(
-                concat!(
-                    "NOTIFY * HTTP/1.1\r\n",
-                    "HOST: {ssdp_addr}:{ssdp_port}\r\n",
-                    "NT: {st}\r\n",
-                    "NTS: ssdp:alive\r\n",
-                    "USN: {usn}\r\n",
-                    "{headers}",
-                    "\r\n"
-                ),
-                ssdp_addr = SSDP_ADDR,
-                ssdp_port = SSDP_PORT,
-                st = device.search_target,
-                usn = device.usn,
-                headers = extra_headers
-            );
+        running.search("upnp:rootdevice", 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert_eq!(
+            header(&response, "SECURELOCATION.UPNP.ORG"),
+            Some("https://192.168.1.100:8443/desc.xml")
+        );
 
-            trace!("Byebye message: {}", message);
+        let sent = running.shutdown().await;
+        let (byebye, _) = sent
+            .iter()
+            .find(|(message, _)| header(message, "NTS") == Some("ssdp:byebye"))
+            .unwrap();
+        assert_eq!(header(byebye, "SECURELOCATION.UPNP.ORG"), None);
+    }
 
-            socket
-                .send_to(message.as_bytes(), (SSDP_ADDR, SSDP_PORT))
-                .await?;
+    #[tokio::test]
+    async fn answers_malformed_searches_with_lenient_parsing() {
+        let malformed = "m-search * HTTP/1.1\n\
+                         HOST: 239.255.255.250:1900\n\
+                         MAN: \"ssdp:discover\"\n\
+                         MX: 1\n\
+                         ST: upnp:rootdevice\0\n";
 
-            // Avoid congestion
-            tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut running = Running::start(server([root_device()]));
+        running.next_notify("ssdp:alive").await;
+        running.receive(malformed, CONTROL_POINT);
+        assert!(
+            running
+                .sent_within(Duration::from_millis(100))
+                .await
+                .is_empty()
+        );
+
+        let mut running = Running::start(server([root_device()]).lenient_parsing(true));
+        running.next_notify("ssdp:alive").await;
+        running.receive(malformed, CONTROL_POINT);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(header(&response, "ST"), Some("upnp:rootdevice"));
+    }
+
+    #[tokio::test]
+    async fn customizes_responses_per_control_point() {
+        let guest = "192.168.100.20:50000";
+        let server = server([root_device()]).customize_responses(
+            |_: &SearchRequest, _: &Device, ctx: &SearchContext| {
+                if ctx.remote_addr.to_string() == "192.168.100.20:50000" {
+                    crate::ResponseOverrides::default()
+                        .location("http://{addr}:8080/guest.xml")
+                        .max_age(60)
+                } else {
+                    crate::ResponseOverrides::default().header("X-SESSION", "6f1c2e")
+                }
+            },
+        );
+        let mut running = Running::start(server);
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_eq!(header(&alive, "X-SESSION"), None);
+
+        running.receive(&search("upnp:rootdevice", 1), guest);
+        let response = running.next_to(guest).await;
+        assert_eq!(
+            header(&response, "LOCATION"),
+            Some("http://192.168.1.100:8080/guest.xml")
+        );
+        assert_eq!(header(&response, "CACHE-CONTROL"), Some("max-age=60"));
+        assert_eq!(header(&response, "X-SESSION"), None);
+
+        running.search("upnp:rootdevice", 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert_eq!(
+            header(&response, "LOCATION"),
+            Some("http://192.168.1.100:8080/desc.xml")
+        );
+        assert_eq!(header(&response, "X-SESSION"), Some("6f1c2e"));
+    }
+
+    #[tokio::test]
+    async fn evaluates_location_provider_per_message() {
+        let port = Arc::new(AtomicUsize::new(8080));
+        let device = Device::new(UUID, "upnp:rootdevice", "").location_fn({
+            let port = Arc::clone(&port);
+            move || format!("http://{{addr}}:{}/desc.xml", port.load(Ordering::SeqCst))
+        });
+        let mut running = Running::start(server([device]));
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_eq!(
+            header(&alive, "LOCATION"),
+            Some("http://192.168.1.100:8080/desc.xml")
+        );
+
+        port.store(8081, Ordering::SeqCst);
+        running.search("upnp:rootdevice", 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert_eq!(
+            header(&response, "LOCATION"),
+            Some("http://192.168.1.100:8081/desc.xml")
+        );
+    }
+
+    #[tokio::test]
+    async fn publishes_server_events() {
+        let server = server([root_device()]);
+        let mut events = server.events();
+        let mut running = Running::start(server);
+        let usn = root_device().usn;
+        let mut next_event = async || {
+            tokio::time::timeout(Duration::from_secs(10), events.recv())
+                .await
+                .unwrap()
+                .unwrap()
+        };
+
+        running.next_notify("ssdp:alive").await;
+        assert!(matches!(next_event().await, ServerEvent::AliveSent { usn: u } if u == usn));
+
+        running.receive("M-SEARCH\r\nST\r\n\r\n", CONTROL_POINT);
+        assert!(matches!(
+            next_event().await,
+            ServerEvent::ParseFailed { remote_addr, .. } if remote_addr.to_string() == CONTROL_POINT
+        ));
+
+        running.search("upnp:rootdevice", 1);
+        running.next_to(CONTROL_POINT).await;
+        assert!(matches!(
+            next_event().await,
+            ServerEvent::SearchReceived { st, remote_addr, .. }
+                if st == "upnp:rootdevice" && remote_addr.to_string() == CONTROL_POINT
+        ));
+        assert!(matches!(next_event().await, ServerEvent::SearchAnswered(_)));
+        assert!(matches!(
+            next_event().await,
+            ServerEvent::ResponseSent { usn: u, .. } if u == usn
+        ));
+
+        running.shutdown().await;
+        assert!(matches!(next_event().await, ServerEvent::ByebyeSent { usn: u } if u == usn));
+    }
+
+    #[tokio::test]
+    async fn bounds_pending_responses() {
+        let other = "192.168.1.21:50000";
+        let delayed = |limit| {
+            server([root_device()])
+                .response_delay(ResponseDelay::Fixed(Duration::from_millis(200)))
+                .max_pending_responses(limit)
+        };
+
+        let mut running = Running::start(delayed(1));
+        running.next_notify("ssdp:alive").await;
+        running.search("upnp:rootdevice", 1);
+        running.receive(&search("upnp:rootdevice", 1), other);
+        let sent = running.sent_within(Duration::from_millis(500)).await;
+        let targets: Vec<_> = sent.iter().map(|(_, to)| to.to_string()).collect();
+        assert_eq!(targets, [CONTROL_POINT]);
+
+        // Once sent, the response makes room for the next one
+        running.receive(&search("upnp:rootdevice", 1), other);
+        running.next_to(other).await;
+
+        // 0 is unlimited
+        let mut running = Running::start(delayed(0));
+        running.next_notify("ssdp:alive").await;
+        running.search("upnp:rootdevice", 1);
+        running.receive(&search("upnp:rootdevice", 1), other);
+        let sent = running.sent_within(Duration::from_millis(500)).await;
+        assert_eq!(sent.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn paces_announcements() {
+        let devices = [
+            root_device(),
+            service("ContentDirectory:1"),
+            service("ConnectionManager:1"),
+        ];
+
+        let paced = server(devices.clone()).pacing(Pacing::Fixed(Duration::from_millis(100)));
+        let mut running = Running::start(paced);
+        running.next_notify("ssdp:alive").await;
+        let start = std::time::Instant::now();
+        running.next_notify("ssdp:alive").await;
+        assert!(start.elapsed() >= Duration::from_millis(90));
+        running.next_notify("ssdp:alive").await;
+        assert!(start.elapsed() >= Duration::from_millis(190));
+
+        let spread = server(devices.clone()).pacing(Pacing::Spread(Duration::from_millis(300)));
+        let mut running = Running::start(spread);
+        let start = std::time::Instant::now();
+        running.next_notify("ssdp:alive").await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+        let sent = running.sent_within(Duration::from_millis(500)).await;
+        assert_eq!(usns(&sent, "ssdp:alive").len(), devices.len() - 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[tokio::test]
+    async fn serves_devices_from_config() {
+        let device: Device = toml::from_str(
+            r#"
+            uuid = "ad8782a0-9e28-422b-a6ae-670fe7c4c043"
+            search_target = "upnp:rootdevice"
+            location = "http://{addr}:8080/desc.xml"
+            max_age = 1800
+
+            [[headers]]
+            name = "X-VENDOR"
+            value = "acme"
+            kind = "alive"
+            "#,
+        )
+        .unwrap();
+
+        let mut running = Running::start(server([device]));
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_eq!(header(&alive, "USN"), Some(root_device().usn.as_str()));
+        assert_eq!(
+            header(&alive, "LOCATION"),
+            Some("http://192.168.1.100:8080/desc.xml")
+        );
+        assert_eq!(header(&alive, "CACHE-CONTROL"), Some("max-age=1800"));
+        assert_eq!(header(&alive, "X-VENDOR"), Some("acme"));
+
+        running.search("upnp:rootdevice", 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert_eq!(header(&response, "X-VENDOR"), None);
+    }
+
+    #[tokio::test]
+    async fn notifies_control_point_by_unicast() {
+        let devices = [root_device(), service("ContentDirectory:1")];
+        let mut running = Running::start(server(devices.clone()));
+        running.next_notify("ssdp:alive").await;
+        running.sent_within(Duration::from_millis(100)).await;
+
+        let target: SocketAddr = CONTROL_POINT.parse().unwrap();
+        running.control(Control::Unicast {
+            target,
+            usn: devices[1].usn.clone(),
+            alive: true,
+        });
+        running.control(Control::Unicast {
+            target,
+            usn: devices[1].usn.clone(),
+            alive: false,
+        });
+        // Unknown devices are not notified
+        running.control(Control::Unicast {
+            target,
+            usn: "uuid:0e5a3ac0-0a24-4d9b-8b3b-6e5e0b2cf5a1::upnp:rootdevice".into(),
+            alive: true,
+        });
+
+        let sent = running.sent_within(Duration::from_millis(200)).await;
+        assert_eq!(sent.len(), 2);
+        for ((message, to), nts) in sent.iter().zip(["ssdp:alive", "ssdp:byebye"]) {
+            assert_eq!(*to, target);
+            assert!(message.starts_with("NOTIFY * HTTP/1.1\r\n"));
+            assert_eq!(header(message, "NTS"), Some(nts));
+            assert_eq!(header(message, "USN"), Some(devices[1].usn.as_str()));
         }
 
-        Ok(())
+        // The device is still served
+        running.search("ssdp:all", 1);
+        assert_eq!(
+            running.sent_within(Duration::from_millis(100)).await.len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn answers_only_searches_for_multicast_group() {
+        let mut running = Running::start(server([root_device()]).strict_destination(true));
+        running.next_notify("ssdp:alive").await;
+
+        let wrong_host = search("upnp:rootdevice", 1)
+            .replace("HOST: 239.255.255.250:1900", "HOST: 192.168.1.100:1900");
+        running.receive(&wrong_host, CONTROL_POINT);
+        let no_host = search("upnp:rootdevice", 1).replace("HOST: 239.255.255.250:1900\r\n", "");
+        running.receive(&no_host, CONTROL_POINT);
+        assert!(
+            running
+                .sent_within(Duration::from_millis(100))
+                .await
+                .is_empty()
+        );
+
+        running.search("upnp:rootdevice", 1);
+        running.next_to(CONTROL_POINT).await;
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[tokio::test]
+    async fn rejects_searches_sent_by_unicast() {
+        let bound = Bound::start(server([root_device()]).strict_destination(true));
+        let client = loopback_socket().await;
+        let request = search("upnp:rootdevice", 1).replace(
+            "239.255.255.250:1900",
+            &format!("239.255.255.250:{}", bound.port),
+        );
+
+        bound.send(&client, &request).await;
+        assert_eq!(recv_within(&client, Duration::from_millis(200)).await, None);
+
+        let std_client = client.into_std().unwrap();
+        crate::iface::set_multicast_if(&std_client, Ipv4Addr::LOCALHOST).unwrap();
+        let client = UdpSocket::from_std(std_client).unwrap();
+        client
+            .send_to(request.as_bytes(), (SSDP_ADDR_V4, bound.port))
+            .await
+            .unwrap();
+        let response = recv_within(&client, Duration::from_secs(5)).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        bound.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn identifies_control_points_of_searches() {
+        let server = server([root_device()]);
+        let mut searches = server.answered_searches();
+        let mut events = server.events();
+        let mut running = Running::start(server);
+        running.next_notify("ssdp:alive").await;
+
+        let request = search("upnp:rootdevice", 1).replace(
+            "\r\n\r\n",
+            "\r\nUSER-AGENT: Linux/6.1 UPnP/1.1 Player/2.0\r\n\
+             CPFN.UPNP.ORG: Living Room\r\n\
+             CPUUID.UPNP.ORG: 0e5a3ac0-0a24-4d9b-8b3b-6e5e0b2cf5a1\r\n\r\n",
+        );
+        running.receive(&request, CONTROL_POINT);
+        running.next_to(CONTROL_POINT).await;
+
+        let expected = ControlPoint {
+            user_agent: Some("Linux/6.1 UPnP/1.1 Player/2.0".into()),
+            name: Some("Living Room".into()),
+            uuid: Some("0e5a3ac0-0a24-4d9b-8b3b-6e5e0b2cf5a1".into()),
+        };
+        let answered = searches.recv().await.unwrap();
+        assert_eq!(answered.control_point, expected);
+        loop {
+            if let ServerEvent::SearchReceived { control_point, .. } = events.recv().await.unwrap()
+            {
+                assert_eq!(control_point, expected);
+                assert_eq!(control_point.to_string(), "Living Room");
+                break;
+            }
+        }
+    }
+
+    #[cfg(all(feature = "signal", feature = "socket2", unix))]
+    #[tokio::test]
+    async fn sends_byebye_on_signal() {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        // Keeps the signal from terminating the test process
+        let _terminate = signal(SignalKind::terminate()).unwrap();
+
+        let port = free_port();
+        let listener = Server::new_rx_socket(port, false).unwrap();
+        listener
+            .join_multicast_v4(&SSDP_ADDR_V4, &Ipv4Addr::LOCALHOST)
+            .unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let listener = UdpSocket::from_std(listener).unwrap();
+
+        let handle = server([root_device()])
+            .multicast_endpoint(SSDP_ADDR_V4, port)
+            .spawn_addr(Ipv4Addr::LOCALHOST)
+            .unwrap();
+        let alive = recv_within(&listener, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(header(&alive, "NTS"), Some("ssdp:alive"));
+
+        let mut task = tokio::spawn(handle.shutdown_on_signal());
+        // The handler is installed once the task runs
+        let res = loop {
+            // SAFETY: raising a signal has no memory safety requirements.
+            unsafe { libc::raise(libc::SIGTERM) };
+            if let Ok(res) = tokio::time::timeout(Duration::from_millis(100), &mut task).await {
+                break res.unwrap();
+            }
+        };
+        res.unwrap();
+
+        let byebye = recv_within(&listener, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(header(&byebye, "NTS"), Some("ssdp:byebye"));
+        assert_eq!(header(&byebye, "USN"), Some(root_device().usn.as_str()));
+    }
+
+    #[cfg(all(feature = "socket2", any(target_os = "linux", target_os = "android")))]
+    #[tokio::test]
+    async fn shares_ssdp_port_with_reuse_port() {
+        use socket2::{Domain, Protocol, Socket, Type};
+
+        let port = free_port();
+        let serve = |server: Server| {
+            server.multicast_endpoint(SSDP_ADDR_V4, port).serve_with(
+                Ipv4Addr::LOCALHOST,
+                Receiver::Bind,
+                None,
+                Arc::default(),
+            )
+        };
+
+        // Another daemon, sharing the port only with `SO_REUSEPORT`
+        let daemon = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
+        daemon.set_reuse_port(true).unwrap();
+        daemon
+            .bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)).into())
+            .unwrap();
+        daemon
+            .join_multicast_v4(&SSDP_ADDR_V4, &Ipv4Addr::LOCALHOST)
+            .unwrap();
+        daemon.set_nonblocking(true).unwrap();
+        let daemon = UdpSocket::from_std(daemon.into()).unwrap();
+
+        let Err(e) = serve(server([root_device()])) else {
+            panic!("served on a port in use");
+        };
+        assert_eq!(e.kind(), std::io::ErrorKind::AddrInUse);
+
+        let task = tokio::spawn(serve(server([root_device()]).reuse_port(true)).unwrap());
+        let alive = recv_within(&daemon, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(header(&alive, "NTS"), Some("ssdp:alive"));
+
+        // Multicast searches reach both
+        let client = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        crate::iface::set_multicast_if(&client, Ipv4Addr::LOCALHOST).unwrap();
+        client.set_nonblocking(true).unwrap();
+        let client = UdpSocket::from_std(client).unwrap();
+        let request = search("upnp:rootdevice", 1)
+            .replace("239.255.255.250:1900", &format!("239.255.255.250:{}", port));
+        client
+            .send_to(request.as_bytes(), (SSDP_ADDR_V4, port))
+            .await
+            .unwrap();
+        let received = recv_within(&daemon, Duration::from_secs(5)).await.unwrap();
+        assert!(received.starts_with("M-SEARCH * HTTP/1.1\r\n"));
+        let response = recv_within(&client, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(header(&response, "ST"), Some("upnp:rootdevice"));
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn announces_on_configured_multicast_endpoint() {
+        let group = Ipv4Addr::new(239, 255, 255, 251);
+        let server = server([root_device()])
+            .multicast_endpoint(group, 1901)
+            .strict_destination(true);
+        let mut running = Running::start(server);
+        let (alive, to) = running.next().await;
+        assert_eq!(header(&alive, "NTS"), Some("ssdp:alive"));
+        assert_eq!(header(&alive, "HOST"), Some("239.255.255.251:1901"));
+        assert_eq!(to, SocketAddr::from((group, 1901)));
+
+        // Searches name the configured endpoint
+        running.search("upnp:rootdevice", 1);
+        assert!(
+            running
+                .sent_within(Duration::from_millis(100))
+                .await
+                .is_empty()
+        );
+        let request =
+            search("upnp:rootdevice", 1).replace("239.255.255.250:1900", "239.255.255.251:1901");
+        running.receive(&request, CONTROL_POINT);
+        running.next_to(CONTROL_POINT).await;
+
+        let sent = running.shutdown().await;
+        let (byebye, to) = sent.last().unwrap();
+        assert_eq!(header(byebye, "NTS"), Some("ssdp:byebye"));
+        assert_eq!(*to, SocketAddr::from((group, 1901)));
+    }
+
+    #[tokio::test]
+    async fn sends_nls_headers() {
+        let mut running = Running::start(server([root_device()]).nls(true));
+        let alive = running.next_notify("ssdp:alive").await;
+        let opt = "\"http://schemas.upnp.org/upnp/1/0/\"; ns=01";
+        assert_eq!(header(&alive, "OPT"), Some(opt));
+        let nls = header(&alive, "01-NLS").unwrap().to_string();
+        assert_eq!(nls.len(), 36);
+
+        running.search("upnp:rootdevice", 1);
+        let response = running.next_to(CONTROL_POINT).await;
+        assert_eq!(header(&response, "OPT"), Some(opt));
+        assert_eq!(header(&response, "01-NLS"), Some(nls.as_str()));
+
+        let sent = running.shutdown().await;
+        let (byebye, _) = sent.last().unwrap();
+        assert_eq!(header(byebye, "NTS"), Some("ssdp:byebye"));
+        assert_eq!(header(byebye, "01-NLS"), Some(nls.as_str()));
+
+        // A new value for every boot
+        let mut running = Running::start(server([root_device()]).nls(true));
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_ne!(header(&alive, "01-NLS"), Some(nls.as_str()));
+
+        let mut running = Running::start(server([root_device()]));
+        let alive = running.next_notify("ssdp:alive").await;
+        assert_eq!(header(&alive, "OPT"), None);
+        assert_eq!(header(&alive, "01-NLS"), None);
+    }
+
+    #[tokio::test]
+    async fn notifies_selected_devices() {
+        let devices = [root_device(), service("ContentDirectory:1")];
+        let server = server(devices.clone())
+            .upnp_version(UpnpVersion::V1_1)
+            .boot_id(7);
+        let mut running = Running::start(server);
+        for _ in &devices {
+            running.next_notify("ssdp:alive").await;
+        }
+
+        running.control(Control::Notify {
+            nts: NotificationSubtype::ByeBye,
+            usns: vec![
+                devices[1].usn.clone(),
+                "uuid:0e5a3ac0-0a24-4d9b-8b3b-6e5e0b2cf5a1::upnp:rootdevice".into(),
+            ],
+        });
+        let sent = running.sent_within(Duration::from_millis(100)).await;
+        assert_eq!(sent.len(), 1);
+        assert_eq!(usns(&sent, "ssdp:byebye"), [devices[1].usn.clone()]);
+
+        // The boot id is shared, so all devices are updated
+        running.control(Control::Notify {
+            nts: NotificationSubtype::Update,
+            usns: vec![devices[0].usn.clone()],
+        });
+        for device in &devices {
+            let update = running.next_notify("ssdp:update").await;
+            assert_eq!(header(&update, "USN"), Some(device.usn.as_str()));
+            assert_eq!(header(&update, "NEXTBOOTID.UPNP.ORG"), Some("8"));
+        }
+        for device in &devices {
+            let alive = running.next_notify("ssdp:alive").await;
+            assert_eq!(header(&alive, "USN"), Some(device.usn.as_str()));
+            assert_eq!(header(&alive, "BOOTID.UPNP.ORG"), Some("8"));
+        }
+    }
+
+    #[tokio::test]
+    async fn passes_received_messages_to_client() {
+        let server = server([]).deny_subnet("192.168.1.66/32".parse().unwrap());
+        let client = server.client();
+        assert_eq!(
+            client.discover("ssdp:all").await.unwrap_err().kind(),
+            std::io::ErrorKind::NotConnected
+        );
+
+        let mut running = Running::start(server);
+        // The client can search once the receive loop runs
+        let mut registry = loop {
+            match client.discover("upnp:rootdevice").await {
+                Ok(registry) => break registry,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        let (request, to) = running.next().await;
+        assert!(request.starts_with("M-SEARCH * HTTP/1.1\r\n"));
+        assert_eq!(header(&request, "ST"), Some("upnp:rootdevice"));
+        assert_eq!(to, SocketAddr::from((SSDP_ADDR_V4, SSDP_PORT)));
+
+        let notify = |uuid: &str| {
+            format!(
+                "NOTIFY * HTTP/1.1\r\n\
+                 HOST: 239.255.255.250:1900\r\n\
+                 CACHE-CONTROL: max-age=1800\r\n\
+                 LOCATION: http://192.168.1.50:8080/desc.xml\r\n\
+                 NT: upnp:rootdevice\r\n\
+                 NTS: ssdp:alive\r\n\
+                 SERVER: Linux/6.1 UPnP/1.0 Device/1.0\r\n\
+                 USN: uuid:{}::upnp:rootdevice\r\n\r\n",
+                uuid
+            )
+        };
+        // Not passed on from denied peers
+        running.receive(
+            &notify("0e5a3ac0-0a24-4d9b-8b3b-6e5e0b2cf5a1"),
+            "192.168.1.66:1900",
+        );
+        running.receive(&notify(UUID), "192.168.1.50:1900");
+
+        let event = tokio::time::timeout(Duration::from_secs(10), registry.next())
+            .await
+            .unwrap();
+        let Some(crate::RegistryEvent::Added(device)) = event else {
+            panic!("unexpected event {:?}", event);
+        };
+        assert_eq!(device.usn, root_device().usn);
+        assert_eq!(device.location, "http://192.168.1.50:8080/desc.xml");
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), registry.next())
+                .await
+                .is_err()
+        );
     }
 }
@@ -0,0 +1,85 @@
+use std::{
+    io::{Error, ErrorKind, Result as IoResult},
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use tokio::sync::mpsc;
+
+use crate::logging::trace;
+
+/// How many datagrams or searches are queued before further ones are dropped.
+const QUEUE_LEN: usize = 64;
+
+/// A datagram received by a server, with the address of its sender.
+pub(crate) type Datagram = (Vec<u8>, SocketAddr);
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// The searches of clients, receiving the datagrams received by the servers.
+    clients: Vec<mpsc::Sender<Datagram>>,
+    /// The running servers, sending the searches of clients from their socket.
+    servers: Vec<mpsc::Sender<Vec<u8>>>,
+}
+
+/// The socket and receive loop of a server, shared with its clients, see
+/// [`Server::client`](crate::Server::client).
+///
+/// Clones share the same state, so clients created before the server is started search
+/// through it once it runs.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SharedLoop {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl SharedLoop {
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Register a running server, returning the searches to send from its socket.
+    pub(crate) fn serve(&self) -> mpsc::Receiver<Vec<u8>> {
+        let (tx, rx) = mpsc::channel(QUEUE_LEN);
+        self.lock().servers.push(tx);
+        rx
+    }
+
+    /// Register a search, returning the datagrams received by the servers.
+    pub(crate) fn subscribe(&self) -> mpsc::Receiver<Datagram> {
+        let (tx, rx) = mpsc::channel(QUEUE_LEN);
+        self.lock().clients.push(tx);
+        rx
+    }
+
+    /// Pass a datagram received by a server from `remote_addr` to the searches.
+    pub(crate) fn received(&self, data: &[u8], remote_addr: SocketAddr) {
+        let mut inner = self.lock();
+        inner.clients.retain(|client| !client.is_closed());
+        for client in &inner.clients {
+            if client.try_send((data.to_vec(), remote_addr)).is_err() {
+                trace!(
+                    "Dropping datagram from {} for a lagging search",
+                    remote_addr
+                );
+            }
+        }
+    }
+
+    /// Send the search `request` from the sockets of the running servers.
+    pub(crate) fn search(&self, request: &[u8]) -> IoResult<()> {
+        let mut inner = self.lock();
+        inner.servers.retain(|server| !server.is_closed());
+        if inner.servers.is_empty() {
+            return Err(Error::new(
+                ErrorKind::NotConnected,
+                "the server is not running",
+            ));
+        }
+        for server in &inner.servers {
+            if server.try_send(request.to_vec()).is_err() {
+                trace!("Dropping M-SEARCH for a lagging server");
+            }
+        }
+        Ok(())
+    }
+}
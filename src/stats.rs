@@ -0,0 +1,55 @@
+//! Metrics recorded through the `metrics` facade if the `metrics` feature is enabled,
+//! and compiled out otherwise.
+//!
+//! The following metrics are recorded:
+//!
+//! - `ssdp_searches_received_total`: `M-SEARCH` requests received
+//! - `ssdp_responses_sent_total`: search responses sent
+//! - `ssdp_notifications_sent_total`: notifications sent, labelled with `nts`
+//! - `ssdp_parse_failures_total`: datagrams which aren't HTTP requests, and invalid
+//!   `M-SEARCH` and `NOTIFY` requests
+//! - `ssdp_send_failures_total`: datagrams that could not be sent
+//! - `ssdp_response_latency_seconds`: histogram of the time from receiving a search to
+//!   sending each response, including the response delay
+
+use std::time::Instant;
+
+#[cfg(feature = "metrics")]
+use metrics::{counter, histogram};
+
+/// An `M-SEARCH` request was received.
+pub(crate) fn search_received() {
+    #[cfg(feature = "metrics")]
+    counter!("ssdp_searches_received_total").increment(1);
+}
+
+/// A response to the search received at `received` was sent.
+pub(crate) fn response_sent(received: Instant) {
+    #[cfg(feature = "metrics")]
+    {
+        counter!("ssdp_responses_sent_total").increment(1);
+        histogram!("ssdp_response_latency_seconds").record(received.elapsed());
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = received;
+}
+
+/// A notification with the `NTS` subtype `nts`, e.g. `ssdp:alive`, was sent.
+pub(crate) fn notification_sent(nts: &'static str) {
+    #[cfg(feature = "metrics")]
+    counter!("ssdp_notifications_sent_total", "nts" => nts).increment(1);
+    #[cfg(not(feature = "metrics"))]
+    let _ = nts;
+}
+
+/// An invalid request was received.
+pub(crate) fn parse_failed() {
+    #[cfg(feature = "metrics")]
+    counter!("ssdp_parse_failures_total").increment(1);
+}
+
+/// Sending a datagram failed.
+pub(crate) fn send_failed() {
+    #[cfg(feature = "metrics")]
+    counter!("ssdp_send_failures_total").increment(1);
+}
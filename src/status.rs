@@ -0,0 +1,201 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{Device, Health, eventlog::escape};
+
+const DEFAULT_RECENT_EVENTS: usize = 32;
+
+/// The announcement schedule of a group of devices.
+#[derive(Debug, Clone)]
+pub(crate) struct ScheduledGroup {
+    pub(crate) max_age: u64,
+    pub(crate) notify_interval: Duration,
+    pub(crate) devices: Vec<Device>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    capacity: usize,
+    groups: Vec<ScheduledGroup>,
+    health: Option<Health>,
+    counters: BTreeMap<String, u64>,
+    recent: VecDeque<String>,
+}
+
+/// A JSON report of the state of a server for operators, see [`Server::status`](crate::Server::status).
+///
+/// The report contains the announced devices with their schedule, the
+/// [`HealthReport`](crate::HealthReport) of the server, the number of events of each kind
+/// and the most recent events, in the format of [`EventLog`](crate::EventLog).
+///
+/// Clones share the same state, so a `Status` handed to a server before it is started
+/// reflects the running server.
+#[derive(Debug, Clone)]
+pub struct Status {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Default for Status {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                capacity: DEFAULT_RECENT_EVENTS,
+                groups: vec![],
+                health: None,
+                counters: BTreeMap::new(),
+                recent: VecDeque::new(),
+            })),
+        }
+    }
+}
+
+impl Status {
+    /// Create a new status report, keeping the last 32 events.
+    ///
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{Server, Status};
+    ///
+    /// let status = Status::new();
+    /// let server = Server::new([]).status(status.clone());
+    ///
+    /// assert!(status.to_json().starts_with('{'));
+    /// ```
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep the last `capacity` events in the report.
+    pub fn recent_events(self, capacity: usize) -> Self {
+        self.lock().capacity = capacity;
+        self
+    }
+
+    /// Render the report as a JSON object.
+    pub fn to_json(&self) -> String {
+        let inner = self.lock();
+        let mut out = String::from("{\"health\":");
+
+        match inner.health.as_ref().map(Health::report) {
+            Some(report) => {
+                let _ = write!(
+                    out,
+                    "{{\"last_receive\":{},\"last_notify\":{},\"consecutive_send_failures\":{},\"multicast_joined\":{}}}",
+                    millis(report.last_receive),
+                    millis(report.last_notify),
+                    report.consecutive_send_failures,
+                    report.multicast_joined,
+                );
+            }
+            None => out.push_str("null"),
+        }
+
+        out.push_str(",\"groups\":[");
+        for (idx, group) in inner.groups.iter().enumerate() {
+            if idx > 0 {
+                out.push(',');
+            }
+            let _ = write!(
+                out,
+                "{{\"max_age\":{},\"notify_interval_ms\":{},\"devices\":[",
+                group.max_age,
+                group.notify_interval.as_millis(),
+            );
+            for (idx, device) in group.devices.iter().enumerate() {
+                if idx > 0 {
+                    out.push(',');
+                }
+                let _ = write!(
+                    out,
+                    "{{\"usn\":\"{}\",\"st\":\"{}\",\"location\":\"{}\"}}",
+                    escape(&device.usn),
+                    escape(&device.search_target),
+                    escape(&device.current_location()),
+                );
+            }
+            out.push_str("]}");
+        }
+
+        out.push_str("],\"events\":{");
+        for (idx, (event, count)) in inner.counters.iter().enumerate() {
+            if idx > 0 {
+                out.push(',');
+            }
+            let _ = write!(out, "\"{}\":{}", escape(event), count);
+        }
+
+        out.push_str("},\"recent_events\":[");
+        for (idx, line) in inner.recent.iter().enumerate() {
+            if idx > 0 {
+                out.push(',');
+            }
+            out.push_str(line);
+        }
+        out.push_str("]}");
+
+        out
+    }
+
+    /// Serve the report as `application/json` on `GET`, to be mounted into an
+    /// existing [`axum::Router`].
+    ///
+    /// # Examples
+    /// ```
+    /// use axum::Router;
+    /// use tokio_ssdp::{Server, Status};
+    ///
+    /// let status = Status::new();
+    /// let server = Server::new([]).status(status.clone());
+    ///
+    /// let app: Router = Router::new().route("/debug/ssdp", status.route());
+    /// ```
+    #[cfg(feature = "axum")]
+    pub fn route<S>(self) -> axum::routing::MethodRouter<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        axum::routing::get(move || {
+            let json = self.to_json();
+            async move {
+                (
+                    [(axum::http::header::CONTENT_TYPE, "application/json")],
+                    json,
+                )
+            }
+        })
+    }
+
+    /// Attach the status to a server which is being started.
+    pub(crate) fn attach(&self, groups: Vec<ScheduledGroup>, health: Health) {
+        let mut inner = self.lock();
+        inner.groups = groups;
+        inner.health = Some(health);
+    }
+
+    /// Count `event` and keep its `line` as a recent event.
+    pub(crate) fn record(&self, event: &str, line: &str) {
+        let mut inner = self.lock();
+        *inner.counters.entry(event.to_string()).or_default() += 1;
+
+        if inner.capacity == 0 {
+            return;
+        }
+        while inner.recent.len() >= inner.capacity {
+            inner.recent.pop_front();
+        }
+        inner.recent.push_back(line.to_string());
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn millis(time: Option<SystemTime>) -> String {
+    time.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or_else(|| "null".to_string(), |d| d.as_millis().to_string())
+}
@@ -0,0 +1,80 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const BURST: Duration = Duration::from_secs(1);
+
+/// A limit on the outbound traffic of a server, see [`Server::throttle`](crate::Server::throttle).
+///
+/// The limit applies to all datagrams sent by the server, i.e. search responses and
+/// notifications combined. Up to one second worth of traffic may be sent in a burst.
+///
+/// Clones share the same state, so a `Throttle` can be shared by several servers to
+/// limit their combined traffic.
+#[derive(Debug, Clone, Default)]
+pub struct Throttle {
+    packets_per_second: Option<u32>,
+    bytes_per_second: Option<u32>,
+    next: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Throttle {
+    /// Create a new, unlimited throttle.
+    ///
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{Server, Throttle};
+    ///
+    /// Server::new([]).throttle(
+    ///     Throttle::new()
+    ///         .packets_per_second(50)
+    ///         .bytes_per_second(16 * 1024),
+    /// );
+    /// ```
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send at most `packets_per_second` datagrams per second.
+    pub fn packets_per_second(mut self, packets_per_second: u32) -> Self {
+        self.packets_per_second = Some(packets_per_second);
+        self
+    }
+
+    /// Send at most `bytes_per_second` bytes of payload per second.
+    pub fn bytes_per_second(mut self, bytes_per_second: u32) -> Self {
+        self.bytes_per_second = Some(bytes_per_second);
+        self
+    }
+
+    /// Wait until a datagram of `len` bytes may be sent.
+    pub(crate) async fn acquire(&self, len: usize) {
+        let cost = [
+            self.packets_per_second.map(|pps| (1, pps)),
+            self.bytes_per_second.map(|bps| (len, bps)),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|(amount, rate)| Duration::from_secs_f64(amount as f64 / rate.max(1) as f64))
+        .max();
+
+        let Some(cost) = cost else {
+            return;
+        };
+
+        // Track the time at which the sent traffic will have drained, and wait while
+        // that is more than the allowed burst ahead of now.
+        let wait = {
+            let mut next = self.next.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            let start = next.map_or(now, |next| next.max(now));
+            *next = Some(start + cost);
+            start.saturating_duration_since(now + BURST)
+        };
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    fmt,
+    future::poll_fn,
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use tokio::{io::ReadBuf, net::UdpSocket};
+
+/// The datagram transport a server sends and receives on, see [`Server::serve_transport`].
+///
+/// Implemented for [`UdpSocket`], and shared transports. Other implementations can stand
+/// in for the network, e.g. to feed a server canned requests in tests and inspect what it
+/// sends.
+///
+/// [`Server::serve_transport`]: crate::Server::serve_transport
+pub trait Transport: Send + Sync + 'static {
+    /// Attempt to send `buf` to `target`, as [`UdpSocket::poll_send_to`].
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>>;
+
+    /// Attempt to receive a datagram into `buf`, as [`UdpSocket::poll_recv_from`].
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<SocketAddr>>;
+
+    /// The local address of the transport. The port is expected to be the SSDP port for
+    /// transports receiving searches.
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// The UDP socket behind the transport, if any, to use platform features like
+    /// `IP_PKTINFO` and multicast group membership.
+    fn udp_socket(&self) -> Option<&UdpSocket> {
+        None
+    }
+}
+
+impl Transport for UdpSocket {
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        UdpSocket::poll_send_to(self, cx, buf, target)
+    }
+
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<SocketAddr>> {
+        UdpSocket::poll_recv_from(self, cx, buf)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+
+    fn udp_socket(&self) -> Option<&UdpSocket> {
+        Some(self)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Arc<T> {
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        (**self).poll_send_to(cx, buf, target)
+    }
+
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<SocketAddr>> {
+        (**self).poll_recv_from(cx, buf)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        (**self).local_addr()
+    }
+
+    fn udp_socket(&self) -> Option<&UdpSocket> {
+        (**self).udp_socket()
+    }
+}
+
+impl fmt::Debug for dyn Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.local_addr() {
+            Ok(addr) => write!(f, "Transport({})", addr),
+            Err(_) => f.write_str("Transport"),
+        }
+    }
+}
+
+/// Send `buf` to `target` on `transport`.
+pub(crate) async fn send_to(
+    transport: &dyn Transport,
+    buf: &[u8],
+    target: SocketAddr,
+) -> io::Result<usize> {
+    match transport.udp_socket() {
+        Some(socket) => socket.send_to(buf, target).await,
+        None => poll_fn(|cx| transport.poll_send_to(cx, buf, target)).await,
+    }
+}
+
+/// Receive a datagram on `transport` into `buf`.
+pub(crate) async fn recv_from(
+    transport: &dyn Transport,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr)> {
+    let mut buf = ReadBuf::new(buf);
+    let addr = poll_fn(|cx| transport.poll_recv_from(cx, &mut buf)).await?;
+    Ok((buf.filled().len(), addr))
+}
@@ -0,0 +1,370 @@
+//! A minimal WS-Discovery target service, announcing the root devices of a server
+//! with `Hello` and `Bye`, and answering `Probe` requests.
+
+use std::{
+    fmt::Write as _,
+    io::Result as IoResult,
+    net::{Ipv4Addr, SocketAddr},
+    sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::logging::{debug, trace, warn};
+use tokio::{net::UdpSocket, sync::watch};
+
+use crate::{SSDP_ADDR_V4, rng};
+
+/// The WS-Discovery port. The multicast group is the same as for SSDP.
+const WSD_PORT: u16 = 3702;
+
+/// The maximum random delay before answering a `Probe`.
+const APP_MAX_DELAY: Duration = Duration::from_millis(500);
+
+const NS_SOAP: &str = "http://www.w3.org/2003/05/soap-envelope";
+const NS_ADDRESSING: &str = "http://schemas.xmlsoap.org/ws/2004/08/addressing";
+const NS_DISCOVERY: &str = "http://schemas.xmlsoap.org/ws/2005/04/discovery";
+const NS_DEVPROF: &str = "http://schemas.xmlsoap.org/ws/2006/02/devprof";
+
+const TO_DISCOVERY: &str = "urn:schemas-xmlsoap-org:ws:2005:04:discovery";
+const TO_ANONYMOUS: &str = "http://schemas.xmlsoap.org/ws/2004/08/addressing/role/anonymous";
+
+/// Announce the root devices over WS-Discovery, see
+/// [`Server::ws_discovery`](crate::Server::ws_discovery).
+///
+/// The devices are announced as target services with their UUID as endpoint address,
+/// and their location as transport address unless set with [`WsDiscovery::xaddrs`].
+#[derive(Debug, Clone)]
+pub struct WsDiscovery {
+    types: Vec<(String, String)>,
+    scopes: Vec<String>,
+    pub(crate) xaddrs: Option<String>,
+}
+
+impl WsDiscovery {
+    /// Announce the devices as `wsdp:Device`, as expected by Windows network discovery.
+    ///
+    /// # Examples
+    /// ```
+    /// use tokio_ssdp::{Device, Server, WsDiscovery};
+    ///
+    /// let uuid = "ad8782a0-9e28-422b-a6ae-670fe7c4c043";
+    /// Server::new([Device::new(uuid, "upnp:rootdevice", "http://{addr}:8080/desc.xml")])
+    ///     .ws_discovery(
+    ///         WsDiscovery::new()
+    ///             .device_type("http://www.onvif.org/ver10/network/wsdl", "NetworkVideoTransmitter")
+    ///             .scope("onvif://www.onvif.org/type/video_encoder")
+    ///             .xaddrs("http://{addr}:8080/onvif/device_service"),
+    ///     );
+    /// ```
+    pub fn new() -> Self {
+        Self {
+            types: vec![(NS_DEVPROF.to_string(), "Device".to_string())],
+            scopes: vec![],
+            xaddrs: None,
+        }
+    }
+
+    /// Also announce the devices as the type `name` in the XML namespace `namespace`.
+    pub fn device_type(mut self, namespace: impl Into<String>, name: impl Into<String>) -> Self {
+        self.types.push((namespace.into(), name.into()));
+        self
+    }
+
+    /// Add the scope `scope`, a URI, e.g. `onvif://www.onvif.org/name/Camera`.
+    pub fn scope(mut self, scope: impl Into<String>) -> Self {
+        self.scopes.push(scope.into());
+        self
+    }
+
+    /// Announce `xaddrs` as the transport addresses of the devices, a space separated
+    /// list of URLs, instead of their location.
+    ///
+    /// `{uuid}`, `{location}` and `{addr}` are expanded as for
+    /// [`Server::extra_header`](crate::Server::extra_header).
+    pub fn xaddrs(mut self, xaddrs: impl Into<String>) -> Self {
+        self.xaddrs = Some(xaddrs.into());
+        self
+    }
+
+    /// Whether a probe for `types` and `scopes` matches.
+    fn matches(&self, types: &[(Option<String>, String)], scopes: &[String]) -> bool {
+        let types_match = types.iter().all(|(namespace, name)| {
+            self.types.iter().any(|(own_ns, own_name)| {
+                own_name == name && namespace.as_ref().is_none_or(|ns| ns == own_ns)
+            })
+        });
+        let scopes_match = scopes.iter().all(|scope| {
+            self.scopes.iter().any(|own| {
+                own.trim_end_matches('/')
+                    .starts_with(scope.trim_end_matches('/'))
+            })
+        });
+        types_match && scopes_match
+    }
+}
+
+impl Default for WsDiscovery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A target service: a root device of the server.
+#[derive(Debug, Clone)]
+pub(crate) struct WsdTarget {
+    /// e.g. `urn:uuid:<uuid>`
+    pub(crate) address: String,
+    pub(crate) xaddrs: String,
+}
+
+struct Responder {
+    config: WsDiscovery,
+    targets: Vec<WsdTarget>,
+    instance_id: u32,
+    message_number: AtomicU32,
+}
+
+/// Announce `targets` on `ip` and answer probes for them, until `shutdown` changes.
+///
+/// `instance_id` identifies this run of the server, and should increase each time it starts.
+pub(crate) async fn announce(
+    ip: Ipv4Addr,
+    config: WsDiscovery,
+    targets: Vec<WsdTarget>,
+    instance_id: u32,
+    mut shutdown: watch::Receiver<()>,
+) -> IoResult<()> {
+    let socket = Arc::new(bind_socket(ip)?);
+    let dest = SocketAddr::from((SSDP_ADDR_V4, WSD_PORT));
+    let responder = Responder {
+        config,
+        targets,
+        instance_id,
+        message_number: AtomicU32::new(1),
+    };
+
+    for target in &responder.targets {
+        socket
+            .send_to(responder.hello(target).as_bytes(), dest)
+            .await?;
+    }
+
+    let mut buf = [0u8; 8192];
+    loop {
+        tokio::select! {
+            res = socket.recv_from(&mut buf) => {
+                let (n, addr) = res?;
+                let Some(probe) = Probe::parse(&String::from_utf8_lossy(&buf[..n])) else {
+                    continue;
+                };
+
+                if responder.targets.is_empty()
+                    || !responder.config.matches(&probe.types, &probe.scopes)
+                {
+                    continue;
+                }
+
+                trace!("Answering WS-Discovery probe from {}", addr);
+                let message = responder.probe_matches(&probe.message_id);
+                let socket = Arc::clone(&socket);
+                tokio::spawn(async move {
+                    tokio::time::sleep(APP_MAX_DELAY.mul_f64(rng::unit())).await;
+                    if let Err(e) = socket.send_to(message.as_bytes(), addr).await {
+                        debug!("Sending WS-Discovery probe match failed: {}", e);
+                    }
+                });
+            }
+            _ = shutdown.changed() => {
+                debug!("Sending WS-Discovery bye");
+                for target in &responder.targets {
+                    socket.send_to(responder.bye(target).as_bytes(), dest).await?;
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn bind_socket(ip: Ipv4Addr) -> IoResult<UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let s = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    s.set_reuse_address(true)?;
+    #[cfg(unix)]
+    if let Err(e) = s.set_reuse_port(true) {
+        warn!("Failed to set SO_REUSEPORT on WS-Discovery socket: {}", e);
+    }
+    s.set_nonblocking(true)?;
+    s.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, WSD_PORT)).into())?;
+    s.join_multicast_v4(&SSDP_ADDR_V4, &ip)?;
+    s.set_multicast_ttl_v4(1)?;
+    if !ip.is_unspecified() {
+        s.set_multicast_if_v4(&ip)?;
+    }
+    UdpSocket::from_std(s.into())
+}
+
+impl Responder {
+    fn hello(&self, target: &WsdTarget) -> String {
+        let mut body = String::from("<wsd:Hello>");
+        self.write_target(&mut body, target);
+        body.push_str("</wsd:Hello>");
+        self.envelope(TO_DISCOVERY, "Hello", None, &body)
+    }
+
+    fn bye(&self, target: &WsdTarget) -> String {
+        let body = format!(
+            "<wsd:Bye><wsa:EndpointReference><wsa:Address>{}</wsa:Address></wsa:EndpointReference></wsd:Bye>",
+            escape(&target.address)
+        );
+        self.envelope(TO_DISCOVERY, "Bye", None, &body)
+    }
+
+    fn probe_matches(&self, relates_to: &str) -> String {
+        let mut body = String::from("<wsd:ProbeMatches>");
+        for target in &self.targets {
+            body.push_str("<wsd:ProbeMatch>");
+            self.write_target(&mut body, target);
+            body.push_str("</wsd:ProbeMatch>");
+        }
+        body.push_str("</wsd:ProbeMatches>");
+        self.envelope(TO_ANONYMOUS, "ProbeMatches", Some(relates_to), &body)
+    }
+
+    fn write_target(&self, out: &mut String, target: &WsdTarget) {
+        let types: Vec<_> = self
+            .config
+            .types
+            .iter()
+            .enumerate()
+            .map(|(idx, (_, name))| format!("t{}:{}", idx, escape(name)))
+            .collect();
+        let scopes: Vec<_> = self.config.scopes.iter().map(|s| escape(s)).collect();
+
+        let _ = write!(
+            out,
+            "<wsa:EndpointReference><wsa:Address>{}</wsa:Address></wsa:EndpointReference>",
+            escape(&target.address)
+        );
+        let _ = write!(out, "<wsd:Types>{}</wsd:Types>", types.join(" "));
+        if !scopes.is_empty() {
+            let _ = write!(out, "<wsd:Scopes>{}</wsd:Scopes>", scopes.join(" "));
+        }
+        let _ = write!(out, "<wsd:XAddrs>{}</wsd:XAddrs>", escape(&target.xaddrs));
+        out.push_str("<wsd:MetadataVersion>1</wsd:MetadataVersion>");
+    }
+
+    fn envelope(&self, to: &str, action: &str, relates_to: Option<&str>, body: &str) -> String {
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+        let _ = write!(
+            out,
+            "<soap:Envelope xmlns:soap=\"{}\" xmlns:wsa=\"{}\" xmlns:wsd=\"{}\"",
+            NS_SOAP, NS_ADDRESSING, NS_DISCOVERY
+        );
+        for (idx, (namespace, _)) in self.config.types.iter().enumerate() {
+            let _ = write!(out, " xmlns:t{}=\"{}\"", idx, escape(namespace));
+        }
+        out.push_str("><soap:Header>");
+        let _ = write!(out, "<wsa:To>{}</wsa:To>", to);
+        let _ = write!(out, "<wsa:Action>{}/{}</wsa:Action>", NS_DISCOVERY, action);
+        let _ = write!(
+            out,
+            "<wsa:MessageID>urn:uuid:{}</wsa:MessageID>",
+            rng::uuid()
+        );
+        if let Some(relates_to) = relates_to {
+            let _ = write!(out, "<wsa:RelatesTo>{}</wsa:RelatesTo>", escape(relates_to));
+        }
+        let _ = write!(
+            out,
+            "<wsd:AppSequence InstanceId=\"{}\" MessageNumber=\"{}\"/>",
+            self.instance_id,
+            self.message_number.fetch_add(1, Ordering::Relaxed)
+        );
+        let _ = write!(
+            out,
+            "</soap:Header><soap:Body>{}</soap:Body></soap:Envelope>",
+            body
+        );
+        out
+    }
+}
+
+/// A `Probe` request.
+#[derive(Debug)]
+struct Probe {
+    message_id: String,
+    /// The probed types, with their namespace if it could be resolved.
+    types: Vec<(Option<String>, String)>,
+    scopes: Vec<String>,
+}
+
+impl Probe {
+    /// Parse `xml` as a probe, or return `None` if it is none.
+    fn parse(xml: &str) -> Option<Self> {
+        let action = element_text(xml, "Action")?;
+        if !action.trim().ends_with("/discovery/Probe") {
+            return None;
+        }
+
+        let types = element_text(xml, "Types")
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(|qname| match qname.split_once(':') {
+                Some((prefix, name)) => (namespace(xml, prefix), name.to_string()),
+                None => (None, qname.to_string()),
+            })
+            .collect();
+        let scopes = element_text(xml, "Scopes")
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(String::from)
+            .collect();
+
+        Some(Self {
+            message_id: element_text(xml, "MessageID")?.trim().to_string(),
+            types,
+            scopes,
+        })
+    }
+}
+
+/// The text of the first element named `local_name` in any namespace.
+fn element_text<'a>(xml: &'a str, local_name: &str) -> Option<&'a str> {
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let end = rest.find(|c: char| c.is_whitespace() || c == '>' || c == '/')?;
+        let name = &rest[..end];
+        if name.rsplit(':').next() != Some(local_name) {
+            continue;
+        }
+
+        let tag_end = rest.find('>')?;
+        if rest[..tag_end].ends_with('/') {
+            return Some("");
+        }
+        let content = &rest[tag_end + 1..];
+        return Some(&content[..content.find("</")?]);
+    }
+    None
+}
+
+/// The namespace bound to `prefix` anywhere in `xml`.
+fn namespace(xml: &str, prefix: &str) -> Option<String> {
+    let decl = format!("xmlns:{}=", prefix);
+    let value = &xml[xml.find(&decl)? + decl.len()..];
+    let quote = value.chars().next()?;
+    let value = &value[1..];
+    Some(value[..value.find(quote)?].to_string())
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}